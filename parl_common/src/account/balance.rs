@@ -1,11 +1,12 @@
 use std::fmt::Display;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use crate::crypto::elgamal::{Ciphertext, CompressedCiphertext, DecompressionError};
 use crate::serializer::{Serializer, ReaderError, Reader, Writer};
 
 use super::CiphertextCache;
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum BalanceType {
     // Only incoming funds were added
@@ -40,7 +41,7 @@ impl Serializer for BalanceType {
     }
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Debug)]
 pub struct VersionedBalance {
     // Output balance is used in case of multi TXs not in same block
     // If you build several TXs at same time but are not in the same block,
@@ -6,6 +6,7 @@ use std::fmt::{self, Display, Formatter};
 
 pub use balance::{VersionedBalance, BalanceType};
 pub use nonce::VersionedNonce;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Serialize, Deserialize};
 use crate::crypto::elgamal::{Ciphertext, CompressedCiphertext, DecompressionError, RISTRETTO_COMPRESSED_SIZE};
 
@@ -156,6 +157,21 @@ impl<'de> Deserialize<'de> for CiphertextCache {
     }
 }
 
+// Always serialized in its compressed form
+impl JsonSchema for CiphertextCache {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        CompressedCiphertext::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        gen.subschema_for::<CompressedCiphertext>()
+    }
+}
+
 impl Display for CiphertextCache {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "CiphertextCache[{}]", match self {
@@ -1,4 +1,5 @@
 use std::fmt::{self, Display, Formatter};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use crate::serializer::{
     Reader,
@@ -8,7 +9,7 @@ use crate::serializer::{
 };
 
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VersionedNonce {
     nonce: u64,
     previous_topoheight: Option<u64>,
@@ -5,6 +5,7 @@ use std::{
 };
 use indexmap::IndexSet;
 use serde::{Deserialize, Serialize, Serializer, Deserializer, de::Error};
+use schemars::JsonSchema;
 use crate::{
     account::{CiphertextCache, VersionedBalance, VersionedNonce},
     block::EXTRA_NONCE_SIZE,
@@ -13,9 +14,14 @@ use crate::{
     network::Network,
     time::{TimestampMillis, TimestampSeconds}
 };
+#[cfg(feature = "rpc_server")]
+use crate::rpc_server::{websocket::SubscriptionFilter, InternalRpcError};
 use super::RPCTransaction;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq)]
+// Max accounts in the filter of a subscription
+pub const MAX_EVENT_FILTER_KEYS: usize = 256;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum BlockType {
     Sync,
     Side,
@@ -38,7 +44,7 @@ pub fn deserialize_extra_nonce<'de, 'a, D: Deserializer<'de>>(deserializer: D) -
 }
 
 // Structure used to map the public key to a human readable address
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct RPCBlockResponse<'a> {
     pub hash: Cow<'a, Hash>,
     pub topoheight: Option<u64>,
@@ -61,6 +67,7 @@ pub struct RPCBlockResponse<'a> {
     pub nonce: u64,
     #[serde(serialize_with = "serialize_extra_nonce")]
     #[serde(deserialize_with = "deserialize_extra_nonce")]
+    #[schemars(with = "String")]
     pub extra_nonce: Cow<'a, [u8; EXTRA_NONCE_SIZE]>,
     pub miner: Cow<'a, Address>,
     pub txs_hashes: Cow<'a, IndexSet<Hash>>,
@@ -71,39 +78,43 @@ pub struct RPCBlockResponse<'a> {
 
 pub type BlockResponse = RPCBlockResponse<'static>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetTopBlockParams {
     #[serde(default)]
     pub include_txs: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBlockAtTopoHeightParams {
     pub topoheight: u64,
     #[serde(default)]
     pub include_txs: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBlocksAtHeightParams {
     pub height: u64,
     #[serde(default)]
     pub include_txs: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBlockByHashParams<'a> {
     pub hash: Cow<'a, Hash>,
     #[serde(default)]
     pub include_txs: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBlockTemplateParams<'a> {
-    pub address: Cow<'a, Address>
+    pub address: Cow<'a, Address>,
+    // long_poll_id of the last template received
+    // if it's still the current one, wait until the template changes
+    #[serde(default)]
+    pub long_poll_id: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct CreateMinerWorkParams<'a> {
     // Block Template in hexadecimal format
     pub template: Cow<'a, String>,
@@ -111,13 +122,13 @@ pub struct CreateMinerWorkParams<'a> {
     pub address: Option<Cow<'a, Address>>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct CreateMinerWorkResult {
     // MinerWork struct in hexadecimal format
     pub miner_work: String
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBlockTemplateResult {
     // block_template is Block Header in hexadecimal format
     // miner jobs can be created from it
@@ -128,9 +139,13 @@ pub struct GetBlockTemplateResult {
     pub topoheight: u64,
     // Difficulty target for the POW challenge
     pub difficulty: Difficulty,
+    // Version of the template, changed on a new tip or mempool update
+    // only set when the GetWork server is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_poll_id: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct GetMinerWorkResult {
     // template is miner job in hex format
     pub template: String,
@@ -143,7 +158,7 @@ pub struct GetMinerWorkResult {
     pub difficulty: Difficulty
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SubmitMinerWorkParams {
     // hex: represent block miner in hexadecimal format
     // NOTE: alias block_template is used for backward compatibility < 1.9.4
@@ -151,7 +166,7 @@ pub struct SubmitMinerWorkParams {
     pub miner_work: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SubmitBlockParams {
     // hex: represent the BlockHeader (Block)
     pub block_template: String,
@@ -159,13 +174,13 @@ pub struct SubmitBlockParams {
     pub miner_work: Option<String>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBalanceParams<'a> {
     pub address: Cow<'a, Address>,
     pub asset: Cow<'a, Hash>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct HasBalanceParams<'a> {
     pub address: Cow<'a, Address>,
     pub asset: Cow<'a, Hash>,
@@ -173,55 +188,55 @@ pub struct HasBalanceParams<'a> {
     pub topoheight: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct HasBalanceResult {
     pub exist: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBalanceAtTopoHeightParams<'a> {
     pub address: Cow<'a, Address>,
     pub asset: Cow<'a, Hash>,
     pub topoheight: u64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetNonceParams<'a> {
     pub address: Cow<'a, Address>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct HasNonceParams<'a> {
     pub address: Cow<'a, Address>,
     #[serde(default)]
     pub topoheight: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetNonceAtTopoHeightParams<'a> {
     pub address: Cow<'a, Address>,
     pub topoheight: u64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetNonceResult {
     pub topoheight: u64,
     #[serde(flatten)]
     pub version: VersionedNonce
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct HasNonceResult {
     pub exist: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBalanceResult {
     pub version: VersionedBalance,
     pub topoheight: u64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetInfoResult {
     pub height: u64,
     pub topoheight: u64,
@@ -247,26 +262,26 @@ pub struct GetInfoResult {
     pub network: Network
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SubmitTransactionParams {
     pub data: String // should be in hex format
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetTransactionParams<'a> {
     pub hash: Cow<'a, Hash>
 }
 
 pub type GetTransactionExecutorParams<'a> = GetTransactionParams<'a>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetTransactionExecutorResult<'a> {
     pub block_topoheight: u64,
     pub block_hash: Cow<'a, Hash>
 }
 
 // Direction is used for cache to knows from which context it got added
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub enum Direction {
     // We don't update it because it's In, we won't send back
     In,
@@ -299,7 +314,7 @@ impl Direction {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetPeersResponse<'a> {
     // Peers that are connected and allows to be displayed
     pub peers: Vec<PeerEntry<'a>>,
@@ -309,7 +324,33 @@ pub struct GetPeersResponse<'a> {
     pub hidden_peers: usize
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct BanPeerParams<'a> {
+    // IP address or CIDR range (192.168.0.0/16, 2001:db8::/32)
+    pub address: Cow<'a, String>,
+    #[serde(default)]
+    pub reason: Option<Cow<'a, String>>,
+    // Ban duration in seconds, permanent if not set
+    #[serde(default)]
+    pub duration: Option<u64>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct UnbanParams<'a> {
+    // IP address or CIDR range used in the ban
+    pub address: Cow<'a, String>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct BanEntry<'a> {
+    pub range: Cow<'a, String>,
+    pub reason: Cow<'a, Option<String>>,
+    pub created_at: TimestampSeconds,
+    // None if the ban is permanent
+    pub expires_at: Option<TimestampSeconds>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct PeerEntry<'a> {
     pub id: u64,
     pub addr: Cow<'a, SocketAddr>,
@@ -323,10 +364,19 @@ pub struct PeerEntry<'a> {
     pub pruned_topoheight: Option<u64>,
     pub peers: Cow<'a, HashMap<SocketAddr, Direction>>,
     pub cumulative_difficulty: Cow<'a, CumulativeDifficulty>,
-    pub connected_on: TimestampSeconds
+    pub connected_on: TimestampSeconds,
+    // Reputation of the peer based on its behavior
+    #[serde(default)]
+    pub score: i64,
+    // Total bytes sent to the peer
+    #[serde(default)]
+    pub bytes_sent: usize,
+    // Total bytes received from the peer
+    #[serde(default)]
+    pub bytes_received: usize
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct P2pStatusResult<'a> {
     pub peer_count: usize,
     pub max_peers: usize,
@@ -337,24 +387,24 @@ pub struct P2pStatusResult<'a> {
     pub peer_id: u64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetTopoHeightRangeParams {
     pub start_topoheight: Option<u64>,
     pub end_topoheight: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetHeightRangeParams {
     pub start_height: Option<u64>,
     pub end_height: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetTransactionsParams {
     pub tx_hashes: Vec<Hash>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct TransactionResponse<'a> {
     // in which blocks it was included
     pub blocks: Option<HashSet<Hash>>,
@@ -374,7 +424,7 @@ fn default_parl_asset() -> Hash {
     crate::config::PARL_ASSET
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAccountHistoryParams {
     pub address: Address,
     #[serde(default = "default_parl_asset")]
@@ -383,7 +433,7 @@ pub struct GetAccountHistoryParams {
     pub maximum_topoheight: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")] 
 pub enum AccountHistoryType {
     DevFee { reward: u64 },
@@ -393,7 +443,7 @@ pub enum AccountHistoryType {
     Incoming { from: Address },
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct AccountHistoryEntry {
     pub topoheight: u64,
     pub hash: Hash,
@@ -402,17 +452,17 @@ pub struct AccountHistoryEntry {
     pub block_timestamp: TimestampMillis
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAccountAssetsParams<'a> {
     pub address: Cow<'a, Address>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAssetParams<'a> {
     pub asset: Cow<'a, Hash>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAssetsParams {
     pub skip: Option<usize>,
     pub maximum: Option<usize>,
@@ -420,7 +470,7 @@ pub struct GetAssetsParams {
     pub maximum_topoheight: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAccountsParams {
     pub skip: Option<usize>,
     pub maximum: Option<usize>,
@@ -428,26 +478,26 @@ pub struct GetAccountsParams {
     pub maximum_topoheight: Option<u64>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct IsAccountRegisteredParams<'a> {
     pub address: Cow<'a, Address>,
     // If it is registered in stable height (confirmed)
     pub in_stable_height: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAccountRegistrationParams<'a> {
     pub address: Cow<'a, Address>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct IsTxExecutedInBlockParams<'a> {
     pub tx_hash: Cow<'a, Hash>,
     pub block_hash: Cow<'a, Hash>
 }
 
 // Struct to define dev fee threshold
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(serde::Serialize, serde::Deserialize, JsonSchema)]
 pub struct DevFeeThreshold {
     // block height to start dev fee
     pub height: u64,
@@ -456,18 +506,18 @@ pub struct DevFeeThreshold {
 }
 
 // Struct to returns the size of the blockchain on disk
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SizeOnDiskResult {
     pub size_bytes: u64,
     pub size_formatted: String
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetMempoolCacheParams<'a> {
     pub address: Cow<'a, Address>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetMempoolCacheResult {
     // lowest nonce used
     min: u64,
@@ -479,14 +529,14 @@ pub struct GetMempoolCacheResult {
     balances: HashMap<Hash, CiphertextCache>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetDifficultyResult {
     pub difficulty: Difficulty,
     pub hashrate: Difficulty,
     pub hashrate_formatted: String
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct ValidateAddressParams<'a> {
     pub address: Cow<'a, Address>,
     #[serde(default)]
@@ -495,27 +545,27 @@ pub struct ValidateAddressParams<'a> {
     pub max_integrated_data_size: Option<usize>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct ValidateAddressResult {
     pub is_valid: bool,
     pub is_integrated: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct ExtractKeyFromAddressParams<'a> {
     pub address: Cow<'a, Address>,
     #[serde(default)]
     pub as_hex: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExtractKeyFromAddressResult {
     Bytes(Vec<u8>),
     Hex(String)
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum NotifyEvent {
     // When a new block is accepted by chain
@@ -527,6 +577,12 @@ pub enum NotifyEvent {
     // When a block that was ordered is not in the new DAG order
     // it contains BlockOrphanedEvent that got orphaned
     BlockOrphaned,
+    // When the DAG order changed above a common base
+    // it contains ChainReorganizedEvent as value
+    ChainReorganized,
+    // When a new block template is available for the miners
+    // it contains NewBlockTemplateEvent as value
+    NewBlockTemplate,
     // When stable height has changed (different than the previous one)
     // it contains StableHeightChangedEvent struct as value
     StableHeightChanged,
@@ -545,6 +601,10 @@ pub enum NotifyEvent {
     // When a new asset has been registered
     // TODO: Smart Contracts
     NewAsset,
+    // When the balance of an account has a new version
+    // Subscription requires a filter with the accounts to track
+    // It contains AccountBalanceChangedEvent as value
+    AccountBalanceChanged,
     // When a new peer has connected to us
     // It contains PeerConnectedEvent struct as value
     PeerConnected,
@@ -563,11 +623,67 @@ pub enum NotifyEvent {
     PeerPeerDisconnected,
 }
 
+impl NotifyEvent {
+    // Events accepting a filter in their subscription
+    pub fn is_filterable(&self) -> bool {
+        matches!(self, Self::NewBlock | Self::TransactionAddedInMempool | Self::TransactionExecuted | Self::TransactionOrphaned | Self::AccountBalanceChanged)
+    }
+}
+
+// Filter of a subscription to the events of the node
+// An event is sent only if it matches all the criteria set
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct EventFilter {
+    // Accounts involved as sender, receiver or block miner
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<Address>,
+    // Assets transferred or burned
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assets: Vec<Hash>,
+    // Only the transactions burning at least this amount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_burn_amount: Option<u64>,
+    // Only the transactions burning at most this amount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_burn_amount: Option<u64>
+}
+
+#[cfg(feature = "rpc_server")]
+impl SubscriptionFilter<NotifyEvent> for EventFilter {
+    fn validate(event: &NotifyEvent, filter: Option<&Self>) -> Result<(), InternalRpcError> {
+        let filter = match filter {
+            Some(filter) => filter,
+            None if *event == NotifyEvent::AccountBalanceChanged => return Err(InternalRpcError::InvalidParams("Accounts to track are required for this event")),
+            None => return Ok(())
+        };
+
+        if !event.is_filterable() {
+            return Err(InternalRpcError::InvalidParams("This event can't be filtered"))
+        }
+
+        if *event == NotifyEvent::AccountBalanceChanged && filter.keys.is_empty() {
+            return Err(InternalRpcError::InvalidParams("Accounts to track are required for this event"))
+        }
+
+        if filter.keys.len() > MAX_EVENT_FILTER_KEYS {
+            return Err(InternalRpcError::InvalidParams("Too many accounts in the filter"))
+        }
+
+        if let (Some(min), Some(max)) = (filter.min_burn_amount, filter.max_burn_amount) {
+            if min > max {
+                return Err(InternalRpcError::InvalidParams("Min burn amount is above the max burn amount"))
+            }
+        }
+
+        Ok(())
+    }
+}
+
 // Value of NotifyEvent::NewBlock
 pub type NewBlockEvent = BlockResponse;
 
 // Value of NotifyEvent::BlockOrdered
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct BlockOrderedEvent<'a> {
     // block hash in which this event was triggered
     pub block_hash: Cow<'a, Hash>,
@@ -577,15 +693,51 @@ pub struct BlockOrderedEvent<'a> {
 }
 
 // Value of NotifyEvent::BlockOrphaned
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct BlockOrphanedEvent<'a> {
     pub block_hash: Cow<'a, Hash>,
     // Tpoheight of the block before being orphaned
     pub old_topoheight: u64
 }
 
+// Topoheights from start to end, both included
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TopoHeightRange {
+    pub start: u64,
+    pub end: u64
+}
+
+// Value of NotifyEvent::ChainReorganized
+// It is sent after the BlockOrphaned and TransactionOrphaned events of the reorganization
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ChainReorganizedEvent<'a> {
+    // Highest block which stayed at the same topoheight
+    pub common_base_hash: Cow<'a, Hash>,
+    pub common_base_topoheight: u64,
+    // Topoheights that were ordered before the reorganization
+    pub old_range: TopoHeightRange,
+    // Topoheights ordered by the reorganization, none if the chain was rewinded
+    pub new_range: Option<TopoHeightRange>,
+    // Blocks that are not in the DAG order anymore
+    pub orphaned_blocks: Vec<Hash>,
+    // Blocks ordered in the new range, by topoheight
+    pub ordered_blocks: Vec<Hash>,
+    // Transactions not executed anymore and added back in mempool
+    pub txs_back_to_mempool: Vec<Hash>
+}
+
+// Value of NotifyEvent::NewBlockTemplate
+// get_block_template must be called with the miner address to build it
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct NewBlockTemplateEvent {
+    pub long_poll_id: u64,
+    pub height: u64,
+    pub topoheight: u64,
+    pub difficulty: Difficulty
+}
+
 // Value of NotifyEvent::StableHeightChanged
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct StableHeightChangedEvent {
     pub previous_stable_height: u64,
     pub new_stable_height: u64
@@ -597,13 +749,23 @@ pub type TransactionAddedInMempoolEvent = TransactionResponse<'static>;
 pub type TransactionOrphanedEvent = TransactionResponse<'static>;
 
 // Value of NotifyEvent::TransactionExecuted
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct TransactionExecutedEvent<'a> {
     pub block_hash: Cow<'a, Hash>,
     pub tx_hash: Cow<'a, Hash>,
     pub topoheight: u64,
 }
 
+// Value of NotifyEvent::AccountBalanceChanged
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct AccountBalanceChangedEvent<'a> {
+    pub address: Cow<'a, Address>,
+    pub asset: Cow<'a, Hash>,
+    // topoheight of the new version
+    pub topoheight: u64,
+    pub version: Cow<'a, VersionedBalance>
+}
+
 // Value of NotifyEvent::PeerConnected
 pub type PeerConnectedEvent = PeerEntry<'static>;
 
@@ -611,7 +773,7 @@ pub type PeerConnectedEvent = PeerEntry<'static>;
 pub type PeerDisconnectedEvent = PeerEntry<'static>;
 
 // Value of NotifyEvent::PeerPeerListUpdated
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct PeerPeerListUpdatedEvent {
     // Peer ID of the peer that sent us the new peer list
     pub peer_id: u64,
@@ -623,7 +785,7 @@ pub struct PeerPeerListUpdatedEvent {
 pub type PeerStateUpdatedEvent = PeerEntry<'static>;
 
 // Value of NotifyEvent::PeerPeerDisconnected
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct PeerPeerDisconnectedEvent {
     // Peer ID of the peer that sent us this notification
     pub peer_id: u64,
@@ -5,6 +5,7 @@ pub mod query;
 
 use std::borrow::Cow;
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use serde_json::Value;
 use bulletproofs::RangeProof;
 use crate::{
@@ -27,26 +28,29 @@ use crate::{
 };
 pub use data::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SubscribeParams<'a, E: Clone> {
-    pub notify: Cow<'a, E>
+    pub notify: Cow<'a, E>,
+    // Only the events matching it are sent, if the server supports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Value>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct EventResult<'a, E: Clone> {
     pub event: Cow<'a, E>,
     #[serde(flatten)]
     pub value: Value
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct DataHash<'a, T: Clone> {
     pub hash: Cow<'a, Hash>,
     #[serde(flatten)]
     pub data: Cow<'a, T>
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RPCTransferPayload<'a> {
     pub asset: Cow<'a, Hash>,
     pub destination: Address,
@@ -71,7 +75,7 @@ impl<'a> From<RPCTransferPayload<'a>> for TransferPayload {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum RPCTransactionType<'a> {
     Transfers(Vec<RPCTransferPayload<'a>>),
@@ -116,7 +120,7 @@ impl From<RPCTransactionType<'_>> for TransactionType {
 // We use this one for serde (de)serialization
 // So we have addresses displayed as strings and not Public Key as bytes
 // This is much more easier for developers relying on the API
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct RPCTransaction<'a> {
     pub hash: Cow<'a, Hash>,
     /// Version of the transaction
@@ -133,6 +137,7 @@ pub struct RPCTransaction<'a> {
     /// We have one source commitment and equality proof per asset used in the tx.
     pub source_commitments: Cow<'a, Vec<SourceCommitment>>,
     /// The range proof is aggregated across all transfers and across all assets.
+    #[schemars(with = "Vec<u8>")]
     pub range_proof: Cow<'a, RangeProof>,
     /// Reference at which block the transaction was built
     pub reference: Cow<'a, Reference>,
@@ -176,13 +181,13 @@ impl<'a> From<RPCTransaction<'a>> for Transaction {
 // and not have to specify the lifetime
 pub type TransactionResponse = RPCTransaction<'static>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SplitAddressParams {
     // address which must be in integrated form
     pub address: Address
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SplitAddressResult {
     // Normal address
     pub address: Address,
@@ -1,7 +1,9 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashSet};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use crate::{
     crypto::{Address, Hash},
+    serializer::{Reader, ReaderError, Serializer, Writer},
     transaction::{
         builder::{FeeBuilder, TransactionTypeBuilder},
         Transaction
@@ -9,7 +11,7 @@ use crate::{
 };
 use super::{DataHash, DataElement, DataValue, query::Query};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct BuildTransactionParams {
     #[serde(flatten)]
     pub tx_type: TransactionTypeBuilder,
@@ -21,7 +23,7 @@ pub struct BuildTransactionParams {
     pub tx_as_hex: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct EstimateFeesParams {
     #[serde(flatten)]
     pub tx_type: TransactionTypeBuilder,
@@ -36,7 +38,7 @@ fn default_false_value() -> bool {
     false
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct ListTransactionsParams {
     pub min_topoheight: Option<u64>,
     pub max_topoheight: Option<u64>,
@@ -54,7 +56,7 @@ pub struct ListTransactionsParams {
     pub query: Option<Query>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct TransactionResponse<'a> {
     #[serde(flatten)]
     pub inner: DataHash<'a, Transaction>,
@@ -62,80 +64,113 @@ pub struct TransactionResponse<'a> {
     pub tx_as_hex: Option<String>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAssetPrecisionParams<'a> {
     pub asset: Cow<'a, Hash>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetAddressParams {
     // Data to use for creating an integrated address
     // Returned address will contains all the data provided here
     pub integrated_data: Option<DataElement>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct RescanParams {
     pub until_topoheight: Option<u64>,
     #[serde(default = "default_false_value")]
     pub auto_reconnect: bool
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct SetOnlineModeParams {
     pub daemon_address: String,
     #[serde(default = "default_false_value")]
     pub auto_reconnect: bool,
+    // Other daemons to use if the main one goes down or falls behind
+    #[serde(default)]
+    pub fallback_daemon_addresses: Vec<String>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct AddWebhookParams {
+    // URL where events are sent using a POST request
+    pub url: String,
+    // Secret used to sign each request body
+    pub secret: String,
+    // Events to send, all events if empty
+    #[serde(default)]
+    pub events: HashSet<NotifyEvent>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct RemoveWebhookParams {
+    pub url: String
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct WebhookResponse<'a> {
+    pub url: Cow<'a, String>,
+    pub events: Cow<'a, HashSet<NotifyEvent>>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct GetDaemonEndpointsResult {
+    // Daemon currently used by the wallet
+    pub active: Option<String>,
+    // All daemons configured
+    pub endpoints: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetBalanceParams {
     pub asset: Option<Hash>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetTransactionParams {
     pub hash: Hash
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct BalanceChanged {
     pub asset: Hash,
     pub balance: u64
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetValueFromKeyParams {
     pub tree: String,
     pub key: DataValue
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct HasKeyParams {
     pub tree: String,
     pub key: DataValue
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct GetMatchingKeysParams {
     pub tree: String,
     pub query: Option<Query>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct StoreParams {
     pub tree: String,
     pub key: DataValue,
     pub value: DataElement
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct DeleteParams {
     pub tree: String,
     pub key: DataValue
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct QueryDBParams {
     pub tree: String,
     pub key: Option<Query>,
@@ -144,7 +179,7 @@ pub struct QueryDBParams {
     pub return_on_first: bool
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum NotifyEvent {
     // When a new topoheight is detected by wallet
@@ -169,7 +204,39 @@ pub enum NotifyEvent {
     Offline,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Serializer for NotifyEvent {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        Ok(match reader.read_u8()? {
+            0 => Self::NewTopoHeight,
+            1 => Self::NewAsset,
+            2 => Self::NewTransaction,
+            3 => Self::BalanceChanged,
+            4 => Self::Rescan,
+            5 => Self::Online,
+            6 => Self::Offline,
+            _ => return Err(ReaderError::InvalidValue)
+        })
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        let id = match self {
+            Self::NewTopoHeight => 0,
+            Self::NewAsset => 1,
+            Self::NewTransaction => 2,
+            Self::BalanceChanged => 3,
+            Self::Rescan => 4,
+            Self::Online => 5,
+            Self::Offline => 6
+        };
+        writer.write_u8(id);
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TransferOut {
     // Destination address
     pub destination: Address,
@@ -181,7 +248,7 @@ pub struct TransferOut {
     pub extra_data: Option<DataElement>
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TransferIn {
     // Asset spent
     pub asset: Hash,
@@ -191,7 +258,7 @@ pub struct TransferIn {
     pub extra_data: Option<DataElement>
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum EntryType {
     // Coinbase is only PARL_ASSET
@@ -217,7 +284,7 @@ pub enum EntryType {
 
 // This struct is used to represent a transaction entry like in wallet
 // But we replace every PublicKey to use Address instead
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TransactionEntry {
     pub hash: Hash,
     pub topoheight: u64,
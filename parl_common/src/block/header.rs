@@ -3,7 +3,7 @@ use indexmap::IndexSet;
 use serde::Deserialize;
 use log::debug;
 use crate::{
-    block::{BLOCK_WORK_SIZE, HEADER_WORK_SIZE},
+    block::{BLOCK_VERSION_STATE_COMMITMENT, BLOCK_WORK_SIZE, HEADER_WORK_SIZE, STATE_COMMITMENT_HEADER_WORK_SIZE},
     config::TIPS_LIMIT,
     crypto::{
         elgamal::CompressedPublicKey,
@@ -33,6 +33,48 @@ pub fn deserialize_extra_nonce<'de, D: serde::Deserializer<'de>>(deserializer: D
     Ok(extra_nonce)
 }
 
+// State root of the chain at a stable topoheight
+// It commits to the last version of every balance and nonce at this topoheight
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StateCommitment {
+    pub topoheight: u64,
+    pub root: Hash
+}
+
+impl StateCommitment {
+    pub fn new(topoheight: u64, root: Hash) -> Self {
+        Self {
+            topoheight,
+            root
+        }
+    }
+
+    pub fn get_topoheight(&self) -> u64 {
+        self.topoheight
+    }
+
+    pub fn get_root(&self) -> &Hash {
+        &self.root
+    }
+}
+
+impl Serializer for StateCommitment {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u64(&self.topoheight);
+        writer.write_hash(&self.root);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let topoheight = reader.read_u64()?;
+        let root = reader.read_hash()?;
+        Ok(Self::new(topoheight, root))
+    }
+
+    fn size(&self) -> usize {
+        self.topoheight.size() + HASH_SIZE
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct BlockHeader {
     // Version of the block
@@ -55,7 +97,11 @@ pub struct BlockHeader {
     // Miner public key
     pub miner: CompressedPublicKey,
     // All transactions hashes of the block
-    pub txs_hashes: IndexSet<Hash>
+    pub txs_hashes: IndexSet<Hash>,
+    // State root committed by the block, required since version 1
+    // Light clients verify the state proofs against it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_commitment: Option<StateCommitment>
 }
 
 impl BlockHeader {
@@ -68,7 +114,8 @@ impl BlockHeader {
             nonce: 0,
             extra_nonce,
             miner,
-            txs_hashes
+            txs_hashes,
+            state_commitment: None
         }
     }
 
@@ -150,18 +197,31 @@ impl BlockHeader {
         self.txs_hashes.len()
     }
 
+    pub fn get_state_commitment(&self) -> Option<&StateCommitment> {
+        self.state_commitment.as_ref()
+    }
+
+    pub fn set_state_commitment(&mut self, commitment: StateCommitment) {
+        self.state_commitment = Some(commitment);
+    }
+
     // Build the header work (immutable part in mining process)
     // This is the part that will be used to compute the header work hash
     // See get_work_hash function and get_serialized_header for final hash computation
     pub fn get_work(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_WORK_SIZE);
+        let size = if self.state_commitment.is_some() { STATE_COMMITMENT_HEADER_WORK_SIZE } else { HEADER_WORK_SIZE };
+        let mut bytes: Vec<u8> = Vec::with_capacity(size);
 
         bytes.push(self.version); // 1
         bytes.extend(&self.height.to_be_bytes()); // 1 + 8 = 9
         bytes.extend(self.get_tips_hash().as_bytes()); // 9 + 32 = 41
         bytes.extend(self.get_txs_hash().as_bytes()); // 41 + 32 = 73
+        if let Some(commitment) = &self.state_commitment {
+            bytes.extend(&commitment.topoheight.to_be_bytes()); // 73 + 8 = 81
+            bytes.extend(commitment.root.as_bytes()); // 81 + 32 = 113
+        }
 
-        debug_assert!(bytes.len() == HEADER_WORK_SIZE, "Error, invalid header work size, got {} but expected {}", bytes.len(), HEADER_WORK_SIZE);
+        debug_assert!(bytes.len() == size, "Error, invalid header work size, got {} but expected {}", bytes.len(), size);
 
         bytes
     }
@@ -213,13 +273,16 @@ impl Serializer for BlockHeader {
         }
         self.miner.write(writer); // 60 + (N*32) + (T*32) + 32 = 92 + (N*32) + (T*32)
         // Minimum size is 92 bytes
+        if let Some(commitment) = &self.state_commitment {
+            commitment.write(writer); // 40 bytes since version 1
+        }
     }
 
     fn read(reader: &mut Reader) -> Result<BlockHeader, ReaderError> {
         let version = reader.read_u8()?;
-        // At this moment we only support version 0, so we check it here directly
-        if version != 0 {
-            debug!("Expected version 0 got version {version}");
+        // Version 1 added the state commitment, we don't support any newer version
+        if version > BLOCK_VERSION_STATE_COMMITMENT {
+            debug!("Expected version 0 or 1 got version {version}");
             return Err(ReaderError::InvalidValue)
         }
 
@@ -252,6 +315,12 @@ impl Serializer for BlockHeader {
         }
 
         let miner = CompressedPublicKey::read(reader)?;
+        let state_commitment = if version >= BLOCK_VERSION_STATE_COMMITMENT {
+            Some(StateCommitment::read(reader)?)
+        } else {
+            None
+        };
+
         Ok(
             BlockHeader {
                 version,
@@ -261,7 +330,8 @@ impl Serializer for BlockHeader {
                 tips,
                 miner,
                 nonce,
-                txs_hashes
+                txs_hashes,
+                state_commitment
             }
         )
     }
@@ -274,7 +344,10 @@ impl Serializer for BlockHeader {
         // Version is u8
         let version_size = 1;
 
-        EXTRA_NONCE_SIZE + tips_size + txs_size + version_size
+        // State commitment is only present since version 1
+        let state_commitment_size = self.state_commitment.as_ref().map(StateCommitment::size).unwrap_or(0);
+
+        EXTRA_NONCE_SIZE + tips_size + txs_size + version_size + state_commitment_size
         + self.miner.size()
         + self.timestamp.size()
         + self.height.size()
@@ -304,7 +377,7 @@ impl Display for BlockHeader {
 mod tests {
     use indexmap::IndexSet;
     use crate::{crypto::{Hash, Hashable, KeyPair}, serializer::Serializer};
-    use super::BlockHeader;
+    use super::{BlockHeader, StateCommitment};
 
     #[test]
     fn test_block_template() {
@@ -321,6 +394,25 @@ mod tests {
         assert!(header.hash() == deserialized.hash());
     }
 
+    #[test]
+    fn test_block_template_with_state_commitment() {
+        let miner = KeyPair::new().get_public_key().compress();
+        let mut header = BlockHeader::new(1, 10, 0, IndexSet::from([Hash::zero()]), [0u8; 32], miner, IndexSet::new());
+        let mut other = header.clone();
+        header.set_state_commitment(StateCommitment::new(2, Hash::zero()));
+
+        let serialized = header.to_bytes();
+        assert!(serialized.len() == header.size());
+
+        let deserialized = BlockHeader::from_bytes(&serialized).unwrap();
+        assert!(header.hash() == deserialized.hash());
+        assert_eq!(deserialized.get_state_commitment(), header.get_state_commitment());
+
+        // The state root is covered by the PoW
+        other.set_state_commitment(StateCommitment::new(2, Hash::max()));
+        assert!(header.hash() != other.hash());
+    }
+
     #[test]
     fn test_block_template_from_hex() {
         let serialized = "00000000000000002d0000018f1cbd697000000000000000000eded85557e887b45989a727b6786e1bd250de65042d9381822fa73d01d2c4ff01d3a0154853dbb01dc28c9102e9d94bea355b8ee0d82c3e078ac80841445e86520000d67ad13934337b85c34985491c437386c95de0d97017131088724cfbedebdc55".to_owned();
@@ -2,7 +2,7 @@ mod header;
 mod block;
 mod miner;
 
-pub use header::BlockHeader;
+pub use header::{BlockHeader, StateCommitment};
 pub use block::Block;
 pub use miner::MinerWork;
 
@@ -10,6 +10,10 @@ use crate::crypto::{Hash, HASH_SIZE};
 
 pub const EXTRA_NONCE_SIZE: usize = 32;
 pub const HEADER_WORK_SIZE: usize = 73;
+// Header work of the blocks committing a state root: 73 + 8 + 32 = 113
+pub const STATE_COMMITMENT_HEADER_WORK_SIZE: usize = 113;
+// First block version committing a state root in its header
+pub const BLOCK_VERSION_STATE_COMMITMENT: u8 = 1;
 pub const BLOCK_WORK_SIZE: usize = 112; // 32 + 8 + 8 + 32 + 32 = 112
 
 // Get combined hash for tips
@@ -1,8 +1,18 @@
-use crate::crypto::Hash;
+use crate::{crypto::Hash, difficulty::Difficulty};
 
 pub const VERSION: &str = env!("BUILD_VERSION");
 pub const PARL_ASSET: Hash = Hash::zero();
 
+// Millis per second, it is used to prevent having random 1000 values anywhere
+pub const MILLIS_PER_SECOND: u64 = 1000;
+// Block Time in milliseconds
+pub const BLOCK_TIME_MILLIS: u64 = 15 * MILLIS_PER_SECOND; // 15s block time
+// Minimum difficulty (each difficulty point is in H/s)
+// Current: BLOCK TIME in millis * 20 = 20 KH/s minimum
+// This is to prevent spamming the network with low difficulty blocks
+// This is active only on mainnet mode
+pub const MAINNET_MINIMUM_DIFFICULTY: Difficulty = Difficulty::from_u64(BLOCK_TIME_MILLIS * 20);
+
 // 0.00010000 PRL per KB
 pub const FEE_PER_KB: u64 = 10000;
 // 0.0100000 PRL per account creation
@@ -16,6 +16,7 @@ use super::{
 use core::fmt;
 use log::debug;
 use serde::de::Error as SerdeError;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use anyhow::Error;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -244,6 +245,17 @@ impl<'a> serde::Deserialize<'a> for Address {
     }
 }
 
+// Address is serialized as its bech32 string
+impl JsonSchema for Address {
+    fn schema_name() -> String {
+        "Address".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
 impl Display for Address {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.as_string().map_err(|_| fmt::Error)?)
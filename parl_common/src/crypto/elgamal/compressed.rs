@@ -1,4 +1,5 @@
 use curve25519_dalek::{ristretto::CompressedRistretto, Scalar};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use crate::{api::DataElement, crypto::{Address, AddressType}, serializer::{Reader, ReaderError, Serializer, Writer}};
@@ -29,23 +30,23 @@ impl SerializableCompressedPoint for CompressedRistretto {
 pub struct DecompressionError;
 
 // A Pedersen commitment compressed to 32 bytes
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub struct CompressedCommitment(CompressedRistretto);
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CompressedCommitment(#[schemars(with = "[u8; 32]")] CompressedRistretto);
 
 // A decrypt handle compressed to 32 bytes
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub struct CompressedHandle(CompressedRistretto);
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CompressedHandle(#[schemars(with = "[u8; 32]")] CompressedRistretto);
 
 // A compressed ciphertext that can be serialized and deserialized with only 64 bytes
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct CompressedCiphertext {
     commitment: CompressedCommitment,
     handle: CompressedHandle
 }
 
 // A compressed public key using only 32 bytes
-#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub struct CompressedPublicKey(CompressedRistretto);
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CompressedPublicKey(#[schemars(with = "[u8; 32]")] CompressedRistretto);
 
 impl CompressedCommitment {
     // Create a new compressed commitment
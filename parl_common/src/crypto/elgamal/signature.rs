@@ -1,4 +1,5 @@
 use curve25519_dalek::{RistrettoPoint, Scalar};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{de::Error, Serialize};
 use sha3::{Digest, Sha3_512};
 use crate::serializer::{Reader, ReaderError, Serializer, Writer};
@@ -56,6 +57,17 @@ impl<'de> serde::Deserialize<'de> for Signature {
     }
 }
 
+// Signature is serialized as a hexadecimal string
+impl JsonSchema for Signature {
+    fn schema_name() -> String {
+        "Signature".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
 impl Serializer for Signature {
     fn write(&self, writer: &mut Writer) {
         self.s.write(writer);
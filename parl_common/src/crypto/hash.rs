@@ -7,6 +7,7 @@ use std::{
 };
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Serialize};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use blake3::hash as blake3_hash;
 
 pub use xelis_hash::{
@@ -119,6 +120,17 @@ impl<'a> Deserialize<'a> for Hash {
     }
 }
 
+// Hash is serialized as a hexadecimal string
+impl JsonSchema for Hash {
+    fn schema_name() -> String {
+        "Hash".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
 pub trait Hashable: Serializer {
     #[inline(always)]
     fn hash(&self) -> Hash {
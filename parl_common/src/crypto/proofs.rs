@@ -71,13 +71,19 @@ pub enum ProofVerificationError {
 
 /// Proof that a commitment and ciphertext are equal.
 #[allow(non_snake_case)]
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, Debug)]
 pub struct CommitmentEqProof {
+    #[schemars(with = "[u8; 32]")]
     Y_0: CompressedRistretto,
+    #[schemars(with = "[u8; 32]")]
     Y_1: CompressedRistretto,
+    #[schemars(with = "[u8; 32]")]
     Y_2: CompressedRistretto,
+    #[schemars(with = "[u8; 32]")]
     z_s: Scalar,
+    #[schemars(with = "[u8; 32]")]
     z_x: Scalar,
+    #[schemars(with = "[u8; 32]")]
     z_r: Scalar,
 }
 
@@ -249,11 +255,15 @@ impl CommitmentEqProof {
 }
 
 #[allow(non_snake_case)]
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, Debug)]
 pub struct CiphertextValidityProof {
+    #[schemars(with = "[u8; 32]")]
     Y_0: CompressedRistretto,
+    #[schemars(with = "[u8; 32]")]
     Y_1: CompressedRistretto,
+    #[schemars(with = "[u8; 32]")]
     z_r: Scalar,
+    #[schemars(with = "[u8; 32]")]
     z_x: Scalar,
 }
 
@@ -0,0 +1,132 @@
+use futures_util::{sink, stream};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream
+};
+use tokio_tungstenite::tungstenite::{Error as TungsteniteError, Message};
+use crate::utils::IPC_ADDRESS_PREFIX;
+
+use super::{
+    websocket::{MessageSink, MessageStream},
+    JsonRPCError
+};
+
+// Messages are JSON encoded and separated by a new line
+const IPC_MESSAGE_DELIMITER: char = '\n';
+
+// Connect to the unix socket of an ipc:// address
+// Lines are mapped to text messages so the client can't tell it from a WebSocket
+pub async fn connect_to(target: &str) -> Result<(MessageSink, MessageStream), JsonRPCError> {
+    let path = target.strip_prefix(IPC_ADDRESS_PREFIX)
+        .ok_or_else(|| JsonRPCError::ConnectionError(format!("Invalid IPC address {}", target)))?;
+
+    let stream = UnixStream::connect(path).await
+        .map_err(|e| JsonRPCError::ConnectionError(format!("Couldn't connect to {}: {}", path, e)))?;
+    let (reader, writer) = stream.into_split();
+
+    let write = sink::unfold(writer, |mut writer, message: Message| async move {
+        match message {
+            Message::Text(mut text) => {
+                text.push(IPC_MESSAGE_DELIMITER);
+                writer.write_all(text.as_bytes()).await?;
+            },
+            Message::Close(_) => writer.shutdown().await?,
+            _ => {}
+        };
+        Ok::<_, TungsteniteError>(writer)
+    });
+
+    let read = stream::unfold(BufReader::new(reader), |mut reader| async move {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            // the daemon closed the socket, let the client reconnect
+            Ok(0) => Some((Err(TungsteniteError::ConnectionClosed), reader)),
+            Ok(_) => {
+                let text = line.trim_end_matches(IPC_MESSAGE_DELIMITER).to_owned();
+                Some((Ok(Message::Text(text)), reader))
+            },
+            Err(e) => Some((Err(TungsteniteError::Io(e)), reader))
+        }
+    });
+
+    Ok((Box::pin(write), Box::pin(read)))
+}
+
+#[cfg(all(test, feature = "rpc_server"))]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::PermissionsExt,
+        sync::Arc,
+        time::Duration
+    };
+    use actix_web::web::Bytes;
+    use async_trait::async_trait;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::time::sleep;
+    use crate::{
+        rpc_server::websocket::{run_ipc_server, WebSocketHandler, WebSocketServer, WebSocketSessionShared},
+        utils::spawn_task
+    };
+    use super::*;
+
+    // Send back every message received
+    struct EchoHandler;
+
+    #[async_trait]
+    impl WebSocketHandler for EchoHandler {
+        async fn on_message(&self, session: &WebSocketSessionShared<Self>, message: Bytes) -> Result<(), anyhow::Error> {
+            session.send_text(String::from_utf8(message.to_vec())?).await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ipc_round_trip() {
+        let dir = std::env::temp_dir().join(format!("parl-ipc-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rpc.sock").to_string_lossy().into_owned();
+
+        let server = WebSocketServer::new(EchoHandler);
+        spawn_task("ipc-test-server", run_ipc_server(Arc::clone(&server), path.clone(), 0o600));
+
+        let target = format!("{}{}", IPC_ADDRESS_PREFIX, path);
+        let mut connection = None;
+        for _ in 0..100 {
+            if let Ok(res) = connect_to(&target).await {
+                connection = Some(res);
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let (mut sink, mut stream) = connection.expect("IPC server is not listening");
+
+        // socket is moved to its path with its permissions already set
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // request and its response
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"get_info"}"#;
+        sink.send(Message::Text(request.to_owned())).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), Message::Text(request.to_owned()));
+
+        // event sent by the server
+        let event = r#"{"jsonrpc":"2.0","id":null,"result":{"event":"new_block"}}"#;
+        for session in server.get_sessions().read().await.iter() {
+            session.send_text(event).await.unwrap();
+        }
+        assert_eq!(stream.next().await.unwrap().unwrap(), Message::Text(event.to_owned()));
+
+        // the server sees the client closing the connection
+        sink.send(Message::Close(None)).await.unwrap();
+        for _ in 0..100 {
+            if server.count_connections().await == 0 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.count_connections().await, 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
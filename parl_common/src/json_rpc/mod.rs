@@ -4,6 +4,8 @@ use thiserror::Error;
 use tokio_tungstenite::tungstenite::Error as TungsteniteError;
 
 mod http;
+#[cfg(unix)]
+mod ipc;
 mod websocket;
 
 pub use http::JsonRPCClient;
@@ -7,32 +7,31 @@ use std::{
     hash::Hash,
     marker::PhantomData,
     borrow::Cow,
+    pin::Pin,
     time::Duration
 };
 use anyhow::Error;
 use futures_util::{
-    StreamExt,
-    stream::{SplitSink, SplitStream},
-    SinkExt
+    Sink,
+    SinkExt,
+    Stream,
+    StreamExt
 };
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{Value, json};
 use tokio::{
-    net::TcpStream,
     sync::{broadcast, oneshot, Mutex},
     task::JoinHandle,
     time::{sleep, timeout}
 };
 use tokio_tungstenite::{
-    WebSocketStream,
-    MaybeTlsStream,
     connect_async,
-    tungstenite::Message
+    tungstenite::{Error as TungsteniteError, Message}
 };
 use log::{debug, error, trace, warn};
 use crate::{
     api::SubscribeParams,
-    utils::{sanitize_daemon_address, spawn_task}
+    utils::{is_ipc_address, sanitize_daemon_address, spawn_task}
 };
 
 use super::{JSON_RPC_VERSION, JsonRPCError, JsonRPCResponse, JsonRPCResult};
@@ -73,14 +72,20 @@ impl<T: DeserializeOwned> EventReceiver<T> {
     }
 }
 
+// Write and read halves of the connection to the server
+// Boxed to support both the WebSocket and the IPC transports
+pub(super) type MessageSink = Pin<Box<dyn Sink<Message, Error = TungsteniteError> + Send>>;
+pub(super) type MessageStream = Pin<Box<dyn Stream<Item = Result<Message, TungsteniteError>> + Send>>;
+
 // It is around a Arc to be shareable easily
 // it has a tokio task running in background to handle all incoming messages
 pub type WebSocketJsonRPCClient<E> = Arc<WebSocketJsonRPCClientImpl<E>>;
 
 // A JSON-RPC Client over WebSocket protocol to support events
+// An ipc:// target connects to the unix socket of a local daemon instead
 // It can be used in multi-thread safely because each request/response are linked using the id attribute.
 pub struct WebSocketJsonRPCClientImpl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> {
-    ws: Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>,
+    ws: Mutex<MessageSink>,
     count: AtomicUsize,
     requests: Mutex<HashMap<usize, oneshot::Sender<JsonRPCResponse>>>,
     // This contains all id sent to register to a event on daemon
@@ -89,7 +94,7 @@ pub struct WebSocketJsonRPCClientImpl<E: Serialize + Hash + Eq + Send + Sync + C
     // This contains all events registered by the app with its usize
     // This allows us to subscribe to same channel if its already subscribed
     events_to_id: Mutex<HashMap<E, usize>>,
-    // websocket server address
+    // websocket server address or ipc:// socket path
     target: String,
     // auto reconnect duration
     auto_reconnect: Mutex<Option<Duration>>,
@@ -108,22 +113,28 @@ pub struct WebSocketJsonRPCClientImpl<E: Serialize + Hash + Eq + Send + Sync + C
 pub const DEFAULT_AUTO_RECONNECT: Duration = Duration::from_secs(5);
 
 impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCClientImpl<E> {
-    async fn connect_to(target: &String) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, JsonRPCError> {
+    async fn connect_to(target: &String) -> Result<(MessageSink, MessageStream), JsonRPCError> {
+        if is_ipc_address(target) {
+            #[cfg(unix)]
+            return super::ipc::connect_to(target).await;
+            #[cfg(not(unix))]
+            return Err(JsonRPCError::ConnectionError("IPC is only supported on unix systems".to_owned()));
+        }
+
         let (ws, response) = connect_async(target).await?;
         let status = response.status();
         if status.is_server_error() || status.is_client_error() {
             return Err(JsonRPCError::ConnectionError(status.to_string()));
         }
 
-        Ok(ws)
+        let (write, read) = ws.split();
+        Ok((Box::pin(write), Box::pin(read)))
     }
 
     pub async fn new(mut target: String) -> Result<WebSocketJsonRPCClient<E>, JsonRPCError> {
         target = sanitize_daemon_address(target.as_str());
 
-        let ws = Self::connect_to(&target).await?;
-        
-        let (write, read) = ws.split();
+        let (write, read) = Self::connect_to(&target).await?;
         let client = Arc::new(WebSocketJsonRPCClientImpl {
             ws: Mutex::new(write),
             count: AtomicUsize::new(0),
@@ -225,6 +236,7 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
             // Send it to the server
             if !self.send::<_, bool>("subscribe", Some(id), &SubscribeParams {
                 notify: Cow::Borrowed(&event),
+                filter: None
             }).await? {
                 error!("Error while resubscribing to event with id {}", id);
             }
@@ -273,8 +285,7 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
         }
 
         {
-            let ws = Self::connect_to(&self.target).await?;
-            let (write, read) = ws.split();
+            let (write, read) = Self::connect_to(&self.target).await?;
             {
                 let mut lock = self.ws.lock().await;
                 *lock = write;
@@ -294,7 +305,7 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
     }
 
     // Try to reconnect to the server
-    async fn try_reconnect(self: &Arc<Self>) -> Option<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>> {
+    async fn try_reconnect(self: &Arc<Self>) -> Option<MessageStream> {
         trace!("try reconnect");
         // We are not online anymore
         self.set_online(false);
@@ -313,8 +324,8 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
             sleep(*duration).await;
             debug!("Trying to reconnect to the server...");
 
-            let ws = match Self::connect_to(&self.target).await {
-                Ok(ws) => ws,
+            let (write, read) = match Self::connect_to(&self.target).await {
+                Ok(connection) => connection,
                 Err(e) => {
                     debug!("Error while reconnecting to the server: {:?}", e);
                     reconnect = {
@@ -326,7 +337,6 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
             };
 
             // We are connected again, set back everything
-            let (write, read) = ws.split();
             {
                 let mut ws = self.ws.lock().await;
                 *ws = write;
@@ -375,7 +385,7 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
 
     // Task running in background to handle every messages from the WebSocket server
     // This includes Events propagated and responses to JSON-RPC requests
-    async fn read(self: Arc<Self>, mut read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>) -> Result<(), JsonRPCError> {
+    async fn read(self: Arc<Self>, mut read: MessageStream) -> Result<(), JsonRPCError> {
         while let Some(res) = read.next().await {
             let msg = match res {
                 Ok(msg) => msg,
@@ -467,7 +477,8 @@ impl<E: Serialize + Hash + Eq + Send + Sync + Clone + 'static> WebSocketJsonRPCC
 
         // Send it to the server
         self.send::<_, bool>("subscribe", Some(id), &SubscribeParams {
-            notify: Cow::Borrowed(&event)
+            notify: Cow::Borrowed(&event),
+            filter: None
         }).await?;
 
         // Create a mapping from the event to the ID used for the request
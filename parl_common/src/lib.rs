@@ -15,6 +15,8 @@ pub mod context;
 pub mod queue;
 pub mod varuint;
 pub mod time;
+pub mod light;
+pub mod metrics;
 
 pub mod thread_pool;
 
@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+use crate::{
+    config::TIPS_LIMIT,
+    crypto::{Hash, Hashable},
+    difficulty::{calculate_difficulty, check_difficulty, CumulativeDifficulty, Difficulty, DifficultyError, P},
+    time::TimestampMillis,
+    varuint::VarUint
+};
+use super::{LightHeader, StateLeaf, StateProof};
+
+#[derive(Error, Debug)]
+pub enum LightClientError {
+    #[error("No checkpoint was set")]
+    NoCheckpoint,
+    #[error("Expected a header at topoheight {}, got {}", _0, _1)]
+    UnexpectedTopoHeight(u64, u64),
+    #[error("Invalid tips count {} for block {}", _0, _1)]
+    InvalidTipsCount(usize, Hash),
+    #[error("Tip {} of block {} is unknown", _0, _1)]
+    UnknownTip(Hash, Hash),
+    #[error("Invalid height {} for block {}, expected {}", _0, _1, _2)]
+    InvalidHeight(u64, Hash, u64),
+    #[error("Block {} has a timestamp lower than its tips", _0)]
+    InvalidTimestamp(Hash),
+    #[error("Difficulty {} of block {} doesn't match the difficulty adjustment of its tips", _0, _1)]
+    InvalidDifficulty(Difficulty, Hash),
+    #[error("Error while computing the PoW hash of block {}", _0)]
+    PowHash(Hash),
+    #[error("Invalid PoW for block {}", _0)]
+    InvalidPow(Hash),
+    #[error(transparent)]
+    Difficulty(#[from] DifficultyError),
+    #[error("Block {} commits a state root at topoheight {} which is not in its past", _0, _1)]
+    InvalidStateCommitment(Hash, u64),
+    #[error("Block {} commits a different state root at topoheight {}", _0, _1)]
+    ConflictingStateRoot(Hash, u64),
+    #[error("No state root known at topoheight {}", _0)]
+    UnknownStateRoot(u64),
+    #[error("Invalid state proof at topoheight {}", _0)]
+    InvalidStateProof(u64)
+}
+
+// Trusted block the light client starts from, like the genesis block
+// Its difficulty and covariance are needed to verify the difficulty of the next blocks
+pub struct LightCheckpoint {
+    pub hash: Hash,
+    pub topoheight: u64,
+    pub height: u64,
+    pub timestamp: TimestampMillis,
+    // Timestamp of the newest tip of the block, zero for the genesis block
+    pub tips_timestamp: TimestampMillis,
+    pub difficulty: Difficulty,
+    pub covariance: VarUint
+}
+
+// Data kept for each verified block to verify the blocks built on it
+struct KnownBlock {
+    height: u64,
+    timestamp: TimestampMillis,
+    tips_timestamp: TimestampMillis,
+    difficulty: Difficulty,
+    covariance: VarUint
+}
+
+// Light client following the chain from a trusted checkpoint
+// Each header must be received in topological order and is only accepted
+// if its tips are known, its height is valid and its PoW matches its difficulty
+// The difficulty is recomputed from the tips like the daemon does
+// NOTE: the best tip is selected by cumulative difficulty, which needs the whole DAG,
+// so the difficulty sent is accepted if it is the adjustment of any of the tips
+pub struct LightClient {
+    minimum_difficulty: Difficulty,
+    block_time_millis: u64,
+    // Block hash at each topoheight
+    topoheights: BTreeMap<u64, Hash>,
+    // All the blocks verified
+    blocks: HashMap<Hash, KnownBlock>,
+    // State roots committed by the headers verified
+    state_roots: BTreeMap<u64, Hash>,
+    // Sum of the difficulties of all the headers verified
+    cumulative_difficulty: CumulativeDifficulty
+}
+
+impl LightClient {
+    pub fn new(minimum_difficulty: Difficulty, block_time_millis: u64) -> Self {
+        Self {
+            minimum_difficulty,
+            block_time_millis,
+            topoheights: BTreeMap::new(),
+            blocks: HashMap::new(),
+            state_roots: BTreeMap::new(),
+            cumulative_difficulty: CumulativeDifficulty::zero()
+        }
+    }
+
+    // Add a trusted block
+    // Several checkpoints may be needed to know all the tips of the next blocks
+    pub fn add_checkpoint(&mut self, checkpoint: LightCheckpoint) {
+        self.topoheights.insert(checkpoint.topoheight, checkpoint.hash.clone());
+        self.blocks.insert(checkpoint.hash, KnownBlock {
+            height: checkpoint.height,
+            timestamp: checkpoint.timestamp,
+            tips_timestamp: checkpoint.tips_timestamp,
+            difficulty: checkpoint.difficulty,
+            covariance: checkpoint.covariance
+        });
+    }
+
+    // Get the highest topoheight known
+    pub fn get_topoheight(&self) -> Option<u64> {
+        self.topoheights.keys().next_back().copied()
+    }
+
+    pub fn get_hash_at_topoheight(&self, topoheight: u64) -> Option<&Hash> {
+        self.topoheights.get(&topoheight)
+    }
+
+    // Get the highest topoheight with a state root committed
+    // State proofs should be requested at this topoheight
+    pub fn get_state_topoheight(&self) -> Option<u64> {
+        self.state_roots.keys().next_back().copied()
+    }
+
+    pub fn get_cumulative_difficulty(&self) -> &CumulativeDifficulty {
+        &self.cumulative_difficulty
+    }
+
+    // Compute the difficulty of a block if this tip is the best one
+    fn get_difficulty_from_tip(&self, tip: &KnownBlock, tips_timestamp: TimestampMillis) -> (Difficulty, VarUint) {
+        calculate_difficulty(tip.tips_timestamp, tips_timestamp, tip.difficulty, tip.covariance, self.minimum_difficulty, self.block_time_millis)
+    }
+
+    // Verify a header and add it on top of the chain
+    // Returns the hash of the block
+    pub fn add_header(&mut self, light_header: &LightHeader) -> Result<Hash, LightClientError> {
+        let expected_topoheight = self.get_topoheight().ok_or(LightClientError::NoCheckpoint)? + 1;
+        if light_header.get_topoheight() != expected_topoheight {
+            return Err(LightClientError::UnexpectedTopoHeight(expected_topoheight, light_header.get_topoheight()))
+        }
+
+        let header = light_header.get_header();
+        let hash = header.hash();
+        let tips = header.get_tips();
+        if tips.is_empty() || tips.len() > TIPS_LIMIT {
+            return Err(LightClientError::InvalidTipsCount(tips.len(), hash))
+        }
+
+        let mut known_tips = Vec::with_capacity(tips.len());
+        for tip in tips {
+            let block = self.blocks.get(tip).ok_or_else(|| LightClientError::UnknownTip(tip.clone(), hash.clone()))?;
+            known_tips.push(block);
+        }
+
+        let tips_height = known_tips.iter().map(|block| block.height).max().unwrap_or(0);
+        if header.get_height() != tips_height + 1 {
+            return Err(LightClientError::InvalidHeight(header.get_height(), hash, tips_height + 1))
+        }
+
+        let tips_timestamp = known_tips.iter().map(|block| block.timestamp).max().unwrap_or(0);
+        if header.get_timestamp() < tips_timestamp {
+            return Err(LightClientError::InvalidTimestamp(hash))
+        }
+
+        // The first blocks are at the minimum difficulty
+        let difficulty = light_header.get_difficulty();
+        let expected = if header.get_height() <= 1 {
+            Some((self.minimum_difficulty, P))
+        } else {
+            known_tips.iter()
+                .map(|tip| self.get_difficulty_from_tip(tip, tips_timestamp))
+                .find(|(expected, _)| expected == difficulty)
+        };
+        let (difficulty, covariance) = expected.filter(|(expected, _)| expected == difficulty)
+            .ok_or_else(|| LightClientError::InvalidDifficulty(*difficulty, hash.clone()))?;
+
+        let pow_hash = header.get_pow_hash().map_err(|_| LightClientError::PowHash(hash.clone()))?;
+        if !check_difficulty(&pow_hash, &difficulty)? {
+            return Err(LightClientError::InvalidPow(hash))
+        }
+
+        if let Some(commitment) = header.get_state_commitment() {
+            let topoheight = commitment.get_topoheight();
+            if topoheight >= expected_topoheight {
+                return Err(LightClientError::InvalidStateCommitment(hash, topoheight))
+            }
+
+            if self.state_roots.get(&topoheight).is_some_and(|root| root != commitment.get_root()) {
+                return Err(LightClientError::ConflictingStateRoot(hash, topoheight))
+            }
+            self.state_roots.insert(topoheight, commitment.get_root().clone());
+        }
+
+        self.cumulative_difficulty += difficulty;
+        self.topoheights.insert(expected_topoheight, hash.clone());
+        self.blocks.insert(hash.clone(), KnownBlock {
+            height: header.get_height(),
+            timestamp: header.get_timestamp(),
+            tips_timestamp,
+            difficulty,
+            covariance
+        });
+
+        Ok(hash)
+    }
+
+    // Verify a state proof against the state roots committed by our headers
+    // Returns the last balance or nonce at the state topoheight
+    pub fn verify_state_proof<'a>(&self, proof: &'a StateProof) -> Result<&'a StateLeaf, LightClientError> {
+        let topoheight = proof.get_state_topoheight();
+        let state_root = self.state_roots.get(&topoheight).ok_or(LightClientError::UnknownStateRoot(topoheight))?;
+        if !proof.verify(state_root) {
+            return Err(LightClientError::InvalidStateProof(topoheight))
+        }
+
+        Ok(proof.get_leaf())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexSet;
+    use crate::{
+        account::VersionedNonce,
+        block::{BlockHeader, StateCommitment},
+        crypto::KeyPair,
+        light::{MerkleProof, StateLeaf}
+    };
+    use super::*;
+
+    const BLOCK_TIME_MILLIS: u64 = 1000;
+
+    fn build_header(height: u64, timestamp: TimestampMillis, tips: &[&Hash]) -> BlockHeader {
+        let tips: IndexSet<Hash> = tips.iter().map(|hash| (*hash).clone()).collect();
+        let miner = KeyPair::new().get_public_key().compress();
+        BlockHeader::new(0, height, timestamp, tips, [0u8; 32], miner, IndexSet::new())
+    }
+
+    fn genesis_checkpoint(difficulty: u64) -> LightCheckpoint {
+        LightCheckpoint {
+            hash: Hash::zero(),
+            topoheight: 0,
+            height: 0,
+            timestamp: 0,
+            tips_timestamp: 0,
+            difficulty: Difficulty::from_u64(difficulty),
+            covariance: P
+        }
+    }
+
+    // Find a nonce matching the difficulty
+    fn mine(header: &mut BlockHeader, difficulty: &Difficulty) {
+        while !check_difficulty(&header.get_pow_hash().unwrap(), difficulty).unwrap() {
+            header.nonce += 1;
+        }
+    }
+
+    #[test]
+    fn test_add_header() {
+        let mut client = LightClient::new(Difficulty::from_u64(1), BLOCK_TIME_MILLIS);
+        let header = build_header(1, 1000, &[&Hash::zero()]);
+        assert!(matches!(client.add_header(&LightHeader::new(1, header.clone(), Difficulty::from_u64(1))), Err(LightClientError::NoCheckpoint)));
+
+        client.add_checkpoint(genesis_checkpoint(1));
+        // Wrong height
+        let invalid = build_header(2, 1000, &[&Hash::zero()]);
+        assert!(matches!(client.add_header(&LightHeader::new(1, invalid, Difficulty::from_u64(1))), Err(LightClientError::InvalidHeight(..))));
+
+        let hash = client.add_header(&LightHeader::new(1, header, Difficulty::from_u64(1))).unwrap();
+        assert_eq!(client.get_hash_at_topoheight(1), Some(&hash));
+        assert_eq!(client.get_topoheight(), Some(1));
+
+        // Unknown tip
+        let orphan = build_header(2, 2000, &[&hash, &Hash::max()]);
+        assert!(matches!(client.add_header(&LightHeader::new(2, orphan, Difficulty::from_u64(1))), Err(LightClientError::UnknownTip(..))));
+
+        // Older than its tip
+        let invalid = build_header(2, 999, &[&hash]);
+        assert!(matches!(client.add_header(&LightHeader::new(2, invalid, Difficulty::from_u64(1))), Err(LightClientError::InvalidTimestamp(..))));
+    }
+
+    #[test]
+    fn test_difficulty_is_recomputed() {
+        let mut client = LightClient::new(Difficulty::from_u64(1), BLOCK_TIME_MILLIS);
+        client.add_checkpoint(genesis_checkpoint(1));
+
+        // Height 1 is always at the minimum difficulty
+        let header = build_header(1, 1000, &[&Hash::zero()]);
+        assert!(matches!(client.add_header(&LightHeader::new(1, header.clone(), Difficulty::from_u64(2))), Err(LightClientError::InvalidDifficulty(..))));
+        client.add_header(&LightHeader::new(1, header, Difficulty::from_u64(1))).unwrap();
+
+        // Use a 1ms block time to keep the difficulty low enough to mine
+        let mut client = LightClient::new(Difficulty::from_u64(1), 1);
+        client.add_checkpoint(LightCheckpoint {
+            hash: Hash::zero(),
+            topoheight: 0,
+            height: 10,
+            timestamp: 2,
+            tips_timestamp: 0,
+            difficulty: Difficulty::from_u64(8),
+            covariance: P
+        });
+
+        // The checkpoint was slower than the block time
+        let (expected, _) = calculate_difficulty(0, 2, Difficulty::from_u64(8), P, Difficulty::from_u64(1), 1);
+        assert!(expected < Difficulty::from_u64(8) && expected > Difficulty::from_u64(1));
+
+        // A node can't lower the difficulty of a block
+        let mut header = build_header(11, 3, &[&Hash::zero()]);
+        assert!(matches!(client.add_header(&LightHeader::new(1, header.clone(), Difficulty::from_u64(1))), Err(LightClientError::InvalidDifficulty(..))));
+
+        mine(&mut header, &expected);
+        client.add_header(&LightHeader::new(1, header, expected)).unwrap();
+        assert_eq!(*client.get_cumulative_difficulty(), expected);
+    }
+
+    #[test]
+    fn test_verify_state_proof() {
+        let mut client = LightClient::new(Difficulty::from_u64(1), BLOCK_TIME_MILLIS);
+        client.add_checkpoint(genesis_checkpoint(1));
+
+        // A tree with a single leaf has it as root
+        let key = KeyPair::new().get_public_key().compress();
+        let leaf = StateLeaf::Nonce { key, version: VersionedNonce::new(3, None) };
+        let root = leaf.get_leaf_hash(0);
+
+        let mut header = build_header(1, 1000, &[&Hash::zero()]);
+        header.set_state_commitment(StateCommitment::new(0, root.clone()));
+        client.add_header(&LightHeader::new(1, header, Difficulty::from_u64(1))).unwrap();
+        assert_eq!(client.get_state_topoheight(), Some(0));
+
+        let proof = StateProof::new(0, 0, leaf.clone(), MerkleProof::new(Vec::new()));
+        assert!(client.verify_state_proof(&proof).is_ok());
+
+        let proof = StateProof::new(0, 1, leaf.clone(), MerkleProof::new(Vec::new()));
+        assert!(matches!(client.verify_state_proof(&proof), Err(LightClientError::UnknownStateRoot(1))));
+
+        let other = StateLeaf::Nonce { key: KeyPair::new().get_public_key().compress(), version: VersionedNonce::new(3, None) };
+        let proof = StateProof::new(0, 0, other, MerkleProof::new(Vec::new()));
+        assert!(matches!(client.verify_state_proof(&proof), Err(LightClientError::InvalidStateProof(0))));
+    }
+
+    #[test]
+    fn test_state_commitment() {
+        let mut client = LightClient::new(Difficulty::from_u64(1), BLOCK_TIME_MILLIS);
+        client.add_checkpoint(genesis_checkpoint(1));
+
+        // The state root must be in the past of the block
+        let mut header = build_header(1, 1000, &[&Hash::zero()]);
+        header.set_state_commitment(StateCommitment::new(1, Hash::zero()));
+        assert!(matches!(client.add_header(&LightHeader::new(1, header, Difficulty::from_u64(1))), Err(LightClientError::InvalidStateCommitment(..))));
+
+        let mut header = build_header(1, 1000, &[&Hash::zero()]);
+        header.set_state_commitment(StateCommitment::new(0, Hash::zero()));
+        let first = client.add_header(&LightHeader::new(1, header, Difficulty::from_u64(1))).unwrap();
+
+        // All the blocks must agree on the state root at a topoheight
+        let mut header = build_header(2, 2000, &[&first]);
+        header.set_state_commitment(StateCommitment::new(0, Hash::max()));
+        let (difficulty, _) = calculate_difficulty(0, 1000, Difficulty::from_u64(1), P, Difficulty::from_u64(1), BLOCK_TIME_MILLIS);
+        mine(&mut header, &difficulty);
+        assert!(matches!(client.add_header(&LightHeader::new(2, header, difficulty)), Err(LightClientError::ConflictingStateRoot(..))));
+    }
+}
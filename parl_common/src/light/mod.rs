@@ -0,0 +1,13 @@
+mod proof;
+mod client;
+
+pub use proof::{
+    get_path_bit,
+    hash_merkle_pair,
+    hash_state_tree_leaf,
+    LightHeader,
+    MerkleProof,
+    StateLeaf,
+    StateProof
+};
+pub use client::{LightCheckpoint, LightClient, LightClientError};
@@ -0,0 +1,362 @@
+use crate::{
+    account::{VersionedBalance, VersionedNonce},
+    block::BlockHeader,
+    crypto::{hash, Hash, PublicKey},
+    difficulty::Difficulty,
+    serializer::{Reader, ReaderError, Serializer, Writer}
+};
+
+// The state tree can't be deeper than the number of bits in a path
+const MAX_STATE_TREE_DEPTH: usize = 256;
+
+const BALANCE_LEAF_ID: u8 = 0;
+const NONCE_LEAF_ID: u8 = 1;
+
+// Hash the two children of a branch of the state tree
+// An empty child is a zero hash
+pub fn hash_merkle_pair(left: &Hash, right: &Hash) -> Hash {
+    hash(&[left.as_bytes().as_ref(), right.as_bytes().as_ref()].concat())
+}
+
+// Hash a leaf of the state tree
+// Its input is one byte longer than a pair so a leaf can't be taken for a node
+pub fn hash_state_tree_leaf(path: &Hash, value: &Hash) -> Hash {
+    hash(&[&[0u8][..], &path.as_bytes()[..], &value.as_bytes()[..]].concat())
+}
+
+// Get the bit of a path at a depth of the state tree
+// 0 goes to the left child, 1 to the right one
+pub fn get_path_bit(path: &Hash, depth: usize) -> bool {
+    path.as_bytes()[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+// Block header served to light clients
+// The difficulty is only accepted by light clients if it is
+// the adjustment of one of the tips, as the best tip can't be known without the whole DAG
+#[derive(Clone, Debug)]
+pub struct LightHeader {
+    topoheight: u64,
+    header: BlockHeader,
+    difficulty: Difficulty
+}
+
+impl LightHeader {
+    pub fn new(topoheight: u64, header: BlockHeader, difficulty: Difficulty) -> Self {
+        Self {
+            topoheight,
+            header,
+            difficulty
+        }
+    }
+
+    pub fn get_topoheight(&self) -> u64 {
+        self.topoheight
+    }
+
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn get_difficulty(&self) -> &Difficulty {
+        &self.difficulty
+    }
+}
+
+impl Serializer for LightHeader {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u64(&self.topoheight);
+        self.header.write(writer);
+        self.difficulty.write(writer);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let topoheight = reader.read_u64()?;
+        let header = BlockHeader::read(reader)?;
+        let difficulty = Difficulty::read(reader)?;
+
+        Ok(Self::new(topoheight, header, difficulty))
+    }
+
+    fn size(&self) -> usize {
+        self.topoheight.size() + self.header.size() + self.difficulty.size()
+    }
+}
+
+// Last version of a balance or nonce of an account
+// Each one has its own leaf in the state tree, located by its path
+#[derive(Clone)]
+pub enum StateLeaf {
+    Balance {
+        key: PublicKey,
+        asset: Hash,
+        version: VersionedBalance
+    },
+    Nonce {
+        key: PublicKey,
+        version: VersionedNonce
+    }
+}
+
+impl StateLeaf {
+    pub fn get_key(&self) -> &PublicKey {
+        match self {
+            Self::Balance { key, .. } => key,
+            Self::Nonce { key, .. } => key
+        }
+    }
+
+    // Path of the leaf in the state tree
+    // There is only one path per account and asset, so a leaf is always the last version
+    pub fn get_path(&self) -> Hash {
+        match self {
+            Self::Balance { key, asset, .. } => hash(&[&[BALANCE_LEAF_ID][..], &key.as_bytes()[..], &asset.as_bytes()[..]].concat()),
+            Self::Nonce { key, .. } => hash(&[&[NONCE_LEAF_ID][..], &key.as_bytes()[..]].concat())
+        }
+    }
+
+    // Hash of the version written at the topoheight
+    // The previous topoheight is not committed as it is rewritten when the chain is pruned
+    pub fn get_value_hash(&self, topoheight: u64) -> Hash {
+        let mut leaf = self.clone();
+        match &mut leaf {
+            Self::Balance { version, .. } => version.set_previous_topoheight(None),
+            Self::Nonce { version, .. } => version.set_previous_topoheight(None)
+        };
+        hash(&[&topoheight.to_be_bytes()[..], &leaf.to_bytes()].concat())
+    }
+
+    // Hash of the leaf in the state tree
+    pub fn get_leaf_hash(&self, topoheight: u64) -> Hash {
+        hash_state_tree_leaf(&self.get_path(), &self.get_value_hash(topoheight))
+    }
+}
+
+// Versions are serialized with optional trailing fields
+// so they must be length prefixed to be embedded
+fn write_version<S: Serializer>(writer: &mut Writer, version: &S) {
+    let bytes = version.to_bytes();
+    writer.write_u16(bytes.len() as u16);
+    writer.write_bytes(&bytes);
+}
+
+fn read_version<S: Serializer>(reader: &mut Reader) -> Result<S, ReaderError> {
+    let len = reader.read_u16()? as usize;
+    S::from_bytes(reader.read_bytes_ref(len)?)
+}
+
+impl Serializer for StateLeaf {
+    fn write(&self, writer: &mut Writer) {
+        match self {
+            Self::Balance { key, asset, version } => {
+                writer.write_u8(BALANCE_LEAF_ID);
+                key.write(writer);
+                writer.write_hash(asset);
+                write_version(writer, version);
+            },
+            Self::Nonce { key, version } => {
+                writer.write_u8(NONCE_LEAF_ID);
+                key.write(writer);
+                write_version(writer, version);
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        Ok(match reader.read_u8()? {
+            BALANCE_LEAF_ID => Self::Balance {
+                key: PublicKey::read(reader)?,
+                asset: reader.read_hash()?,
+                version: read_version(reader)?
+            },
+            NONCE_LEAF_ID => Self::Nonce {
+                key: PublicKey::read(reader)?,
+                version: read_version(reader)?
+            },
+            _ => return Err(ReaderError::InvalidValue)
+        })
+    }
+}
+
+// Path from a leaf to the root of the state tree
+// The state tree is a sparse merkle tree where a subtree with a single leaf is replaced by it
+// so a proof only contains the siblings down to the depth of the leaf
+#[derive(Clone, Debug)]
+pub struct MerkleProof {
+    // Sibling of the node at each depth, from the root to the leaf
+    siblings: Vec<Hash>
+}
+
+impl MerkleProof {
+    pub fn new(siblings: Vec<Hash>) -> Self {
+        Self {
+            siblings
+        }
+    }
+
+    pub fn get_siblings(&self) -> &[Hash] {
+        &self.siblings
+    }
+
+    // Compute the state root from the leaf hash at this path
+    pub fn compute_root(&self, path: &Hash, leaf: &Hash) -> Hash {
+        let mut current = leaf.clone();
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            current = if get_path_bit(path, depth) {
+                hash_merkle_pair(sibling, &current)
+            } else {
+                hash_merkle_pair(&current, sibling)
+            };
+        }
+
+        current
+    }
+}
+
+impl Serializer for MerkleProof {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u16(self.siblings.len() as u16);
+        for sibling in &self.siblings {
+            writer.write_hash(sibling);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let count = reader.read_u16()? as usize;
+        if count > MAX_STATE_TREE_DEPTH {
+            return Err(ReaderError::InvalidSize)
+        }
+
+        let mut siblings = Vec::with_capacity(count);
+        for _ in 0..count {
+            siblings.push(reader.read_hash()?);
+        }
+
+        Ok(Self::new(siblings))
+    }
+
+    fn size(&self) -> usize {
+        2 + self.siblings.len() * 32
+    }
+}
+
+// Proof of the last version of a balance or nonce of an account at a topoheight
+// The state root is not sent: it must be committed by a block header known by the light client
+#[derive(Clone)]
+pub struct StateProof {
+    // Topoheight at which the version was written
+    topoheight: u64,
+    // Topoheight of the state root it is proven against
+    state_topoheight: u64,
+    leaf: StateLeaf,
+    proof: MerkleProof
+}
+
+impl StateProof {
+    pub fn new(topoheight: u64, state_topoheight: u64, leaf: StateLeaf, proof: MerkleProof) -> Self {
+        Self {
+            topoheight,
+            state_topoheight,
+            leaf,
+            proof
+        }
+    }
+
+    pub fn get_topoheight(&self) -> u64 {
+        self.topoheight
+    }
+
+    pub fn get_state_topoheight(&self) -> u64 {
+        self.state_topoheight
+    }
+
+    pub fn get_leaf(&self) -> &StateLeaf {
+        &self.leaf
+    }
+
+    // Verify that the leaf is the last version in the state tree
+    pub fn verify(&self, state_root: &Hash) -> bool {
+        self.topoheight <= self.state_topoheight
+            && self.proof.compute_root(&self.leaf.get_path(), &self.leaf.get_leaf_hash(self.topoheight)) == *state_root
+    }
+}
+
+impl Serializer for StateProof {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u64(&self.topoheight);
+        writer.write_u64(&self.state_topoheight);
+        self.leaf.write(writer);
+        self.proof.write(writer);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let topoheight = reader.read_u64()?;
+        let state_topoheight = reader.read_u64()?;
+        let leaf = StateLeaf::read(reader)?;
+        let proof = MerkleProof::read(reader)?;
+
+        Ok(Self::new(topoheight, state_topoheight, leaf, proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::KeyPair;
+    use super::*;
+
+    #[test]
+    fn test_path_bit() {
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0b1010_0000;
+        bytes[31] = 1;
+        let path = Hash::new(bytes);
+        assert!(get_path_bit(&path, 0));
+        assert!(!get_path_bit(&path, 1));
+        assert!(get_path_bit(&path, 2));
+        assert!(!get_path_bit(&path, 254));
+        assert!(get_path_bit(&path, 255));
+    }
+
+    #[test]
+    fn test_state_proof() {
+        let key = KeyPair::new().get_public_key().compress();
+        let balance = StateLeaf::Balance { key: key.clone(), asset: Hash::zero(), version: VersionedBalance::zero() };
+        let nonce = StateLeaf::Nonce { key, version: VersionedNonce::new(1, Some(5)) };
+
+        // Build a tree with two leaves splitting at the first bit where their paths differ
+        let (balance_path, nonce_path) = (balance.get_path(), nonce.get_path());
+        let depth = (0..256).find(|depth| get_path_bit(&balance_path, *depth) != get_path_bit(&nonce_path, *depth)).unwrap();
+        let (balance_hash, nonce_hash) = (balance.get_leaf_hash(10), nonce.get_leaf_hash(7));
+        let mut root = if get_path_bit(&nonce_path, depth) {
+            hash_merkle_pair(&balance_hash, &nonce_hash)
+        } else {
+            hash_merkle_pair(&nonce_hash, &balance_hash)
+        };
+        for current in (0..depth).rev() {
+            root = if get_path_bit(&nonce_path, current) {
+                hash_merkle_pair(&Hash::zero(), &root)
+            } else {
+                hash_merkle_pair(&root, &Hash::zero())
+            };
+        }
+
+        let mut siblings = vec![Hash::zero(); depth];
+        siblings.push(balance_hash);
+        let proof = StateProof::new(7, 10, nonce.clone(), MerkleProof::new(siblings.clone()));
+        let proof = StateProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert!(proof.verify(&root));
+
+        // The previous topoheight is not committed
+        let mut pruned = nonce.clone();
+        if let StateLeaf::Nonce { version, .. } = &mut pruned {
+            version.set_previous_topoheight(None);
+        }
+        assert!(StateProof::new(7, 10, pruned, MerkleProof::new(siblings.clone())).verify(&root));
+
+        // An older version of the same leaf must not be accepted
+        assert!(!StateProof::new(6, 10, nonce.clone(), MerkleProof::new(siblings.clone())).verify(&root));
+        // A version above the state topoheight can't be in it
+        assert!(!StateProof::new(7, 6, nonce, MerkleProof::new(siblings.clone())).verify(&root));
+        // A valid path for another leaf must not be accepted
+        assert!(!StateProof::new(10, 10, balance, MerkleProof::new(siblings)).verify(&root));
+    }
+}
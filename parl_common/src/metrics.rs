@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex
+    },
+    time::Duration
+};
+#[cfg(feature = "metrics_server")]
+use std::{future::Future, net::SocketAddr, sync::Arc};
+#[cfg(feature = "metrics_server")]
+use log::{debug, info, warn};
+#[cfg(feature = "metrics_server")]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout
+};
+#[cfg(feature = "metrics_server")]
+use crate::utils::spawn_task;
+
+// Content type of the Prometheus text format
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Max time to read a request or write a response of the metrics server
+#[cfg(feature = "metrics_server")]
+const METRICS_SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Buckets in seconds for the latencies
+pub const DEFAULT_LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+// Value only increasing
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Counters identified by a label value, like a RPC method name
+#[derive(Default)]
+pub struct LabeledCounter {
+    values: Mutex<HashMap<String, u64>>
+}
+
+impl LabeledCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inc(&self, label: &str) {
+        let mut values = match self.values.lock() {
+            Ok(values) => values,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        match values.get_mut(label) {
+            Some(value) => *value += 1,
+            None => {
+                values.insert(label.to_owned(), 1);
+            }
+        }
+    }
+
+    // Copy of all the values sorted by label
+    pub fn get_values(&self) -> Vec<(String, u64)> {
+        let values = match self.values.lock() {
+            Ok(values) => values,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        let mut values: Vec<(String, u64)> = values.iter().map(|(label, value)| (label.clone(), *value)).collect();
+        values.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        values
+    }
+}
+
+// Distribution of durations
+pub struct Histogram {
+    // upper bounds in seconds
+    buckets: &'static [f64],
+    // observations in each bucket, not cumulative
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+    count: AtomicU64
+}
+
+impl Histogram {
+    pub fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: buckets.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0)
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = self.buckets.iter().position(|bound| seconds <= *bound) {
+            self.counts[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(&DEFAULT_LATENCY_BUCKETS)
+    }
+}
+
+// Write metrics in the Prometheus text format
+#[derive(Default)]
+pub struct MetricsEncoder {
+    output: String
+}
+
+impl MetricsEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        // Writing in a String can't fail
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn gauge<V: Display>(&mut self, name: &str, help: &str, value: V) {
+        self.header(name, help, "gauge");
+        let _ = writeln!(self.output, "{} {}", name, value);
+    }
+
+    pub fn counter<V: Display>(&mut self, name: &str, help: &str, value: V) {
+        self.header(name, help, "counter");
+        let _ = writeln!(self.output, "{} {}", name, value);
+    }
+
+    // Gauge with a value per label
+    pub fn labeled_gauge<'a, V: Display, I: IntoIterator<Item = (&'a str, V)>>(&mut self, name: &str, help: &str, label: &str, values: I) {
+        self.header(name, help, "gauge");
+        self.labeled_values(name, label, values);
+    }
+
+    // Counter with a value per label
+    pub fn labeled_counter<'a, V: Display, I: IntoIterator<Item = (&'a str, V)>>(&mut self, name: &str, help: &str, label: &str, values: I) {
+        self.header(name, help, "counter");
+        self.labeled_values(name, label, values);
+    }
+
+    fn labeled_values<'a, V: Display, I: IntoIterator<Item = (&'a str, V)>>(&mut self, name: &str, label: &str, values: I) {
+        for (label_value, value) in values {
+            let escaped = label_value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            let _ = writeln!(self.output, "{}{{{}=\"{}\"}} {}", name, label, escaped, value);
+        }
+    }
+
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+        self.header(name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in histogram.buckets.iter().zip(histogram.counts.iter()) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(self.output, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+
+        let count = histogram.count.load(Ordering::Relaxed);
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000f64;
+        let _ = writeln!(self.output, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(self.output, "{}_sum {}", name, sum);
+        let _ = writeln!(self.output, "{}_count {}", name, count);
+    }
+
+    pub fn finish(self) -> String {
+        self.output
+    }
+}
+
+// Serve the metrics built by `encode` on GET /metrics
+// Each connection is handled in its own task so a slow client can't block the others
+#[cfg(feature = "metrics_server")]
+pub async fn run_metrics_server<F, Fut>(bind_address: String, encode: F) -> Result<(), std::io::Error>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = String> + Send + 'static
+{
+    let listener = TcpListener::bind(&bind_address).await?;
+    info!("Metrics are available on http://{}/metrics", bind_address);
+    let encode = Arc::new(encode);
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let encode = Arc::clone(&encode);
+                spawn_task(format!("metrics-{}", addr), handle_metrics_connection(stream, addr, encode));
+            },
+            Err(e) => warn!("Error while accepting a metrics connection: {}", e)
+        };
+    }
+}
+
+// Read the request of a metrics connection and send the response
+#[cfg(feature = "metrics_server")]
+async fn handle_metrics_connection<F, Fut>(mut stream: TcpStream, addr: SocketAddr, encode: Arc<F>)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>
+{
+    let mut buffer = [0u8; 1024];
+    let read = match timeout(METRICS_SERVER_TIMEOUT, stream.read(&mut buffer)).await {
+        Ok(Ok(read)) => read,
+        _ => {
+            debug!("Couldn't read the metrics request of {}", addr);
+            return;
+        }
+    };
+
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let response = if request.starts_with("GET /metrics ") {
+        let body = encode().await;
+        format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", METRICS_CONTENT_TYPE, body.len(), body)
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+
+    if !matches!(timeout(METRICS_SERVER_TIMEOUT, stream.write_all(response.as_bytes())).await, Ok(Ok(()))) {
+        debug!("Couldn't send the metrics to {}", addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoder() {
+        let mut encoder = MetricsEncoder::new();
+        encoder.gauge("parl_height", "Current height", 10);
+        let counter = LabeledCounter::new();
+        counter.inc("get_info");
+        counter.inc("get_info");
+        counter.inc("get_height");
+        let values = counter.get_values();
+        encoder.labeled_counter("parl_rpc_requests_total", "RPC requests", "method", values.iter().map(|(label, value)| (label.as_str(), *value)));
+
+        let output = encoder.finish();
+        assert!(output.contains("# TYPE parl_height gauge\nparl_height 10\n"));
+        assert!(output.contains("parl_rpc_requests_total{method=\"get_height\"} 1\nparl_rpc_requests_total{method=\"get_info\"} 2\n"));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(&[0.01, 0.1]);
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_secs(1));
+
+        let mut encoder = MetricsEncoder::new();
+        encoder.histogram("latency", "Latency", &histogram);
+        let output = encoder.finish();
+        assert!(output.contains("latency_bucket{le=\"0.01\"} 1\n"));
+        assert!(output.contains("latency_bucket{le=\"0.1\"} 2\n"));
+        assert!(output.contains("latency_bucket{le=\"+Inf\"} 3\n"));
+        assert!(output.contains("latency_count 3\n"));
+        assert_eq!(histogram.get_count(), 3);
+    }
+}
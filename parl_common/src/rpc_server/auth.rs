@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use actix_web_httpauth::headers::authorization::{Basic, Bearer, Scheme};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use super::InternalRpcError;
+
+// Compare a secret without leaking through the timing how many bytes matched
+fn is_same_secret(expected: &str, value: &str) -> bool {
+    expected.as_bytes().ct_eq(value.as_bytes()).into()
+}
+
+// Methods and events allowed for a role
+// A missing list allows all of them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RpcRole {
+    #[serde(default)]
+    pub methods: Option<HashSet<String>>,
+    // Events names allowed in WebSocket subscriptions
+    #[serde(default)]
+    pub events: Option<HashSet<String>>
+}
+
+impl RpcRole {
+    // Role allowed to call everything
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn with_methods<I: IntoIterator<Item = S>, S: Into<String>>(methods: I) -> Self {
+        Self {
+            methods: Some(methods.into_iter().map(Into::into).collect()),
+            events: None
+        }
+    }
+
+    pub fn is_method_allowed(&self, method: &str) -> bool {
+        self.methods.as_ref().map_or(true, |methods| methods.contains(method))
+    }
+
+    pub fn is_event_allowed(&self, event: &str) -> bool {
+        self.events.as_ref().map_or(true, |events| events.contains(event))
+    }
+}
+
+// Client allowed to use the RPC server
+// It is authenticated using basic auth or a bearer token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcUser {
+    Basic {
+        username: String,
+        password: String,
+        role: String
+    },
+    Bearer {
+        token: String,
+        role: String
+    }
+}
+
+impl RpcUser {
+    pub fn get_role(&self) -> &str {
+        match self {
+            Self::Basic { role, .. } | Self::Bearer { role, .. } => role
+        }
+    }
+}
+
+// Authentication configuration of a RPC server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RpcAuthConfig {
+    // Role used by the requests without credentials
+    // If not set, all requests must be authenticated
+    #[serde(default)]
+    pub anonymous_role: Option<String>,
+    // Additional roles, they override the roles registered with the same name
+    #[serde(default)]
+    pub roles: HashMap<String, RpcRole>,
+    #[serde(default)]
+    pub users: Vec<RpcUser>
+}
+
+impl RpcAuthConfig {
+    // Find the role name of the client using the headers of its request
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<&str, InternalRpcError> {
+        let Some(value) = headers.get(AUTHORIZATION) else {
+            return self.anonymous_role.as_deref().ok_or(InternalRpcError::Unauthorized)
+        };
+
+        if let Ok(credentials) = Basic::parse(value) {
+            let password = credentials.password().unwrap_or_default();
+            return self.users.iter()
+                .find_map(|user| match user {
+                    RpcUser::Basic { username, password: expected, role } if *username == *credentials.user_id() && is_same_secret(expected, password) => Some(role.as_str()),
+                    _ => None
+                })
+                .ok_or(InternalRpcError::Unauthorized)
+        }
+
+        if let Ok(credentials) = Bearer::parse(value) {
+            let token = credentials.token();
+            return self.users.iter()
+                .find_map(|user| match user {
+                    RpcUser::Bearer { token: expected, role } if is_same_secret(expected, token) => Some(role.as_str()),
+                    _ => None
+                })
+                .ok_or(InternalRpcError::Unauthorized)
+        }
+
+        Err(InternalRpcError::Unauthorized)
+    }
+
+    // Get the bearer token of the request if it belongs to a user
+    pub fn get_bearer_token(&self, headers: &HeaderMap) -> Option<&str> {
+        let credentials = Bearer::parse(headers.get(AUTHORIZATION)?).ok()?;
+        self.users.iter().find_map(|user| match user {
+            RpcUser::Bearer { token, .. } if is_same_secret(token, credentials.token()) => Some(token.as_str()),
+            _ => None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use super::*;
+
+    fn config() -> RpcAuthConfig {
+        serde_json::from_str(r#"{
+            "anonymous_role": "public",
+            "users": [
+                { "username": "admin", "password": "secret", "role": "admin" },
+                { "token": "miner-token", "role": "miner" }
+            ]
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let config = config();
+        let request = TestRequest::default().to_http_request();
+        assert_eq!(config.authenticate(request.headers()).unwrap(), "public");
+
+        // "admin:secret"
+        let request = TestRequest::default().insert_header((AUTHORIZATION, "Basic YWRtaW46c2VjcmV0")).to_http_request();
+        assert_eq!(config.authenticate(request.headers()).unwrap(), "admin");
+
+        // "admin:wrong"
+        let request = TestRequest::default().insert_header((AUTHORIZATION, "Basic YWRtaW46d3Jvbmc=")).to_http_request();
+        assert!(config.authenticate(request.headers()).is_err());
+
+        let request = TestRequest::default().insert_header((AUTHORIZATION, "Bearer miner-token")).to_http_request();
+        assert_eq!(config.authenticate(request.headers()).unwrap(), "miner");
+
+        assert_eq!(config.get_bearer_token(request.headers()), Some("miner-token"));
+
+        let request = TestRequest::default().insert_header((AUTHORIZATION, "Bearer unknown")).to_http_request();
+        assert!(config.authenticate(request.headers()).is_err());
+        assert!(config.get_bearer_token(request.headers()).is_none());
+    }
+
+    #[test]
+    fn test_is_same_secret() {
+        assert!(is_same_secret("secret", "secret"));
+        assert!(!is_same_secret("secret", "secreT"));
+        // a prefix of the secret isn't accepted
+        assert!(!is_same_secret("secret", "secre"));
+        assert!(!is_same_secret("secret", ""));
+    }
+
+    #[test]
+    fn test_role() {
+        let role = RpcRole::with_methods(["get_info"]);
+        assert!(role.is_method_allowed("get_info"));
+        assert!(!role.is_method_allowed("submit_block"));
+        assert!(role.is_event_allowed("new_block"));
+        assert!(RpcRole::all().is_method_allowed("submit_block"));
+    }
+}
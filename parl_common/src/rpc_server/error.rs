@@ -43,6 +43,16 @@ pub enum InternalRpcError {
     EventNotSubscribed,
     #[error("Event is already subscribed")]
     EventAlreadySubscribed,
+    #[error("Event is not allowed")]
+    EventNotAllowed,
+    #[error("Invalid credentials")]
+    Unauthorized,
+    #[error("Method '{}' is not allowed", _0)]
+    MethodNotAllowed(String),
+    #[error("Too many requests, retry later")]
+    RateLimited,
+    #[error("Batch of {} requests exceeds the limit of {}", _0, _1)]
+    BatchTooLarge(usize, usize),
     #[error(transparent)]
     SerializeResponse(SerdeError),
     // Custom errors must have a code between -3 and -31999
@@ -59,7 +69,7 @@ impl InternalRpcError {
         match self {
             // JSON RPC errors
             Self::ParseBodyError => -32700,
-            Self::InvalidJSONRequest | Self::InvalidRequestStr(_) | InternalRpcError::InvalidVersion => -32600,
+            Self::InvalidJSONRequest | Self::InvalidRequestStr(_) | InternalRpcError::InvalidVersion | Self::BatchTooLarge(..) => -32600,
             Self::MethodNotFound(_) => -32601,
             Self::InvalidJSONParams(_) | Self::InvalidParams(_) |  Self::InvalidParamsAny(_) | InternalRpcError::UnexpectedParams | InternalRpcError::ExpectedParams => -32602,
             // Internal errors
@@ -70,9 +80,13 @@ impl InternalRpcError {
             Self::ClientNotFound => -32002,
             InternalRpcError::SerializeResponse(_) => -32003,
             InternalRpcError::AnyError(_) => -32004,
+            Self::Unauthorized => -32005,
+            Self::MethodNotAllowed(_) => -32006,
+            Self::RateLimited => -32007,
             // Events invalid requests
             Self::EventNotSubscribed => -1,
             Self::EventAlreadySubscribed => -2,
+            Self::EventNotAllowed => -3,
             // Custom errors
             Self::Custom(code, _) | Self::CustomStr(code, _) | Self::CustomAny(code, _) => *code,
         }
@@ -93,6 +107,10 @@ impl RpcResponseError {
         }
     }
 
+    pub fn get_error(&self) -> &InternalRpcError {
+        &self.error
+    }
+
     pub fn get_id(&self) -> Value {
         match &self.id {
             Some(id) => json!(id),
@@ -1,10 +1,16 @@
 pub mod websocket;
+mod auth;
 mod error;
+mod openrpc;
+mod rate_limit;
 mod rpc_handler;
 
 use std::borrow::Cow;
 
+pub use auth::{RpcAuthConfig, RpcRole, RpcUser};
 pub use error::{RpcResponseError, InternalRpcError};
+pub use openrpc::{OpenRpcInfo, RpcMethodSchema, DISCOVER_METHOD, OPENRPC_VERSION};
+pub use rate_limit::{RpcClientKey, RpcRateLimitConfig, RpcRateLimiter, DEFAULT_MAX_BATCH_SIZE};
 pub use rpc_handler::{RPCHandler, Handler};
 pub use rpc_handler::parse_params;
 
@@ -54,12 +60,14 @@ pub trait RPCServerHandler<T: Send + Clone> {
 }
 
 // JSON RPC handler endpoint
-pub async fn json_rpc<T, H>(server: Data<H>, body: web::Bytes) -> Result<impl Responder, RpcResponseError>
+pub async fn json_rpc<T, H>(server: Data<H>, request: HttpRequest, body: web::Bytes) -> Result<impl Responder, RpcResponseError>
 where
     T: Send + Sync + Clone + 'static,
     H: RPCServerHandler<T>
 {
-    let result = server.get_rpc_handler().handle_request(&body).await?;
+    let handler = server.get_rpc_handler();
+    let context = handler.create_context(&request)?;
+    let result = handler.handle_request_with_context(context, &body).await?;
     Ok(HttpResponse::Ok().json(result))
 }
 
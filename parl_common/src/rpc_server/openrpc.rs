@@ -0,0 +1,155 @@
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{InstanceType, ObjectValidation, Schema, SchemaObject, SingleOrVec},
+    JsonSchema
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// Method returning the OpenRPC document of the server
+pub const DISCOVER_METHOD: &str = "rpc.discover";
+// Version of the OpenRPC specification used
+pub const OPENRPC_VERSION: &str = "1.2.6";
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+// Types of the params and result of a RPC method
+#[derive(Clone, Copy)]
+pub struct RpcMethodSchema {
+    params: SchemaFn,
+    result: SchemaFn
+}
+
+impl RpcMethodSchema {
+    // Use `()` as params for methods without params
+    pub fn new<P: JsonSchema, R: JsonSchema>() -> Self {
+        Self {
+            params: P::json_schema,
+            result: SchemaGenerator::subschema_for::<R>
+        }
+    }
+}
+
+// Info of the API described in the OpenRPC document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenRpcInfo {
+    pub title: String,
+    pub version: String
+}
+
+// Each field of the params is described as a param as they are sent by name
+fn build_params(schema: Schema) -> Vec<Value> {
+    let object = match schema {
+        Schema::Object(object) => object,
+        Schema::Bool(_) => return Vec::new()
+    };
+
+    if object.instance_type == Some(SingleOrVec::Single(Box::new(InstanceType::Null))) {
+        return Vec::new()
+    }
+
+    match object.object {
+        // a flattened enum can't be split in several params
+        Some(validation) if !validation.properties.is_empty() && object.subschemas.is_none() => {
+            let ObjectValidation { properties, required, .. } = *validation;
+            properties.into_iter()
+                .map(|(name, schema)| json!({
+                    "required": required.contains(&name),
+                    "name": name,
+                    "schema": schema
+                }))
+                .collect()
+        },
+        validation => {
+            // params that are not a struct are described as a whole
+            let schema = SchemaObject { object: validation, ..object };
+            vec![json!({
+                "name": "params",
+                "required": true,
+                "schema": schema
+            })]
+        }
+    }
+}
+
+// Build the OpenRPC document of the methods
+// Types used are shared in the components of the document
+pub fn build_document<'a, I: IntoIterator<Item = (&'a str, &'a RpcMethodSchema)>>(info: &OpenRpcInfo, methods: I) -> Value {
+    let settings = SchemaSettings::draft07().with(|settings| {
+        settings.definitions_path = "#/components/schemas/".into();
+    });
+    let mut generator = settings.into_generator();
+
+    let mut methods: Vec<(&str, &RpcMethodSchema)> = methods.into_iter().collect();
+    methods.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+    let methods: Vec<Value> = methods.into_iter().map(|(name, schema)| {
+        let params = build_params((schema.params)(&mut generator));
+        let result = (schema.result)(&mut generator);
+        json!({
+            "name": name,
+            "paramStructure": "by-name",
+            "params": params,
+            "result": {
+                "name": "result",
+                "schema": result
+            }
+        })
+    }).collect();
+
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": info,
+        "methods": methods,
+        "components": {
+            "schemas": generator.take_definitions()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct TestParams {
+        height: u64,
+        #[serde(default)]
+        include_txs: bool
+    }
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct TestResult {
+        hash: String
+    }
+
+    #[test]
+    fn test_build_document() {
+        let info = OpenRpcInfo {
+            title: "Test".into(),
+            version: "1.0.0".into()
+        };
+        let with_params = RpcMethodSchema::new::<TestParams, TestResult>();
+        let without_params = RpcMethodSchema::new::<(), u64>();
+        let document = build_document(&info, [("get_block", &with_params), ("get_height", &without_params)]);
+
+        assert_eq!(document["openrpc"], OPENRPC_VERSION);
+        let methods = document["methods"].as_array().unwrap();
+        assert_eq!(methods.len(), 2);
+
+        let get_block = &methods[0];
+        assert_eq!(get_block["name"], "get_block");
+        let params = get_block["params"].as_array().unwrap();
+        assert_eq!(params.len(), 2);
+        assert!(params.iter().any(|param| param["name"] == "height" && param["required"] == true));
+        assert!(params.iter().any(|param| param["name"] == "include_txs" && param["required"] == false));
+        assert_eq!(get_block["result"]["schema"]["$ref"], "#/components/schemas/TestResult");
+        assert!(document["components"]["schemas"]["TestResult"].is_object());
+
+        let get_height = &methods[1];
+        assert!(get_height["params"].as_array().unwrap().is_empty());
+        assert_eq!(get_height["result"]["schema"]["type"], "integer");
+    }
+}
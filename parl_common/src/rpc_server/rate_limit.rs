@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant}
+};
+use serde::{Deserialize, Serialize};
+use super::InternalRpcError;
+
+// Max requests in a JSON-RPC batch
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+// Buckets are pruned when more clients than this are tracked
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// Client identity used for the rate limits
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RpcClientKey {
+    // Authenticated bearer token, its quota is shared across all IPs using it
+    Token(String),
+    Ip(IpAddr),
+    Unknown
+}
+
+impl From<Option<SocketAddr>> for RpcClientKey {
+    fn from(addr: Option<SocketAddr>) -> Self {
+        match addr {
+            Some(addr) => Self::Ip(addr.ip()),
+            None => Self::Unknown
+        }
+    }
+}
+
+// Limits applied to each client of a RPC server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcRateLimitConfig {
+    // Tokens given back to a client each second
+    pub requests_per_second: u64,
+    // Max tokens a client can have, allowing bursts of requests
+    pub burst: u64,
+    // Tokens taken by a method, 1 if not set
+    pub method_costs: HashMap<String, u64>,
+    // Max requests in a JSON-RPC batch
+    pub max_batch_size: usize,
+    // Max WebSocket sessions opened at the same time by an IP
+    pub max_ws_sessions_per_ip: Option<usize>
+}
+
+impl Default for RpcRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 20,
+            burst: 100,
+            method_costs: HashMap::new(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_ws_sessions_per_ip: Some(10)
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant
+}
+
+// Token bucket for each client
+pub struct RpcRateLimiter {
+    config: RpcRateLimitConfig,
+    buckets: Mutex<HashMap<RpcClientKey, Bucket>>
+}
+
+impl RpcRateLimiter {
+    pub fn new(mut config: RpcRateLimitConfig) -> Self {
+        // A rate of 0 would never refill the buckets
+        config.requests_per_second = config.requests_per_second.max(1);
+        config.burst = config.burst.max(1);
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn get_config(&self) -> &RpcRateLimitConfig {
+        &self.config
+    }
+
+    // A method costing more than the burst could never be called
+    pub fn get_method_cost(&self, method: &str) -> u64 {
+        self.config.method_costs.get(method).copied().unwrap_or(1).min(self.config.burst)
+    }
+
+    // Take the tokens needed by this method for the client
+    pub fn check(&self, client: &RpcClientKey, method: &str) -> Result<(), InternalRpcError> {
+        self.check_at(client, method, Instant::now())
+    }
+
+    fn check_at(&self, client: &RpcClientKey, method: &str, now: Instant) -> Result<(), InternalRpcError> {
+        let cost = self.get_method_cost(method) as f64;
+        let burst = self.config.burst as f64;
+        let rate = self.config.requests_per_second as f64;
+
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            // a bucket refilled completely is the same as a new one
+            let refill_time = Duration::from_secs_f64(burst / rate);
+            buckets.retain(|_, bucket| now.saturating_duration_since(bucket.last_refill) < refill_time);
+        }
+
+        let bucket = buckets.entry(client.clone()).or_insert_with(|| Bucket {
+            tokens: burst,
+            last_refill: now
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens < cost {
+            return Err(InternalRpcError::RateLimited)
+        }
+
+        bucket.tokens -= cost;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut config = RpcRateLimitConfig::default();
+        config.requests_per_second = 2;
+        config.burst = 4;
+        config.method_costs.insert("heavy".into(), 3);
+        let limiter = RpcRateLimiter::new(config);
+
+        let client = RpcClientKey::Ip([127, 0, 0, 1].into());
+        let now = Instant::now();
+        assert!(limiter.check_at(&client, "heavy", now).is_ok());
+        assert!(limiter.check_at(&client, "light", now).is_ok());
+        assert!(matches!(limiter.check_at(&client, "light", now), Err(InternalRpcError::RateLimited)));
+
+        // Other clients have their own bucket
+        assert!(limiter.check_at(&RpcClientKey::Unknown, "heavy", now).is_ok());
+
+        // 1.5s refills 3 tokens
+        let later = now + Duration::from_millis(1500);
+        assert!(limiter.check_at(&client, "heavy", later).is_ok());
+        assert!(limiter.check_at(&client, "light", later).is_err());
+    }
+
+    #[test]
+    fn test_method_cost_capped() {
+        let mut config = RpcRateLimitConfig::default();
+        config.burst = 5;
+        config.method_costs.insert("huge".into(), 50);
+        let limiter = RpcRateLimiter::new(config);
+        assert_eq!(limiter.get_method_cost("huge"), 5);
+        assert!(limiter.check(&RpcClientKey::Unknown, "huge").is_ok());
+    }
+}
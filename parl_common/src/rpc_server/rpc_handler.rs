@@ -1,15 +1,48 @@
-use std::{collections::HashMap, pin::Pin, future::Future};
+use std::{collections::{HashMap, HashSet}, pin::Pin, future::Future};
+use actix_web::{dev::RequestHead, http::header::HeaderMap, HttpRequest};
+use anyhow::anyhow;
+use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde_json::{json, Map, Value};
-use crate::context::Context;
+use crate::{context::Context, metrics::LabeledCounter};
 
-use super::{InternalRpcError, RpcResponseError, RpcRequest, JSON_RPC_VERSION};
-use log::{error, trace};
+use super::{
+    openrpc::build_document,
+    InternalRpcError,
+    OpenRpcInfo,
+    RpcAuthConfig,
+    RpcClientKey,
+    RpcMethodSchema,
+    RpcRateLimitConfig,
+    RpcRateLimiter,
+    RpcResponseError,
+    RpcRequest,
+    RpcRole,
+    DEFAULT_MAX_BATCH_SIZE,
+    DISCOVER_METHOD,
+    JSON_RPC_VERSION
+};
+use log::{debug, error, trace};
 
 pub type Handler = fn(&'_ Context, Value) -> Pin<Box<dyn Future<Output = Result<Value, InternalRpcError>> + Send + '_>>;
 
 pub struct RPCHandler<T: Send + Clone + 'static> {
     methods: HashMap<String, Handler>, // all RPC methods registered
+    // params and result types of each method
+    schemas: HashMap<String, RpcMethodSchema>,
+    // methods handled in their own task on the WebSocket and IPC sessions
+    concurrent_methods: HashSet<String>,
+    // if set, the OpenRPC document is served by rpc.discover
+    discover_info: Option<OpenRpcInfo>,
+    // named sets of methods that can be given to clients
+    roles: HashMap<String, RpcRole>,
+    // if set, each request must be authenticated to get its role
+    auth_config: Option<RpcAuthConfig>,
+    // if set, each client has a quota of requests
+    rate_limiter: Option<RpcRateLimiter>,
+    max_batch_size: usize,
+    // calls of each method registered
+    method_calls: LabeledCounter,
     data: T
 }
 
@@ -20,10 +53,31 @@ where
     pub fn new(data: T) -> Self {
         Self {
             methods: HashMap::new(),
+            schemas: HashMap::new(),
+            concurrent_methods: HashSet::new(),
+            discover_info: None,
+            roles: HashMap::new(),
+            auth_config: None,
+            rate_limiter: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            method_calls: LabeledCounter::new(),
             data
         }
     }
 
+    // Create the context for a HTTP request
+    // It contains the role of the client if authentication is enabled
+    pub fn create_context(&self, request: &HttpRequest) -> Result<Context, RpcResponseError> {
+        let mut context = Context::new();
+        context.store(self.get_data().clone());
+        context.store(self.get_client_key(request.head()));
+        if let Some(role) = self.get_role(request.headers()).map_err(|e| RpcResponseError::new(None, e))? {
+            context.store(role.clone());
+        }
+
+        Ok(context)
+    }
+
     pub async fn handle_request(&self, body: &[u8]) -> Result<Value, RpcResponseError> {
         let mut context = Context::new();
 
@@ -40,6 +94,7 @@ where
         match request {
             e @ Value::Object(_) => self.execute_method(&context, self.parse_request(e)?).await.map(|e| e.unwrap_or(Value::Null)),
             Value::Array(requests) => {
+                self.check_batch_size(requests.len())?;
                 let mut responses = Vec::new();
                 for value in requests {
                     if value.is_object() {
@@ -79,12 +134,31 @@ where
 
     pub async fn execute_method<'a>(&'a self, context: &'a Context, mut request: RpcRequest) -> Result<Option<Value>, RpcResponseError> {
         let handler = match self.methods.get(&request.method) {
-            Some(handler) => handler,
+            Some(handler) => Some(handler),
+            // The document only contains the methods allowed for the client
+            None if request.method == DISCOVER_METHOD && self.discover_info.is_some() => None,
             None => return Err(RpcResponseError::new(request.id, InternalRpcError::MethodNotFound(request.method)))
         };
+        self.method_calls.inc(&request.method);
+
+        if handler.is_some() {
+            self.check_role(context, &request.method).map_err(|e| RpcResponseError::new(request.id.clone(), e))?;
+        }
+        self.check_rate_limit(context, &request.method).map_err(|e| RpcResponseError::new(request.id.clone(), e))?;
+
         trace!("executing '{}' RPC method", request.method);
         let params = request.params.take().unwrap_or(Value::Null);
-        let result = handler(context, params).await.map_err(|err| RpcResponseError::new(request.id.clone(), err))?;
+        let result = match handler {
+            Some(handler) => handler(context, params).await.map_err(|err| RpcResponseError::new(request.id.clone(), err))?,
+            None => {
+                // OpenRPC clients may send an empty array
+                if !params.is_null() && !params.as_array().is_some_and(Vec::is_empty) {
+                    return Err(RpcResponseError::new(request.id, InternalRpcError::UnexpectedParams))
+                }
+                self.build_openrpc_document(context.get_optional::<RpcRole>())
+                    .ok_or_else(|| RpcResponseError::new(request.id.clone(), InternalRpcError::MethodNotFound(request.method.clone())))?
+            }
+        };
         Ok(if request.id.is_some() {
             Some(json!({
                 "jsonrpc": JSON_RPC_VERSION,
@@ -96,11 +170,154 @@ where
         })
     }
 
+    // Call a registered method outside of a JSON-RPC request, like from a REST endpoint
+    // The role and the rate limits of the client are applied the same way
+    pub async fn call_method(&self, context: &Context, method: &str, params: Value) -> Result<Value, InternalRpcError> {
+        let handler = self.methods.get(method).ok_or_else(|| InternalRpcError::MethodNotFound(method.to_owned()))?;
+        self.method_calls.inc(method);
+        self.check_role(context, method)?;
+        self.check_rate_limit(context, method)?;
+
+        trace!("calling '{}' RPC method", method);
+        handler(context, params).await
+    }
+
+    fn check_role(&self, context: &Context, method: &str) -> Result<(), InternalRpcError> {
+        if let Some(role) = context.get_optional::<RpcRole>() {
+            if !role.is_method_allowed(method) {
+                debug!("'{}' RPC method is not allowed for this client", method);
+                return Err(InternalRpcError::MethodNotAllowed(method.to_owned()))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Take the cost of the method from the quota of the client
+    // Also used for the methods handled outside of this handler, like the event subscriptions
+    pub fn check_rate_limit(&self, context: &Context, method: &str) -> Result<(), InternalRpcError> {
+        if let (Some(limiter), Some(client)) = (&self.rate_limiter, context.get_optional::<RpcClientKey>()) {
+            if let Err(e) = limiter.check(client, method) {
+                debug!("'{}' RPC method call rate limited for {:?}", method, client);
+                return Err(e)
+            }
+        }
+
+        Ok(())
+    }
+
     // register a new RPC method handler
-    pub fn register_method(&mut self, name: &str, handler: Handler) {
+    // P and R are the types of its params and result, used in the OpenRPC document
+    pub fn register_method<P: JsonSchema, R: JsonSchema>(&mut self, name: &str, handler: Handler) {
         if self.methods.insert(name.into(), handler).is_some() {
             error!("The method '{}' was already registered !", name);
         }
+        self.schemas.insert(name.into(), RpcMethodSchema::new::<P, R>());
+    }
+
+    // Handle the calls of this method in their own task on the WebSocket and IPC sessions
+    // For the methods that can wait a long time, such as a long poll, so they don't block the session
+    // Their responses may be sent after the ones of the next messages
+    pub fn set_method_concurrent(&mut self, name: &str) {
+        self.concurrent_methods.insert(name.into());
+    }
+
+    // Verify if the calls of this method are handled in their own task
+    pub fn is_method_concurrent(&self, name: &str) -> bool {
+        self.concurrent_methods.contains(name)
+    }
+
+    // Serve the OpenRPC document of the registered methods on rpc.discover
+    pub fn enable_discover(&mut self, info: OpenRpcInfo) {
+        self.discover_info = Some(info);
+    }
+
+    // Build the OpenRPC document with only the methods allowed by the role
+    // Returns None if rpc.discover is not enabled
+    pub fn build_openrpc_document(&self, role: Option<&RpcRole>) -> Option<Value> {
+        let info = self.discover_info.as_ref()?;
+        let methods = self.schemas.iter()
+            .filter(|(name, _)| role.map_or(true, |role| role.is_method_allowed(name)))
+            .map(|(name, schema)| (name.as_str(), schema));
+
+        Some(build_document(info, methods))
+    }
+
+    // names of all the RPC methods registered
+    pub fn get_method_names(&self) -> impl Iterator<Item = &str> {
+        self.methods.keys().map(String::as_str)
+    }
+
+    // register a new role or replace an existing one
+    pub fn register_role(&mut self, name: &str, role: RpcRole) {
+        self.roles.insert(name.into(), role);
+    }
+
+    // Enable the authentication of all requests
+    // All the roles used in the config must be registered or defined in it
+    pub fn set_auth_config(&mut self, mut config: RpcAuthConfig) -> Result<(), anyhow::Error> {
+        self.roles.extend(config.roles.drain());
+
+        let used_roles = config.anonymous_role.iter()
+            .map(String::as_str)
+            .chain(config.users.iter().map(|user| user.get_role()));
+        for role in used_roles {
+            if !self.roles.contains_key(role) {
+                return Err(anyhow!("RPC role '{}' is not defined", role))
+            }
+        }
+
+        self.auth_config = Some(config);
+        Ok(())
+    }
+
+    pub fn is_auth_enabled(&self) -> bool {
+        self.auth_config.is_some()
+    }
+
+    // Get the role of the client using the headers of its request
+    // Returns None if authentication is disabled, every method is then allowed
+    pub fn get_role(&self, headers: &HeaderMap) -> Result<Option<&RpcRole>, InternalRpcError> {
+        match &self.auth_config {
+            Some(config) => {
+                let name = config.authenticate(headers)?;
+                self.roles.get(name).map(Some).ok_or(InternalRpcError::Unauthorized)
+            },
+            None => Ok(None)
+        }
+    }
+
+    // Enable the rate limits for each client
+    // Only the daemon RPC server configures them, the wallet RPC server isn't rate limited
+    pub fn set_rate_limit_config(&mut self, config: RpcRateLimitConfig) {
+        self.max_batch_size = config.max_batch_size;
+        self.rate_limiter = Some(RpcRateLimiter::new(config));
+    }
+
+    pub fn get_max_ws_sessions_per_ip(&self) -> Option<usize> {
+        self.rate_limiter.as_ref().and_then(|limiter| limiter.get_config().max_ws_sessions_per_ip)
+    }
+
+    pub fn check_batch_size(&self, size: usize) -> Result<(), RpcResponseError> {
+        if size > self.max_batch_size {
+            return Err(RpcResponseError::new(None, InternalRpcError::BatchTooLarge(size, self.max_batch_size)))
+        }
+
+        Ok(())
+    }
+
+    // Identify the client for the rate limits
+    // Bearer tokens are only used once authenticated, otherwise a client could send a new one in each request
+    pub fn get_client_key(&self, head: &RequestHead) -> RpcClientKey {
+        if let Some(token) = self.auth_config.as_ref().and_then(|config| config.get_bearer_token(&head.headers)) {
+            return RpcClientKey::Token(token.to_owned())
+        }
+
+        RpcClientKey::from(head.peer_addr)
+    }
+
+    pub fn get_method_calls(&self) -> &LabeledCounter {
+        &self.method_calls
     }
 
     pub fn get_data(&self) -> &T {
@@ -3,7 +3,7 @@ use actix_web::web::Bytes;
 use async_trait::async_trait;
 use log::{trace, debug};
 use serde_json::{Value, json};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::sync::RwLock;
 use crate::{
     api::{EventResult, SubscribeParams},
@@ -14,21 +14,52 @@ use crate::{
         RPCHandler,
         RpcRequest,
         RpcResponse,
-        RpcResponseError
+        RpcResponseError,
+        RpcRole
     }
 };
 use super::{WebSocketSessionShared, WebSocketHandler};
 
+// Filter sent with a subscription to only receive some of the events
+pub trait SubscriptionFilter<E>: DeserializeOwned + Sync + Send + Clone + 'static {
+    // Verify that the filter, or its absence, is valid for this event
+    fn validate(event: &E, filter: Option<&Self>) -> Result<(), InternalRpcError>;
+}
+
+// No filter is supported
+impl<E> SubscriptionFilter<E> for () {
+    fn validate(_: &E, filter: Option<&Self>) -> Result<(), InternalRpcError> {
+        if filter.is_some() {
+            return Err(InternalRpcError::InvalidParams("Events can't be filtered"))
+        }
+        Ok(())
+    }
+}
+
+// Only the method is read to know how a message is handled
+#[derive(Deserialize)]
+struct RequestMethod<'a> {
+    #[serde(borrow)]
+    method: Cow<'a, str>
+}
+
+#[derive(Clone)]
+struct Subscription<F> {
+    id: Option<Id>,
+    filter: Option<F>
+}
+
 // generic websocket handler supporting event subscriptions 
-pub struct EventWebSocketHandler<T: Sync + Send + Clone + 'static, E: Serialize + DeserializeOwned + Sync + Send + Eq + Hash + Clone + 'static> {
-    events: RwLock<HashMap<WebSocketSessionShared<Self>, HashMap<E, Option<Id>>>>,
+pub struct EventWebSocketHandler<T: Sync + Send + Clone + 'static, E: Serialize + DeserializeOwned + Sync + Send + Eq + Hash + Clone + 'static, F: SubscriptionFilter<E> = ()> {
+    events: RwLock<HashMap<WebSocketSessionShared<Self>, HashMap<E, Subscription<F>>>>,
     handler: RPCHandler<T>
 }
 
-impl<T, E> EventWebSocketHandler<T, E>
+impl<T, E, F> EventWebSocketHandler<T, E, F>
 where
     T: Sync + Send + Clone + 'static,
-    E: Serialize + DeserializeOwned + Sync + Send + Eq + Hash + Clone + 'static
+    E: Serialize + DeserializeOwned + Sync + Send + Eq + Hash + Clone + 'static,
+    F: SubscriptionFilter<E>
 {
     pub fn new(handler: RPCHandler<T>) -> Self {
         Self {
@@ -55,6 +86,12 @@ where
     }
 
     pub async fn notify(&self, event: &E, value: Value) {
+        self.notify_with_filter(event, value, |_| true).await
+    }
+
+    // Send the event to the subscriptions without filter
+    // and to the ones with a filter accepted by `matches`
+    pub async fn notify_with_filter<M: Fn(&F) -> bool>(&self, event: &E, value: Value, matches: M) {
         let value = json!(EventResult { event: Cow::Borrowed(event), value });
         debug!("notifying event");
         let sessions = {
@@ -64,8 +101,12 @@ where
         };
 
         for (session, subscriptions) in sessions.iter() {
-            if let Some(id) = subscriptions.get(event) {
-                let response = json!(RpcResponse::new(Cow::Borrowed(&id), Cow::Borrowed(&value)));
+            if let Some(subscription) = subscriptions.get(event) {
+                if !subscription.filter.as_ref().map_or(true, &matches) {
+                    continue;
+                }
+
+                let response = json!(RpcResponse::new(Cow::Borrowed(&subscription.id), Cow::Borrowed(&value)));
                 trace!("sending event to #{}", session.id);
                 if let Err(e) = session.send_text(response.to_string()).await {
                     debug!("Error occured while notifying a new event: {}", e);
@@ -77,7 +118,7 @@ where
         debug!("end event propagation");
     }
 
-    async fn subscribe_session_to_event(&self, session: &WebSocketSessionShared<Self>, event: E, id: Option<Id>) -> Result<(), RpcResponseError> {
+    async fn subscribe_session_to_event(&self, session: &WebSocketSessionShared<Self>, event: E, filter: Option<F>, id: Option<Id>) -> Result<(), RpcResponseError> {
         trace!("subscribing session to event");
         let mut sessions = self.events.write().await;
         trace!("subscribe events locked");
@@ -86,7 +127,7 @@ where
             return Err(RpcResponseError::new(id, InternalRpcError::EventAlreadySubscribed));
         }
 
-        events.insert(event, id);
+        events.insert(event, Subscription { id, filter });
         Ok(())
     }
 
@@ -104,23 +145,49 @@ where
         Ok(())
     }
 
-    fn parse_event(&self, request: &mut RpcRequest) -> Result<E, RpcResponseError> {
+    // Name of the event as used in the role allowlist
+    // Events are serialized as a string or as an object with a single key
+    fn get_event_name(event: &E) -> Option<String> {
+        match serde_json::to_value(event).ok()? {
+            Value::String(name) => Some(name),
+            Value::Object(map) => map.keys().next().cloned(),
+            _ => None
+        }
+    }
+
+    fn parse_subscription(&self, request: &mut RpcRequest) -> Result<(E, Option<F>), RpcResponseError> {
         let value = request.params.take().ok_or_else(|| RpcResponseError::new(request.id.clone(), InternalRpcError::ExpectedParams))?;
         let params: SubscribeParams<E> = serde_json::from_value(value).map_err(|e| RpcResponseError::new(request.id.clone(), InternalRpcError::InvalidJSONParams(e)))?;
-        Ok(params.notify.into_owned())
+        let filter = params.filter
+            .map(|filter| serde_json::from_value(filter))
+            .transpose()
+            .map_err(|e| RpcResponseError::new(request.id.clone(), InternalRpcError::InvalidJSONParams(e)))?;
+        Ok((params.notify.into_owned(), filter))
     }
 
     async fn execute_method_internal(&self, context: &Context, value: Value) -> Result<Option<Value>, RpcResponseError> {
         let mut request = self.handler.parse_request(value)?;
         let method = request.method.clone();
+        if method == "subscribe" || method == "unsubscribe" {
+            self.handler.check_rate_limit(context, &method).map_err(|e| RpcResponseError::new(request.id.clone(), e))?;
+        }
+
         match method.as_str() {
             "subscribe" => {
-                let event = self.parse_event(&mut request)?;
-                self.subscribe_session_to_event(context.get::<WebSocketSessionShared<Self>>().unwrap(), event, request.id.clone()).await?;
+                let (event, filter) = self.parse_subscription(&mut request)?;
+                F::validate(&event, filter.as_ref()).map_err(|e| RpcResponseError::new(request.id.clone(), e))?;
+                if let Some(role) = context.get_optional::<RpcRole>() {
+                    let allowed = Self::get_event_name(&event).map_or(false, |name| role.is_event_allowed(&name));
+                    if !allowed {
+                        debug!("event subscription is not allowed for this client");
+                        return Err(RpcResponseError::new(request.id, InternalRpcError::EventNotAllowed))
+                    }
+                }
+                self.subscribe_session_to_event(context.get::<WebSocketSessionShared<Self>>().unwrap(), event, filter, request.id.clone()).await?;
                 Ok(Some(json!(RpcResponse::new(Cow::Borrowed(&request.id), Cow::Owned(Value::Bool(true))))))
             },
             "unsubscribe" => {
-                let event = self.parse_event(&mut request)?;
+                let (event, _) = self.parse_subscription(&mut request)?;
                 self.unsubscribe_session_from_event(context.get::<WebSocketSessionShared<Self>>().unwrap(), event, request.id.clone()).await?;
                 Ok(Some(json!(RpcResponse::new(Cow::Borrowed(&request.id), Cow::Owned(Value::Bool(true))))))
             },
@@ -135,10 +202,19 @@ where
         let mut context = Context::default();
         context.store(session.clone());
         context.store(self.handler.get_data().clone());
+        // IPC clients are trusted by the socket permissions, no role or rate limit apply
+        if !session.is_ipc() {
+            context.store(self.handler.get_client_key(session.get_request().head()));
+            // role was already verified when the session was opened
+            if let Ok(Some(role)) = self.handler.get_role(session.get_request().headers()) {
+                context.store(role.clone());
+            }
+        }
 
         match request {
             e @ Value::Object(_) => self.execute_method_internal(&context, e).await.map(|e| e.unwrap_or(Value::Null)),
             Value::Array(requests) => {
+                self.handler.check_batch_size(requests.len())?;
                 let mut responses = Vec::new();
                 for value in requests {
                     if value.is_object() {
@@ -163,11 +239,31 @@ where
 }
 
 #[async_trait]
-impl<T, E> WebSocketHandler for EventWebSocketHandler<T, E>
+impl<T, E, F> WebSocketHandler for EventWebSocketHandler<T, E, F>
 where
     T: Sync + Send + Clone + 'static,
-    E: Serialize + DeserializeOwned + Sync + Send + Eq + Hash + Clone + 'static
+    E: Serialize + DeserializeOwned + Sync + Send + Eq + Hash + Clone + 'static,
+    F: SubscriptionFilter<E>
 {
+    fn get_max_sessions_per_ip(&self) -> Option<usize> {
+        self.handler.get_max_ws_sessions_per_ip()
+    }
+
+    // Calls of the concurrent methods are handled in their own task
+    // Batches are always handled in order
+    fn is_concurrent_message(&self, message: &[u8]) -> bool {
+        serde_json::from_slice::<RequestMethod>(message)
+            .map_or(false, |request| self.handler.is_method_concurrent(&request.method))
+    }
+
+    async fn on_connection(&self, session: &WebSocketSessionShared<Self>) -> Result<(), anyhow::Error> {
+        // reject the clients which can't be authenticated
+        if !session.is_ipc() {
+            self.handler.get_role(session.get_request().headers())?;
+        }
+        Ok(())
+    }
+
     async fn on_close(&self, session: &WebSocketSessionShared<Self>) -> Result<(), anyhow::Error> {
         trace!("deleting ws session from events");
         let mut sessions = self.events.write().await;
@@ -9,6 +9,13 @@ pub struct HttpRequest {
 }
 
 impl HttpRequest {
+    // Request used by the sessions which are not opened over HTTP
+    pub fn local() -> Self {
+        Self {
+            head: RequestHead::default()
+        }
+    }
+
     #[inline]
     pub fn head(&self) -> &RequestHead {
         &self.head
@@ -0,0 +1,252 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    io::ErrorKind,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::UnixStream as StdUnixStream
+    },
+    path::Path,
+    sync::Arc
+};
+use actix_web::web::Bytes;
+use log::{debug, info, trace, warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver},
+        Mutex,
+        Semaphore
+    },
+    time::timeout
+};
+use crate::utils::spawn_task;
+use super::{
+    HttpRequest,
+    InnerMessage,
+    WebSocketError,
+    WebSocketHandler,
+    WebSocketServer,
+    WebSocketSession,
+    WebSocketSessionShared,
+    MAX_CONCURRENT_MESSAGES_PER_SESSION,
+    MESSAGE_TIME_OUT
+};
+
+// Max size in bytes of a message received on the IPC socket
+pub const IPC_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+// Messages are JSON encoded and separated by a new line
+const IPC_MESSAGE_DELIMITER: u8 = b'\n';
+
+impl<H> WebSocketServer<H> where H: WebSocketHandler + 'static {
+    // Handle a new connection on the IPC socket, register it and start handling it
+    // The session is then managed like a WebSocket one by the handler
+    pub async fn handle_ipc_connection(self: &Arc<Self>, stream: UnixStream) {
+        let id = self.next_id();
+        debug!("Created new IPC session with id {}", id);
+
+        let (tx, rx) = unbounded_channel();
+        let session = Arc::new(WebSocketSession {
+            id,
+            request: HttpRequest::local(),
+            server: Arc::clone(self),
+            inner: Mutex::new(None),
+            ipc: true,
+            channel: tx,
+            concurrent_messages: Arc::new(Semaphore::new(MAX_CONCURRENT_MESSAGES_PER_SESSION))
+        });
+
+        {
+            let mut sessions = self.sessions.write().await;
+            sessions.insert(Arc::clone(&session));
+        }
+
+        spawn_task(format!("ipc-session-{}", id), Arc::clone(self).handle_ipc_internal(session, stream, rx));
+    }
+
+    // Internal function to handle an IPC connection
+    // No heartbeat is needed, the connection is closed when the socket is
+    async fn handle_ipc_internal(self: Arc<Self>, session: WebSocketSessionShared<H>, stream: UnixStream, mut rx: UnboundedReceiver<InnerMessage>) {
+        if let Err(e) = self.handler.on_connection(&session).await {
+            debug!("Error while calling on_connection: {}", e);
+            self.delete_session(&session, None).await;
+            return;
+        }
+
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        // partially read data is kept in the buffer if the read is interrupted by another branch
+        let mut buffer = Vec::new();
+        loop {
+            select! {
+                Some(msg) = rx.recv() => {
+                    match msg {
+                        InnerMessage::Text(mut text) => {
+                            trace!("Sending text message to IPC session #{}: {}", session.id, text);
+                            text.push(IPC_MESSAGE_DELIMITER as char);
+                            let res: Result<(), WebSocketError> = match timeout(MESSAGE_TIME_OUT, writer.write_all(text.as_bytes())).await {
+                                Ok(res) => res.map_err(WebSocketError::from),
+                                Err(e) => Err(e.into())
+                            };
+                            if let Err(e) = res {
+                                debug!("Error while sending text message to IPC session #{}: {}", session.id, e);
+                                break;
+                            }
+                        },
+                        InnerMessage::Close(reason) => {
+                            debug!("Closing IPC session #{} with reason: {:?}", session.id, reason);
+                            break;
+                        }
+                    }
+                },
+                res = (&mut reader).take((IPC_MAX_MESSAGE_SIZE - buffer.len()) as u64).read_until(IPC_MESSAGE_DELIMITER, &mut buffer) => {
+                    match res {
+                        Ok(0) => {
+                            debug!("Stream closed for IPC session #{}", session.id);
+                            break;
+                        },
+                        Ok(_) => {},
+                        Err(e) => {
+                            debug!("Error while receiving message: {}", e);
+                            break;
+                        }
+                    };
+
+                    if buffer.last() != Some(&IPC_MESSAGE_DELIMITER) {
+                        if buffer.len() >= IPC_MAX_MESSAGE_SIZE {
+                            debug!("IPC session #{} sent a message bigger than {} bytes", session.id, IPC_MAX_MESSAGE_SIZE);
+                            break;
+                        }
+                        // wait for the rest of the message
+                        continue;
+                    }
+
+                    let message = Bytes::from(std::mem::take(&mut buffer));
+                    let message = message.slice(..message.len() - 1);
+                    if message.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+
+                    trace!("Received message for IPC session #{}", session.id);
+                    self.handle_message(&session, message).await;
+                }
+            };
+        }
+
+        debug!("IPC session #{} is closing", session.id);
+        self.delete_session(&session, None).await;
+    }
+}
+
+// Bind the unix socket in a private directory and move it to its path once its permissions are set
+// Otherwise it would be reachable with the default permissions until they are changed
+fn bind_ipc_socket(path: &str, mode: u32) -> Result<UnixListener, std::io::Error> {
+    let path = Path::new(path);
+    let parent = path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file_name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "IPC path has no file name"))?;
+
+    let private_dir = parent.join(format!(".{}.{}", file_name.to_string_lossy(), std::process::id()));
+    let private_path = private_dir.join("socket");
+    // remove what a previous run may have left
+    let _ = fs::remove_file(&private_path);
+    let _ = fs::remove_dir(&private_dir);
+
+    DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let res = UnixListener::bind(&private_path)
+        .and_then(|listener| {
+            fs::set_permissions(&private_path, Permissions::from_mode(mode))?;
+            remove_stale_socket(path)?;
+            // unlike a rename, the link fails if something was created at the path meanwhile
+            fs::hard_link(&private_path, path)?;
+            Ok(listener)
+        });
+
+    let _ = fs::remove_file(&private_path);
+    let _ = fs::remove_dir(&private_dir);
+    res
+}
+
+// Delete the socket left at this path by a previous run
+// Anything else, such as a regular file, a symlink or a socket still accepting connections, is an error
+fn remove_stale_socket(path: &Path) -> Result<(), std::io::Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e)
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(std::io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists and is not a socket", path.display())))
+    }
+
+    if StdUnixStream::connect(path).is_ok() {
+        return Err(std::io::Error::new(ErrorKind::AddrInUse, format!("{} is used by another process", path.display())))
+    }
+
+    debug!("Deleting the stale IPC socket {}", path.display());
+    fs::remove_file(path)
+}
+
+// Listen on a unix socket and forward the connections to the server
+// Access is restricted by the permissions set on the socket file
+pub async fn run_ipc_server<H>(server: Arc<WebSocketServer<H>>, path: String, mode: u32) -> Result<(), std::io::Error>
+where
+    H: WebSocketHandler + 'static
+{
+    let listener = bind_ipc_socket(&path, mode)?;
+    info!("IPC server is listening on {} (mode {:o})", path, mode);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => server.handle_ipc_connection(stream).await,
+            Err(e) => warn!("Error while accepting an IPC connection: {}", e)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::{fs::symlink, net::UnixListener as StdUnixListener};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_ipc_socket_replaces_only_a_stale_socket() {
+        let dir = std::env::temp_dir().join(format!("parl-ipc-bind-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rpc.sock");
+        let path_str = path.to_string_lossy().into_owned();
+
+        // a regular file is never replaced
+        fs::write(&path, b"data").unwrap();
+        assert_eq!(bind_ipc_socket(&path_str, 0o600).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+        fs::remove_file(&path).unwrap();
+
+        // neither a symlink
+        let target = dir.join("target");
+        fs::write(&target, b"data").unwrap();
+        symlink(&target, &path).unwrap();
+        assert_eq!(bind_ipc_socket(&path_str, 0o600).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert!(fs::symlink_metadata(&path).unwrap().file_type().is_symlink());
+        fs::remove_file(&path).unwrap();
+
+        // nor a socket used by another process
+        let live = StdUnixListener::bind(&path).unwrap();
+        assert_eq!(bind_ipc_socket(&path_str, 0o600).unwrap_err().kind(), ErrorKind::AddrInUse);
+        drop(live);
+
+        // the socket is now stale and is replaced
+        let listener = bind_ipc_socket(&path_str, 0o600).unwrap();
+        assert!(StdUnixStream::connect(&path).is_ok());
+        drop(listener);
+
+        // only the socket is left, the private directory is deleted
+        fs::remove_file(&target).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
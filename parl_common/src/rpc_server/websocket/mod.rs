@@ -1,5 +1,7 @@
 mod handler;
 mod http_request;
+#[cfg(unix)]
+mod ipc;
 
 use std::{
     collections::HashSet,
@@ -34,17 +36,21 @@ use tokio::{
             UnboundedSender
         },
         Mutex,
-        RwLock
+        RwLock,
+        Semaphore
     },
     time::{
         error::Elapsed,
         timeout
     }
 };
+use crate::utils::spawn_task;
 pub use self::{
-    handler::EventWebSocketHandler,
+    handler::{EventWebSocketHandler, SubscriptionFilter},
     http_request::HttpRequest
 };
+#[cfg(unix)]
+pub use self::ipc::{run_ipc_server, IPC_MAX_MESSAGE_SIZE};
 
 pub type WebSocketServerShared<H> = Arc<WebSocketServer<H>>;
 pub type WebSocketSessionShared<H> = Arc<WebSocketSession<H>>;
@@ -56,6 +62,8 @@ const MESSAGE_TIME_OUT: Duration = Duration::from_secs(1);
 const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
 // timeout in seconds to receive a pong message
 const KEEP_ALIVE_TIME_OUT: Duration = Duration::from_secs(30);
+// maximum messages handled in their own task at the same time by a session
+const MAX_CONCURRENT_MESSAGES_PER_SESSION: usize = 8;
 
 #[derive(Debug, thiserror::Error)]
 pub enum WebSocketError {
@@ -67,6 +75,8 @@ pub enum WebSocketError {
     ChannelClosed,
     #[error(transparent)]
     Elapsed(#[from] Elapsed),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 enum InnerMessage {
@@ -79,8 +89,12 @@ pub struct WebSocketSession<H: WebSocketHandler + 'static> {
     request: HttpRequest,
     server: WebSocketServerShared<H>,
     inner: Mutex<Option<Session>>,
+    // Session opened on the local IPC socket instead of a WebSocket
+    ipc: bool,
     // Sender to send messages to the session
-    channel: UnboundedSender<InnerMessage>
+    channel: UnboundedSender<InnerMessage>,
+    // Slots of the messages handled in their own task
+    concurrent_messages: Arc<Semaphore>
 }
 
 impl<H> WebSocketSession<H>
@@ -138,9 +152,18 @@ where
     }
 
     pub async fn is_closed(&self) -> bool {
+        if self.ipc {
+            return self.channel.is_closed()
+        }
+
         self.inner.lock().await.is_none()
     }
 
+    // Is this session connected through the local IPC socket
+    pub fn is_ipc(&self) -> bool {
+        self.ipc
+    }
+
     pub fn get_request(&self) -> &HttpRequest {
         &self.request
     }
@@ -175,12 +198,23 @@ where
 
 #[async_trait]
 pub trait WebSocketHandler: Sized + Sync + Send {
+    // max sessions opened at the same time by an IP, None for no limit
+    fn get_max_sessions_per_ip(&self) -> Option<usize> {
+        None
+    }
+
     // called when a new Session is added in websocket server
     // if an error is returned, maintaining the session is aborted
     async fn on_connection(&self, _: &WebSocketSessionShared<Self>) -> Result<(), anyhow::Error> {
         Ok(())
     }
 
+    // Verify if this message can be handled in its own task
+    // By default, the messages of a session are handled in order one at a time
+    fn is_concurrent_message(&self, _: &[u8]) -> bool {
+        false
+    }
+
     // called when a new message is received
     async fn on_message(&self, _: &WebSocketSessionShared<Self>, _: Bytes) -> Result<(), anyhow::Error> {
         Ok(())
@@ -246,6 +280,19 @@ impl<H> WebSocketServer<H> where H: WebSocketHandler + 'static {
     // Handle a new WebSocket connection request, register it and start handling it
     pub async fn handle_connection(self: &Arc<Self>, request: ActixHttpRequest, body: Payload) -> Result<HttpResponse, actix_web::Error> {
         debug!("Handling new WebSocket connection");
+        // the limit is checked under the same lock as the insertion
+        // so concurrent connections from the same IP can't exceed it
+        let mut sessions = self.sessions.write().await;
+        if let (Some(max), Some(addr)) = (self.handler.get_max_sessions_per_ip(), request.peer_addr()) {
+            let count = sessions.iter()
+                .filter(|session| session.request.head().peer_addr.map(|a| a.ip()) == Some(addr.ip()))
+                .count();
+            if count >= max {
+                debug!("Too many WebSocket sessions for {}", addr.ip());
+                return Ok(HttpResponse::TooManyRequests().body("Too many WebSocket sessions opened"))
+            }
+        }
+
         let (response, session, stream) = actix_ws::handle(&request, body)?;
         let id = self.next_id();
         debug!("Created new WebSocketSession with id {}", id);
@@ -256,15 +303,15 @@ impl<H> WebSocketServer<H> where H: WebSocketHandler + 'static {
             request: request.into(),
             server: Arc::clone(&self),
             inner: Mutex::new(Some(session)),
-            channel: tx
+            ipc: false,
+            channel: tx,
+            concurrent_messages: Arc::new(Semaphore::new(MAX_CONCURRENT_MESSAGES_PER_SESSION))
         });
 
-        {
-            debug!("Inserting session #{} into sessions", id);
-            let mut sessions = self.sessions.write().await;
-            let res = sessions.insert(Arc::clone(&session));
-            debug!("Session #{} has been inserted into sessions: {}", id, res);
-        }
+        debug!("Inserting session #{} into sessions", id);
+        let res = sessions.insert(Arc::clone(&session));
+        debug!("Session #{} has been inserted into sessions: {}", id, res);
+        drop(sessions);
 
         actix_rt::spawn(Arc::clone(self).handle_ws_internal(session, stream, rx));
         Ok(response)
@@ -278,9 +325,11 @@ impl<H> WebSocketServer<H> where H: WebSocketHandler + 'static {
     // Delete a session from the server
     pub async fn delete_session(self: &Arc<Self>, session: &WebSocketSessionShared<H>, reason: Option<CloseReason>) {
         trace!("deleting session #{}", session.id);
-        // close session
-        if let Err(e) = session.close_internal(reason).await {
-            debug!("Error while closing session: {}", e);
+        // close session, IPC sessions are closed by their task
+        if !session.ipc {
+            if let Err(e) = session.close_internal(reason).await {
+                debug!("Error while closing session: {}", e);
+            }
         }
         trace!("session closed");
 
@@ -300,6 +349,35 @@ impl<H> WebSocketServer<H> where H: WebSocketHandler + 'static {
         trace!("sessions unlocked");
     }
 
+    // Handle a message received on a session
+    // Messages are handled in order, except the ones the handler allows to run in their own task
+    // When all the slots of the session are used, reading its next messages waits for a free one
+    async fn handle_message(self: &Arc<Self>, session: &WebSocketSessionShared<H>, message: Bytes) {
+        if !self.handler.is_concurrent_message(&message) {
+            if let Err(e) = self.handler.on_message(session, message).await {
+                debug!("Error while calling on_message: {}", e);
+            }
+            return;
+        }
+
+        let permit = match Arc::clone(&session.concurrent_messages).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(e) => {
+                debug!("Error while waiting for a message slot of session #{}: {}", session.id, e);
+                return;
+            }
+        };
+
+        let server = Arc::clone(self);
+        let session = Arc::clone(session);
+        spawn_task(format!("session-{}-message", session.id), async move {
+            if let Err(e) = server.handler.on_message(&session, message).await {
+                debug!("Error while calling on_message: {}", e);
+            }
+            drop(permit);
+        });
+    }
+
     // Internal function to handle a WebSocket connection
     // This will send a ping every 5 seconds and close the connection if no pong is received within 30 seconds
     // It will also translate all messages to the handler
@@ -369,9 +447,7 @@ impl<H> WebSocketServer<H> where H: WebSocketHandler + 'static {
                     match msg {
                         Message::Text(text) => {
                             trace!("Received text message for session #{}: {}", session.id, text);
-                            if let Err(e) = self.handler.on_message(&session, text.into_bytes()).await {
-                                debug!("Error while calling on_message: {}", e);
-                            }
+                            self.handle_message(&session, text.into_bytes()).await;
                         },
                         Message::Close(reason) => {
                             trace!("Received close message for session #{}: {:?}", session.id, reason);
@@ -4,6 +4,7 @@
 
 use bulletproofs::RangeProof;
 use curve25519_dalek::Scalar;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
@@ -78,7 +79,7 @@ pub enum GenerationError<T> {
     Proof(#[from] ProofGenerationError),
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum FeeBuilder {
     // calculate tx fees based on its size and multiply by this value
@@ -130,7 +131,7 @@ pub trait AccountState: FeeHelper {
     fn update_nonce(&mut self, new_nonce: u64) -> Result<(), Self::Error>;
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum TransactionTypeBuilder {
     Transfers(Vec<TransferBuilder>),
@@ -138,7 +139,7 @@ pub enum TransactionTypeBuilder {
     Burn(BurnPayload)
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct TransferBuilder {
     pub asset: Hash,
     pub amount: u64,
@@ -72,7 +72,7 @@ pub struct PlaintextData(pub Vec<u8>);
 // A wrapper around a Vec<u8>.
 // This is used for outside the wallet as we don't know what is used
 // Cipher format isn't validated
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone, Debug)]
 pub struct UnknownExtraDataFormat(pub Vec<u8>);
 
 // New version of Extra Data due to the issue of commitment randomness reuse
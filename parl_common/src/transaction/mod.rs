@@ -11,6 +11,7 @@ use crate::{
 };
 use bulletproofs::RangeProof;
 use log::debug;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use self::extra_data::UnknownExtraDataFormat;
 
@@ -25,7 +26,7 @@ mod tests;
 pub const EXTRA_DATA_LIMIT_SIZE: usize = 1024;
 pub const MAX_TRANSFER_COUNT: usize = 255;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Reference {
     pub hash: Hash,
     pub topoheight: u64,
@@ -48,14 +49,14 @@ pub enum Role {
     Receiver,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, JsonSchema, Clone, Debug)]
 pub struct SourceCommitment {
     commitment: CompressedCommitment,
     proof: CommitmentEqProof,
     asset: Hash,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct TransferPayload {
     asset: Hash,
     destination: CompressedPublicKey,
@@ -70,14 +71,14 @@ pub struct TransferPayload {
 }
 
 // Burn is a public payload allowing to use it as a proof of burn
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct BurnPayload {
     pub asset: Hash,
     pub amount: u64
 }
 
 // this enum represent all types of transaction available on PARL Network
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum TransactionType {
     Transfers(Vec<TransferPayload>),
@@ -85,7 +86,7 @@ pub enum TransactionType {
 }
 
 // Transaction to be sent over the network
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Transaction {
     /// Version of the transaction
     version: u8,
@@ -101,6 +102,7 @@ pub struct Transaction {
     /// We have one source commitment and equality proof per asset used in the tx.
     source_commitments: Vec<SourceCommitment>,
     /// The range proof is aggregated across all transfers and across all assets.
+    #[schemars(with = "Vec<u8>")]
     range_proof: RangeProof,
     /// At which block the TX is built
     reference: Reference,
@@ -105,9 +105,22 @@ pub fn format_difficulty(mut difficulty: Difficulty) -> String {
     return format!("{}{}{}", difficulty, left_str, DIFFICULTY_FORMATS[count]);
 }
 
+// Prefix of the daemon addresses pointing to a local unix socket
+pub const IPC_ADDRESS_PREFIX: &str = "ipc://";
+
+// Is the daemon address pointing to a local unix socket
+pub fn is_ipc_address(target: &str) -> bool {
+    target.starts_with(IPC_ADDRESS_PREFIX)
+}
+
 // Sanitize a daemon address to make sure it's a valid websocket address
 // By default, will use ws:// if no protocol is specified
+// IPC addresses are kept as is because the path is case sensitive
 pub fn sanitize_daemon_address(target: &str) -> String {
+    if is_ipc_address(target) {
+        return target.to_owned()
+    }
+
     let mut target = target.to_lowercase();
     if target.starts_with("https://") {
         target.replace_range(..8, "wss://");
@@ -158,6 +171,13 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sanitize_daemon_address() {
+        assert_eq!(sanitize_daemon_address("127.0.0.1:5800"), "ws://127.0.0.1:5800");
+        assert_eq!(sanitize_daemon_address("https://Node.example.com/"), "wss://node.example.com");
+        assert_eq!(sanitize_daemon_address("ipc:///run/Parl/daemon.sock"), "ipc:///run/Parl/daemon.sock");
+    }
+
     #[test]
     fn test_difficulty_format_zero() {
         let value = Difficulty::zero();
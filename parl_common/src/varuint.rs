@@ -4,6 +4,7 @@ use std::{
 };
 use log::debug;
 use primitive_types::U256;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
 use crate::serializer::{Reader, ReaderError, Serializer, Writer};
 
@@ -251,6 +252,17 @@ impl <'de> Deserialize<'de> for VarUint {
     }
 }
 
+// VarUint is serialized as a decimal string as it can be bigger than a JSON number
+impl JsonSchema for VarUint {
+    fn schema_name() -> String {
+        "VarUint".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use primitive_types::U256;
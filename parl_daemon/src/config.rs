@@ -1,6 +1,7 @@
 use lazy_static::lazy_static;
 use parl_common::{
     api::daemon::DevFeeThreshold,
+    config::MAINNET_MINIMUM_DIFFICULTY,
     crypto::{
         Address,
         Hash,
@@ -18,20 +19,15 @@ pub const NETWORK_ID: [u8; NETWORK_ID_SIZE] = [0x73, 0x6c, 0x69, 0x78, 0x65, 0x5
 // bind addresses
 pub const DEFAULT_P2P_BIND_ADDRESS: &str = "0.0.0.0:5801";
 pub const DEFAULT_RPC_BIND_ADDRESS: &str = "0.0.0.0:5800";
+// Default permissions (octal) of the RPC IPC socket, only the daemon user can connect
+pub const DEFAULT_RPC_IPC_PERMISSIONS: &str = "600";
 
 // Default cache size for storage DB
 pub const DEFAULT_CACHE_SIZE: usize = 1024;
 
 // Block rules
-// Millis per second, it is used to prevent having random 1000 values anywhere
-pub const MILLIS_PER_SECOND: u64 = 1000;
-// Block Time in milliseconds
-pub const BLOCK_TIME_MILLIS: u64 = 15 * MILLIS_PER_SECOND; // 15s block time
-// Minimum difficulty (each difficulty point is in H/s)
-// Current: BLOCK TIME in millis * 20 = 20 KH/s minimum
-// This is to prevent spamming the network with low difficulty blocks
-// This is active only on mainnet mode
-pub const MAINNET_MINIMUM_DIFFICULTY: Difficulty = Difficulty::from_u64(BLOCK_TIME_MILLIS * 20);
+// Shared with the light clients which must compute the same difficulty
+pub use parl_common::config::{MILLIS_PER_SECOND, BLOCK_TIME_MILLIS};
 // Testnet & Devnet minimum difficulty
 pub const OTHER_MINIMUM_DIFFICULTY: Difficulty = Difficulty::from_u64(BLOCK_TIME_MILLIS * 2);
 // This is also used as testnet and devnet minimum difficulty
@@ -109,6 +105,12 @@ pub const CHAIN_SYNC_DEFAULT_RESPONSE_BLOCKS: usize = 4096;
 pub const CHAIN_SYNC_RESPONSE_MAX_BLOCKS: usize = 16384;
 // send last 10 heights
 pub const CHAIN_SYNC_TOP_BLOCKS: usize = 10;
+// maximum number of objects requested ahead of the next one to apply during a sync
+pub const CHAIN_SYNC_DOWNLOAD_WINDOW: usize = 128;
+// maximum number of parallel requests sent to the same peer during a sync
+pub const CHAIN_SYNC_MAX_REQUESTS_PER_PEER: usize = 16;
+// maximum number of tries to download an object before aborting the sync
+pub const CHAIN_SYNC_MAX_ATTEMPTS: usize = 3;
 
 // P2p rules
 // time between each ping
@@ -133,12 +135,50 @@ pub const P2P_HEARTBEAT_INTERVAL: u64 = P2P_PING_DELAY / 2;
 // If we didn't receive any packet from a peer during this time, we disconnect it
 pub const P2P_PING_TIMEOUT: u64 = P2P_PING_DELAY * 6;
 
+// Dandelion++ rules
+// duration in seconds of an epoch
+// a new stem peer and mode are selected at each epoch
+pub const DANDELION_EPOCH_DURATION: u64 = 10 * 60;
+// probability in percent for an epoch to be in fluff mode
+pub const DANDELION_FLUFF_PROBABILITY: u32 = 10;
+// minimum time in seconds before we fluff a stem tx ourself
+pub const DANDELION_EMBARGO_MIN: u64 = 30;
+// random time in seconds added to the embargo of each tx
+pub const DANDELION_EMBARGO_RANDOM: u64 = 30;
+// interval in seconds between each check of the embargoes
+pub const DANDELION_EMBARGO_CHECK_INTERVAL: u64 = 1;
+
+// Compact blocks rules
+// number of seconds to wait the missing txs of a compact block
+// before requesting the full block
+pub const COMPACT_BLOCK_TIMEOUT: u64 = 5;
+// interval in seconds between each check of the pending compact blocks
+pub const COMPACT_BLOCK_CHECK_INTERVAL: u64 = 1;
+
+// Light client rules
+// maximum number of headers sent in one response
+pub const LIGHT_CLIENT_MAX_HEADERS: u16 = 64;
+// maximum number of topoheights added to the state tree
+// before releasing the storage lock
+pub const STATE_TREE_BATCH_SIZE: u64 = 64;
+// planned heights of the state commitment hard fork
+// the dev network has it enabled since its first block
+pub const MAINNET_STATE_COMMITMENT_FORK_HEIGHT: u64 = 1_200_000;
+pub const TESTNET_STATE_COMMITMENT_FORK_HEIGHT: u64 = 600_000;
+// maximum distance in topoheight between the state root committed in a block
+// and the stable topoheight of its tips
+// it stays below PRUNE_SAFETY_LIMIT so the committed roots are above the pruned topoheight
+pub const STATE_COMMITMENT_MAX_DEPTH: u64 = STABLE_LIMIT * 4;
+
 // Peer rules
 // number of seconds to reset the counter
 // Set to 30 minutes
 pub const PEER_FAIL_TIME_RESET: u64 = 30 * 60;
 // number of fail to disconnect the peer
 pub const PEER_FAIL_LIMIT: u8 = 50;
+// number of seconds before trying again the Diffie-Hellman key exchange
+// with a peer that didn't support it
+pub const PEER_LEGACY_KEY_EXCHANGE_TIME: u64 = 60 * 60;
 // number of fail during handshake before temp ban
 pub const PEER_FAIL_TO_CONNECT_LIMIT: u8 = 3;
 // number of seconds to temp ban the peer in case of fail reached
@@ -147,6 +187,24 @@ pub const PEER_TEMP_BAN_TIME: u64 = 15 * 60;
 // number of seconds to temp ban the peer in case of fail reached during handshake
 // Set to 1 minute
 pub const PEER_TEMP_BAN_TIME_ON_CONNECT: u64 = 60;
+
+// Peer scoring
+// Score of a peer is kept between these bounds
+pub const PEER_SCORE_MIN: i64 = -100;
+pub const PEER_SCORE_MAX: i64 = 100;
+// Peer is disconnected and temp banned when its score reach this value
+pub const PEER_SCORE_BAN_THRESHOLD: i64 = -50;
+// number of seconds for the score to be halved
+// Set to 1 hour
+pub const PEER_SCORE_HALF_LIFE: u64 = 60 * 60;
+// Reward for a new block delivered first
+pub const PEER_SCORE_REWARD_BLOCK: i64 = 5;
+// Reward for a new transaction delivered first
+pub const PEER_SCORE_REWARD_TX: i64 = 1;
+// We don't request objects propagated by peers below this score
+pub const PEER_SCORE_MIN_OBJECT_REQUEST: i64 = -20;
+// Peers below this score can be evicted for a better one when the peerlist is full
+pub const PEER_SCORE_EVICTION_THRESHOLD: i64 = 0;
 // millis until we timeout
 pub const PEER_TIMEOUT_REQUEST_OBJECT: u64 = 15_000;
 // millis until we timeout during a bootstrap request
@@ -236,6 +294,19 @@ pub const fn get_minimum_difficulty(network: &Network) -> Difficulty {
     }
 }
 
+// Get the height at which blocks must commit the state root in their header
+// Starting at this height, a block must use the version 1 and commit the state root
+// at a topoheight between the topoheight of the common base of its tips
+// minus STATE_COMMITMENT_MAX_DEPTH and the topoheight of this base
+// Blocks below this height must use the version 0 without any commitment
+pub const fn get_state_commitment_fork_height(network: &Network) -> u64 {
+    match network {
+        Network::Mainnet => MAINNET_STATE_COMMITMENT_FORK_HEIGHT,
+        Network::Testnet => TESTNET_STATE_COMMITMENT_FORK_HEIGHT,
+        Network::Dev => 1,
+    }
+}
+
 
 
 
@@ -5,11 +5,14 @@ use serde_json::{Value, json};
 use parl_common::{
     api::{
         daemon::{
+            AccountBalanceChangedEvent,
             BlockOrderedEvent,
             BlockOrphanedEvent,
             BlockType,
+            ChainReorganizedEvent,
             NotifyEvent,
             StableHeightChangedEvent,
+            TopoHeightRange,
             TransactionExecutedEvent,
             TransactionResponse
         },
@@ -19,6 +22,8 @@ use parl_common::{
     block::{
         Block,
         BlockHeader,
+        StateCommitment,
+        BLOCK_VERSION_STATE_COMMITMENT,
         EXTRA_NONCE_SIZE
     },
     config::{
@@ -36,6 +41,7 @@ use parl_common::{
     },
     difficulty::{check_difficulty, CumulativeDifficulty, Difficulty},
     immutable::Immutable,
+    light::StateLeaf,
     network::Network,
     serializer::Serializer,
     time::{
@@ -49,12 +55,12 @@ use parl_common::{
 };
 use crate::{
     config::{
-        get_genesis_block_hash, get_hex_genesis_block, get_minimum_difficulty,
+        get_genesis_block_hash, get_hex_genesis_block, get_minimum_difficulty, get_state_commitment_fork_height,
         BLOCK_TIME_MILLIS, CHAIN_SYNC_RESPONSE_MAX_BLOCKS, CHAIN_SYNC_RESPONSE_MIN_BLOCKS,
-        DEFAULT_CACHE_SIZE, DEFAULT_P2P_BIND_ADDRESS, DEFAULT_RPC_BIND_ADDRESS, DEV_FEES,
+        DEFAULT_CACHE_SIZE, DEFAULT_P2P_BIND_ADDRESS, DEFAULT_RPC_BIND_ADDRESS, DEFAULT_RPC_IPC_PERMISSIONS, DEV_FEES,
         DEV_PUBLIC_KEY, EMISSION_SPEED_FACTOR, GENESIS_BLOCK_DIFFICULTY, MAX_BLOCK_SIZE,
         MILLIS_PER_SECOND, P2P_DEFAULT_MAX_PEERS, SIDE_BLOCK_REWARD_MAX_BLOCKS, PRUNE_SAFETY_LIMIT,
-        SIDE_BLOCK_REWARD_PERCENT, SIDE_BLOCK_REWARD_MIN_PERCENT, STABLE_LIMIT, TIMESTAMP_IN_FUTURE_LIMIT,
+        SIDE_BLOCK_REWARD_PERCENT, SIDE_BLOCK_REWARD_MIN_PERCENT, STABLE_LIMIT, STATE_COMMITMENT_MAX_DEPTH, STATE_TREE_BATCH_SIZE, TIMESTAMP_IN_FUTURE_LIMIT,
         P2P_DEFAULT_CONCURRENCY_TASK_COUNT_LIMIT
     },
     core::{
@@ -62,19 +68,22 @@ use crate::{
         difficulty,
         error::BlockchainError,
         mempool::Mempool,
+        metrics::BlockchainMetrics,
         nonce_checker::NonceChecker,
         simulator::Simulator,
+        state_tree::{StateTreeChanges, StateTreeNode},
         storage::{DagOrderProvider, DifficultyProvider, Storage},
         tx_selector::{TxSelector, TxSelectorEntry},
         state::{ChainState, ApplicableChainState},
     },
-    p2p::P2pServer,
+    p2p::{bandwidth::BandwidthLimits, identity::parse_node_entry, P2pServer},
     rpc::{
         rpc::{
             get_block_type_for_block,
             get_block_response
         },
         DaemonRpcServer,
+        EventScope,
         SharedDaemonRpcServer
     }
 };
@@ -94,7 +103,7 @@ use std::{
     },
     time::Instant
 };
-use tokio::{sync::{Mutex, RwLock}, net::lookup_host};
+use tokio::{sync::{Mutex, Notify, RwLock}, net::lookup_host};
 use log::{info, error, debug, warn, trace};
 use rand::Rng;
 
@@ -121,10 +130,14 @@ pub struct Config {
     pub rpc_bind_address: String,
     /// Add a priority node to connect when P2p is started.
     /// A priority node is connected only one time.
+    /// 
+    /// Use the format `<identity public key>@<address>` to pin the node identity.
     #[clap(long)]
     pub priority_nodes: Vec<String>,
     /// An exclusive node is connected and its connection is maintained in case of disconnect
     /// it also replaces seed nodes.
+    /// 
+    /// Use the format `<identity public key>@<address>` to pin the node identity.
     #[clap(long)]
     pub exclusive_nodes: Vec<String>,
     /// Set dir path for blockchain storage.
@@ -138,10 +151,42 @@ pub struct Config {
     /// Disable GetWork Server (WebSocket for miners).
     #[clap(long)]
     pub disable_getwork_server: bool,
+    /// Minimum fees (in atomic units) of the transactions added in mempool
+    /// to build a new block template for the miners, long polls and NewBlockTemplate event.
+    /// 
+    /// New tips always build a new block template.
+    #[clap(long, default_value_t = 0)]
+    pub block_template_fee_threshold: u64,
     /// Disable RPC Server
     /// This will also disable the GetWork Server as it is loaded on RPC server.
     #[clap(long)]
     pub disable_rpc_server: bool,
+    /// Enable the RPC methods used to manage the node (ban_peer, unban, list_bans).
+    /// 
+    /// Don't enable it on a RPC server reachable by others.
+    #[clap(long)]
+    pub enable_rpc_admin_methods: bool,
+    /// Path to a JSON file enabling the authentication of the RPC clients.
+    /// 
+    /// It maps users (`username`/`password` or `token`) to roles, the built-in ones are
+    /// `public`, `miner` and `admin`. Custom roles can be defined with the allowed `methods` and `events`.
+    #[clap(long)]
+    pub rpc_auth_config: Option<String>,
+    /// Path to a JSON file enabling per-client rate limits on the RPC server.
+    /// 
+    /// Fields (all optional): `requests_per_second`, `burst`, `method_costs`,
+    /// `max_batch_size` and `max_ws_sessions_per_ip`.
+    #[clap(long)]
+    pub rpc_rate_limit_config: Option<String>,
+    /// Path of a unix socket serving the RPC methods and events to local clients (unix only).
+    /// 
+    /// It speaks the WebSocket JSON-RPC protocol with one message per line,
+    /// authentication and rate limits don't apply: access is given by the socket permissions.
+    #[clap(long)]
+    pub rpc_ipc_path: Option<String>,
+    /// Permissions in octal of the RPC unix socket.
+    #[clap(long, default_value_t = String::from(DEFAULT_RPC_IPC_PERMISSIONS))]
+    pub rpc_ipc_permissions: String,
     /// Enable the simulator (skip PoW verification, generate a new block for every BLOCK_TIME).
     #[clap(long)]
     pub simulator: Option<Simulator>,
@@ -192,9 +237,36 @@ pub struct Config {
     /// This is useful for seed nodes under heavy load or for nodes that don't want to connect to others.
     #[clap(long)]
     pub disable_p2p_outgoing_connections: bool,
+    /// Disable the Dandelion++ propagation of transactions.
+    /// 
+    /// Transactions submitted to this node are then broadcasted directly to all peers,
+    /// which can reveal that they come from this node.
+    #[clap(long)]
+    pub disable_dandelion: bool,
     /// Limit of concurrent tasks accepting new incoming connections.
     #[clap(long, default_value_t = P2P_DEFAULT_CONCURRENCY_TASK_COUNT_LIMIT)]
-    pub p2p_concurrency_task_count_limit: usize
+    pub p2p_concurrency_task_count_limit: usize,
+    /// Route all outgoing P2P connections through a SOCKS5 proxy.
+    /// 
+    /// Format is socks5://[username:password@]host:port
+    /// 
+    /// IP sharing is disabled automatically when a proxy is set.
+    #[clap(long)]
+    pub p2p_proxy: Option<String>,
+    /// Maximum P2P upload rate for all peers in KiB/s.
+    /// 
+    /// Block propagation is never delayed but is counted in the limit.
+    #[clap(long)]
+    pub p2p_max_upload_rate: Option<u64>,
+    /// Maximum P2P download rate for all peers in KiB/s.
+    #[clap(long)]
+    pub p2p_max_download_rate: Option<u64>,
+    /// Maximum P2P upload rate for each peer in KiB/s.
+    #[clap(long)]
+    pub p2p_max_peer_upload_rate: Option<u64>,
+    /// Maximum P2P download rate for each peer in KiB/s.
+    #[clap(long)]
+    pub p2p_max_peer_download_rate: Option<u64>
 }
 
 pub struct Blockchain<S: Storage> {
@@ -232,7 +304,21 @@ pub struct Blockchain<S: Storage> {
     // using base hash, current tip hash and base height, this cache is used to store the DAG order
     full_order_cache: Mutex<LruCache<(Hash, Hash, u64), IndexSet<Hash>>>,
     // auto prune mode if enabled, will delete all blocks every N and keep only N top blocks (topoheight based)
-    auto_prune_keep_n_blocks: Option<u64>
+    auto_prune_keep_n_blocks: Option<u64>,
+    // counters and latencies exposed on the RPC server
+    metrics: BlockchainMetrics,
+    // wake up the task computing the state roots of the new stable topoheights
+    state_tree_notify: Arc<Notify>
+}
+
+// State roots built but not saved yet
+struct StateRootsBatch {
+    // state root topoheight when the batch was built
+    base_topoheight: Option<u64>,
+    // topoheight, block hash and state root
+    roots: Vec<(u64, Hash, Hash)>,
+    // new nodes of the state tree
+    nodes: HashMap<Hash, StateTreeNode>
 }
 
 impl<S: Storage> Blockchain<S> {
@@ -294,7 +380,9 @@ impl<S: Storage> Blockchain<S> {
             tip_base_cache: Mutex::new(LruCache::new(NonZeroUsize::new(1024).unwrap())),
             tip_work_score_cache: Mutex::new(LruCache::new(NonZeroUsize::new(1024).unwrap())),
             full_order_cache: Mutex::new(LruCache::new(NonZeroUsize::new(1024).unwrap())),
-            auto_prune_keep_n_blocks: config.auto_prune_keep_n_blocks
+            auto_prune_keep_n_blocks: config.auto_prune_keep_n_blocks,
+            metrics: BlockchainMetrics::default(),
+            state_tree_notify: Arc::new(Notify::new())
         };
 
         // include genesis block
@@ -324,9 +412,20 @@ impl<S: Storage> Blockchain<S> {
         // create P2P Server
         if !config.disable_p2p_server {
             info!("Starting P2p server...");
+            // identity keys pinned for exclusive and priority nodes
+            let mut pinned_identities: HashMap<SocketAddr, PublicKey> = HashMap::new();
+
             // setup exclusive nodes
             let mut exclusive_nodes: Vec<SocketAddr> = Vec::with_capacity(config.exclusive_nodes.len());
             for peer in config.exclusive_nodes {
+                let (identity, peer) = match parse_node_entry(&peer) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        error!("Error while parsing exclusive node: {}", e);
+                        continue;
+                    }
+                };
+
                 let addr: SocketAddr = match peer.parse() {
                     Ok(addr) => addr,
                     Err(e) => {
@@ -334,31 +433,61 @@ impl<S: Storage> Blockchain<S> {
                         continue;
                     }
                 };
+
+                if let Some(identity) = identity {
+                    pinned_identities.insert(addr, identity);
+                }
                 exclusive_nodes.push(addr);
             }
 
-            match P2pServer::new(config.p2p_concurrency_task_count_limit, config.dir_path, config.tag, config.max_peers, config.p2p_bind_address, Arc::clone(&arc), exclusive_nodes.is_empty(), exclusive_nodes, config.allow_fast_sync, config.allow_boost_sync, config.max_chain_response_size, !config.disable_ip_sharing, config.disable_p2p_outgoing_connections) {
-                Ok(p2p) => {
-                    // connect to priority nodes
-                    for addr in config.priority_nodes {
-                        let addr: SocketAddr = match addr.parse() {
-                            Ok(addr) => addr,
-                            Err(e) => {
-                                match lookup_host(&addr).await {
-                                    Ok(it) => {
-                                        info!("Valid host found for {}", addr);
-                                        for addr in it {
-                                            info!("Trying to connect to priority node with IP from DNS resolution: {}", addr);
-                                            p2p.try_to_connect_to_peer(addr, true).await;
-                                        }
-                                    },
-                                    Err(e2) => {
-                                        error!("Error while parsing priority node address: {}, {}", e, e2);
-                                    }
-                                };
+            // resolve priority nodes
+            let mut priority_nodes: Vec<SocketAddr> = Vec::with_capacity(config.priority_nodes.len());
+            for peer in config.priority_nodes {
+                let (identity, peer) = match parse_node_entry(&peer) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        error!("Error while parsing priority node: {}", e);
+                        continue;
+                    }
+                };
+
+                let addrs: Vec<SocketAddr> = match peer.parse() {
+                    Ok(addr) => vec![addr],
+                    Err(e) => {
+                        match lookup_host(peer).await {
+                            Ok(it) => {
+                                info!("Valid host found for {}", peer);
+                                it.collect()
+                            },
+                            Err(e2) => {
+                                error!("Error while parsing priority node address: {}, {}", e, e2);
                                 continue;
                             }
-                        };
+                        }
+                    }
+                };
+
+                for addr in addrs {
+                    if let Some(identity) = &identity {
+                        pinned_identities.insert(addr, identity.clone());
+                    }
+                    priority_nodes.push(addr);
+                }
+            }
+
+            // Limits are configured in KiB/s
+            let to_bytes = |rate: Option<u64>| rate.map(|rate| rate.saturating_mul(1024));
+            let bandwidth_limits = BandwidthLimits {
+                global_upload: to_bytes(config.p2p_max_upload_rate),
+                global_download: to_bytes(config.p2p_max_download_rate),
+                peer_upload: to_bytes(config.p2p_max_peer_upload_rate),
+                peer_download: to_bytes(config.p2p_max_peer_download_rate)
+            };
+
+            match P2pServer::new(config.p2p_concurrency_task_count_limit, config.dir_path, config.tag, config.max_peers, config.p2p_bind_address, Arc::clone(&arc), exclusive_nodes.is_empty(), exclusive_nodes, pinned_identities, config.allow_fast_sync, config.allow_boost_sync, config.max_chain_response_size, !config.disable_ip_sharing, config.disable_p2p_outgoing_connections, config.p2p_proxy, bandwidth_limits, config.disable_dandelion) {
+                Ok(p2p) => {
+                    // connect to priority nodes
+                    for addr in priority_nodes {
                         info!("Trying to connect to priority node: {}", addr);
                         p2p.try_to_connect_to_peer(addr, true).await;
                     }
@@ -371,12 +500,34 @@ impl<S: Storage> Blockchain<S> {
         // create RPC Server
         if !config.disable_rpc_server {
             info!("RPC Server will listen on: {}", config.rpc_bind_address);
-            match DaemonRpcServer::new(config.rpc_bind_address, Arc::clone(&arc), config.disable_getwork_server).await {
+            match DaemonRpcServer::new(config.rpc_bind_address, Arc::clone(&arc), config.disable_getwork_server, config.block_template_fee_threshold, config.enable_rpc_admin_methods, config.rpc_auth_config, config.rpc_rate_limit_config, config.rpc_ipc_path, config.rpc_ipc_permissions).await {
                 Ok(server) => *arc.rpc.write().await = Some(server),
                 Err(e) => error!("Error while starting RPC server: {}", e)
             };
         }
 
+        // Compute the state roots each time the stable topoheight changes
+        // The task stops with the blockchain
+        {
+            let blockchain = Arc::downgrade(&arc);
+            let notify = Arc::clone(&arc.state_tree_notify);
+            // Build the roots missing since the last run
+            notify.notify_one();
+            spawn_task("state-tree", async move {
+                loop {
+                    notify.notified().await;
+                    let blockchain = match blockchain.upgrade() {
+                        Some(blockchain) => blockchain,
+                        None => break
+                    };
+
+                    if let Err(e) = blockchain.update_state_roots().await {
+                        error!("Error while building the state roots: {}", e);
+                    }
+                }
+            });
+        }
+
         // Start the simulator task if necessary
         if let Some(simulator) = arc.simulator {
             warn!("Simulator {} mode enabled!", simulator);
@@ -451,6 +602,7 @@ impl<S: Storage> Blockchain<S> {
         // Research stable topoheight also
         let stable_topoheight = storage.get_topo_height_for_hash(&stable_hash).await?;
         self.stable_topoheight.store(stable_topoheight, Ordering::SeqCst);
+        self.state_tree_notify.notify_one();
 
         // Recompute the difficulty with new tips
         let (difficulty, _) = self.get_difficulty_at_tips(&*storage, tips.iter()).await?;
@@ -569,6 +721,9 @@ impl<S: Storage> Blockchain<S> {
         debug!("Located sync topoheight found: {}", located_sync_topoheight);
 
         if located_sync_topoheight > last_pruned_topoheight {
+            // build the state roots before deleting the balances and nonces they are made of
+            self.update_state_roots_for_storage(storage, located_sync_topoheight.min(self.get_stable_topoheight())).await?;
+
             // create snapshots of balances to located_sync_topoheight
             storage.create_snapshot_balances_at_topoheight(located_sync_topoheight).await?;
             storage.create_snapshot_nonces_at_topoheight(located_sync_topoheight).await?;
@@ -1257,6 +1412,11 @@ impl<S: Storage> Blockchain<S> {
         &self.storage
     }
 
+    // Returns the counters and latencies of the blockchain
+    pub fn get_metrics(&self) -> &BlockchainMetrics {
+        &self.metrics
+    }
+
     // Returns the blockchain mempool used
     pub fn get_mempool(&self) -> &RwLock<Mempool> {
         &self.mempool
@@ -1274,9 +1434,52 @@ impl<S: Storage> Blockchain<S> {
         self.add_tx_to_mempool_with_storage_and_hash(&*storage, Arc::new(tx), hash, broadcast).await
     }
 
+    // Add a tx in the Dandelion++ stem phase to the mempool
+    // It is relayed to a single peer if broadcast is set, and kept out of the gossip,
+    // the RPC events and the miners until it's fluffed
+    // Without the P2P server or Dandelion++, it is added directly in the fluff phase
+    pub async fn add_stem_tx_to_mempool_with_hash(&self, tx: Transaction, hash: Hash, broadcast: bool) -> Result<(), BlockchainError> {
+        let stem = {
+            let p2p = self.p2p.read().await;
+            is_stem_phase_available(p2p.is_some(), p2p.as_ref().is_some_and(|p2p| p2p.is_dandelion_enabled()))
+        };
+        if !stem {
+            debug!("No Dandelion++ relay available, adding tx {} in the fluff phase", hash);
+        }
+
+        let storage = self.storage.read().await;
+        self.add_tx_to_mempool_internal(&*storage, Arc::new(tx), hash, broadcast, stem).await
+    }
+
+    // Move a tx from the Dandelion++ stem phase to the fluff phase
+    // Returns true if the tx was in stem phase, it must then be broadcasted to our peers
+    pub async fn fluff_tx(&self, hash: &Hash) -> bool {
+        let tx = {
+            let mut mempool = self.mempool.write().await;
+            if !mempool.fluff_tx(hash) {
+                return false
+            }
+
+            match mempool.get_tx(hash) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    debug!("Fluffed tx {} not found in mempool: {}", hash, e);
+                    return true
+                }
+            }
+        };
+
+        self.notify_tx_added_in_mempool(&tx, hash).await;
+        true
+    }
+
     // Add a tx to the mempool with the given hash, it will verify the TX and check that it is not already in mempool or in blockchain
     // and its validity (nonce, balance, etc...)
     pub async fn add_tx_to_mempool_with_storage_and_hash<'a>(&'a self, storage: &S, tx: Arc<Transaction>, hash: Hash, broadcast: bool) -> Result<(), BlockchainError> {
+        self.add_tx_to_mempool_internal(storage, tx, hash, broadcast, false).await
+    }
+
+    async fn add_tx_to_mempool_internal(&self, storage: &S, tx: Arc<Transaction>, hash: Hash, broadcast: bool, stem: bool) -> Result<(), BlockchainError> {
         let tx_size = tx.size();
         if tx_size > MAX_TRANSACTION_SIZE {
             return Err(BlockchainError::TxTooBig(tx_size, MAX_TRANSACTION_SIZE))
@@ -1311,7 +1514,9 @@ impl<S: Storage> Blockchain<S> {
                 }
             }
 
-            mempool.add_tx(storage, current_topoheight, hash.clone(), tx.clone(), tx_size).await?;
+            let start = Instant::now();
+            mempool.add_tx(storage, current_topoheight, hash.clone(), tx.clone(), tx_size, stem).await?;
+            self.metrics.tx_verification.observe(start.elapsed());
         }
 
         if broadcast {
@@ -1320,49 +1525,202 @@ impl<S: Storage> Blockchain<S> {
                 let p2p = p2p.clone();
                 let hash = hash.clone();
                 spawn_task("tx-notify-p2p", async move {
-                    p2p.broadcast_tx_hash(hash).await;
+                    if stem {
+                        p2p.stem_tx(hash, None).await;
+                    } else {
+                        p2p.broadcast_tx_hash(hash).await;
+                    }
                 });
             }
 
-            // broadcast to websocket this tx
-            if let Some(rpc) = self.rpc.read().await.as_ref() {
-                // Notify miners if getwork is enabled
-                if let Some(getwork) = rpc.getwork_server() {
-                    let getwork = getwork.clone();
-                    spawn_task("tx-notify-new-job", async move {
-                        if let Err(e) = getwork.notify_new_job_rate_limited().await {
-                            debug!("Error while notifying miners for new tx: {}", e);
-                        }
-                    });
+            // Stem txs are notified once fluffed
+            if !stem {
+                self.notify_tx_added_in_mempool(&tx, &hash).await;
+            }
+        }
+        
+        Ok(())
+    }
+
+    // Notify the miners and the websocket clients of a new public tx in mempool
+    async fn notify_tx_added_in_mempool(&self, tx: &Arc<Transaction>, hash: &Hash) {
+        // broadcast to websocket this tx
+        if let Some(rpc) = self.rpc.read().await.as_ref() {
+            // Notify miners if getwork is enabled
+            if let Some(getwork) = rpc.getwork_server() {
+                let getwork = getwork.clone();
+                let fee = tx.get_fee();
+                spawn_task("tx-notify-new-job", async move {
+                    if let Err(e) = getwork.notify_new_job_rate_limited(fee).await {
+                        debug!("Error while notifying miners for new tx: {}", e);
+                    }
+                });
+            }
+
+            if rpc.is_event_tracked(&NotifyEvent::TransactionAddedInMempool).await {
+                let data = RPCTransaction::from_tx(tx, hash, self.network.is_mainnet());
+                let data: TransactionResponse<'_> = TransactionResponse {
+                    blocks: None,
+                    executed_in_block: None,
+                    in_mempool: true,
+                    first_seen: Some(get_current_time_in_seconds()),
+                    data,
+                };
+                let json = json!(data);
+                let scope = EventScope::from_transaction(tx);
+
+                let rpc = rpc.clone();
+                spawn_task("rpc-notify-tx", async move {
+                    if let Err(e) = rpc.notify_clients_in_scope(&NotifyEvent::TransactionAddedInMempool, json, &scope).await {
+                        debug!("Error while broadcasting event TransactionAddedInMempool to websocket: {}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    // Build the state roots after the current state root topoheight until the requested one
+    // At most STATE_TREE_BATCH_SIZE topoheights are built, returns None if there is nothing to build
+    async fn build_state_roots(&self, storage: &S, until: u64) -> Result<Option<StateRootsBatch>, BlockchainError> {
+        let base_topoheight = storage.get_state_root_topoheight().await?;
+        let (start, mut root) = match base_topoheight {
+            Some(topoheight) => (topoheight + 1, storage.get_balances_merkle_hash_at_topoheight(topoheight).await?),
+            None => {
+                // A pruned chain without state tree doesn't have the leaves to build it
+                if storage.get_pruned_topoheight().await?.is_some() {
+                    trace!("Chain is pruned, state roots can't be built");
+                    return Ok(None)
                 }
+                (0, Hash::zero())
+            }
+        };
 
-                if rpc.is_event_tracked(&NotifyEvent::TransactionAddedInMempool).await {
-                    let data = RPCTransaction::from_tx(&tx, &hash, storage.is_mainnet());
-                    let data: TransactionResponse<'_> = TransactionResponse {
-                        blocks: None,
-                        executed_in_block: None,
-                        in_mempool: true,
-                        first_seen: Some(get_current_time_in_seconds()),
-                        data,
-                    };
-                    let json = json!(data);
+        if start > until {
+            return Ok(None)
+        }
 
-                    let rpc = rpc.clone();
-                    spawn_task("rpc-notify-tx", async move {
-                        if let Err(e) = rpc.notify_clients(&NotifyEvent::TransactionAddedInMempool, json).await {
-                            debug!("Error while broadcasting event TransactionAddedInMempool to websocket: {}", e);
-                        }
-                    });
+        let end = until.min(start + STATE_TREE_BATCH_SIZE - 1);
+        debug!("Building state roots from topoheight {} to {}", start, end);
+        let mut changes = StateTreeChanges::new(storage);
+        let mut roots = Vec::with_capacity((end - start + 1) as usize);
+        for topoheight in start..=end {
+            // Only the last version of each balance and nonce is in the tree
+            for leaf in storage.get_state_leaves_at_topoheight(topoheight).await? {
+                root = changes.insert(&root, leaf.get_path(), leaf.get_value_hash(topoheight)).await?;
+            }
+            let hash = storage.get_hash_at_topo_height(topoheight).await?;
+            roots.push((topoheight, hash, root.clone()));
+        }
+
+        Ok(Some(StateRootsBatch {
+            base_topoheight,
+            roots,
+            nodes: changes.consume()
+        }))
+    }
+
+    // Save the state roots built if the chain didn't change in the meantime
+    // Returns false if the batch was discarded
+    async fn save_state_roots(&self, storage: &mut S, batch: StateRootsBatch) -> Result<bool, BlockchainError> {
+        if storage.get_state_root_topoheight().await? != batch.base_topoheight {
+            return Ok(false)
+        }
+
+        for (topoheight, hash, _) in batch.roots.iter() {
+            if storage.get_hash_at_topo_height(*topoheight).await.ok().as_ref() != Some(hash) {
+                return Ok(false)
+            }
+        }
+
+        for (hash, node) in batch.nodes.iter() {
+            storage.set_state_tree_node(hash, node).await?;
+        }
+
+        let mut last_topoheight = None;
+        for (topoheight, _, root) in batch.roots {
+            storage.set_balances_merkle_hash_at_topoheight(topoheight, &root).await?;
+            last_topoheight = Some(topoheight);
+        }
+
+        if let Some(topoheight) = last_topoheight {
+            storage.set_state_root_topoheight(topoheight).await?;
+        }
+
+        Ok(true)
+    }
+
+    // Build the state roots of the new stable topoheights
+    // Each batch is built under the storage read lock and only saving it requires the write lock
+    async fn update_state_roots(&self) -> Result<(), BlockchainError> {
+        loop {
+            let batch = {
+                let storage = self.storage.read().await;
+                match self.build_state_roots(&storage, self.get_stable_topoheight()).await? {
+                    Some(batch) => batch,
+                    None => return Ok(())
                 }
+            };
+
+            let mut storage = self.storage.write().await;
+            if !self.save_state_roots(&mut storage, batch).await? {
+                debug!("Chain changed while building the state roots, building them again");
             }
         }
-        
+    }
+
+    // Build the state roots until the requested topoheight using the requested storage
+    // This is used when the background task is late
+    async fn update_state_roots_for_storage(&self, storage: &mut S, until: u64) -> Result<(), BlockchainError> {
+        while let Some(batch) = self.build_state_roots(storage, until).await? {
+            self.save_state_roots(storage, batch).await?;
+        }
         Ok(())
     }
 
-    // this will be used in future for hard fork versions
-    pub fn get_version_at_height(&self, _height: u64) -> u8 {
-        0
+    // Get the range of topoheights a block built on these tips can commit
+    // It only depends on the tips: the upper bound is the topoheight of their common base,
+    // which is stable for this block and can't be reordered anymore
+    async fn get_state_commitment_range(&self, storage: &S, tips: &IndexSet<Hash>) -> Result<(u64, u64), BlockchainError> {
+        let (base_hash, _) = self.find_common_base(storage, tips).await?;
+        let base_topoheight = storage.get_topo_height_for_hash(&base_hash).await?;
+        Ok((base_topoheight.saturating_sub(STATE_COMMITMENT_MAX_DEPTH), base_topoheight))
+    }
+
+    // Verify the state root committed in a block header
+    // The committed topoheight must be in the stable range of the block tips
+    // A node that doesn't have the state root rejects the block instead of skipping the verification
+    async fn verify_state_commitment(&self, storage: &mut S, block_hash: &Hash, tips: &IndexSet<Hash>, commitment: &StateCommitment) -> Result<(), BlockchainError> {
+        let topoheight = commitment.get_topoheight();
+        let (min_topoheight, max_topoheight) = self.get_state_commitment_range(storage, tips).await?;
+        if topoheight < min_topoheight || topoheight > max_topoheight {
+            debug!("Block {} commits the state at topoheight {} but expected a topoheight between {} and {}", block_hash, topoheight, min_topoheight, max_topoheight);
+            return Err(BlockchainError::InvalidStateCommitment(block_hash.clone(), topoheight))
+        }
+
+        self.update_state_roots_for_storage(storage, topoheight).await?;
+        match storage.get_balances_merkle_hash_at_topoheight(topoheight).await {
+            Ok(root) => if root != *commitment.get_root() {
+                debug!("Block {} commits an invalid state root at topoheight {}", block_hash, topoheight);
+                return Err(BlockchainError::InvalidBalancesMerkleHash(block_hash.clone(), root, commitment.get_root().clone()))
+            },
+            // State roots aren't available below the topoheight we bootstrapped from
+            Err(BlockchainError::NotFoundOnDisk(_)) => {
+                warn!("No state root at topoheight {}, can't verify the commitment of block {}", topoheight, block_hash);
+                return Err(BlockchainError::StateRootNotAvailable(topoheight))
+            },
+            Err(e) => return Err(e)
+        };
+
+        Ok(())
+    }
+
+    // Get the block version expected at this height
+    pub fn get_version_at_height(&self, height: u64) -> u8 {
+        if height >= get_state_commitment_fork_height(&self.network) {
+            BLOCK_VERSION_STATE_COMMITMENT
+        } else {
+            0
+        }
     }
 
     // Get a block template for the new block work (mining)
@@ -1458,7 +1816,19 @@ impl<S: Storage> Blockchain<S> {
         }
 
         let height = blockdag::calculate_height_at_tips(storage, sorted_tips.iter()).await?;
-        let block = BlockHeader::new(self.get_version_at_height(height), height, get_current_time_in_millis(), sorted_tips, extra_nonce, address, IndexSet::new());
+        let version = self.get_version_at_height(height);
+        let mut block = BlockHeader::new(version, height, get_current_time_in_millis(), sorted_tips, extra_nonce, address, IndexSet::new());
+
+        // Commit the last state root built in the stable range of the tips
+        if version >= BLOCK_VERSION_STATE_COMMITMENT {
+            let (min_topoheight, max_topoheight) = self.get_state_commitment_range(storage, block.get_tips()).await?;
+            let topoheight = storage.get_state_root_topoheight().await?
+                .map(|topoheight| topoheight.min(max_topoheight))
+                .filter(|topoheight| *topoheight >= min_topoheight)
+                .ok_or(BlockchainError::NoStateRoot)?;
+            let root = storage.get_balances_merkle_hash_at_topoheight(topoheight).await?;
+            block.set_state_commitment(StateCommitment::new(topoheight, root));
+        }
 
         Ok(block)
     }
@@ -1477,14 +1847,17 @@ impl<S: Storage> Blockchain<S> {
         let caches = mempool.get_caches();
         let mut entries: Vec<Vec<TxSelectorEntry>> = Vec::with_capacity(caches.len());
         for cache in caches.values() {
-            let cache_txs = cache.get_txs();
-            let mut txs = Vec::with_capacity(cache_txs.len());
+            let mut txs = Vec::with_capacity(cache.get_txs().len());
             // Map every tx hash to a TxSelectorEntry
-            for tx_hash in cache_txs.iter() {
+            // Stem txs are kept out of the miners until they are fluffed
+            for tx_hash in cache.get_minable_txs(|hash| mempool.is_stem_tx(hash)) {
                 let sorted_tx = mempool.get_sorted_tx(tx_hash)?;
                 txs.push(TxSelectorEntry { size: sorted_tx.get_size(), hash: tx_hash, tx: sorted_tx.get_tx() });
             }
-            entries.push(txs);
+
+            if !txs.is_empty() {
+                entries.push(txs);
+            }
         }
 
         // Build the tx selector using the mempool
@@ -1566,6 +1939,11 @@ impl<S: Storage> Blockchain<S> {
             return Err(BlockchainError::InvalidBlockVersion)
         }
 
+        // Only the blocks from this version commit a state root
+        if (block.get_version() >= BLOCK_VERSION_STATE_COMMITMENT) != block.get_state_commitment().is_some() {
+            return Err(BlockchainError::InvalidBlockVersion)
+        }
+
         let block_hash = block.hash();
         debug!("Add new block {}", block_hash);
         if storage.has_block_with_hash(&block_hash).await? {
@@ -1667,6 +2045,10 @@ impl<S: Storage> Blockchain<S> {
             }
         }
 
+        if let Some(commitment) = block.get_state_commitment() {
+            self.verify_state_commitment(storage, &block_hash, block.get_tips(), commitment).await?;
+        }
+
         // verify PoW and get difficulty for this block based on tips
         let skip_pow = self.skip_pow_verification();
         let pow_hash = if skip_pow {
@@ -1810,10 +2192,16 @@ impl<S: Storage> Blockchain<S> {
         };
 
         // track all events to notify websocket
-        let mut events: HashMap<NotifyEvent, Vec<Value>> = HashMap::new();
+        // events of transactions, blocks and balances have a scope to filter the subscriptions
+        let mut events: HashMap<NotifyEvent, Vec<(Value, Option<EventScope>)>> = HashMap::new();
         // Track all orphaned tranasctions
         let mut orphaned_transactions = HashSet::new();
 
+        // Blocks removed from the DAG order and ordered again, to detect a reorganization
+        let track_reorganization = should_track_events.contains(&NotifyEvent::ChainReorganized);
+        let mut reorganization = DagReorganization::default();
+        let mut txs_back_to_mempool = Vec::new();
+
         // order the DAG (up to TOP_HEIGHT - STABLE_LIMIT)
         let mut highest_topo = 0;
         // Tells if the new block added is ordered in DAG or not
@@ -1850,13 +2238,20 @@ impl<S: Storage> Blockchain<S> {
 
                     // Block may be orphaned if its not in the new full order set
                     let is_orphaned = !full_order.contains(&hash_at_topo);
+                    if is_orphaned {
+                        self.metrics.orphaned_blocks.inc();
+                    }
+
+                    if track_reorganization {
+                        reorganization.remove_block(topoheight, &hash_at_topo, is_orphaned);
+                    }
                     // Notify if necessary that we have a block orphaned
                     if is_orphaned && should_track_events.contains(&NotifyEvent::BlockOrphaned) {
                         let value = json!(BlockOrphanedEvent {
                             block_hash: Cow::Borrowed(&hash_at_topo),
                             old_topoheight: topoheight,
                         });
-                        events.entry(NotifyEvent::BlockOrphaned).or_insert_with(Vec::new).push(value);
+                        events.entry(NotifyEvent::BlockOrphaned).or_insert_with(Vec::new).push((value, None));
                     }
 
                     // mark txs as unexecuted if it was executed in this block
@@ -1898,6 +2293,9 @@ impl<S: Storage> Blockchain<S> {
                 is_written = true;
 
                 trace!("Ordering block {} at topoheight {}", hash, highest_topo);
+                if track_reorganization {
+                    reorganization.order_block(&hash);
+                }
 
                 storage.set_topo_height_for_block(&hash, highest_topo).await?;
                 let past_supply = if highest_topo == 0 {
@@ -1996,7 +2394,7 @@ impl<S: Storage> Blockchain<S> {
                                 block_hash: Cow::Borrowed(&hash),
                                 topoheight: highest_topo,
                             });
-                            events.entry(NotifyEvent::TransactionExecuted).or_insert_with(Vec::new).push(value);
+                            events.entry(NotifyEvent::TransactionExecuted).or_insert_with(Vec::new).push((value, Some(EventScope::from_transaction(tx))));
                         }
 
                         // Increase total tx fees for miner
@@ -2019,13 +2417,33 @@ impl<S: Storage> Blockchain<S> {
                 // apply changes from Chain State
                 chain_state.apply_changes().await?;
 
+                // Each balance written is a new version for its account
+                // The state root is built once the topoheight is stable
+                if should_track_events.contains(&NotifyEvent::AccountBalanceChanged) {
+                    let leaves = storage.get_state_leaves_at_topoheight(highest_topo).await?;
+                    for leaf in leaves.iter() {
+                        if let StateLeaf::Balance { key, asset, version } = leaf {
+                            let value = json!(AccountBalanceChangedEvent {
+                                address: Cow::Owned(key.clone().to_address(self.network.is_mainnet())),
+                                asset: Cow::Borrowed(asset),
+                                topoheight: highest_topo,
+                                version: Cow::Borrowed(version)
+                            });
+                            let mut scope = EventScope::new();
+                            scope.add_key(key.clone());
+                            scope.add_asset(asset.clone());
+                            events.entry(NotifyEvent::AccountBalanceChanged).or_insert_with(Vec::new).push((value, Some(scope)));
+                        }
+                    }
+                }
+
                 if should_track_events.contains(&NotifyEvent::BlockOrdered) {
                     let value = json!(BlockOrderedEvent {
                         block_hash: Cow::Borrowed(&hash),
                         block_type: get_block_type_for_block(self, &storage, &hash).await.unwrap_or(BlockType::Normal),
                         topoheight: highest_topo,
                     });
-                    events.entry(NotifyEvent::BlockOrdered).or_insert_with(Vec::new).push(value);
+                    events.entry(NotifyEvent::BlockOrdered).or_insert_with(Vec::new).push((value, None));
                 }
             }
         }
@@ -2113,7 +2531,7 @@ impl<S: Storage> Blockchain<S> {
                         previous_stable_height,
                         new_stable_height: stable_height
                     });
-                    events.entry(NotifyEvent::StableHeightChanged).or_insert_with(Vec::new).push(value);
+                    events.entry(NotifyEvent::StableHeightChanged).or_insert_with(Vec::new).push((value, None));
                 }
             }
 
@@ -2122,6 +2540,7 @@ impl<S: Storage> Blockchain<S> {
             // Search the topoheight of the stable block
             let stable_topoheight = storage.get_topo_height_for_hash(&stable_hash).await?;
             self.stable_topoheight.store(stable_topoheight, Ordering::SeqCst);
+            self.state_tree_notify.notify_one();
 
             trace!("update difficulty in cache");
             let (difficulty, _) = self.get_difficulty_at_tips(storage, tips.iter()).await?;
@@ -2162,7 +2581,8 @@ impl<S: Storage> Blockchain<S> {
                     first_seen: Some(sorted_tx.get_first_seen()),
                     data,
                 };
-                events.entry(NotifyEvent::TransactionOrphaned).or_insert_with(Vec::new).push(json!(data));
+                let scope = EventScope::from_transaction(&sorted_tx.get_tx());
+                events.entry(NotifyEvent::TransactionOrphaned).or_insert_with(Vec::new).push((json!(data), Some(scope)));
             }
         }
 
@@ -2182,8 +2602,10 @@ impl<S: Storage> Blockchain<S> {
 
                 // Clone only if its necessary
                 if !orphan_event_tracked {
-                    if let Err(e) = self.add_tx_to_mempool_with_storage_and_hash(&storage, tx, tx_hash, false).await {
+                    if let Err(e) = self.add_tx_to_mempool_with_storage_and_hash(&storage, tx, tx_hash.clone(), false).await {
                         warn!("Error while adding back orphaned tx: {}", e);
+                    } else if track_reorganization {
+                        txs_back_to_mempool.push(tx_hash);
                     }
                 } else {
                     if let Err(e) = self.add_tx_to_mempool_with_storage_and_hash(&storage, tx.clone(), tx_hash.clone(), false).await {
@@ -2197,12 +2619,25 @@ impl<S: Storage> Blockchain<S> {
                             first_seen: None,
                             data,
                         };
-                        events.entry(NotifyEvent::TransactionOrphaned).or_insert_with(Vec::new).push(json!(data));
+                        let scope = EventScope::from_transaction(&tx);
+                        events.entry(NotifyEvent::TransactionOrphaned).or_insert_with(Vec::new).push((json!(data), Some(scope)));
+                    } else if track_reorganization {
+                        txs_back_to_mempool.push(tx_hash);
                     }
                 }
             }
         }
 
+        // The chain is reorganized only if a block previously ordered is not at the same topoheight
+        let chain_reorganized = match reorganization.get_common_base_topoheight() {
+            Some(common_base_topoheight) => {
+                let common_base_hash = storage.get_hash_at_topo_height(common_base_topoheight).await?;
+                reorganization.build_event(common_base_hash, txs_back_to_mempool).map(|event| json!(event))
+            },
+            None => None
+        };
+
+        self.metrics.block_verification.observe(start.elapsed());
         info!("Processed block {} at height {} in {}ms with {} txs (DAG: {})", block_hash, block.get_height(), start.elapsed().as_millis(), block.get_txs_count(), block_is_ordered);
 
         // Broadcast to p2p nodes
@@ -2215,7 +2650,7 @@ impl<S: Storage> Blockchain<S> {
                 let block = block.clone();
                 let block_hash = block_hash.clone();
                 spawn_task("broadcast-block", async move {
-                    p2p.broadcast_block(&block, cumulative_difficulty, current_topoheight, current_height, pruned_topoheight, &block_hash, mining).await;
+                    p2p.broadcast_block(&block, &txs, cumulative_difficulty, current_topoheight, current_height, pruned_topoheight, &block_hash, mining).await;
                 });
             }
         }
@@ -2237,9 +2672,15 @@ impl<S: Storage> Blockchain<S> {
             // atm, we always notify websocket clients
             trace!("Notifying websocket clients");
             if should_track_events.contains(&NotifyEvent::NewBlock) {
+                let mut scope = EventScope::new();
+                scope.add_key(block.get_miner().clone());
+                for tx in txs.iter() {
+                    scope.add_transaction(tx);
+                }
+
                 match get_block_response(self, storage, &block_hash, &Block::new(Immutable::Arc(block), txs), block_size).await {
                     Ok(response) => {
-                        events.entry(NotifyEvent::NewBlock).or_insert_with(Vec::new).push(response);
+                        events.entry(NotifyEvent::NewBlock).or_insert_with(Vec::new).push((response, Some(scope)));
                     },
                     Err(e) => {
                         debug!("Error while getting block response for websocket: {}", e);
@@ -2251,12 +2692,24 @@ impl<S: Storage> Blockchain<S> {
             // don't block mutex/lock more than necessary, we move it in another task
             spawn_task("rpc-notify-events", async move {
                 for (event, values) in events {
-                    for value in values {
-                        if let Err(e) = rpc.notify_clients(&event, value).await {
+                    for (value, scope) in values {
+                        let res = match scope {
+                            Some(scope) => rpc.notify_clients_in_scope(&event, value, &scope).await,
+                            None => rpc.notify_clients(&event, value).await
+                        };
+
+                        if let Err(e) = res {
                             debug!("Error while broadcasting event to websocket: {}", e);
                         }
                     }
                 }
+
+                // sent last so clients already received the orphaned blocks and txs
+                if let Some(value) = chain_reorganized {
+                    if let Err(e) = rpc.notify_clients(&NotifyEvent::ChainReorganized, value).await {
+                        debug!("Error while broadcasting event ChainReorganized to websocket: {}", e);
+                    }
+                }
             });
         }
 
@@ -2419,16 +2872,40 @@ impl<S: Storage> Blockchain<S> {
         } else {
             0
         };
-        let (new_height, new_topoheight, txs) = storage.pop_blocks(current_height, current_topoheight, count, until).await?;
+        let (new_height, new_topoheight, blocks, txs) = storage.pop_blocks(current_height, current_topoheight, count, until).await?;
         debug!("New topoheight: {} (diff: {})", new_topoheight, current_topoheight - new_topoheight);
 
         // Try to add all txs back to mempool if possible
         // We try to prevent lost/to be orphaned
+        let mut txs_back_to_mempool = Vec::new();
         {
             for (hash, tx) in txs {
                 debug!("Trying to add TX {} to mempool again", hash);
-                if let Err(e) = self.add_tx_to_mempool_with_storage_and_hash(storage, tx, hash, false).await {
+                if let Err(e) = self.add_tx_to_mempool_with_storage_and_hash(storage, tx, hash.clone(), false).await {
                     debug!("TX rewinded is not compatible anymore: {}", e);
+                } else {
+                    txs_back_to_mempool.push(hash);
+                }
+            }
+        }
+
+        // Notify the blocks removed from the chain
+        if !blocks.is_empty() {
+            if let Some(rpc) = self.rpc.read().await.as_ref() {
+                if rpc.is_event_tracked(&NotifyEvent::ChainReorganized).await {
+                    // blocks are already deleted, the rewind must not fail because of the event
+                    match storage.get_hash_at_topo_height(new_topoheight).await {
+                        Ok(common_base_hash) => {
+                            let event = json!(build_rewind_event(common_base_hash, new_topoheight, current_topoheight, blocks, txs_back_to_mempool));
+                            let rpc = rpc.clone();
+                            spawn_task("rpc-notify-chain-reorganized", async move {
+                                if let Err(e) = rpc.notify_clients(&NotifyEvent::ChainReorganized, event).await {
+                                    debug!("Error while broadcasting event ChainReorganized to websocket: {}", e);
+                                }
+                            });
+                        },
+                        Err(e) => warn!("Couldn't notify the chain reorganization, no block found at topoheight {}: {}", new_topoheight, e)
+                    };
                 }
             }
         }
@@ -2462,6 +2939,7 @@ impl<S: Storage> Blockchain<S> {
             self.stable_height.store(stable_height, Ordering::SeqCst);
             let stable_topoheight = storage.get_topo_height_for_hash(&stable_hash).await?;
             self.stable_topoheight.store(stable_topoheight, Ordering::SeqCst);
+            self.state_tree_notify.notify_one();
         }
 
         Ok(new_topoheight)
@@ -2565,6 +3043,100 @@ pub fn get_block_dev_fee(height: u64) -> u64 {
     percentage
 }
 
+// Verify if a tx can start in the Dandelion++ stem phase
+// A peer must relay it, otherwise nobody would fluff it and it would never be mined
+fn is_stem_phase_available(p2p_enabled: bool, dandelion_enabled: bool) -> bool {
+    p2p_enabled && dandelion_enabled
+}
+
+// Blocks removed from the DAG order and ordered again while adding a block
+// It is used to build the ChainReorganized event
+#[derive(Default)]
+struct DagReorganization {
+    // topoheight of the first block removed from the DAG order
+    start: u64,
+    // blocks removed from the DAG order, by topoheight
+    old_order: Vec<Hash>,
+    // blocks ordered again from the start topoheight
+    new_order: Vec<Hash>,
+    // removed blocks that are not in the new DAG order
+    orphaned_blocks: Vec<Hash>
+}
+
+impl DagReorganization {
+    // Track a block removed from the DAG order
+    // Blocks must be removed by ascending topoheight
+    fn remove_block(&mut self, topoheight: u64, hash: &Hash, orphaned: bool) {
+        if self.old_order.is_empty() {
+            self.start = topoheight;
+        }
+        self.old_order.push(hash.clone());
+        if orphaned {
+            self.orphaned_blocks.push(hash.clone());
+        }
+    }
+
+    // Track a block ordered in the DAG
+    // Only the blocks ordered after a removed one are part of the reorganization
+    fn order_block(&mut self, hash: &Hash) {
+        if !self.old_order.is_empty() {
+            self.new_order.push(hash.clone());
+        }
+    }
+
+    // Index in the old order of the first block that is not at the same topoheight anymore
+    fn get_reorganization_index(&self) -> Option<usize> {
+        self.old_order.iter().enumerate().position(|(i, hash)| self.new_order.get(i) != Some(hash))
+    }
+
+    // Topoheight of the highest block which stayed at the same topoheight
+    // Returns None if every block removed was ordered again at the same topoheight
+    fn get_common_base_topoheight(&self) -> Option<u64> {
+        // genesis block always stays at topoheight 0
+        self.get_reorganization_index().map(|index| (self.start + index as u64).saturating_sub(1))
+    }
+
+    // Build the ChainReorganized event, None if the chain was not reorganized
+    fn build_event(mut self, common_base_hash: Hash, txs_back_to_mempool: Vec<Hash>) -> Option<ChainReorganizedEvent<'static>> {
+        let index = self.get_reorganization_index()?;
+        let start = self.start + index as u64;
+        let ordered_blocks = self.new_order.split_off(index);
+        Some(ChainReorganizedEvent {
+            common_base_hash: Cow::Owned(common_base_hash),
+            common_base_topoheight: start.saturating_sub(1),
+            old_range: TopoHeightRange {
+                start,
+                end: self.start + self.old_order.len() as u64 - 1
+            },
+            new_range: (!ordered_blocks.is_empty()).then(|| TopoHeightRange {
+                start,
+                end: start + ordered_blocks.len() as u64 - 1
+            }),
+            orphaned_blocks: self.orphaned_blocks,
+            ordered_blocks,
+            txs_back_to_mempool
+        })
+    }
+}
+
+// Build the ChainReorganized event of a rewind
+// Blocks are deleted from the highest topoheight
+fn build_rewind_event(common_base_hash: Hash, new_topoheight: u64, previous_topoheight: u64, mut deleted_blocks: Vec<Hash>, txs_back_to_mempool: Vec<Hash>) -> ChainReorganizedEvent<'static> {
+    deleted_blocks.reverse();
+    ChainReorganizedEvent {
+        common_base_hash: Cow::Owned(common_base_hash),
+        common_base_topoheight: new_topoheight,
+        old_range: TopoHeightRange {
+            start: new_topoheight + 1,
+            end: previous_topoheight
+        },
+        new_range: None,
+        orphaned_blocks: deleted_blocks,
+        ordered_blocks: Vec::new(),
+        txs_back_to_mempool
+    }
+}
+
 // Compute the combined merkle root of the tips
 // pub async fn build_merkle_tips_hash<'a, S: DifficultyProvider, I: Iterator<Item = &'a Hash> + ExactSizeIterator>(storage: &S, sorted_tips: I) -> Result<Hash, BlockchainError> {
 //     let mut merkles = Vec::with_capacity(sorted_tips.len());
@@ -2606,4 +3178,107 @@ mod tests {
         assert_eq!(get_block_dev_fee(DEV_FEES[1].height), 5);
         assert_eq!(get_block_dev_fee(DEV_FEES[1].height + 1), 5);
     }
+
+    #[test]
+    fn test_stem_phase_requires_a_relay() {
+        // Submitted txs are added in the fluff phase without P2P server
+        assert!(!is_stem_phase_available(false, false));
+        assert!(!is_stem_phase_available(false, true));
+        // or when Dandelion++ is disabled
+        assert!(!is_stem_phase_available(true, false));
+        assert!(is_stem_phase_available(true, true));
+    }
+
+    fn hashes(values: &[u8]) -> Vec<Hash> {
+        values.iter().map(|value| Hash::new([*value; HASH_SIZE])).collect()
+    }
+
+    #[test]
+    fn test_reorganization_same_order() {
+        let blocks = hashes(&[1, 2, 3]);
+        let mut reorganization = DagReorganization::default();
+        for (i, hash) in blocks.iter().enumerate() {
+            reorganization.remove_block(10 + i as u64, hash, false);
+        }
+        // A new block is ordered after the same blocks
+        for hash in blocks.iter().chain(hashes(&[4]).iter()) {
+            reorganization.order_block(hash);
+        }
+
+        assert_eq!(reorganization.get_common_base_topoheight(), None);
+        assert!(reorganization.build_event(Hash::zero(), Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_reorganization_reordered_blocks() {
+        let blocks = hashes(&[1, 2, 3]);
+        let mut reorganization = DagReorganization::default();
+        for (i, hash) in blocks.iter().enumerate() {
+            reorganization.remove_block(10 + i as u64, hash, false);
+        }
+        // Block 4 is ordered before blocks 2 and 3
+        for hash in hashes(&[1, 4, 2, 3]).iter() {
+            reorganization.order_block(hash);
+        }
+
+        // Block at topoheight 10 stays the same
+        assert_eq!(reorganization.get_common_base_topoheight(), Some(10));
+        let event = reorganization.build_event(blocks[0].clone(), Vec::new()).unwrap();
+        assert_eq!(event.common_base_hash.as_ref(), &blocks[0]);
+        assert_eq!(event.common_base_topoheight, 10);
+        assert_eq!((event.old_range.start, event.old_range.end), (11, 12));
+        let new_range = event.new_range.unwrap();
+        assert_eq!((new_range.start, new_range.end), (11, 13));
+        assert!(event.orphaned_blocks.is_empty());
+        assert_eq!(event.ordered_blocks, hashes(&[4, 2, 3]));
+    }
+
+    #[test]
+    fn test_reorganization_orphaned_blocks() {
+        let mut reorganization = DagReorganization::default();
+        reorganization.remove_block(5, &hashes(&[1])[0], true);
+        reorganization.remove_block(6, &hashes(&[2])[0], false);
+        // Blocks ordered before any removal are not part of it
+        let mut ignored = DagReorganization::default();
+        ignored.order_block(&hashes(&[9])[0]);
+        assert!(ignored.new_order.is_empty());
+
+        for hash in hashes(&[2, 3]).iter() {
+            reorganization.order_block(hash);
+        }
+
+        assert_eq!(reorganization.get_common_base_topoheight(), Some(4));
+        let event = reorganization.build_event(Hash::zero(), hashes(&[7])).unwrap();
+        assert_eq!((event.old_range.start, event.old_range.end), (5, 6));
+        let new_range = event.new_range.unwrap();
+        assert_eq!((new_range.start, new_range.end), (5, 6));
+        assert_eq!(event.orphaned_blocks, hashes(&[1]));
+        assert_eq!(event.ordered_blocks, hashes(&[2, 3]));
+        assert_eq!(event.txs_back_to_mempool, hashes(&[7]));
+    }
+
+    #[test]
+    fn test_reorganization_without_new_order() {
+        let mut reorganization = DagReorganization::default();
+        reorganization.remove_block(0, &hashes(&[1])[0], true);
+
+        // Genesis block is the common base
+        assert_eq!(reorganization.get_common_base_topoheight(), Some(0));
+        let event = reorganization.build_event(Hash::zero(), Vec::new()).unwrap();
+        assert_eq!(event.common_base_topoheight, 0);
+        assert!(event.new_range.is_none());
+        assert!(event.ordered_blocks.is_empty());
+    }
+
+    #[test]
+    fn test_rewind_event() {
+        // Blocks at topoheights 8, 7 and 6 were deleted
+        let event = build_rewind_event(hashes(&[5])[0].clone(), 5, 8, hashes(&[8, 7, 6]), hashes(&[1]));
+        assert_eq!(event.common_base_topoheight, 5);
+        assert_eq!((event.old_range.start, event.old_range.end), (6, 8));
+        assert!(event.new_range.is_none());
+        assert_eq!(event.orphaned_blocks, hashes(&[6, 7, 8]));
+        assert!(event.ordered_blocks.is_empty());
+        assert_eq!(event.txs_back_to_mempool, hashes(&[1]));
+    }
 }
@@ -62,6 +62,8 @@ pub enum DiskContext {
     EstimatedCovarianceForBlockHash,
     #[error("get balances merkle hash at topoheight")]
     BalancesMerkleHashAtTopoHeight,
+    #[error("get state tree node")]
+    StateTreeNode,
     #[error("get last topoheight for nonce")]
     LastTopoheightForNonce,
     #[error("get last nonce")]
@@ -89,6 +91,8 @@ pub enum DiskContext {
     TopTopoHeight,
     #[error("get top height")]
     TopHeight,
+    #[error("get state root topoheight")]
+    StateRootTopoHeight,
     // Default
     #[error("delete data")]
     DeleteData,
@@ -309,6 +313,14 @@ pub enum BlockchainError {
     TransactionProof(ProofVerificationError),
     #[error("Error while generating pow hash")]
     POWHashError(#[from] XelisHashError),
+    #[error("Block {} commits a state root at topoheight {} which is outside of the stable range of its tips", _0, _1)]
+    InvalidStateCommitment(Hash, u64),
+    #[error("No state root at topoheight {}, state commitments can't be verified below it", _0)]
+    StateRootNotAvailable(u64),
+    #[error("No state root is available to build a block template")]
+    NoStateRoot,
+    #[error("State tree is corrupted")]
+    CorruptedStateTree,
 }
 
 impl BlockchainError {
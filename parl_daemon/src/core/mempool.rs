@@ -28,7 +28,10 @@ use parl_common::{
 pub struct SortedTx {
     tx: Arc<Transaction>,
     first_seen: TimestampSeconds, // timestamp when the tx was added
-    size: usize
+    size: usize,
+    // TX is in the Dandelion++ stem phase and must not be gossiped
+    #[serde(skip)]
+    stem: bool
 }
 
 // This struct is used to keep nonce cache for a specific key for faster verification
@@ -69,7 +72,7 @@ impl Mempool {
     }
 
     // All checks are made in Blockchain before calling this function
-    pub async fn add_tx<S: Storage>(&mut self, storage: &S, topoheight: u64, hash: Hash, tx: Arc<Transaction>, size: usize) -> Result<(), BlockchainError> {
+    pub async fn add_tx<S: Storage>(&mut self, storage: &S, topoheight: u64, hash: Hash, tx: Arc<Transaction>, size: usize, stem: bool) -> Result<(), BlockchainError> {
         let mut state = MempoolState::new(&self, storage, topoheight);
         tx.verify(&mut state).await?;
 
@@ -128,7 +131,8 @@ impl Mempool {
         let sorted_tx = SortedTx {
             size,
             first_seen: get_current_time_in_seconds(),
-            tx
+            tx,
+            stem
         };
 
         // insert in map
@@ -208,6 +212,23 @@ impl Mempool {
         self.txs.contains_key(hash)
     }
 
+    // Verify if a TX is still in the Dandelion++ stem phase
+    pub fn is_stem_tx(&self, hash: &Hash) -> bool {
+        self.txs.get(hash).map_or(false, |tx| tx.stem)
+    }
+
+    // Move a TX from the stem phase to the fluff phase
+    // Returns true if the TX was in stem phase
+    pub fn fluff_tx(&mut self, hash: &Hash) -> bool {
+        match self.txs.get_mut(hash) {
+            Some(tx) if tx.stem => {
+                tx.stem = false;
+                true
+            },
+            _ => false
+        }
+    }
+
     // Get a sorted TX from its hash
     // This is useful to get its size along the TX and its first seen
     pub fn get_sorted_tx(&self, hash: &Hash) -> Result<&SortedTx, BlockchainError> {
@@ -465,6 +486,10 @@ impl SortedTx {
         self.first_seen
     }
 
+    pub fn is_stem(&self) -> bool {
+        self.stem
+    }
+
     pub fn consume(self) -> Arc<Transaction> {
         self.tx
     }
@@ -492,6 +517,15 @@ impl AccountCache {
         &self.txs
     }
 
+    // Get the txs hashes that can be mined, ordered by nonce
+    // A tx in the Dandelion++ stem phase stops them as the next nonces depend on it
+    pub fn get_minable_txs<'a, F>(&'a self, is_stem: F) -> impl Iterator<Item = &'a Arc<Hash>>
+    where
+        F: Fn(&Hash) -> bool + 'a
+    {
+        self.txs.iter().take_while(move |hash| !is_stem(hash))
+    }
+
     // Update balances cache
     fn set_balances(&mut self, balances: HashMap<Hash, Ciphertext>) {
         self.balances = balances;
@@ -532,3 +566,33 @@ impl AccountCache {
         self.txs.get_index(index)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_with_txs(count: u8) -> AccountCache {
+        let txs: IndexSet<Arc<Hash>> = (0..count).map(|i| Arc::new(Hash::new([i; 32]))).collect();
+        AccountCache {
+            min: 0,
+            max: count as u64 - 1,
+            txs,
+            balances: HashMap::new()
+        }
+    }
+
+    #[test]
+    fn test_minable_txs_skip_stem_txs() {
+        let cache = cache_with_txs(4);
+        let stem = Hash::new([2; 32]);
+
+        // The tx at nonce 2 is in stem phase, the one at nonce 3 depends on it
+        let txs: Vec<_> = cache.get_minable_txs(|hash| *hash == stem).collect();
+        assert_eq!(txs, cache.get_txs().iter().take(2).collect::<Vec<_>>());
+
+        // All the txs are minable once fluffed
+        assert_eq!(cache.get_minable_txs(|_| false).count(), 4);
+        // Nothing is minable if the first one is still in stem phase
+        assert_eq!(cache.get_minable_txs(|_| true).count(), 0);
+    }
+}
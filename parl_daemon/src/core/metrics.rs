@@ -0,0 +1,12 @@
+use parl_common::metrics::{Counter, Histogram};
+
+// Metrics updated by the blockchain, exposed on the RPC server
+#[derive(Default)]
+pub struct BlockchainMetrics {
+    // blocks removed from the DAG order
+    pub orphaned_blocks: Counter,
+    // time to verify and add a new block
+    pub block_verification: Histogram,
+    // time to verify and add a tx in mempool
+    pub tx_verification: Histogram
+}
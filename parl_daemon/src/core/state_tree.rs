@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use parl_common::{
+    crypto::Hash,
+    light::{get_path_bit, hash_merkle_pair, hash_state_tree_leaf, MerkleProof},
+    serializer::{Reader, ReaderError, Serializer, Writer}
+};
+use super::{error::BlockchainError, storage::MerkleHashProvider};
+
+const LEAF_NODE_ID: u8 = 0;
+const BRANCH_NODE_ID: u8 = 1;
+
+// Number of bits in a path
+const PATH_BITS: usize = 256;
+
+// Node of the state tree, stored by its hash
+// The state tree is a sparse merkle tree indexed by the path of the state leaves
+// A subtree with a single leaf is replaced by it and an empty subtree is a zero hash
+// Nodes are never deleted, so a root stays valid once the chain moved on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateTreeNode {
+    Leaf {
+        path: Hash,
+        value: Hash
+    },
+    // Subtree with at least two leaves
+    Branch {
+        left: Hash,
+        right: Hash
+    }
+}
+
+impl StateTreeNode {
+    pub fn hash(&self) -> Hash {
+        match self {
+            Self::Leaf { path, value } => hash_state_tree_leaf(path, value),
+            Self::Branch { left, right } => hash_merkle_pair(left, right)
+        }
+    }
+
+    // Build the branch at this depth with the node on the side of the path
+    fn branch(path: &Hash, depth: usize, node: Hash, sibling: Hash) -> Self {
+        if get_path_bit(path, depth) {
+            Self::Branch { left: sibling, right: node }
+        } else {
+            Self::Branch { left: node, right: sibling }
+        }
+    }
+}
+
+impl Serializer for StateTreeNode {
+    fn write(&self, writer: &mut Writer) {
+        match self {
+            Self::Leaf { path, value } => {
+                writer.write_u8(LEAF_NODE_ID);
+                writer.write_hash(path);
+                writer.write_hash(value);
+            },
+            Self::Branch { left, right } => {
+                writer.write_u8(BRANCH_NODE_ID);
+                writer.write_hash(left);
+                writer.write_hash(right);
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        Ok(match reader.read_u8()? {
+            LEAF_NODE_ID => Self::Leaf {
+                path: reader.read_hash()?,
+                value: reader.read_hash()?
+            },
+            BRANCH_NODE_ID => Self::Branch {
+                left: reader.read_hash()?,
+                right: reader.read_hash()?
+            },
+            _ => return Err(ReaderError::InvalidValue)
+        })
+    }
+
+    fn size(&self) -> usize {
+        1 + 64
+    }
+}
+
+// Changes applied on the state tree that are not stored yet
+// This allows to compute the roots without holding the storage write lock
+pub struct StateTreeChanges<'a, P: MerkleHashProvider + Sync + ?Sized> {
+    provider: &'a P,
+    nodes: HashMap<Hash, StateTreeNode>
+}
+
+impl<'a, P: MerkleHashProvider + Sync + ?Sized> StateTreeChanges<'a, P> {
+    pub fn new(provider: &'a P) -> Self {
+        Self {
+            provider,
+            nodes: HashMap::new()
+        }
+    }
+
+    async fn get_node(&self, hash: &Hash) -> Result<StateTreeNode, BlockchainError> {
+        if let Some(node) = self.nodes.get(hash) {
+            return Ok(node.clone())
+        }
+
+        self.provider.get_state_tree_node(hash).await
+    }
+
+    fn add_node(&mut self, node: StateTreeNode) -> Hash {
+        let hash = node.hash();
+        self.nodes.insert(hash.clone(), node);
+        hash
+    }
+
+    // Set the value of the leaf at this path
+    // Returns the new root of the tree
+    pub async fn insert(&mut self, root: &Hash, path: Hash, value: Hash) -> Result<Hash, BlockchainError> {
+        // Siblings of the branches from the root to the leaf
+        let mut siblings = Vec::new();
+        let mut current = root.clone();
+        let node = loop {
+            if current == Hash::zero() {
+                break self.add_node(StateTreeNode::Leaf { path: path.clone(), value })
+            }
+
+            match self.get_node(&current).await? {
+                StateTreeNode::Branch { left, right } => {
+                    if get_path_bit(&path, siblings.len()) {
+                        siblings.push(left);
+                        current = right;
+                    } else {
+                        siblings.push(right);
+                        current = left;
+                    }
+                },
+                StateTreeNode::Leaf { path: leaf_path, .. } if leaf_path == path => {
+                    break self.add_node(StateTreeNode::Leaf { path: path.clone(), value })
+                },
+                StateTreeNode::Leaf { path: leaf_path, .. } => {
+                    // Both leaves go down until their paths diverge
+                    let depth = siblings.len();
+                    let split = (depth..PATH_BITS).find(|i| get_path_bit(&path, *i) != get_path_bit(&leaf_path, *i))
+                        .ok_or(BlockchainError::CorruptedStateTree)?;
+
+                    let leaf = self.add_node(StateTreeNode::Leaf { path: path.clone(), value });
+                    let mut node = self.add_node(StateTreeNode::branch(&path, split, leaf, current));
+                    for i in (depth..split).rev() {
+                        node = self.add_node(StateTreeNode::branch(&path, i, node, Hash::zero()));
+                    }
+                    break node
+                }
+            }
+        };
+
+        // Rebuild the branches up to the root
+        let mut current = node;
+        for (depth, sibling) in siblings.into_iter().enumerate().rev() {
+            current = self.add_node(StateTreeNode::branch(&path, depth, current, sibling));
+        }
+
+        Ok(current)
+    }
+
+    // Get all the nodes created to store them
+    pub fn consume(self) -> HashMap<Hash, StateTreeNode> {
+        self.nodes
+    }
+}
+
+// Build the proof of the leaf at this path
+// Returns its value and the proof, or None if the path has no leaf
+pub async fn build_state_tree_proof<P>(provider: &P, root: &Hash, path: &Hash) -> Result<Option<(Hash, MerkleProof)>, BlockchainError>
+where
+    P: MerkleHashProvider + Sync + ?Sized
+{
+    let mut siblings = Vec::new();
+    let mut current = root.clone();
+    while current != Hash::zero() {
+        match provider.get_state_tree_node(&current).await? {
+            StateTreeNode::Branch { left, right } => {
+                if get_path_bit(path, siblings.len()) {
+                    siblings.push(left);
+                    current = right;
+                } else {
+                    siblings.push(right);
+                    current = left;
+                }
+            },
+            StateTreeNode::Leaf { path: leaf_path, value } => {
+                if leaf_path != *path {
+                    break
+                }
+                return Ok(Some((value, MerkleProof::new(siblings))))
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use async_trait::async_trait;
+    use parl_common::{crypto::hash, light::StateLeaf};
+    use crate::core::error::DiskContext;
+    use super::*;
+
+    // Only the state tree nodes are needed by the tree
+    #[derive(Default)]
+    struct MemoryProvider {
+        nodes: Mutex<HashMap<Hash, StateTreeNode>>
+    }
+
+    impl MemoryProvider {
+        fn apply(&self, nodes: HashMap<Hash, StateTreeNode>) {
+            self.nodes.lock().unwrap().extend(nodes);
+        }
+    }
+
+    #[async_trait]
+    impl MerkleHashProvider for MemoryProvider {
+        async fn get_balances_merkle_hash_at_topoheight(&self, _: u64) -> Result<Hash, BlockchainError> {
+            Err(BlockchainError::UnsupportedOperation)
+        }
+
+        async fn set_balances_merkle_hash_at_topoheight(&mut self, _: u64, _: &Hash) -> Result<(), BlockchainError> {
+            Err(BlockchainError::UnsupportedOperation)
+        }
+
+        async fn get_state_leaves_at_topoheight(&self, _: u64) -> Result<Vec<StateLeaf>, BlockchainError> {
+            Err(BlockchainError::UnsupportedOperation)
+        }
+
+        async fn get_state_tree_node(&self, hash: &Hash) -> Result<StateTreeNode, BlockchainError> {
+            self.nodes.lock().unwrap().get(hash).cloned().ok_or(BlockchainError::NotFoundOnDisk(DiskContext::StateTreeNode))
+        }
+
+        async fn set_state_tree_node(&mut self, _: &Hash, _: &StateTreeNode) -> Result<(), BlockchainError> {
+            Err(BlockchainError::UnsupportedOperation)
+        }
+
+        async fn get_state_root_topoheight(&self) -> Result<Option<u64>, BlockchainError> {
+            Err(BlockchainError::UnsupportedOperation)
+        }
+
+        async fn set_state_root_topoheight(&mut self, _: u64) -> Result<(), BlockchainError> {
+            Err(BlockchainError::UnsupportedOperation)
+        }
+    }
+
+    async fn insert(provider: &MemoryProvider, root: &Hash, path: &Hash, value: &Hash) -> Hash {
+        let mut changes = StateTreeChanges::new(provider);
+        let root = changes.insert(root, path.clone(), value.clone()).await.unwrap();
+        provider.apply(changes.consume());
+        root
+    }
+
+    async fn assert_proof(provider: &MemoryProvider, root: &Hash, path: &Hash, value: &Hash) {
+        let (found, proof) = build_state_tree_proof(provider, root, path).await.unwrap().unwrap();
+        assert_eq!(found, *value);
+        assert_eq!(proof.compute_root(path, &hash_state_tree_leaf(path, value)), *root);
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_prove() {
+        let provider = MemoryProvider::default();
+        let paths: Vec<Hash> = (0..32u8).map(|i| hash(&[i])).collect();
+
+        let mut root = Hash::zero();
+        for (i, path) in paths.iter().enumerate() {
+            root = insert(&provider, &root, path, &hash(&[i as u8, 0])).await;
+            // All the leaves inserted so far are proven
+            for (j, path) in paths.iter().enumerate().take(i + 1) {
+                assert_proof(&provider, &root, path, &hash(&[j as u8, 0])).await;
+            }
+        }
+
+        // A path without leaf has no proof
+        assert!(build_state_tree_proof(&provider, &root, &hash(b"missing")).await.unwrap().is_none());
+        assert!(build_state_tree_proof(&provider, &Hash::zero(), &paths[0]).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_root_is_independent_of_the_history() {
+        let provider = MemoryProvider::default();
+        let (a, b, c) = (hash(b"a"), hash(b"b"), hash(b"c"));
+
+        let mut first = Hash::zero();
+        for path in [&a, &b, &c] {
+            first = insert(&provider, &first, path, &hash(b"old")).await;
+        }
+        // Only the last value of a leaf is in the tree
+        let updated = insert(&provider, &first, &b, &hash(b"new")).await;
+        assert!(updated != first);
+        assert_proof(&provider, &updated, &b, &hash(b"new")).await;
+
+        let mut second = Hash::zero();
+        for (path, value) in [(&c, b"old"), (&b, b"new"), (&a, b"old")] {
+            second = insert(&provider, &second, path, &hash(value)).await;
+        }
+        assert_eq!(updated, second);
+
+        // The previous root is still valid
+        assert_proof(&provider, &first, &b, &hash(b"old")).await;
+    }
+
+    #[tokio::test]
+    async fn test_paths_with_a_common_prefix() {
+        let provider = MemoryProvider::default();
+        let mut bytes = [0u8; 32];
+        let a = Hash::new(bytes);
+        bytes[31] = 1;
+        let b = Hash::new(bytes);
+
+        let root = insert(&provider, &Hash::zero(), &a, &hash(b"a")).await;
+        // A single leaf is the root
+        assert_eq!(root, hash_state_tree_leaf(&a, &hash(b"a")));
+
+        let root = insert(&provider, &root, &b, &hash(b"b")).await;
+        let (_, proof) = build_state_tree_proof(&provider, &root, &b).await.unwrap().unwrap();
+        assert_eq!(proof.get_siblings().len(), PATH_BITS);
+        assert_proof(&provider, &root, &a, &hash(b"a")).await;
+        assert_proof(&provider, &root, &b, &hash(b"b")).await;
+    }
+}
@@ -75,7 +75,8 @@ pub trait Storage: BlockExecutionOrderProvider + DagOrderProvider + PrunedTopohe
     fn set_network(&mut self, network: &Network) -> Result<(), BlockchainError>;
 
     // Count is the number of blocks (topoheight) to rewind
-    async fn pop_blocks(&mut self, mut height: u64, mut topoheight: u64, count: u64, stable_height: u64) -> Result<(u64, u64, Vec<(Hash, Arc<Transaction>)>), BlockchainError>;
+    // Returns the new height and topoheight, the deleted blocks and their txs
+    async fn pop_blocks(&mut self, mut height: u64, mut topoheight: u64, count: u64, stable_height: u64) -> Result<(u64, u64, Vec<Hash>, Vec<(Hash, Arc<Transaction>)>), BlockchainError>;
 
     // Get the top block hash of the chain
     async fn get_top_block_hash(&self) -> Result<Hash, BlockchainError>;
@@ -1,10 +1,19 @@
 use async_trait::async_trait;
 use log::trace;
-use parl_common::{crypto::Hash, serializer::Serializer};
-use crate::core::{error::{BlockchainError, DiskContext}, storage::SledStorage};
+use parl_common::{
+    account::{VersionedBalance, VersionedNonce},
+    crypto::{Hash, PublicKey},
+    light::StateLeaf,
+    serializer::Serializer
+};
+use crate::core::{
+    error::{BlockchainError, DiskContext},
+    state_tree::StateTreeNode,
+    storage::{sled::STATE_ROOT_TOPOHEIGHT, SledStorage}
+};
 
 // Merkle Hash provider allow to give a Hash at a specific topoheight
-// The merkle hash only contains account balances
+// The merkle hash contains the account balances and nonces written at this topoheight
 // Because TXs and block rewards are applied on account balances
 // Balances are the only thing that needs to be proven
 // NOTE: We are based on the topoheight because of DAG reorgs as it's the main consensus
@@ -15,6 +24,22 @@ pub trait MerkleHashProvider {
 
     // Set the merkle hash at a specific topoheight
     async fn set_balances_merkle_hash_at_topoheight(&mut self, topoheight: u64, merkle_proof: &Hash) -> Result<(), BlockchainError>;
+
+    // Get all the versioned balances then nonces written at a specific topoheight
+    // They are the leaves inserted in the state tree at this topoheight
+    async fn get_state_leaves_at_topoheight(&self, topoheight: u64) -> Result<Vec<StateLeaf>, BlockchainError>;
+
+    // Get a node of the state tree using its hash
+    async fn get_state_tree_node(&self, hash: &Hash) -> Result<StateTreeNode, BlockchainError>;
+
+    // Store a node of the state tree using its hash
+    async fn set_state_tree_node(&mut self, hash: &Hash, node: &StateTreeNode) -> Result<(), BlockchainError>;
+
+    // Get the highest topoheight having its state root computed
+    async fn get_state_root_topoheight(&self) -> Result<Option<u64>, BlockchainError>;
+
+    // Set the highest topoheight having its state root computed
+    async fn set_state_root_topoheight(&mut self, topoheight: u64) -> Result<(), BlockchainError>;
 }
 
 #[async_trait]
@@ -29,4 +54,54 @@ impl MerkleHashProvider for SledStorage {
         self.merkle_hashes.insert(&topoheight.to_bytes(), merkle_proof.as_bytes())?;
         Ok(())
     }
+
+    async fn get_state_leaves_at_topoheight(&self, topoheight: u64) -> Result<Vec<StateLeaf>, BlockchainError> {
+        trace!("get state leaves at topoheight {}", topoheight);
+        let mut leaves = Vec::new();
+        // Keys are prefixed by the topoheight, and sled iterate them in order
+        for el in self.versioned_balances.scan_prefix(&topoheight.to_be_bytes()) {
+            let (key, value) = el?;
+            leaves.push(StateLeaf::Balance {
+                key: PublicKey::from_bytes(&key[8..40])?,
+                asset: Hash::from_bytes(&key[40..72])?,
+                version: VersionedBalance::from_bytes(&value)?
+            });
+        }
+
+        for el in self.versioned_nonces.scan_prefix(&topoheight.to_be_bytes()) {
+            let (key, value) = el?;
+            leaves.push(StateLeaf::Nonce {
+                key: PublicKey::from_bytes(&key[8..40])?,
+                version: VersionedNonce::from_bytes(&value)?
+            });
+        }
+
+        Ok(leaves)
+    }
+
+    async fn get_state_tree_node(&self, hash: &Hash) -> Result<StateTreeNode, BlockchainError> {
+        trace!("get state tree node {}", hash);
+        self.load_from_disk(&self.state_tree_nodes, hash.as_bytes(), DiskContext::StateTreeNode)
+    }
+
+    async fn set_state_tree_node(&mut self, hash: &Hash, node: &StateTreeNode) -> Result<(), BlockchainError> {
+        trace!("set state tree node {}", hash);
+        self.state_tree_nodes.insert(hash.as_bytes(), node.to_bytes())?;
+        Ok(())
+    }
+
+    async fn get_state_root_topoheight(&self) -> Result<Option<u64>, BlockchainError> {
+        trace!("get state root topoheight");
+        match self.load_from_disk(&self.extra, STATE_ROOT_TOPOHEIGHT, DiskContext::StateRootTopoHeight) {
+            Ok(topoheight) => Ok(Some(topoheight)),
+            Err(BlockchainError::NotFoundOnDisk(_)) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    async fn set_state_root_topoheight(&mut self, topoheight: u64) -> Result<(), BlockchainError> {
+        trace!("set state root topoheight to {}", topoheight);
+        self.extra.insert(STATE_ROOT_TOPOHEIGHT, &topoheight.to_be_bytes())?;
+        Ok(())
+    }
 }
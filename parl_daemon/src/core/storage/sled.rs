@@ -31,6 +31,7 @@ use super::{
     BlocksAtHeightProvider,
     DagOrderProvider,
     DifficultyProvider,
+    MerkleHashProvider,
     NonceProvider,
     PrunedTopoheightProvider,
     ClientProtocolProvider,
@@ -46,6 +47,7 @@ const TOP_TOPO_HEIGHT: &[u8; 4] = b"TOPO";
 const TOP_HEIGHT: &[u8; 4] = b"TOPH";
 const NETWORK: &[u8] = b"NET";
 pub(super) const PRUNED_TOPOHEIGHT: &[u8; 4] = b"PRUN";
+pub(super) const STATE_ROOT_TOPOHEIGHT: &[u8; 4] = b"STRT";
 // Counters (prevent to perform a O(n))
 pub(super) const ACCOUNTS_COUNT: &[u8; 4] = b"CACC";
 pub(super) const TXS_COUNT: &[u8; 4] = b"CTXS";
@@ -97,6 +99,8 @@ pub struct SledStorage {
     pub(super) versioned_balances: Tree,
     // Tree that store all merkle hashes for each topoheight
     pub(super) merkle_hashes: Tree,
+    // Tree that store all state tree nodes using their hash
+    pub(super) state_tree_nodes: Tree,
     // Account registrations topoheight
     pub(super) registrations: Tree,
     // Account registrations prefixed by their topoheight for easier deletion
@@ -176,6 +180,7 @@ impl SledStorage {
             balances: sled.open_tree("balances")?,
             versioned_balances: sled.open_tree("versioned_balances")?,
             merkle_hashes: sled.open_tree("merkle_hashes")?,
+            state_tree_nodes: sled.open_tree("state_tree_nodes")?,
             registrations: sled.open_tree("registrations")?,
             registrations_prefixed: sled.open_tree("registrations_prefixed")?,
             db: sled,
@@ -475,6 +480,9 @@ impl Storage for SledStorage {
         let cumulative_difficulty: CumulativeDifficulty = self.delete_cacheable_data(&self.cumulative_difficulty, &self.cumulative_difficulty_cache, &hash).await?;
         trace!("Cumulative difficulty deleted: {}", cumulative_difficulty);
 
+        trace!("Deleting balances merkle hash");
+        self.merkle_hashes.remove(&topoheight.to_bytes())?;
+
         let mut txs = Vec::new();
         for tx_hash in block.get_transactions() {
             if self.has_tx_blocks(tx_hash)? {
@@ -784,7 +792,7 @@ impl Storage for SledStorage {
         Ok(self.extra.contains_key(NETWORK)?)
     }
 
-    async fn pop_blocks(&mut self, mut height: u64, mut topoheight: u64, count: u64, stable_topo_height: u64) -> Result<(u64, u64, Vec<(Hash, Arc<Transaction>)>), BlockchainError> {
+    async fn pop_blocks(&mut self, mut height: u64, mut topoheight: u64, count: u64, stable_topo_height: u64) -> Result<(u64, u64, Vec<Hash>, Vec<(Hash, Arc<Transaction>)>), BlockchainError> {
         trace!("pop blocks from height: {}, topoheight: {}, count: {}", height, topoheight, count);
         if topoheight < count as u64 { // also prevent removing genesis block
             return Err(BlockchainError::NotEnoughBlocks);
@@ -815,6 +823,8 @@ impl Storage for SledStorage {
             }
         }
 
+        // all blocks deleted, from the highest topoheight
+        let mut blocks = Vec::new();
         // all txs to be rewinded
         let mut txs = Vec::new();
         let mut done = 0;
@@ -829,6 +839,7 @@ impl Storage for SledStorage {
             let (hash, block, block_txs) = self.delete_block_at_topoheight(topoheight).await?;
             trace!("Block {} at topoheight {} deleted", hash, topoheight);
             txs.extend(block_txs);
+            blocks.push(hash.clone());
 
             // generate new tips
             trace!("Removing {} from {} tips", hash, tips.len());
@@ -863,6 +874,14 @@ impl Storage for SledStorage {
 
         debug!("Blocks processed {}, new topoheight: {}, new height: {}, tips: {}", done, topoheight, height, tips.len());
 
+        // State roots of the deleted topoheights are deleted with their blocks
+        if let Some(state_root_topoheight) = self.get_state_root_topoheight().await? {
+            if state_root_topoheight > topoheight {
+                debug!("Lowering state root topoheight from {} to {}", state_root_topoheight, topoheight);
+                self.set_state_root_topoheight(topoheight).await?;
+            }
+        }
+
         trace!("Cleaning assets");
 
         // All deleted assets
@@ -995,7 +1014,7 @@ impl Storage for SledStorage {
         let count = self.count_blocks().await? - done;
         self.extra.insert(BLOCKS_COUNT, &count.to_be_bytes())?;
 
-        Ok((height, topoheight, txs))
+        Ok((height, topoheight, blocks, txs))
     }
 
     async fn get_top_block_hash(&self) -> Result<Hash, BlockchainError> {
@@ -7,7 +7,7 @@ use config::{DEV_PUBLIC_KEY, STABLE_LIMIT};
 use fern::colors::Color;
 use humantime::format_duration;
 use log::{trace, error, info, warn};
-use p2p::P2pServer;
+use p2p::{ban::IpRange, P2pServer};
 use rpc::{
     getwork_server::SharedGetWorkServer,
     rpc::get_block_response_for_hash
@@ -182,6 +182,9 @@ async fn run_prompt<S: Storage>(prompt: ShareablePrompt, blockchain: Arc<Blockch
     command_manager.add_command(Command::new("status", "Current daemon status", CommandHandler::Async(async_handler!(status::<S>))))?;
     command_manager.add_command(Command::with_optional_arguments("blacklist", "View blacklist or add a peer address in it", vec![Arg::new("address", ArgType::String)], CommandHandler::Async(async_handler!(blacklist::<S>))))?;
     command_manager.add_command(Command::with_optional_arguments("whitelist", "View whitelist or add a peer address in it", vec![Arg::new("address", ArgType::String)], CommandHandler::Async(async_handler!(whitelist::<S>))))?;
+    command_manager.add_command(Command::with_arguments("ban", "Ban an IP address or a CIDR range, optionally for N seconds", vec![Arg::new("range", ArgType::String)], vec![Arg::new("duration", ArgType::Number), Arg::new("reason", ArgType::String)], CommandHandler::Async(async_handler!(ban::<S>))))?;
+    command_manager.add_command(Command::with_required_arguments("unban", "Remove the ban of an IP address or a CIDR range", vec![Arg::new("range", ArgType::String)], CommandHandler::Async(async_handler!(unban::<S>))))?;
+    command_manager.add_command(Command::new("list_bans", "List all banned IP ranges", CommandHandler::Async(async_handler!(list_bans::<S>))))?;
     command_manager.add_command(Command::with_optional_arguments("verify_chain", "Check chain supply", vec![Arg::new("topoheight", ArgType::Number)], CommandHandler::Async(async_handler!(verify_chain::<S>))))?;
     command_manager.add_command(Command::with_required_arguments("kick_peer", "Kick a peer using its ip:port", vec![Arg::new("address", ArgType::String)], CommandHandler::Async(async_handler!(kick_peer::<S>))))?;
     command_manager.add_command(Command::new("clear_caches", "Clear storage caches", CommandHandler::Async(async_handler!(clear_caches::<S>))))?;
@@ -768,6 +771,74 @@ async fn whitelist<S: Storage>(manager: &CommandManager, mut arguments: Argument
     Ok(())
 }
 
+async fn ban<S: Storage>(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let range: IpRange = arguments.get_value("range")?.to_string_value()?.parse().context("Error while parsing IP range")?;
+    let duration = if arguments.has_argument("duration") {
+        Some(arguments.get_value("duration")?.to_number()?)
+    } else {
+        None
+    };
+    let reason = if arguments.has_argument("reason") {
+        Some(arguments.get_value("reason")?.to_string_value()?)
+    } else {
+        None
+    };
+
+    let context = manager.get_context().lock()?;
+    let blockchain: &Arc<Blockchain<S>> = context.get()?;
+    match blockchain.get_p2p().read().await.as_ref() {
+        Some(p2p) => {
+            p2p.get_peer_list().ban_range(range, reason, duration).await;
+            manager.message(format!("{} has been banned", range));
+        },
+        None => {
+            manager.error("P2P is not enabled");
+        }
+    };
+
+    Ok(())
+}
+
+async fn unban<S: Storage>(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let range: IpRange = arguments.get_value("range")?.to_string_value()?.parse().context("Error while parsing IP range")?;
+
+    let context = manager.get_context().lock()?;
+    let blockchain: &Arc<Blockchain<S>> = context.get()?;
+    match blockchain.get_p2p().read().await.as_ref() {
+        Some(p2p) => {
+            if p2p.get_peer_list().unban_range(&range).await {
+                manager.message(format!("{} is not banned anymore", range));
+            } else {
+                manager.error(format!("No ban found for {}", range));
+            }
+        },
+        None => {
+            manager.error("P2P is not enabled");
+        }
+    };
+
+    Ok(())
+}
+
+async fn list_bans<S: Storage>(manager: &CommandManager, _: ArgumentManager) -> Result<(), CommandError> {
+    let context = manager.get_context().lock()?;
+    let blockchain: &Arc<Blockchain<S>> = context.get()?;
+    match blockchain.get_p2p().read().await.as_ref() {
+        Some(p2p) => {
+            let bans = p2p.get_peer_list().get_bans().await;
+            manager.message(format!("Current bans ({}):", bans.len()));
+            for rule in bans {
+                manager.message(format!("- {}", rule));
+            }
+        },
+        None => {
+            manager.error("P2P is not enabled");
+        }
+    };
+
+    Ok(())
+}
+
 // Create a dataset from chain with solve time and difficulty at each block
 async fn difficulty_dataset<S: Storage>(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
     let output_path = if arguments.has_argument("output") {
@@ -0,0 +1,303 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    net::IpAddr,
+    str::FromStr
+};
+use serde::{Serialize, Deserialize};
+use log::{error, info, trace, warn};
+use parl_common::time::TimestampSeconds;
+use super::error::P2pError;
+
+// IPv4 or IPv6 range in the CIDR notation
+// A single IP address is a range with the full prefix length
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange {
+    // Network address, host bits are always cleared
+    addr: IpAddr,
+    prefix_len: u8
+}
+
+impl IpRange {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, P2pError> {
+        let max = Self::max_prefix_len(&addr);
+        if prefix_len > max {
+            return Err(P2pError::InvalidIpRange(format!("{}/{}", addr, prefix_len)))
+        }
+
+        Ok(Self {
+            addr: Self::mask(&addr, prefix_len),
+            prefix_len
+        })
+    }
+
+    fn max_prefix_len(addr: &IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        }
+    }
+
+    // Clear the host bits of the address
+    fn mask(addr: &IpAddr, prefix_len: u8) -> IpAddr {
+        match addr {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                IpAddr::V4((u32::from(*ip) & mask).into())
+            },
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                IpAddr::V6((u128::from(*ip) & mask).into())
+            }
+        }
+    }
+
+    pub fn get_addr(&self) -> &IpAddr {
+        &self.addr
+    }
+
+    pub fn get_prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    // Check if the IP address is in this range
+    // An IPv4-mapped IPv6 address is checked as its IPv4 address
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            ip => *ip
+        };
+
+        if ip.is_ipv4() != self.addr.is_ipv4() {
+            return false
+        }
+
+        Self::mask(&ip, self.prefix_len) == self.addr
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = P2pError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || P2pError::InvalidIpRange(value.to_owned());
+        match value.split_once('/') {
+            Some((addr, prefix_len)) => {
+                let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+                let prefix_len: u8 = prefix_len.parse().map_err(|_| invalid())?;
+                Self::new(addr, prefix_len)
+            },
+            None => {
+                let addr: IpAddr = value.parse().map_err(|_| invalid())?;
+                Self::new(addr, Self::max_prefix_len(&addr))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = P2pError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BanRule {
+    range: IpRange,
+    // Why this range was banned
+    reason: Option<String>,
+    created_at: TimestampSeconds,
+    // None means the ban is permanent
+    expires_at: Option<TimestampSeconds>
+}
+
+impl BanRule {
+    pub fn new(range: IpRange, reason: Option<String>, created_at: TimestampSeconds, expires_at: Option<TimestampSeconds>) -> Self {
+        Self {
+            range,
+            reason,
+            created_at,
+            expires_at
+        }
+    }
+
+    pub fn get_range(&self) -> &IpRange {
+        &self.range
+    }
+
+    pub fn get_reason(&self) -> &Option<String> {
+        &self.reason
+    }
+
+    pub fn get_created_at(&self) -> TimestampSeconds {
+        self.created_at
+    }
+
+    pub fn get_expires_at(&self) -> Option<TimestampSeconds> {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self, now: TimestampSeconds) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+impl Display for BanRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.range)?;
+        match self.expires_at {
+            Some(expires_at) => write!(f, " until {}", expires_at)?,
+            None => write!(f, " permanently")?
+        };
+
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+// All ban rules, saved in their own file next to the peerlist
+pub struct BanList {
+    rules: Vec<BanRule>,
+    filename: String
+}
+
+impl BanList {
+    // Load the ban rules from the file
+    // An invalid file is replaced by an empty list
+    pub fn load(filename: String) -> Self {
+        let rules = match fs::read_to_string(&filename) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    error!("Error while deserializing ban list: {}", e);
+                    warn!("Ban list will be overwritten with an empty one");
+                    Vec::new()
+                }
+            },
+            Err(_) => {
+                info!("Ban list file not found, creating a new one");
+                Vec::new()
+            }
+        };
+
+        Self {
+            rules,
+            filename
+        }
+    }
+
+    fn save(&self) {
+        trace!("saving ban list to file");
+        let res = serde_json::to_string_pretty(&self.rules)
+            .map_err(P2pError::from)
+            .and_then(|content| fs::write(&self.filename, content).map_err(P2pError::from));
+
+        if let Err(e) = res {
+            error!("Error while saving ban list to file: {}", e);
+        }
+    }
+
+    // Add a new rule, replacing the existing one for the same range
+    pub fn add(&mut self, rule: BanRule) {
+        self.rules.retain(|r| r.range != rule.range);
+        self.rules.push(rule);
+        self.save();
+    }
+
+    // Remove the rule for this exact range
+    // Returns false if no rule was found
+    pub fn remove(&mut self, range: &IpRange) -> bool {
+        let len = self.rules.len();
+        self.rules.retain(|r| r.range != *range);
+        let removed = len != self.rules.len();
+        if removed {
+            self.save();
+        }
+
+        removed
+    }
+
+    // Remove all the expired rules
+    pub fn clean_expired(&mut self, now: TimestampSeconds) {
+        let len = self.rules.len();
+        self.rules.retain(|r| !r.is_expired(now));
+        if len != self.rules.len() {
+            self.save();
+        }
+    }
+
+    // Find the active rule banning this IP address
+    pub fn get_rule_for(&self, ip: &IpAddr, now: TimestampSeconds) -> Option<&BanRule> {
+        self.rules.iter().find(|r| !r.is_expired(now) && r.range.contains(ip))
+    }
+
+    pub fn get_rules(&self) -> &Vec<BanRule> {
+        &self.rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_range() {
+        let range: IpRange = "192.168.1.42/24".parse().unwrap();
+        assert_eq!(range.to_string(), "192.168.1.0/24");
+
+        let range: IpRange = "10.0.0.1".parse().unwrap();
+        assert_eq!(range.get_prefix_len(), 32);
+
+        let range: IpRange = "2001:db8::1/32".parse().unwrap();
+        assert_eq!(range.to_string(), "2001:db8::/32");
+
+        assert!("10.0.0.1/33".parse::<IpRange>().is_err());
+        assert!("2001:db8::/129".parse::<IpRange>().is_err());
+        assert!("invalid/8".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_ip_range_contains() {
+        let range: IpRange = "192.168.0.0/16".parse().unwrap();
+        assert!(range.contains(&"192.168.42.1".parse().unwrap()));
+        assert!(range.contains(&"::ffff:192.168.42.1".parse().unwrap()));
+        assert!(!range.contains(&"192.169.0.1".parse().unwrap()));
+        assert!(!range.contains(&"2001:db8::1".parse().unwrap()));
+
+        let range: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(range.contains(&"8.8.8.8".parse().unwrap()));
+
+        let range: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains(&"2001:db8:ffff::1".parse().unwrap()));
+        assert!(!range.contains(&"2001:db9::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ban_rule_expiry() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        let rule = BanRule::new(range, None, 0, Some(100));
+        assert!(!rule.is_expired(99));
+        assert!(rule.is_expired(100));
+
+        let rule = BanRule::new(range, None, 0, None);
+        assert!(!rule.is_expired(u64::MAX));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant}
+};
+use tokio::time::sleep;
+use log::trace;
+
+// Limits in bytes per second, None means unlimited
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BandwidthLimits {
+    pub global_upload: Option<u64>,
+    pub global_download: Option<u64>,
+    pub peer_upload: Option<u64>,
+    pub peer_download: Option<u64>
+}
+
+struct BucketState {
+    // Can be negative when a packet bigger than the available tokens is sent
+    // The debt is paid by waiting before the next packet
+    tokens: i64,
+    last_refill: Instant
+}
+
+// Token bucket refilled at a constant rate
+// Its capacity allows a burst of one second of traffic
+pub struct TokenBucket {
+    // bytes per second
+    rate: u64,
+    state: Mutex<BucketState>
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        // A rate of 0 would never refill the bucket
+        let rate = rate.max(1);
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate as i64,
+                last_refill: Instant::now()
+            })
+        }
+    }
+
+    // Take the tokens for this amount of bytes
+    // Returns how long we have to wait before the bytes can be transferred
+    pub fn take(&self, amount: usize) -> Duration {
+        self.take_at(amount, Instant::now())
+    }
+
+    fn take_at(&self, amount: usize, now: Instant) -> Duration {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        let refill = (elapsed.as_micros() * self.rate as u128 / 1_000_000).min(i64::MAX as u128) as i64;
+        if refill > 0 {
+            state.tokens = state.tokens.saturating_add(refill).min(self.rate as i64);
+            state.last_refill = now;
+        }
+
+        state.tokens = state.tokens.saturating_sub(amount as i64);
+        if state.tokens >= 0 {
+            return Duration::ZERO
+        }
+
+        let missing = state.tokens.unsigned_abs() as u128;
+        Duration::from_micros((missing * 1_000_000 / self.rate as u128) as u64)
+    }
+}
+
+// Upload and download buckets
+pub struct BandwidthLimiter {
+    upload: Option<TokenBucket>,
+    download: Option<TokenBucket>
+}
+
+impl BandwidthLimiter {
+    pub fn new(upload: Option<u64>, download: Option<u64>) -> Self {
+        Self {
+            upload: upload.map(TokenBucket::new),
+            download: download.map(TokenBucket::new)
+        }
+    }
+
+    fn take_upload(&self, amount: usize) -> Duration {
+        self.upload.as_ref().map_or(Duration::ZERO, |bucket| bucket.take(amount))
+    }
+
+    fn take_download(&self, amount: usize) -> Duration {
+        self.download.as_ref().map_or(Duration::ZERO, |bucket| bucket.take(amount))
+    }
+}
+
+// Limits applied on a connection
+// The global limiter is shared by all the connections
+pub struct ConnectionLimiter {
+    global: Arc<BandwidthLimiter>,
+    peer: BandwidthLimiter
+}
+
+impl ConnectionLimiter {
+    pub fn new(global: Arc<BandwidthLimiter>, limits: &BandwidthLimits) -> Self {
+        Self {
+            global,
+            peer: BandwidthLimiter::new(limits.peer_upload, limits.peer_download)
+        }
+    }
+
+    // Take the upload tokens for this amount of bytes
+    // Returns how long we have to wait before the bytes can be sent
+    pub fn take_upload(&self, amount: usize) -> Duration {
+        self.global.take_upload(amount).max(self.peer.take_upload(amount))
+    }
+
+    // Wait until the bytes can be sent
+    // High priority packets are never delayed, but they are still counted
+    // so the other packets wait longer to pay for them
+    pub async fn throttle_upload(&self, amount: usize, high_priority: bool) {
+        let delay = self.take_upload(amount);
+        if !high_priority && !delay.is_zero() {
+            trace!("Delaying upload of {} bytes by {:?}", amount, delay);
+            sleep(delay).await;
+        }
+    }
+
+    // Wait before reading the next packet
+    // Delaying the reads will slow down the peer through TCP backpressure
+    pub async fn throttle_download(&self, amount: usize) {
+        let delay = self.global.take_download(amount).max(self.peer.take_download(amount));
+        if !delay.is_zero() {
+            trace!("Delaying download after {} bytes by {:?}", amount, delay);
+            sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(1000);
+        let now = Instant::now();
+
+        // Burst of one second is allowed
+        assert_eq!(bucket.take_at(1000, now), Duration::ZERO);
+        // Bucket is empty, we have to wait for the refill
+        assert_eq!(bucket.take_at(500, now), Duration::from_millis(500));
+        // Debt is paid after half a second
+        assert_eq!(bucket.take_at(0, now + Duration::from_millis(500)), Duration::ZERO);
+        // Refill is capped to the capacity
+        assert_eq!(bucket.take_at(1500, now + Duration::from_secs(60)), Duration::from_millis(500));
+    }
+}
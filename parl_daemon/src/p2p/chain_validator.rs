@@ -10,6 +10,7 @@ use parl_common::{
         Difficulty
     },
     immutable::Immutable,
+    light::StateLeaf,
     time::TimestampMillis,
     varuint::VarUint
 };
@@ -17,6 +18,7 @@ use crate::core::{
     blockchain::Blockchain,
     blockdag,
     error::BlockchainError,
+    state_tree::StateTreeNode,
     storage::{
         BlocksAtHeightProvider,
         DagOrderProvider,
@@ -320,4 +322,27 @@ impl<S: Storage> MerkleHashProvider for ChainValidator<'_, S> {
     async fn set_balances_merkle_hash_at_topoheight(&mut self,  _: u64, _: &Hash) -> Result<(), BlockchainError> {
         Err(BlockchainError::UnsupportedOperation)
     }
+
+    async fn get_state_leaves_at_topoheight(&self, topoheight: u64) -> Result<Vec<StateLeaf>, BlockchainError> {
+        let storage = self.blockchain.get_storage().read().await;
+        storage.get_state_leaves_at_topoheight(topoheight).await
+    }
+
+    async fn get_state_tree_node(&self, hash: &Hash) -> Result<StateTreeNode, BlockchainError> {
+        let storage = self.blockchain.get_storage().read().await;
+        storage.get_state_tree_node(hash).await
+    }
+
+    async fn set_state_tree_node(&mut self, _: &Hash, _: &StateTreeNode) -> Result<(), BlockchainError> {
+        Err(BlockchainError::UnsupportedOperation)
+    }
+
+    async fn get_state_root_topoheight(&self) -> Result<Option<u64>, BlockchainError> {
+        let storage = self.blockchain.get_storage().read().await;
+        storage.get_state_root_topoheight().await
+    }
+
+    async fn set_state_root_topoheight(&mut self, _: u64) -> Result<(), BlockchainError> {
+        Err(BlockchainError::UnsupportedOperation)
+    }
 }
@@ -1,14 +1,25 @@
 use crate::config::{PEER_TIMEOUT_DISCONNECT, PEER_TIMEOUT_INIT_CONNECTION};
 use super::{
-    encryption::Encryption,
+    bandwidth::ConnectionLimiter,
+    encryption::{
+        Encryption,
+        DHPublicKeyBytes,
+        SessionId,
+        DH_KEY_EXCHANGE_VERSION
+    },
     error::P2pError,
-    packet::Packet,
+    packet::{
+        key_exchange::DHKeyExchange,
+        is_high_priority_packet,
+        Packet
+    },
     EncryptionKey
 };
 use std::{
     borrow::Cow,
     convert::TryInto,
     fmt::{Display, Error, Formatter},
+    io::ErrorKind,
     net::SocketAddr,
     sync::atomic::{
         AtomicBool,
@@ -29,6 +40,7 @@ use tokio::{
     time::timeout
 };
 use parl_common::{
+    crypto::PublicKey,
     time::{TimestampSeconds, get_current_time_in_seconds},
     serializer::{Reader, Serializer},
 };
@@ -71,14 +83,21 @@ pub struct Connection {
     // How many key rotation we sent
     rotate_key_out: AtomicUsize,
     // Encryption state used for packets
-    encryption: Encryption
+    encryption: Encryption,
+    // Use the legacy key exchange where keys are sent to the peer
+    // Only used with peers that don't support the Diffie-Hellman key exchange
+    legacy_key_exchange: bool,
+    // Identity public key of the peer once authenticated
+    identity: Option<PublicKey>,
+    // Bandwidth limits, None if unlimited
+    limiter: Option<ConnectionLimiter>
 }
 
 // We are rotating every 1GB sent
 const ROTATE_EVERY_N_BYTES: usize = 1024 * 1024 * 1024;
 
 impl Connection {
-    pub fn new(stream: TcpStream, addr: SocketAddr, out: bool) -> Self {
+    pub fn new(stream: TcpStream, addr: SocketAddr, out: bool, limiter: Option<ConnectionLimiter>) -> Self {
         let (read, write) = stream.into_split();
         Self {
             out,
@@ -94,15 +113,21 @@ impl Connection {
             rotate_key_in: AtomicUsize::new(0),
             rotate_key_out: AtomicUsize::new(0),
             encryption: Encryption::new(),
+            legacy_key_exchange: false,
+            identity: None,
+            limiter
         }
     }
 
     // Do a key exchange with the peer
-    // If we are the client, we send our key first in plaintext
-    // We wait for the peer to send its key
-    // If we are the server, we send back our key
+    // If we are the client, we send our ephemeral public key first in plaintext
+    // If we are the server, we detect which key exchange the peer supports
+    // from its first packet and reply with our ephemeral public key
+    // Both sides derive the same keys from the Diffie-Hellman shared secret,
+    // so a passive observer can't decrypt the session.
+    // The key exchange packets are bound to the keys, a modified packet ends the connection.
     // NOTE: This doesn't prevent any MITM at this point
-    // Because a MITM could intercept the key and send its own key to the peer
+    // Because a MITM could intercept the public key and send its own key to the peer
     // and play the role as a proxy.
     // Afaik, there is no way to have a decentralized way to prevent MITM without trusting a third party
     // (That's what TLS/SSL does with the CA, but it's not decentralized and it's not trustless)
@@ -114,26 +139,135 @@ impl Connection {
         // Update our state
         self.set_state(State::KeyExchange);
 
-        // Send our key if we initiated the connection
         if self.is_out() {
-            trace!("Sending our key to {}", self.addr);
-            let packet = self.rotate_key_packet().await?;
-            self.send_bytes(&packet).await?;
-            self.encryption.mark_as_ready();
+            if self.legacy_key_exchange {
+                return self.exchange_keys_legacy(buffer, None).await
+            }
+
+            return self.exchange_keys_dh(buffer, None).await
         }
 
-        trace!("Waiting for key from {}", self.addr);
-        // Wait for the peer to receive its key
-        let Packet::KeyExchange(peer_key) = timeout(
+        trace!("Waiting for key exchange from {}", self.addr);
+        match self.read_key_exchange_packet(buffer).await? {
+            Packet::DHKeyExchange(key_exchange) => self.exchange_keys_dh(buffer, Some(key_exchange.into_owned())).await,
+            Packet::KeyExchange(peer_key) => {
+                debug!("{} is using the legacy key exchange", self.addr);
+                self.legacy_key_exchange = true;
+                self.exchange_keys_legacy(buffer, Some(peer_key.into_owned())).await
+            },
+            _ => {
+                error!("Expected KeyExchange packet");
+                Err(P2pError::InvalidPacket)
+            }
+        }
+    }
+
+    // Read the next packet during the key exchange
+    async fn read_key_exchange_packet(&self, buffer: &mut [u8]) -> P2pResult<Packet<'static>> {
+        timeout(
             Duration::from_millis(PEER_TIMEOUT_INIT_CONNECTION),
             self.read_packet(buffer, 256)
-        ).await?? else {
-            error!("Expected KeyExchange packet");
-            return Err(P2pError::InvalidPacket);
+        ).await?
+    }
+
+    // Diffie-Hellman key exchange
+    // peer_key_exchange is set if we are the server and already received it
+    async fn exchange_keys_dh(&mut self, buffer: &mut [u8], peer_key_exchange: Option<DHKeyExchange>) -> P2pResult<()> {
+        let (secret, public_key) = Encryption::generate_dh_secret();
+
+        // packet sent by the client, set once we sent it or received it
+        let mut client_packet = None;
+        let peer_key_exchange = match peer_key_exchange {
+            Some(key_exchange) => key_exchange,
+            None => {
+                trace!("Sending our public key to {}", self.addr);
+                client_packet = Some(self.send_dh_key_exchange(DH_KEY_EXCHANGE_VERSION, public_key).await?);
+
+                trace!("Waiting for public key from {}", self.addr);
+                // Peers not supporting it will close the connection as they can't read the packet
+                // any other error is not a reason to fallback on the legacy key exchange
+                match self.read_key_exchange_packet(buffer).await {
+                    Ok(Packet::DHKeyExchange(key_exchange)) => key_exchange.into_owned(),
+                    Ok(_) => {
+                        error!("Expected DHKeyExchange packet");
+                        return Err(P2pError::InvalidPacket)
+                    },
+                    Err(P2pError::Disconnected) => {
+                        debug!("{} closed the connection on our public key", self.addr);
+                        return Err(P2pError::DHKeyExchangeNotSupported)
+                    },
+                    Err(P2pError::ErrorStd(e)) if e.kind() == ErrorKind::ConnectionReset => {
+                        debug!("{} reset the connection on our public key", self.addr);
+                        return Err(P2pError::DHKeyExchangeNotSupported)
+                    },
+                    Err(e) => return Err(e)
+                }
+            }
+        };
+
+        // Only one version exists for now, but newer peers must reply with a version we support
+        let version = peer_key_exchange.get_version();
+        if version == 0 || (self.is_out() && version > DH_KEY_EXCHANGE_VERSION) {
+            return Err(P2pError::InvalidKeyExchangeVersion(version))
+        }
+
+        let peer_packet = Packet::DHKeyExchange(Cow::Borrowed(&peer_key_exchange)).to_bytes();
+        let mut transcript = match client_packet {
+            Some(client_packet) => client_packet,
+            None => peer_packet.clone()
+        };
+
+        // Reply with our public key if we are the server
+        if !self.is_out() {
+            trace!("Replying with our public key to {}", self.addr);
+            let server_packet = self.send_dh_key_exchange(version.min(DH_KEY_EXCHANGE_VERSION), public_key).await?;
+            transcript.extend(server_packet);
+        } else {
+            transcript.extend(peer_packet);
+        }
+
+        self.encryption.complete_dh_key_exchange(secret, *peer_key_exchange.get_public_key(), self.is_out(), &transcript).await?;
+        self.rotate_key_in.fetch_add(1, Ordering::Relaxed);
+        self.rotate_key_out.fetch_add(1, Ordering::Relaxed);
+        self.encryption.mark_as_ready();
+
+        trace!("Key exchange with {} successful", self.addr);
+
+        Ok(())
+    }
+
+    // Send our public key in plaintext
+    // Returns the packet sent for the transcript
+    async fn send_dh_key_exchange(&self, version: u8, public_key: DHPublicKeyBytes) -> P2pResult<Vec<u8>> {
+        let packet = Packet::DHKeyExchange(Cow::Owned(DHKeyExchange::new(version, public_key))).to_bytes();
+        self.send_bytes(&packet).await?;
+        Ok(packet)
+    }
+
+    // Legacy key exchange, each side sends its key in plaintext
+    // Kept for peers that don't support the Diffie-Hellman key exchange yet
+    // peer_key is set if we are the server and already received it
+    async fn exchange_keys_legacy(&mut self, buffer: &mut [u8], peer_key: Option<EncryptionKey>) -> P2pResult<()> {
+        let peer_key = match peer_key {
+            Some(key) => key,
+            None => {
+                // Send our key as we initiated the connection
+                trace!("Sending our key to {}", self.addr);
+                let packet = self.rotate_key_packet().await?;
+                self.send_bytes(&packet).await?;
+                self.encryption.mark_as_ready();
+
+                trace!("Waiting for key from {}", self.addr);
+                let Packet::KeyExchange(peer_key) = self.read_key_exchange_packet(buffer).await? else {
+                    error!("Expected KeyExchange packet");
+                    return Err(P2pError::InvalidPacket);
+                };
+                peer_key.into_owned()
+            }
         };
 
         // Now that we got the peer key, update our encryption state
-        self.rotate_peer_key(peer_key.into_owned()).await?;
+        self.rotate_peer_key(peer_key).await?;
 
         // Send back our key if we are the server
         if !self.is_out() {
@@ -143,11 +277,37 @@ impl Connection {
             self.encryption.mark_as_ready();
         }
 
-        trace!("Key exchange with {} successful", self.addr);
+        trace!("Legacy key exchange with {} successful", self.addr);
 
         Ok(())
     }
 
+    // Use the legacy key exchange for this connection
+    pub fn set_legacy_key_exchange(&mut self, legacy: bool) {
+        self.legacy_key_exchange = legacy;
+    }
+
+    // Verify if the legacy key exchange is used
+    pub fn is_legacy_key_exchange(&self) -> bool {
+        self.legacy_key_exchange
+    }
+
+    // Session id of the Diffie-Hellman key exchange
+    // None if the legacy key exchange was used
+    pub async fn get_session_id(&self) -> Option<SessionId> {
+        self.encryption.get_session_id().await
+    }
+
+    // Set the identity of the peer once its proof has been verified
+    pub fn set_identity(&mut self, identity: PublicKey) {
+        self.identity = Some(identity);
+    }
+
+    // Get the authenticated identity of the peer
+    pub fn get_identity(&self) -> Option<&PublicKey> {
+        self.identity.as_ref()
+    }
+
     // Verify if its a outgoing connection
     pub fn is_out(&self) -> bool {
         self.out
@@ -156,12 +316,16 @@ impl Connection {
     // This will send to the peer a packet to rotate the key
     async fn rotate_key_packet(&self) -> P2pResult<Bytes> {
         trace!("rotating our encryption key for peer {}", self.get_address());
-        // Generate a new key to use
-        let new_key = self.encryption.generate_key();
-        // Verify if we already have one set
-        
-        // Build the packet
-        let mut packet = Bytes::from(Packet::KeyExchange(Cow::Borrowed(&new_key)).to_bytes());
+        let (mut packet, new_key) = if self.legacy_key_exchange {
+            // Generate a new key to use
+            let new_key = self.encryption.generate_key();
+            (Bytes::from(Packet::KeyExchange(Cow::Borrowed(&new_key)).to_bytes()), new_key)
+        } else {
+            // Derive a new key from a fresh Diffie-Hellman
+            let (public_key, new_key) = self.encryption.prepare_dh_key_rotation().await?;
+            let key_exchange = DHKeyExchange::new(DH_KEY_EXCHANGE_VERSION, public_key);
+            (Bytes::from(Packet::DHKeyExchange(Cow::Owned(key_exchange)).to_bytes()), new_key)
+        };
 
         // This is used to determine if we need to encrypt the packet or not
         // Check if we already had a key set, if so, encrypt it
@@ -189,6 +353,25 @@ impl Connection {
     // as all next packets will be encrypted with the new key and we have updated it before
     pub async fn rotate_peer_key(&self, key: EncryptionKey) -> P2pResult<()> {
         trace!("Rotating encryption key of peer {}", self.get_address());
+        // Don't allow a peer to downgrade to keys sent over the network
+        if !self.legacy_key_exchange {
+            return Err(P2pError::InvalidPacket)
+        }
+
+        self.encryption.rotate_key(key, false).await?;
+        // Increment the key rotation counter
+        self.rotate_key_in.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Rotate the peer symetric key using the ephemeral public key it sent
+    pub async fn rotate_peer_key_dh(&self, public_key: DHPublicKeyBytes) -> P2pResult<()> {
+        trace!("Rotating encryption key of peer {} using Diffie-Hellman", self.get_address());
+        if self.legacy_key_exchange {
+            return Err(P2pError::InvalidPacket)
+        }
+
+        let key = self.encryption.derive_dh_peer_key(public_key).await?;
         self.encryption.rotate_key(key, false).await?;
         // Increment the key rotation counter
         self.rotate_key_in.fetch_add(1, Ordering::Relaxed);
@@ -208,6 +391,16 @@ impl Connection {
     // Send bytes to the peer
     // Encrypt must be used all time starting handshake
     pub async fn send_bytes(&self, packet: &[u8]) -> P2pResult<()> {
+        if let Some(limiter) = &self.limiter {
+            limiter.throttle_upload(packet.len(), is_high_priority_packet(packet)).await;
+        }
+
+        self.send_bytes_unthrottled(packet).await
+    }
+
+    // Send bytes to the peer without waiting on the bandwidth limits
+    // The upload tokens must have been taken by the caller
+    pub async fn send_bytes_unthrottled(&self, packet: &[u8]) -> P2pResult<()> {
         trace!("Sending {} bytes to {}", packet.len(), self.get_address());
         let mut stream = self.write.lock().await;
 
@@ -253,6 +446,10 @@ impl Connection {
         trace!("Size received: {}", size);
 
         let bytes = self.read_all_bytes(&mut stream, buf, size).await?;
+        if let Some(limiter) = &self.limiter {
+            limiter.throttle_download(size as usize).await;
+        }
+
         Ok(bytes)
     }
 
@@ -374,6 +571,11 @@ impl Connection {
         &self.addr
     }
 
+    // Get the bandwidth limits applied on this connection
+    pub fn get_limiter(&self) -> Option<&ConnectionLimiter> {
+        self.limiter.as_ref()
+    }
+
     // Get the total bytes sent
     pub fn bytes_out(&self) -> usize {
         self.bytes_out.load(Ordering::Relaxed)
@@ -416,3 +618,72 @@ impl Display for Connection {
         write!(f, "Connection[state: {:?}, peer: {}, read: {}, sent: {}, key rotation (in/out): ({}/{}), connected since: {}, closed: {}]", self.state, self.get_address(), human_bytes(self.bytes_in() as f64), human_bytes(self.bytes_out() as f64), self.key_rotation_in(), self.key_rotation_out(), self.get_human_uptime(), self.is_closed())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use parl_common::{
+        crypto::Hash,
+        difficulty::CumulativeDifficulty,
+        network::Network
+    };
+    use tokio::net::TcpListener;
+    use crate::p2p::packet::handshake::Handshake;
+    use super::*;
+
+    // Returns the client and server sides of a new connection
+    async fn connect() -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (server, client_addr) = server.unwrap();
+        (Connection::new(client.unwrap(), addr, true, None), Connection::new(server, client_addr, false, None))
+    }
+
+    fn handshake_packet(version: &str) -> Vec<u8> {
+        let handshake = Handshake::new(Cow::Owned(version.to_owned()), Network::Testnet, Cow::Owned(None), Cow::Owned([7; 16]), 42, 2125, 1700000000, 100, 90, None, Cow::Owned(Hash::zero()), Cow::Owned(Hash::zero()), Cow::Owned(CumulativeDifficulty::zero()), true);
+        Packet::Handshake(Cow::Owned(handshake)).to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_old_node_connects_to_new_node() {
+        let (mut old, mut new) = connect().await;
+        // an old node only knows the legacy key exchange
+        old.set_legacy_key_exchange(true);
+
+        let (mut old_buffer, mut new_buffer) = ([0; 512], [0; 512]);
+        let (old_res, new_res) = tokio::join!(old.exchange_keys(&mut old_buffer), new.exchange_keys(&mut new_buffer));
+        old_res.unwrap();
+        new_res.unwrap();
+        assert!(new.is_legacy_key_exchange());
+        assert!(new.get_session_id().await.is_none());
+
+        // both handshakes are in the format known by the old node
+        old.send_bytes(&handshake_packet("1.0.1-abcdef1")).await.unwrap();
+        let Packet::Handshake(handshake) = new.read_packet(&mut new_buffer, 512).await.unwrap() else {
+            panic!("Expected handshake packet")
+        };
+        assert!(!handshake.supports_features());
+
+        new.send_bytes(&handshake_packet("1.1.0-abcdef1")).await.unwrap();
+        let Packet::Handshake(handshake) = old.read_packet(&mut old_buffer, 512).await.unwrap() else {
+            panic!("Expected handshake packet")
+        };
+        assert_eq!(handshake.get_version(), "1.1.0-abcdef1");
+    }
+
+    #[tokio::test]
+    async fn test_new_nodes_use_dh_key_exchange() {
+        let (mut client, mut server) = connect().await;
+
+        let (mut client_buffer, mut server_buffer) = ([0; 512], [0; 512]);
+        let (client_res, server_res) = tokio::join!(client.exchange_keys(&mut client_buffer), server.exchange_keys(&mut server_buffer));
+        client_res.unwrap();
+        server_res.unwrap();
+        assert!(!client.is_legacy_key_exchange() && !server.is_legacy_key_exchange());
+        assert!(client.get_session_id().await.is_some());
+        assert_eq!(client.get_session_id().await, server.get_session_id().await);
+
+        client.send_bytes(&Packet::Features(3).to_bytes()).await.unwrap();
+        assert!(matches!(server.read_packet(&mut server_buffer, 512).await.unwrap(), Packet::Features(3)));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc
+};
+use rand::{seq::IteratorRandom, Rng};
+use tokio::sync::Mutex;
+use log::debug;
+use parl_common::{
+    crypto::Hash,
+    time::{get_current_time_in_seconds, TimestampSeconds}
+};
+use crate::config::{
+    DANDELION_EMBARGO_MIN,
+    DANDELION_EMBARGO_RANDOM,
+    DANDELION_EPOCH_DURATION,
+    DANDELION_FLUFF_PROBABILITY
+};
+use super::peer::Peer;
+
+// All stem txs are relayed to the same peer during an epoch
+struct Epoch {
+    // when this epoch ends
+    expires_at: TimestampSeconds,
+    // id of the peer selected to relay the stem txs
+    stem_peer: Option<u64>,
+    // In fluff mode, stem txs received from peers are broadcasted directly
+    fluff: bool
+}
+
+// Dandelion++ state of the node
+// A tx is first relayed from peer to peer (stem phase) for a random number of hops
+// until a node in fluff mode broadcasts it to all its peers (fluff phase)
+// This prevents linking a tx to the IP address of its sender
+pub struct Dandelion {
+    epoch: Mutex<Epoch>,
+    // Deadline of each tx we relayed in stem phase
+    // If it's not seen in fluff phase before, we fluff it ourself
+    embargoes: Mutex<HashMap<Hash, TimestampSeconds>>
+}
+
+impl Dandelion {
+    pub fn new() -> Self {
+        Self {
+            // Expired so the first epoch is started on first use
+            epoch: Mutex::new(Epoch {
+                expires_at: 0,
+                stem_peer: None,
+                fluff: false
+            }),
+            embargoes: Mutex::new(HashMap::new())
+        }
+    }
+
+    // Start a new epoch if the current one is expired
+    fn refresh_epoch(epoch: &mut Epoch, now: TimestampSeconds) {
+        if epoch.expires_at > now {
+            return;
+        }
+
+        epoch.expires_at = now + DANDELION_EPOCH_DURATION;
+        epoch.stem_peer = None;
+        epoch.fluff = rand::thread_rng().gen_ratio(DANDELION_FLUFF_PROBABILITY, 100);
+        debug!("New Dandelion++ epoch started (fluff: {})", epoch.fluff);
+    }
+
+    // Check if the stem txs received from peers must be fluffed in this epoch
+    pub async fn is_fluff_epoch(&self) -> bool {
+        let mut epoch = self.epoch.lock().await;
+        Self::refresh_epoch(&mut epoch, get_current_time_in_seconds());
+        epoch.fluff
+    }
+
+    // Select the id of the peer to relay a stem tx to, from the ids of the peers supporting Dandelion++
+    // The stem peer of the epoch is kept while it's connected
+    // The peer that relayed the tx to us (from) is never selected
+    fn select_stem_peer(epoch: &mut Epoch, candidates: &[u64], from: Option<u64>) -> Option<u64> {
+        let current = epoch.stem_peer.filter(|id| candidates.contains(id));
+        if let Some(id) = current.filter(|id| Some(*id) != from) {
+            return Some(id)
+        }
+
+        let id = candidates.iter()
+            .copied()
+            .filter(|id| Some(*id) != from)
+            .choose(&mut rand::thread_rng())?;
+
+        // The sender is the stem peer of this epoch, only this tx is relayed to another one
+        if current.is_none() {
+            epoch.stem_peer = Some(id);
+        }
+        Some(id)
+    }
+
+    // Get the peer to relay a stem tx to during this epoch
+    // A new one is selected if the previous one is disconnected
+    // Returns None if no other peer supports Dandelion++
+    pub async fn get_stem_peer(&self, peers: &HashSet<Arc<Peer>>, from: Option<u64>) -> Option<Arc<Peer>> {
+        let mut epoch = self.epoch.lock().await;
+        Self::refresh_epoch(&mut epoch, get_current_time_in_seconds());
+
+        let candidates: Vec<u64> = peers.iter()
+            .filter(|peer| peer.supports_dandelion())
+            .map(|peer| peer.get_id())
+            .collect();
+
+        let previous = epoch.stem_peer;
+        let id = Self::select_stem_peer(&mut epoch, &candidates, from)?;
+        let peer = peers.iter().find(|peer| peer.get_id() == id)?;
+        if previous != epoch.stem_peer {
+            debug!("{} selected as Dandelion++ stem peer", peer);
+        }
+
+        Some(Arc::clone(peer))
+    }
+
+    // Set a random embargo on a stem tx
+    pub async fn add_embargo(&self, hash: Hash) {
+        let delay = DANDELION_EMBARGO_MIN + rand::thread_rng().gen_range(0..=DANDELION_EMBARGO_RANDOM);
+        let mut embargoes = self.embargoes.lock().await;
+        embargoes.entry(hash).or_insert(get_current_time_in_seconds() + delay);
+    }
+
+    // Remove the embargo of a tx seen in fluff phase
+    // Returns true if the tx was under embargo
+    pub async fn remove_embargo(&self, hash: &Hash) -> bool {
+        self.embargoes.lock().await.remove(hash).is_some()
+    }
+
+    // Remove and returns all the txs with an expired embargo
+    pub async fn take_expired_embargoes(&self, now: TimestampSeconds) -> Vec<Hash> {
+        let mut embargoes = self.embargoes.lock().await;
+        let expired: Vec<Hash> = embargoes.iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+
+        for hash in &expired {
+            embargoes.remove(hash);
+        }
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_epoch(stem_peer: Option<u64>) -> Epoch {
+        Epoch {
+            expires_at: 0,
+            stem_peer,
+            fluff: false
+        }
+    }
+
+    #[test]
+    fn test_stem_peer_is_kept_during_epoch() {
+        let mut epoch = new_epoch(None);
+        let id = Dandelion::select_stem_peer(&mut epoch, &[1, 2, 3], None).unwrap();
+        assert_eq!(epoch.stem_peer, Some(id));
+
+        for _ in 0..10 {
+            assert_eq!(Dandelion::select_stem_peer(&mut epoch, &[1, 2, 3], None), Some(id));
+        }
+    }
+
+    #[test]
+    fn test_stem_peer_excludes_sender() {
+        // The stem peer sent us the tx, relay it to another peer
+        // without changing the stem peer of the epoch
+        let mut epoch = new_epoch(Some(1));
+        for _ in 0..10 {
+            let id = Dandelion::select_stem_peer(&mut epoch, &[1, 2, 3], Some(1)).unwrap();
+            assert_ne!(id, 1);
+            assert_eq!(epoch.stem_peer, Some(1));
+        }
+
+        // The sender is the only peer supporting Dandelion++
+        let mut epoch = new_epoch(None);
+        assert_eq!(Dandelion::select_stem_peer(&mut epoch, &[1], Some(1)), None);
+        assert_eq!(epoch.stem_peer, None);
+
+        // A new stem peer is never the sender
+        for _ in 0..10 {
+            let mut epoch = new_epoch(None);
+            assert_eq!(Dandelion::select_stem_peer(&mut epoch, &[1, 2], Some(1)), Some(2));
+            assert_eq!(epoch.stem_peer, Some(2));
+        }
+    }
+
+    #[test]
+    fn test_stem_peer_disconnected() {
+        let mut epoch = new_epoch(Some(1));
+        assert_eq!(Dandelion::select_stem_peer(&mut epoch, &[2], None), Some(2));
+        assert_eq!(epoch.stem_peer, Some(2));
+
+        assert_eq!(Dandelion::select_stem_peer(&mut epoch, &[], None), None);
+    }
+
+    #[test]
+    fn test_refresh_epoch() {
+        let mut epoch = new_epoch(Some(1));
+        Dandelion::refresh_epoch(&mut epoch, 100);
+        assert_eq!(epoch.expires_at, 100 + DANDELION_EPOCH_DURATION);
+        assert_eq!(epoch.stem_peer, None);
+
+        // Not expired yet, the stem peer is kept
+        epoch.stem_peer = Some(2);
+        Dandelion::refresh_epoch(&mut epoch, 100 + DANDELION_EPOCH_DURATION - 1);
+        assert_eq!(epoch.stem_peer, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_embargo_expiry() {
+        let dandelion = Dandelion::new();
+        let now = get_current_time_in_seconds();
+        dandelion.add_embargo(Hash::zero()).await;
+
+        assert!(dandelion.take_expired_embargoes(now).await.is_empty());
+
+        let expired = dandelion.take_expired_embargoes(now + DANDELION_EMBARGO_MIN + DANDELION_EMBARGO_RANDOM + 1).await;
+        assert_eq!(expired, vec![Hash::zero()]);
+        assert!(!dandelion.remove_embargo(&Hash::zero()).await);
+    }
+}
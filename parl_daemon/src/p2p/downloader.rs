@@ -0,0 +1,430 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::Display,
+    future::Future,
+    pin::Pin,
+    sync::Arc
+};
+use tokio::task::JoinSet;
+use log::{debug, trace};
+use crate::config::{
+    CHAIN_SYNC_DOWNLOAD_WINDOW,
+    CHAIN_SYNC_MAX_ATTEMPTS,
+    CHAIN_SYNC_MAX_REQUESTS_PER_PEER
+};
+use super::{
+    error::P2pError,
+    packet::object::{ObjectRequest, OwnedObjectResponse},
+    peer::Peer
+};
+
+type RequestResult<P> = (usize, Arc<P>, Result<OwnedObjectResponse, P2pError>);
+type RequestFuture = Pin<Box<dyn Future<Output = Result<OwnedObjectResponse, P2pError>> + Send>>;
+
+// Peer we can download objects from
+pub trait DownloadPeer: Display + Send + Sync + 'static {
+    fn get_id(&self) -> u64;
+
+    fn get_height(&self) -> u64;
+
+    fn is_closed(&self) -> bool;
+
+    // Request the object and wait on it until we receive it or until timeout
+    fn request_object(self: Arc<Self>, request: ObjectRequest) -> RequestFuture;
+
+    // Called when the peer failed to give us an object
+    fn on_request_failure(&self, err: &P2pError);
+}
+
+impl DownloadPeer for Peer {
+    fn get_id(&self) -> u64 {
+        Peer::get_id(self)
+    }
+
+    fn get_height(&self) -> u64 {
+        Peer::get_height(self)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.get_connection().is_closed()
+    }
+
+    fn request_object(self: Arc<Self>, request: ObjectRequest) -> RequestFuture {
+        Box::pin(async move { self.request_blocking_object(request).await })
+    }
+
+    fn on_request_failure(&self, err: &P2pError) {
+        self.increment_fail_count();
+        self.penalize(err);
+    }
+}
+
+// Object to download with the state of its requests
+struct Download {
+    request: ObjectRequest,
+    // Height of the block, only peers at this height or above can have it
+    height: u64,
+    attempts: usize,
+    // Peers that failed to give us this object
+    failed_peers: HashSet<u64>
+}
+
+// Download objects from several peers in parallel
+// Requests are sent in a sliding window ahead of the next object to return
+// and the responses are returned in the same order as the requests
+// A failed request is assigned to another peer
+pub struct ObjectDownloader<P: DownloadPeer = Peer> {
+    // Peer we are syncing with, it's expected to have all the objects
+    sync_peer: Arc<P>,
+    // All the peers we can request from, including the sync peer
+    peers: Vec<Arc<P>>,
+    downloads: Vec<Download>,
+    // Index of the next object to return
+    next: usize,
+    // Index of the next object to request for the first time
+    next_request: usize,
+    // Failed requests to send again
+    retries: VecDeque<usize>,
+    in_flight: JoinSet<RequestResult<P>>,
+    requests_per_peer: HashMap<u64, usize>,
+    max_requests_per_peer: usize,
+    // Responses received in advance
+    ready: BTreeMap<usize, OwnedObjectResponse>
+}
+
+impl<P: DownloadPeer> ObjectDownloader<P> {
+    // Requests are (object, height) and must be ordered as they have to be applied
+    pub fn new(sync_peer: Arc<P>, mut peers: Vec<Arc<P>>, requests: Vec<(ObjectRequest, u64)>) -> Self {
+        if !peers.iter().any(|peer| peer.get_id() == sync_peer.get_id()) {
+            peers.push(Arc::clone(&sync_peer));
+        }
+
+        let downloads = requests.into_iter()
+            .map(|(request, height)| Download {
+                request,
+                height,
+                attempts: 0,
+                failed_peers: HashSet::new()
+            })
+            .collect();
+
+        Self {
+            sync_peer,
+            peers,
+            downloads,
+            next: 0,
+            next_request: 0,
+            retries: VecDeque::new(),
+            in_flight: JoinSet::new(),
+            requests_per_peer: HashMap::new(),
+            max_requests_per_peer: CHAIN_SYNC_MAX_REQUESTS_PER_PEER,
+            ready: BTreeMap::new()
+        }
+    }
+
+    // Set how many requests can be sent at same time to a peer
+    // A trusted peer can be requested for the whole window (boost sync)
+    pub fn set_max_requests_per_peer(&mut self, max: usize) {
+        self.max_requests_per_peer = max.max(1);
+    }
+
+    fn has_capacity(&self, peer: &P) -> bool {
+        self.requests_per_peer.get(&peer.get_id()).copied().unwrap_or(0) < self.max_requests_per_peer
+    }
+
+    // Select the peer to request this object from
+    // A failed object is requested again to the sync peer
+    // otherwise we select the least busy peer having it
+    // Returns None if all the peers available are busy
+    fn select_peer(&self, download: &Download) -> Option<Arc<P>> {
+        if !download.failed_peers.is_empty() && !download.failed_peers.contains(&self.sync_peer.get_id()) {
+            return self.has_capacity(&self.sync_peer).then(|| Arc::clone(&self.sync_peer))
+        }
+
+        self.peers.iter()
+            .filter(|peer| !peer.is_closed())
+            .filter(|peer| peer.get_height() >= download.height && !download.failed_peers.contains(&peer.get_id()))
+            .filter(|peer| self.has_capacity(peer))
+            .min_by_key(|peer| self.requests_per_peer.get(&peer.get_id()).copied().unwrap_or(0))
+            .cloned()
+    }
+
+    // Send as many requests as possible in the window
+    fn fill_window(&mut self) {
+        let window_end = self.downloads.len().min(self.next + CHAIN_SYNC_DOWNLOAD_WINDOW);
+        loop {
+            let (index, is_retry) = match self.retries.front() {
+                Some(index) => (*index, true),
+                None if self.next_request < window_end => (self.next_request, false),
+                None => break
+            };
+
+            let download = &self.downloads[index];
+            let Some(peer) = self.select_peer(download) else {
+                trace!("No peer available to request {}, waiting on pending requests", download.request);
+                break;
+            };
+
+            if is_retry {
+                self.retries.pop_front();
+            } else {
+                self.next_request += 1;
+            }
+
+            *self.requests_per_peer.entry(peer.get_id()).or_insert(0) += 1;
+            let request = download.request.clone();
+            trace!("Requesting {} from {}", request, peer);
+            self.in_flight.spawn(async move {
+                let result = Arc::clone(&peer).request_object(request).await;
+                (index, peer, result)
+            });
+        }
+    }
+
+    // Handle the response of a request
+    fn handle_result(&mut self, index: usize, peer: Arc<P>, result: Result<OwnedObjectResponse, P2pError>) -> Result<(), P2pError> {
+        if let Some(count) = self.requests_per_peer.get_mut(&peer.get_id()) {
+            *count = count.saturating_sub(1);
+        }
+
+        let download = &mut self.downloads[index];
+        let err = match result {
+            Ok(response) => {
+                self.ready.insert(index, response);
+                return Ok(())
+            },
+            Err(e) => e
+        };
+
+        debug!("Error while requesting {} from {}: {}", download.request, peer, err);
+        // A peer that doesn't have an object is not at fault, the object is maybe on a side chain it doesn't know
+        if !matches!(err, P2pError::ObjectNotFound(_)) || peer.get_id() == self.sync_peer.get_id() {
+            peer.on_request_failure(&err);
+        }
+
+        download.attempts += 1;
+        download.failed_peers.insert(peer.get_id());
+        if download.attempts >= CHAIN_SYNC_MAX_ATTEMPTS {
+            return Err(P2pError::SyncDownloadFailed(download.request.get_hash().clone()))
+        }
+
+        self.retries.push_back(index);
+        Ok(())
+    }
+
+    // Get the next object in the requests order
+    // Returns None once all the objects were returned
+    pub async fn next(&mut self) -> Result<Option<OwnedObjectResponse>, P2pError> {
+        loop {
+            if let Some(response) = self.ready.remove(&self.next) {
+                self.next += 1;
+                return Ok(Some(response))
+            }
+
+            if self.next >= self.downloads.len() {
+                return Ok(None)
+            }
+
+            self.fill_window();
+
+            let Some(res) = self.in_flight.join_next().await else {
+                // Nothing is in progress and no peer can be used
+                return Err(P2pError::SyncDownloadFailed(self.downloads[self.next].request.get_hash().clone()))
+            };
+
+            let (index, peer, result) = res.map_err(|_| P2pError::SyncDownloadInterrupted)?;
+            self.handle_result(index, peer, result)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration
+    };
+    use parl_common::crypto::{Hash, HASH_SIZE};
+    use tokio::time::{sleep, timeout};
+    use super::*;
+
+    #[derive(Default)]
+    struct Counter {
+        current: AtomicUsize,
+        max: AtomicUsize
+    }
+
+    impl Counter {
+        fn increment(&self) {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(current, Ordering::SeqCst);
+        }
+
+        fn decrement(&self) {
+            self.current.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        fn get_max(&self) -> usize {
+            self.max.load(Ordering::SeqCst)
+        }
+    }
+
+    struct MockPeer {
+        id: u64,
+        // All the requests sent to this peer time out
+        time_out: bool,
+        // Delay in millis of each response, computed from the object index
+        delay: fn(u64) -> u64,
+        in_flight: Counter,
+        // Requests in flight on all the peers
+        total_in_flight: Arc<Counter>,
+        failures: AtomicUsize
+    }
+
+    impl MockPeer {
+        fn new(id: u64, total_in_flight: &Arc<Counter>) -> Arc<Self> {
+            Self::with(id, false, |_| 1, total_in_flight)
+        }
+
+        fn with(id: u64, time_out: bool, delay: fn(u64) -> u64, total_in_flight: &Arc<Counter>) -> Arc<Self> {
+            Arc::new(Self {
+                id,
+                time_out,
+                delay,
+                in_flight: Counter::default(),
+                total_in_flight: Arc::clone(total_in_flight),
+                failures: AtomicUsize::new(0)
+            })
+        }
+    }
+
+    impl fmt::Display for MockPeer {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "MockPeer[{}]", self.id)
+        }
+    }
+
+    impl DownloadPeer for MockPeer {
+        fn get_id(&self) -> u64 {
+            self.id
+        }
+
+        fn get_height(&self) -> u64 {
+            u64::MAX
+        }
+
+        fn is_closed(&self) -> bool {
+            false
+        }
+
+        fn request_object(self: Arc<Self>, request: ObjectRequest) -> RequestFuture {
+            Box::pin(async move {
+                self.in_flight.increment();
+                self.total_in_flight.increment();
+                sleep(Duration::from_millis((self.delay)(get_index(request.get_hash())))).await;
+                self.in_flight.decrement();
+                self.total_in_flight.decrement();
+
+                if self.time_out {
+                    let elapsed = timeout(Duration::ZERO, std::future::pending::<()>()).await.unwrap_err();
+                    return Err(P2pError::AsyncTimeOut(elapsed))
+                }
+
+                Ok(OwnedObjectResponse::NotFound(request))
+            })
+        }
+
+        fn on_request_failure(&self, _: &P2pError) {
+            self.failures.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn get_hash(index: u64) -> Hash {
+        let mut bytes = [0u8; HASH_SIZE];
+        bytes[..8].copy_from_slice(&index.to_be_bytes());
+        Hash::new(bytes)
+    }
+
+    fn get_index(hash: &Hash) -> u64 {
+        u64::from_be_bytes(hash.as_bytes()[..8].try_into().unwrap())
+    }
+
+    fn build_requests(count: u64) -> Vec<(ObjectRequest, u64)> {
+        (0..count).map(|index| (ObjectRequest::Block(get_hash(index)), 0)).collect()
+    }
+
+    // Returns the indexes of the objects in the order they were delivered
+    async fn download_all(downloader: &mut ObjectDownloader<MockPeer>) -> Result<Vec<u64>, P2pError> {
+        let mut indexes = Vec::new();
+        while let Some(response) = downloader.next().await? {
+            indexes.push(get_index(response.get_hash()));
+        }
+        Ok(indexes)
+    }
+
+    #[tokio::test]
+    async fn test_window_limit() {
+        let total = Arc::new(Counter::default());
+        let peers: Vec<_> = (0..20).map(|id| MockPeer::new(id, &total)).collect();
+        let count = CHAIN_SYNC_DOWNLOAD_WINDOW as u64 * 3;
+
+        let mut downloader = ObjectDownloader::new(Arc::clone(&peers[0]), peers.clone(), build_requests(count));
+        let indexes = download_all(&mut downloader).await.unwrap();
+        assert_eq!(indexes.len() as u64, count);
+
+        // Enough peers to go above the window, it must be the limit
+        assert!(total.get_max() <= CHAIN_SYNC_DOWNLOAD_WINDOW);
+        for peer in peers {
+            assert!(peer.in_flight.get_max() <= CHAIN_SYNC_MAX_REQUESTS_PER_PEER);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_requests_per_peer() {
+        let total = Arc::new(Counter::default());
+        let peer = MockPeer::new(0, &total);
+        let count = CHAIN_SYNC_DOWNLOAD_WINDOW as u64 * 2;
+
+        let mut downloader = ObjectDownloader::new(Arc::clone(&peer), Vec::new(), build_requests(count));
+        downloader.set_max_requests_per_peer(4);
+        assert_eq!(download_all(&mut downloader).await.unwrap().len() as u64, count);
+        assert!(peer.in_flight.get_max() <= 4);
+    }
+
+    #[tokio::test]
+    async fn test_in_order_delivery() {
+        let total = Arc::new(Counter::default());
+        // The last objects are received first
+        let peer = MockPeer::with(0, false, |index| 50 - index, &total);
+        let peers = vec![Arc::clone(&peer), MockPeer::with(1, false, |index| 50 - index, &total)];
+
+        let mut downloader = ObjectDownloader::new(peer, peers, build_requests(50));
+        let indexes = download_all(&mut downloader).await.unwrap();
+        assert_eq!(indexes, (0..50).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_requests_are_reassigned() {
+        let total = Arc::new(Counter::default());
+        let sync_peer = MockPeer::new(0, &total);
+        let slow_peer = MockPeer::with(1, true, |_| 1, &total);
+
+        let mut downloader = ObjectDownloader::new(Arc::clone(&sync_peer), vec![Arc::clone(&slow_peer)], build_requests(40));
+        let indexes = download_all(&mut downloader).await.unwrap();
+        assert_eq!(indexes, (0..40).collect::<Vec<_>>());
+
+        // Each object requested to the slow peer was requested again to the sync peer
+        assert!(slow_peer.failures.load(Ordering::SeqCst) > 0);
+        assert_eq!(sync_peer.failures.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_download_failed() {
+        let total = Arc::new(Counter::default());
+        let sync_peer = MockPeer::with(0, true, |_| 1, &total);
+
+        let mut downloader = ObjectDownloader::new(Arc::clone(&sync_peer), Vec::new(), build_requests(3));
+        assert!(matches!(download_all(&mut downloader).await, Err(P2pError::SyncDownloadFailed(_))));
+    }
+}
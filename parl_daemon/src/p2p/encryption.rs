@@ -1,12 +1,34 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use chacha20poly1305::{aead::AeadMut, ChaCha20Poly1305, KeyInit};
+use hkdf::Hkdf;
 use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use tokio::sync::Mutex;
+use x25519_dalek::{
+    EphemeralSecret,
+    PublicKey as DHPublicKey,
+    StaticSecret
+};
 
 // This symetric key is used to encrypt/decrypt the data
 pub type EncryptionKey = [u8; 32];
+// X25519 public key sent to the peer during the key exchange
+pub type DHPublicKeyBytes = [u8; 32];
+// Unique value bound to the Diffie-Hellman session
+// It is signed by both peers to authenticate the key exchange
+pub type SessionId = [u8; 32];
+
+// Current version of the Diffie-Hellman key exchange
+// Peers not supporting it are using the legacy key exchange
+pub const DH_KEY_EXCHANGE_VERSION: u8 = 1;
+
+// HKDF labels used to derive a key for each direction
+const CLIENT_TO_SERVER_INFO: &[u8] = b"parl-p2p-client-to-server";
+const SERVER_TO_CLIENT_INFO: &[u8] = b"parl-p2p-server-to-client";
+const KEY_ROTATION_INFO: &[u8] = b"parl-p2p-key-rotation";
+const SESSION_ID_INFO: &[u8] = b"parl-p2p-session-id";
 
 // Each peer has its own key and can rotate as he want
 // The nonce is incremented by one on each encrypt/decrypt
@@ -16,11 +38,32 @@ pub type EncryptionKey = [u8; 32];
 // Also, we rotate the keys every 1 GB of data to avoid any potential attack
 // We would reach 1 GB much before the nonce overflow
 // This is a simple implementation and we can improve it later
+//
+// Keys are derived using an ephemeral X25519 Diffie-Hellman exchange
+// and HKDF, so no key is ever sent over the network.
+// Each direction has its own key.
+// To rotate our key, we generate a fresh ephemeral secret and do a DH
+// with the peer public key received during the key exchange.
+// The new key is derived from this shared secret and the previous key.
+// The key exchange packets sent by both peers are hashed in the salt,
+// so a MITM changing the version advertised by one of them ends with different keys.
 
 struct CipherState {
     cipher: ChaCha20Poly1305,
     nonce: u64,
     nonce_buffer: [u8; 12],
+    // Current key used, needed to derive the next one
+    key: EncryptionKey
+}
+
+// Diffie-Hellman state kept for the whole connection to rotate the keys
+struct DHState {
+    // Our secret used during the key exchange
+    secret: StaticSecret,
+    // Public key sent by the peer during the key exchange
+    peer_public_key: DHPublicKey,
+    // Derived from the shared secret, same for both peers
+    session_id: SessionId
 }
 
 pub struct Encryption {
@@ -28,6 +71,8 @@ pub struct Encryption {
     our_cipher: Mutex<Option<CipherState>>,
     // Cipher using the peer key to decrypt packets
     peer_cipher: Mutex<Option<CipherState>>,
+    // Set only if the keys were exchanged using Diffie-Hellman
+    dh_state: Mutex<Option<DHState>>,
     // This flag helps us to know if the encryption is ready
     // In case we want to use it before the handshake is done
     ready: AtomicBool,
@@ -56,6 +101,7 @@ impl Encryption {
         Self {
             our_cipher: Mutex::new(None),
             peer_cipher: Mutex::new(None),
+            dh_state: Mutex::new(None),
             ready: AtomicBool::new(false),
         }
     }
@@ -80,6 +126,111 @@ impl Encryption {
         ChaCha20Poly1305::generate_key(&mut OsRng).into()
     }
 
+    // Generate the secret to use for a Diffie-Hellman key exchange with its public key
+    pub fn generate_dh_secret() -> (StaticSecret, DHPublicKeyBytes) {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public_key = DHPublicKey::from(&secret).to_bytes();
+        (secret, public_key)
+    }
+
+    // Derive a key from a shared secret using HKDF-SHA256
+    fn derive_key(salt: &[u8], shared_secret: &[u8], info: &[u8]) -> Result<EncryptionKey, EncryptionError> {
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), shared_secret);
+        let mut key = [0; 32];
+        hkdf.expand(info, &mut key).map_err(|_| EncryptionError::InvalidKey)?;
+        Ok(key)
+    }
+
+    // Finish the Diffie-Hellman key exchange using the peer public key
+    // Our key and the peer key are derived from the shared secret, one for each direction
+    // out is true if we initiated the connection
+    // transcript contains the key exchange packets of the client and then of the server
+    pub async fn complete_dh_key_exchange(&self, secret: StaticSecret, peer_public_key: DHPublicKeyBytes, out: bool, transcript: &[u8]) -> Result<(), EncryptionError> {
+        let our_public_key = DHPublicKey::from(&secret);
+        let peer_public_key = DHPublicKey::from(peer_public_key);
+        let shared_secret = secret.diffie_hellman(&peer_public_key);
+        // Reject low order points that would produce a predictable secret
+        if !shared_secret.was_contributory() {
+            return Err(EncryptionError::InvalidKey)
+        }
+
+        // Both sides must use the same salt
+        let (client_key, server_key) = if out {
+            (our_public_key.as_bytes(), peer_public_key.as_bytes())
+        } else {
+            (peer_public_key.as_bytes(), our_public_key.as_bytes())
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(client_key);
+        hasher.update(server_key);
+        hasher.update(transcript);
+        let salt = hasher.finalize();
+
+        let client_to_server = Self::derive_key(&salt, shared_secret.as_bytes(), CLIENT_TO_SERVER_INFO)?;
+        let server_to_client = Self::derive_key(&salt, shared_secret.as_bytes(), SERVER_TO_CLIENT_INFO)?;
+        let session_id = Self::derive_key(&salt, shared_secret.as_bytes(), SESSION_ID_INFO)?;
+        let (our_key, peer_key) = if out {
+            (client_to_server, server_to_client)
+        } else {
+            (server_to_client, client_to_server)
+        };
+
+        self.rotate_key(our_key, true).await?;
+        self.rotate_key(peer_key, false).await?;
+
+        let mut dh_state = self.dh_state.lock().await;
+        *dh_state = Some(DHState {
+            secret,
+            peer_public_key,
+            session_id
+        });
+
+        Ok(())
+    }
+
+    // Check if the keys were exchanged using Diffie-Hellman
+    pub async fn is_dh_enabled(&self) -> bool {
+        self.dh_state.lock().await.is_some()
+    }
+
+    // Get the session id of the Diffie-Hellman key exchange
+    pub async fn get_session_id(&self) -> Option<SessionId> {
+        self.dh_state.lock().await.as_ref().map(|state| state.session_id)
+    }
+
+    // Prepare the rotation of our key using a fresh ephemeral secret
+    // Returns the public key to send to the peer and our new key
+    // The new key must be set using `rotate_key` once the public key is sent
+    pub async fn prepare_dh_key_rotation(&self) -> Result<(DHPublicKeyBytes, EncryptionKey), EncryptionError> {
+        let dh_state = self.dh_state.lock().await;
+        let dh_state = dh_state.as_ref().ok_or(EncryptionError::NotSupported)?;
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public_key = DHPublicKey::from(&secret).to_bytes();
+        let shared_secret = secret.diffie_hellman(&dh_state.peer_public_key);
+
+        let our_cipher = self.our_cipher.lock().await;
+        let current_key = our_cipher.as_ref().ok_or(EncryptionError::WriteNotReady)?.key;
+        let new_key = Self::derive_key(&current_key, shared_secret.as_bytes(), KEY_ROTATION_INFO)?;
+
+        Ok((public_key, new_key))
+    }
+
+    // Compute the new peer key from the ephemeral public key sent by the peer
+    pub async fn derive_dh_peer_key(&self, public_key: DHPublicKeyBytes) -> Result<EncryptionKey, EncryptionError> {
+        let dh_state = self.dh_state.lock().await;
+        let dh_state = dh_state.as_ref().ok_or(EncryptionError::NotSupported)?;
+
+        let shared_secret = dh_state.secret.diffie_hellman(&DHPublicKey::from(public_key));
+        if !shared_secret.was_contributory() {
+            return Err(EncryptionError::InvalidKey)
+        }
+
+        let peer_cipher = self.peer_cipher.lock().await;
+        let current_key = peer_cipher.as_ref().ok_or(EncryptionError::ReadNotReady)?.key;
+        Self::derive_key(&current_key, shared_secret.as_bytes(), KEY_ROTATION_INFO)
+    }
+
     // Encrypt a packet using the shared symetric key
     pub async fn encrypt_packet(&self, input: &[u8]) -> Result<Vec<u8>, EncryptionError> {
         let mut lock = self.our_cipher.lock().await;
@@ -121,11 +272,13 @@ impl Encryption {
         if let Some(cipher_state) = state.as_mut() {
             cipher_state.cipher = cipher;
             cipher_state.nonce = 0;
+            cipher_state.key = key;
         } else {
             *state = Some(CipherState {
                 nonce_buffer: [0; 12],
                 cipher,
-                nonce: 0
+                nonce: 0,
+                key
             });
         }
         Ok(())
@@ -143,4 +296,64 @@ impl Encryption {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dh_key_exchange_and_rotation() {
+        let client = Encryption::new();
+        let server = Encryption::new();
+
+        let (client_secret, client_public_key) = Encryption::generate_dh_secret();
+        let (server_secret, server_public_key) = Encryption::generate_dh_secret();
+        client.complete_dh_key_exchange(client_secret, server_public_key, true, b"transcript").await.unwrap();
+        server.complete_dh_key_exchange(server_secret, client_public_key, false, b"transcript").await.unwrap();
+        client.mark_as_ready();
+        server.mark_as_ready();
+
+        assert_eq!(client.get_session_id().await, server.get_session_id().await);
+
+        let encrypted = client.encrypt_packet(b"hello").await.unwrap();
+        assert_eq!(server.decrypt_packet(&encrypted).await.unwrap(), b"hello");
+        let encrypted = server.encrypt_packet(b"world").await.unwrap();
+        assert_eq!(client.decrypt_packet(&encrypted).await.unwrap(), b"world");
+
+        // Rotate the client key
+        let (public_key, new_key) = client.prepare_dh_key_rotation().await.unwrap();
+        client.rotate_key(new_key, true).await.unwrap();
+        let peer_key = server.derive_dh_peer_key(public_key).await.unwrap();
+        assert_eq!(new_key, peer_key);
+        server.rotate_key(peer_key, false).await.unwrap();
+
+        let encrypted = client.encrypt_packet(b"rotated").await.unwrap();
+        assert_eq!(server.decrypt_packet(&encrypted).await.unwrap(), b"rotated");
+    }
+
+    #[tokio::test]
+    async fn test_dh_rejects_low_order_point() {
+        let encryption = Encryption::new();
+        let (secret, _) = Encryption::generate_dh_secret();
+        assert!(encryption.complete_dh_key_exchange(secret, [0; 32], true, &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dh_binds_the_transcript() {
+        let client = Encryption::new();
+        let server = Encryption::new();
+
+        let (client_secret, client_public_key) = Encryption::generate_dh_secret();
+        let (server_secret, server_public_key) = Encryption::generate_dh_secret();
+        // the server received a packet modified by a MITM
+        client.complete_dh_key_exchange(client_secret, server_public_key, true, b"version 1").await.unwrap();
+        server.complete_dh_key_exchange(server_secret, client_public_key, false, b"version 0").await.unwrap();
+        client.mark_as_ready();
+        server.mark_as_ready();
+
+        assert_ne!(client.get_session_id().await, server.get_session_id().await);
+        let encrypted = client.encrypt_packet(b"hello").await.unwrap();
+        assert!(server.decrypt_packet(&encrypted).await.is_err());
+    }
+}
@@ -27,6 +27,7 @@ use std::{
 use thiserror::Error;
 use super::{
     encryption::EncryptionError,
+    proxy::ProxyError,
     packet::{
         bootstrap_chain::StepKind,
         object::ObjectRequest,
@@ -99,6 +100,8 @@ pub enum P2pError {
     InvalidHandshake,
     #[error("Expected Handshake packet")]
     ExpectedHandshake,
+    #[error("Expected Features packet")]
+    ExpectedFeatures,
     #[error("Invalid peer address, {}", _0)]
     InvalidPeerAddress(String), // peer address from handshake
     #[error("Invalid network")]
@@ -151,10 +154,6 @@ pub enum P2pError {
     InvalidObjectResponseType,
     #[error("Error while receiving blocker response in boost sync mode: {}", _0)]
     BoostSyncModeBlockerResponseError(#[from] RecvError),
-    #[error("Error while waiting on blocker in boost sync mode")]
-    BoostSyncModeBlockerError,
-    #[error("Boost sync mode failed: {}", _0)]
-    BoostSyncModeFailed(Box<P2pError>),
     #[error("Expected a block type")]
     ExpectedBlock,
     #[error("Expected a transaction type")]
@@ -175,6 +174,176 @@ pub enum P2pError {
     SemaphoreAcquireError(#[from] AcquireError),
     #[error(transparent)]
     EncryptionError(#[from] EncryptionError),
+    #[error("Peer doesn't support the Diffie-Hellman key exchange")]
+    DHKeyExchangeNotSupported,
+    #[error("Peer supports the Diffie-Hellman key exchange but used the legacy one")]
+    KeyExchangeDowngrade,
+    #[error("Invalid key exchange version {}", _0)]
+    InvalidKeyExchangeVersion(u8),
+    #[error("Invalid identity file {}", _0)]
+    InvalidIdentityFile(String),
+    #[error("Invalid node identity public key in '{}'", _0)]
+    InvalidPinnedIdentity(String),
+    #[error("Expected an identity proof packet")]
+    ExpectedIdentityProof,
+    #[error("Invalid identity proof signature")]
+    InvalidIdentityProof,
+    #[error("Peer id {} is not derived from its identity key", _0)]
+    PeerIdMismatch(u64),
+    #[error("Identity of {} doesn't match the pinned key", _0)]
+    UnexpectedIdentity(SocketAddr),
+    #[error("{} has a pinned identity but can't authenticate", _0)]
+    IdentityRequired(SocketAddr),
+    #[error(transparent)]
+    ProxyError(#[from] ProxyError),
+    #[error("Invalid IP range '{}', expected an IP address or a CIDR range", _0)]
+    InvalidIpRange(String),
+    #[error("Compact block {} can't be rebuilt with the transactions received", _0)]
+    InvalidCompactBlock(Hash),
+    #[error("Received transactions for compact block {} that we didn't request", _0)]
+    UnrequestedBlockTransactions(Hash),
+    #[error("Invalid transaction index {} requested for block {}", _0, _1)]
+    InvalidBlockTransactionIndex(u16, Hash),
+    #[error("Requested transactions of block {} that we didn't propagate as a compact block", _0)]
+    UnknownCompactBlock(Hash),
+    #[error("Invalid transaction indexes requested for block {}", _0)]
+    InvalidBlockTransactionsRequest(Hash),
+    #[error("Object {} couldn't be downloaded from any peer during sync", _0)]
+    SyncDownloadFailed(Hash),
+    #[error("Download task was interrupted during sync")]
+    SyncDownloadInterrupted,
+}
+
+// Verify if the blockchain rejected data because it's invalid
+// Other errors may be local failures (storage, pruned data) or ordering races
+// and must not be held against the peer
+fn is_invalid_peer_data(err: &BlockchainError) -> bool {
+    matches!(
+        err,
+        BlockchainError::InvalidBalancesMerkleHash(..)
+        | BlockchainError::InvalidTipsMerkleHash(..)
+        | BlockchainError::TxTooBig(..)
+        | BlockchainError::TimestampIsLessThanParent(_)
+        | BlockchainError::TimestampIsInFuture(..)
+        | BlockchainError::InvalidBlockHeight(..)
+        | BlockchainError::BlockHeightZeroNotAllowed
+        | BlockchainError::InvalidBlockHeightStableHeight
+        | BlockchainError::InvalidDifficulty
+        | BlockchainError::InvalidHash(..)
+        | BlockchainError::InvalidPreviousBlockHash(..)
+        | BlockchainError::InvalidBlockSize(..)
+        | BlockchainError::InvalidBlockTxs(..)
+        | BlockchainError::InvalidTxInBlock(_)
+        | BlockchainError::TxEmpty(_)
+        | BlockchainError::TooManyOutputInTx(_)
+        | BlockchainError::DuplicateRegistration(_)
+        | BlockchainError::InvalidTxFee(..)
+        | BlockchainError::CoinbaseTxNotAllowed(_)
+        | BlockchainError::InvalidBlockReward(..)
+        | BlockchainError::InvalidFeeReward(..)
+        | BlockchainError::InvalidTxRegistrationPoW(_)
+        | BlockchainError::InvalidTxRegistrationSignature(_)
+        | BlockchainError::InvalidTransactionToSender(_)
+        | BlockchainError::InvalidTransactionExtraDataTooBig(..)
+        | BlockchainError::InvalidNetwork
+        | BlockchainError::InvalidTransactionSignature
+        | BlockchainError::UnexpectedTransactionSignature
+        | BlockchainError::InvalidMinerTx
+        | BlockchainError::GenesisBlockMiner
+        | BlockchainError::InvalidGenesisBlock
+        | BlockchainError::NoTxSignature
+        | BlockchainError::UnexpectedTransactionVariant
+        | BlockchainError::ExpectedTips
+        | BlockchainError::InvalidTipsCount(..)
+        | BlockchainError::InvalidTipsDifficulty(..)
+        | BlockchainError::InvalidBlockVersion
+        | BlockchainError::InvalidTxVersion
+        | BlockchainError::InvalidReachability
+        | BlockchainError::BlockDeviation
+        | BlockchainError::InvalidGenesisHash
+        | BlockchainError::NoValueForBurn
+        | BlockchainError::InvalidCiphertext
+        | BlockchainError::NoSenderOutput
+        | BlockchainError::SenderIsReceiver
+        | BlockchainError::TransactionProof(_)
+        | BlockchainError::ErrorOnReader(_)
+        | BlockchainError::ErrorOnSignature(_)
+        | BlockchainError::DecompressionError(_)
+        | BlockchainError::InvalidStateCommitment(..)
+    )
+}
+
+impl P2pError {
+    // Score penalty applied to a peer for this error
+    // Protocol violations are penalized the most, network issues the least
+    // because they are mostly due to a flaky connection
+    pub fn get_score_penalty(&self) -> i64 {
+        match self {
+            // Races with another peer sending the same data
+            Self::BlockchainError(e) if matches!(
+                **e,
+                BlockchainError::TxAlreadyInMempool(_)
+                | BlockchainError::TxAlreadyInBlock(_)
+                | BlockchainError::TxAlreadyInBlockchain(_)
+                | BlockchainError::AlreadyInChain
+            ) => 0,
+            // Invalid data sent by the peer
+            Self::BlockchainError(e) if is_invalid_peer_data(e) => 20,
+            // Local failures or data that is valid on another chain state
+            Self::BlockchainError(_) => 1,
+            // Network issues
+            Self::AsyncTimeOut(_)
+            | Self::NoResponse
+            | Self::TrackerRequestExpired
+            | Self::ErrorStd(_)
+            | Self::Disconnected
+            | Self::SendError(_) => 1,
+            // Requests too fast or duplicated data
+            Self::RequestSyncChainTooFast
+            | Self::PeerInvalidPeerListCountdown(_)
+            | Self::PeerInvalidPingCoutdown
+            | Self::AlreadyTrackedBlock(..)
+            | Self::AlreadyTrackedTx(_)
+            | Self::DuplicatedPeer(..) => 5,
+            // Useless or unrequested data
+            Self::ObjectNotRequested(_)
+            | Self::ObjectHashNotPresentInQueue(_)
+            | Self::UnrequestedChainResponse
+            | Self::UnrequestedBootstrapChainResponse
+            | Self::BlockPropagatedUnderStableHeight(..)
+            | Self::UnrequestedBlockTransactions(_)
+            | Self::UnknownCompactBlock(_) => 10,
+            // Invalid data
+            Self::InvalidObjectHash(..)
+            | Self::InvalidObjectResponse(_)
+            | Self::InvalidObjectResponseType
+            | Self::InvalidCommonPoint(_)
+            | Self::InvalidMerkleHash
+            | Self::ExpectedBlock
+            | Self::ExpectedTransaction
+            | Self::InvalidCompactBlock(_)
+            | Self::InvalidBlockTransactionIndex(..)
+            | Self::InvalidBlockTransactionsRequest(_) => 20,
+            // Protocol violations
+            Self::InvalidPacket
+            | Self::InvalidPacketSize
+            | Self::InvalidPacketNotFullRead
+            | Self::ReaderError(_)
+            | Self::MalformedChainRequest(_)
+            | Self::InvaliChainResponseSize(..)
+            | Self::InvalidPeerlist
+            | Self::InvalidInventoryPagination
+            | Self::InvalidBlockIdList
+            | Self::InvalidPrunedTopoHeight(..)
+            | Self::InvalidNewPrunedTopoHeight(..)
+            | Self::InvalidPrunedTopoHeightChange
+            | Self::OwnSocketAddress(_)
+            | Self::LocalSocketAddress(_)
+            | Self::InvalidBootstrapStep(..)
+            | Self::EncryptionError(_) => 30,
+            _ => 5
+        }
+    }
 }
 
 impl From<BlockchainError> for P2pError {
@@ -201,3 +370,22 @@ impl<T> From<TSendError<T>> for P2pError {
         Self::SendError(format!("{}", err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::core::error::DiskContext;
+    use super::*;
+
+    #[test]
+    fn test_blockchain_error_penalty() {
+        // invalid data sent by the peer
+        assert_eq!(P2pError::from(BlockchainError::InvalidBlockHeight(10, 20)).get_score_penalty(), 20);
+        assert_eq!(P2pError::from(BlockchainError::InvalidTransactionSignature).get_score_penalty(), 20);
+        // same data sent by another peer
+        assert_eq!(P2pError::from(BlockchainError::TxAlreadyInMempool(Hash::zero())).get_score_penalty(), 0);
+        // local failures
+        assert_eq!(P2pError::from(BlockchainError::NotFoundOnDisk(DiskContext::Tips)).get_score_penalty(), 1);
+        assert_eq!(P2pError::from(BlockchainError::UnsupportedOperation).get_score_penalty(), 1);
+        assert_eq!(P2pError::from(BlockchainError::BlockNotFound(Hash::zero())).get_score_penalty(), 1);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use log::{info, warn};
+use parl_common::{
+    crypto::{hash, Hash, KeyPair, PrivateKey, PublicKey},
+    serializer::Serializer
+};
+use super::{encryption::SessionId, error::P2pError};
+
+// Domain separator for the handshake transcript
+const TRANSCRIPT_DOMAIN: &[u8] = b"parl-p2p-identity";
+
+// Persistent identity of the node
+// The private key is stored in the data dir so the peer id doesn't change between restarts
+pub struct NodeIdentity {
+    keypair: KeyPair,
+    public_key: PublicKey,
+    peer_id: u64
+}
+
+impl NodeIdentity {
+    // Load the identity from the file or generate a new one if it doesn't exist
+    pub fn load_or_create(filename: &str) -> Result<Self, P2pError> {
+        let keypair = match fs::read_to_string(filename) {
+            Ok(content) => {
+                let private_key = PrivateKey::from_hex(content.trim().to_owned())
+                    .map_err(|_| P2pError::InvalidIdentityFile(filename.to_owned()))?;
+                KeyPair::from_private_key(private_key)
+            },
+            Err(e) => {
+                if fs::metadata(filename).is_ok() {
+                    return Err(e.into())
+                }
+
+                info!("No identity found, generating a new one in {}", filename);
+                let keypair = KeyPair::new();
+                fs::write(filename, keypair.get_private_key().to_hex())?;
+                Self::restrict_permissions(filename);
+                keypair
+            }
+        };
+
+        let public_key = keypair.get_public_key().compress();
+        let peer_id = get_peer_id_from_public_key(&public_key);
+
+        Ok(Self {
+            keypair,
+            public_key,
+            peer_id
+        })
+    }
+
+    // Only the owner can read the private key
+    #[cfg(unix)]
+    fn restrict_permissions(filename: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(e) = fs::set_permissions(filename, fs::Permissions::from_mode(0o600)) {
+            warn!("Couldn't restrict permissions of identity file {}: {}", filename, e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn restrict_permissions(_: &str) {}
+
+    pub fn get_public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn get_peer_id(&self) -> u64 {
+        self.peer_id
+    }
+
+    pub fn get_keypair(&self) -> &KeyPair {
+        &self.keypair
+    }
+}
+
+// Peer id is the first 8 bytes of the hash of the identity public key
+pub fn get_peer_id_from_public_key(public_key: &PublicKey) -> u64 {
+    let hash = hash(public_key.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash.as_bytes()[0..8]);
+    u64::from_le_bytes(bytes)
+}
+
+// Build the transcript signed by both peers
+// It contains the Diffie-Hellman session id to bind the identity to this connection,
+// so a proof can't be replayed by a MITM on another session
+pub fn build_handshake_transcript(session_id: &SessionId, client_handshake: &[u8], server_handshake: &[u8]) -> Hash {
+    let mut transcript = Vec::with_capacity(TRANSCRIPT_DOMAIN.len() + session_id.len() + 8 + client_handshake.len() + server_handshake.len());
+    transcript.extend_from_slice(TRANSCRIPT_DOMAIN);
+    transcript.extend_from_slice(session_id);
+    // Prefix each handshake with its size so they can't be shifted
+    for handshake in [client_handshake, server_handshake] {
+        transcript.extend_from_slice(&(handshake.len() as u32).to_be_bytes());
+        transcript.extend_from_slice(handshake);
+    }
+    hash(&transcript)
+}
+
+// Parse a node entry from the config
+// It can be `<address>` or `<identity public key in hex>@<address>` to pin its identity
+pub fn parse_node_entry(value: &str) -> Result<(Option<PublicKey>, &str), P2pError> {
+    match value.split_once('@') {
+        Some((key, addr)) => {
+            let key = PublicKey::from_hex(key.to_owned())
+                .map_err(|_| P2pError::InvalidPinnedIdentity(value.to_owned()))?;
+            Ok((Some(key), addr))
+        },
+        None => Ok((None, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_node_entry() {
+        let keypair = KeyPair::new();
+        let public_key = keypair.get_public_key().compress();
+
+        let (identity, addr) = parse_node_entry("127.0.0.1:2125").unwrap();
+        assert!(identity.is_none());
+        assert_eq!(addr, "127.0.0.1:2125");
+
+        let entry = format!("{}@127.0.0.1:2125", public_key.to_hex());
+        let (identity, addr) = parse_node_entry(&entry).unwrap();
+        assert_eq!(identity, Some(public_key));
+        assert_eq!(addr, "127.0.0.1:2125");
+
+        assert!(parse_node_entry("invalid@127.0.0.1:2125").is_err());
+    }
+}
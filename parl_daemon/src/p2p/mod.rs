@@ -4,6 +4,13 @@ pub mod error;
 pub mod packet;
 pub mod peer_list;
 pub mod chain_validator;
+pub mod identity;
+pub mod proxy;
+pub mod ban;
+pub mod bandwidth;
+pub mod dandelion;
+mod downloader;
+mod score;
 mod tracker;
 mod encryption;
 
@@ -20,15 +27,18 @@ use parl_common::{
     },
     block::{Block, BlockHeader},
     config::{TIPS_LIMIT, VERSION},
-    crypto::{Hash, Hashable},
+    crypto::{Hash, Hashable, PublicKey},
     difficulty::CumulativeDifficulty,
     immutable::Immutable,
+    light::{LightHeader, StateLeaf, StateProof},
     serializer::Serializer,
+    transaction::Transaction,
     thread_pool::ThreadPool,
     time::{
         get_current_time_in_millis,
         get_current_time_in_seconds,
-        TimestampMillis
+        TimestampMillis,
+        TimestampSeconds
     },
     utils::spawn_task
 };
@@ -36,16 +46,18 @@ use crate::{
     config::{
         get_genesis_block_hash,
         get_seed_nodes,
-        CHAIN_SYNC_DEFAULT_RESPONSE_BLOCKS, CHAIN_SYNC_DELAY, CHAIN_SYNC_REQUEST_EXPONENTIAL_INDEX_START,
+        CHAIN_SYNC_DEFAULT_RESPONSE_BLOCKS, CHAIN_SYNC_DELAY, CHAIN_SYNC_DOWNLOAD_WINDOW, CHAIN_SYNC_REQUEST_EXPONENTIAL_INDEX_START,
         CHAIN_SYNC_REQUEST_MAX_BLOCKS, CHAIN_SYNC_RESPONSE_MAX_BLOCKS, CHAIN_SYNC_RESPONSE_MIN_BLOCKS,
-        CHAIN_SYNC_TOP_BLOCKS, MILLIS_PER_SECOND, NETWORK_ID, P2P_AUTO_CONNECT_PRIORITY_NODES_DELAY,
+        CHAIN_SYNC_TOP_BLOCKS, COMPACT_BLOCK_CHECK_INTERVAL, COMPACT_BLOCK_TIMEOUT, DANDELION_EMBARGO_CHECK_INTERVAL, LIGHT_CLIENT_MAX_HEADERS, MILLIS_PER_SECOND, NETWORK_ID, P2P_AUTO_CONNECT_PRIORITY_NODES_DELAY,
         P2P_EXTEND_PEERLIST_DELAY, P2P_PING_DELAY, P2P_PING_PEER_LIST_DELAY, P2P_PING_PEER_LIST_LIMIT,
-        PEER_FAIL_LIMIT, PEER_MAX_PACKET_SIZE, PEER_TIMEOUT_INIT_CONNECTION, PEER_TIMEOUT_INIT_OUTGOING_CONNECTION,
+        PEER_FAIL_LIMIT, PEER_LEGACY_KEY_EXCHANGE_TIME, PEER_MAX_PACKET_SIZE, PEER_TIMEOUT_INIT_CONNECTION, PEER_TIMEOUT_INIT_OUTGOING_CONNECTION,
+        PEER_SCORE_MIN, PEER_SCORE_MIN_OBJECT_REQUEST, PEER_SCORE_REWARD_BLOCK,
         PRUNE_SAFETY_LIMIT, STABLE_LIMIT, P2P_PING_TIMEOUT, P2P_HEARTBEAT_INTERVAL, PEER_SEND_BYTES_TIMEOUT
     },
     core::{
         blockchain::Blockchain,
         error::BlockchainError,
+        state_tree::build_state_tree_proof,
         storage::Storage
     },
     p2p::{
@@ -59,6 +71,7 @@ use crate::{
                 MAX_ITEMS_PER_PAGE
             },
             chain::CommonPoint,
+            identity::IdentityProof,
             inventory::{
                 NotifyInventoryRequest,
                 NotifyInventoryResponse,
@@ -70,11 +83,29 @@ use crate::{
     rpc::rpc::get_peer_entry
 };
 use self::{
+    bandwidth::{BandwidthLimiter, BandwidthLimits, ConnectionLimiter},
     connection::{Connection, State},
+    dandelion::Dandelion,
+    downloader::ObjectDownloader,
     error::P2pError,
+    identity::{build_handshake_transcript, get_peer_id_from_public_key, NodeIdentity},
+    proxy::Socks5Proxy,
     packet::{
         chain::{BlockId, ChainRequest, ChainResponse},
-        handshake::Handshake,
+        compact_block::{
+            are_valid_tx_indexes,
+            get_short_tx_id,
+            get_txs_to_request,
+            rebuild_header,
+            resolve_short_tx_ids,
+            BlockTransactions,
+            CompactBlock,
+            GetBlockTransactions,
+            PrefilledTransaction,
+            ShortTxId
+        },
+        handshake::{Handshake, FEATURE_DH_KEY_EXCHANGE, SUPPORTED_FEATURES},
+        light::{LightHeadersResponse, StateProofRequest, StateProofResponse},
         object::{ObjectRequest, ObjectResponse, OwnedObjectResponse},
         ping::Ping,
         Packet,
@@ -104,7 +135,7 @@ use tokio::{
 use log::{info, warn, error, debug, trace};
 use std::{
     borrow::Cow,
-    collections::{hash_map::Entry, HashSet},
+    collections::{hash_map::Entry, HashMap, HashSet},
     io,
     net::{IpAddr, SocketAddr},
     num::NonZeroUsize,
@@ -115,14 +146,33 @@ use std::{
     time::Duration
 };
 use bytes::Bytes;
-use rand::{seq::IteratorRandom, Rng};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    seq::IteratorRandom,
+    Rng
+};
+
+// Compact block waiting on the missing transactions requested to the peer
+struct PendingCompactBlock {
+    // Peer that sent the compact block
+    peer: Arc<Peer>,
+    // When the missing txs were requested
+    requested_at: TimestampSeconds,
+    // Header without the tx hashes
+    header: BlockHeader,
+    salt: u64,
+    short_ids: Vec<ShortTxId>,
+    // Resolved tx hashes, None for the missing ones
+    txs: Vec<Option<Hash>>
+}
 
 // P2pServer is a fully async TCP server
 // Each connection will block on a data to send or to receive
 // useful for low end hardware
 pub struct P2pServer<S: Storage> {
-    // unique peer id
-    peer_id: u64,
+    // Persistent identity of the node
+    // unique peer id is derived from its public key
+    identity: NodeIdentity,
     // node tag sent on handshake
     tag: Option<String>,
     // max peers accepted by this server
@@ -141,6 +191,11 @@ pub struct P2pServer<S: Storage> {
     is_running: AtomicBool,
     // Synced cache to prevent concurrent tasks adding the block
     blocks_propagation_queue: Mutex<LruCache<Hash, ()>>,
+    // Compact blocks waiting on a BlockTransactions response
+    pending_compact_blocks: Mutex<LruCache<Hash, PendingCompactBlock>>,
+    // Blocks we propagated as compact blocks with their txs count
+    // only their txs can be requested by our peers
+    propagated_compact_blocks: Mutex<LruCache<Hash, usize>>,
     // Sender for the blocks processing task to have a ordered queue
     blocks_processor: Sender<(Arc<Peer>, BlockHeader, Hash)>,
     // allow fast syncing (only balances / assets / Smart Contracts changes)
@@ -157,20 +212,41 @@ pub struct P2pServer<S: Storage> {
     // Configured exclusive nodes
     // If not empty, no other peer than those listed can connect to this node
     exclusive_nodes: IndexSet<SocketAddr>,
+    // Identity keys expected for some nodes
+    // Connections to these nodes are rejected if they can't prove it
+    pinned_identities: HashMap<SocketAddr, PublicKey>,
     // Are we allowing others nodes to share us as a potential peer ?
     // Also if we allows to be listed in get_peers RPC API
     sharable: bool,
+    // SOCKS5 proxy used for all outgoing connections
+    // When set, we don't share our IP nor our peers addresses
+    proxy: Option<Socks5Proxy>,
+    // Bandwidth limits configured for all peers and for each peer
+    bandwidth_limits: BandwidthLimits,
+    // Shared by all connections to apply the global limits
+    global_bandwidth_limiter: Arc<BandwidthLimiter>,
+    // Dandelion++ state to relay our txs in stem phase
+    // None if disabled, all txs are then fluffed directly
+    dandelion: Option<Dandelion>,
     // Do we try to connect to others nodes
     // If this is enabled, only way to have peers is to let them connect to us
     outgoing_connections_disabled: AtomicBool,
     // Are we syncing the chain with another peer
     is_syncing: AtomicBool,
+    // Peers that didn't support the Diffie-Hellman key exchange with the time of the failure
+    // We fallback to the legacy key exchange when connecting to them
+    // until PEER_LEGACY_KEY_EXCHANGE_TIME is elapsed
+    legacy_key_exchange_peers: Mutex<HashMap<SocketAddr, TimestampSeconds>>,
+    // Peers with which we completed a Diffie-Hellman key exchange
+    // A closed connection is not enough to fallback on the legacy key exchange with them,
+    // because anyone on the path can close it
+    dh_key_exchange_peers: Mutex<HashSet<SocketAddr>>,
     // Exit channel to notify all tasks to stop
     exit_sender: broadcast::Sender<()>
 }
 
 impl<S: Storage> P2pServer<S> {
-    pub fn new(concurrency: usize, dir_path: Option<String>, tag: Option<String>, max_peers: usize, bind_address: String, blockchain: Arc<Blockchain<S>>, use_peerlist: bool, exclusive_nodes: Vec<SocketAddr>, allow_fast_sync_mode: bool, allow_boost_sync_mode: bool, max_chain_response_size: Option<usize>, sharable: bool, disable_outgoing_connections: bool) -> Result<Arc<Self>, P2pError> {
+    pub fn new(concurrency: usize, dir_path: Option<String>, tag: Option<String>, max_peers: usize, bind_address: String, blockchain: Arc<Blockchain<S>>, use_peerlist: bool, exclusive_nodes: Vec<SocketAddr>, pinned_identities: HashMap<SocketAddr, PublicKey>, allow_fast_sync_mode: bool, allow_boost_sync_mode: bool, max_chain_response_size: Option<usize>, sharable: bool, disable_outgoing_connections: bool, proxy: Option<String>, bandwidth_limits: BandwidthLimits, disable_dandelion: bool) -> Result<Arc<Self>, P2pError> {
         if tag.as_ref().is_some_and(|tag| tag.len() == 0 || tag.len() > 16) {
             return Err(P2pError::InvalidTag);
         }
@@ -183,9 +259,22 @@ impl<S: Storage> P2pServer<S> {
             return Err(P2pError::InvalidMaxPeers);
         }
 
+        let proxy = match proxy {
+            Some(url) => {
+                let proxy = Socks5Proxy::from_url(&url)?;
+                info!("All outgoing P2p connections will use the proxy {}, IP sharing is disabled", proxy);
+                Some(proxy)
+            },
+            None => None
+        };
+
+        let dir_path = dir_path.unwrap_or_default();
+        let network = blockchain.get_network().to_string().to_lowercase();
+        // load our identity, peer id is derived from it
+        let identity = NodeIdentity::load_or_create(&format!("{}identity-{}.key", dir_path, network))?;
+        info!("P2p identity public key: {}", identity.get_public_key().to_hex());
+
         // set channel to communicate with listener thread
-        let mut rng = rand::thread_rng();
-        let peer_id: u64 = rng.gen(); // generate a random peer id for network
         let addr: SocketAddr = bind_address.parse()?; // parse the bind address
         // create mspc channel for connections to peers
         let (connections_sender, connections_receiver) = mpsc::channel(max_peers);
@@ -196,11 +285,11 @@ impl<S: Storage> P2pServer<S> {
         let object_tracker = ObjectTracker::new(blockchain.clone(), exit_receiver);
 
         let (sender, event_receiver) = channel::<Arc<Peer>>(max_peers); 
-        let peer_list = PeerList::new(max_peers, format!("{}peerlist-{}.json", dir_path.unwrap_or_default(), blockchain.get_network().to_string().to_lowercase()), Some(sender));
+        let peer_list = PeerList::new(max_peers, format!("{}peerlist-{}.json", dir_path, network), format!("{}bans-{}.json", dir_path, network), Some(sender));
 
 
         let server = Self {
-            peer_id,
+            identity,
             tag,
             max_peers,
             bind_address: addr,
@@ -210,14 +299,24 @@ impl<S: Storage> P2pServer<S> {
             object_tracker,
             is_running: AtomicBool::new(true),
             blocks_propagation_queue: Mutex::new(LruCache::new(NonZeroUsize::new(STABLE_LIMIT as usize * TIPS_LIMIT).unwrap())),
+            pending_compact_blocks: Mutex::new(LruCache::new(NonZeroUsize::new(STABLE_LIMIT as usize * TIPS_LIMIT).unwrap())),
+            propagated_compact_blocks: Mutex::new(LruCache::new(NonZeroUsize::new(STABLE_LIMIT as usize * TIPS_LIMIT).unwrap())),
             blocks_processor,
             allow_fast_sync_mode,
             allow_boost_sync_mode,
             max_chain_response_size: max_chain_response_size.unwrap_or(CHAIN_SYNC_DEFAULT_RESPONSE_BLOCKS),
             exclusive_nodes: IndexSet::from_iter(exclusive_nodes.into_iter()),
-            sharable,
+            pinned_identities,
+            // Our IP must not be shared when using a proxy
+            sharable: sharable && proxy.is_none(),
+            proxy,
+            global_bandwidth_limiter: Arc::new(BandwidthLimiter::new(bandwidth_limits.global_upload, bandwidth_limits.global_download)),
+            bandwidth_limits,
+            dandelion: if disable_dandelion { None } else { Some(Dandelion::new()) },
             is_syncing: AtomicBool::new(false),
             outgoing_connections_disabled: AtomicBool::new(disable_outgoing_connections),
+            legacy_key_exchange_peers: Mutex::new(HashMap::new()),
+            dh_key_exchange_peers: Mutex::new(HashSet::new()),
             exit_sender,
         };
 
@@ -338,9 +437,17 @@ impl<S: Storage> P2pServer<S> {
         // start the blocks processing task to have a queued handler
         spawn_task("p2p-blocks", Arc::clone(&self).blocks_processing_task(blocks_processor_receiver));
 
+        // start the task requesting the full block of the expired compact blocks
+        spawn_task("p2p-compact-blocks", Arc::clone(&self).compact_blocks_loop());
+
         // start the event loop task to handle peer disconnect events
         spawn_task("p2p-events", Arc::clone(&self).event_loop(event_receiver));
 
+        // start the task fluffing the stem txs with an expired embargo
+        if self.dandelion.is_some() {
+            spawn_task("p2p-dandelion", Arc::clone(&self).dandelion_loop());
+        }
+
 
         // start another task for peerlist loop
         if use_peerlist {
@@ -462,6 +569,17 @@ impl<S: Storage> P2pServer<S> {
                 Ok(handshake) => handshake,
                 Err(e) => {
                     debug!("Error while verifying connection to address {}: {}", addr, e);
+                    // A pinned node must support the Diffie-Hellman key exchange to be authenticated
+                    // and a peer that already used it can't go back to the legacy one
+                    if matches!(e, P2pError::DHKeyExchangeNotSupported) && !self.pinned_identities.contains_key(&addr) {
+                        if self.dh_key_exchange_peers.lock().await.contains(&addr) {
+                            warn!("{} closed the connection on the Diffie-Hellman key exchange it supports, not using the legacy one", addr);
+                        } else {
+                            debug!("{} doesn't support the Diffie-Hellman key exchange, using the legacy one on next connection", addr);
+                            self.legacy_key_exchange_peers.lock().await.insert(addr, get_current_time_in_seconds());
+                        }
+                    }
+
                     if !priority {
                         self.peer_list.increase_fail_count_for_stored_peer(&addr.ip(), false).await;
                     }
@@ -469,6 +587,10 @@ impl<S: Storage> P2pServer<S> {
                 }
             };
 
+            if !peer.0.get_connection().is_legacy_key_exchange() {
+                self.dh_key_exchange_peers.lock().await.insert(addr);
+            }
+
             // Peer is valid, send it to connect
             if let Err(e) = tx.send(peer).await {
                 error!("Error while sending new connection to listener: {}, exiting task", e);
@@ -499,7 +621,7 @@ impl<S: Storage> P2pServer<S> {
             return Ok(())
         }
 
-        let connection = Connection::new(stream, addr, false);
+        let connection = Connection::new(stream, addr, false, self.create_connection_limiter());
         let zelf = Arc::clone(&self);
         let tx = tx.clone();
         thread_pool.execute(async move {
@@ -584,6 +706,83 @@ impl<S: Storage> P2pServer<S> {
         Ok(())
     }
 
+    // Get the pinned identity of a peer
+    // For incoming connections, the peer is also searched using its local port
+    fn get_pinned_identity(&self, addr: &SocketAddr, local_port: u16) -> Option<&PublicKey> {
+        self.pinned_identities.get(addr)
+            .or_else(|| self.pinned_identities.get(&SocketAddr::new(addr.ip(), local_port)))
+    }
+
+    // Authenticate the peer using its identity key
+    // Each side signs the handshake transcript bound to the Diffie-Hellman session
+    // Peers using the legacy key exchange can't be authenticated
+    async fn verify_identity(&self, buf: &mut [u8], connection: &mut Connection, handshake: &Handshake<'_>, our_handshake: &[u8], peer_handshake: &[u8]) -> Result<(), P2pError> {
+        let pinned_identity = self.get_pinned_identity(connection.get_address(), handshake.get_local_port());
+        let Some(session_id) = connection.get_session_id().await else {
+            if pinned_identity.is_some() {
+                return Err(P2pError::IdentityRequired(*connection.get_address()))
+            }
+
+            debug!("{} is using the legacy key exchange, its identity can't be verified", connection);
+            return Ok(())
+        };
+
+        let (client_handshake, server_handshake) = if connection.is_out() {
+            (our_handshake, peer_handshake)
+        } else {
+            (peer_handshake, our_handshake)
+        };
+        let transcript = build_handshake_transcript(&session_id, client_handshake, server_handshake);
+
+        trace!("Sending identity proof to {}", connection);
+        let signature = self.identity.get_keypair().sign(transcript.as_bytes());
+        let proof = IdentityProof::new(self.identity.get_public_key().clone(), signature);
+        connection.send_bytes(&Packet::IdentityProof(Cow::Owned(proof)).to_bytes()).await?;
+
+        let proof = match timeout(Duration::from_millis(PEER_TIMEOUT_INIT_CONNECTION), connection.read_packet(buf, buf.len() as u32)).await?? {
+            Packet::IdentityProof(proof) => proof.into_owned(),
+            _ => return Err(P2pError::ExpectedIdentityProof)
+        };
+
+        let (public_key, signature) = proof.consume();
+        let key = public_key.decompress().map_err(|_| P2pError::InvalidIdentityProof)?;
+        if !signature.verify(transcript.as_bytes(), &key) {
+            return Err(P2pError::InvalidIdentityProof)
+        }
+
+        if get_peer_id_from_public_key(&public_key) != handshake.get_peer_id() {
+            return Err(P2pError::PeerIdMismatch(handshake.get_peer_id()))
+        }
+
+        if let Some(expected) = pinned_identity {
+            if *expected != public_key {
+                return Err(P2pError::UnexpectedIdentity(*connection.get_address()))
+            }
+        }
+
+        trace!("Identity of {} has been verified", connection);
+        connection.set_identity(public_key);
+
+        Ok(())
+    }
+
+    // Exchange the supported features with the peer
+    // Only peers recent enough send them, older ones would reject the packet
+    async fn exchange_features(&self, buf: &mut [u8], connection: &Connection, handshake: &Handshake<'_>) -> Result<u8, P2pError> {
+        if !handshake.supports_features() {
+            debug!("{} doesn't exchange its features (version {})", connection, handshake.get_version());
+            return Ok(0)
+        }
+
+        trace!("Sending our features to {}", connection);
+        connection.send_bytes(&Packet::Features(SUPPORTED_FEATURES).to_bytes()).await?;
+
+        match timeout(Duration::from_millis(PEER_TIMEOUT_INIT_CONNECTION), connection.read_packet(buf, buf.len() as u32)).await?? {
+            Packet::Features(features) => Ok(features),
+            _ => Err(P2pError::ExpectedFeatures)
+        }
+    }
+
     // Build a handshake packet
     // We feed the packet with all chain data
     async fn build_handshake(&self) -> Result<Vec<u8>, P2pError> {
@@ -599,8 +798,8 @@ impl<S: Storage> P2pServer<S> {
 
     // Create a valid peer using the connection, if an error happen, it will close the stream and return the error
     async fn create_verified_peer(&self, buf: &mut [u8], mut connection: Connection, priority: bool) -> Result<(Peer, Rx), P2pError> {
-        let handshake = match self.verify_connection(buf, &mut connection).await {
-            Ok(handshake) => handshake,
+        let (handshake, features) = match self.verify_connection(buf, &mut connection).await {
+            Ok(res) => res,
             Err(e) => {
                 debug!("Error while verifying connection with {}: {}", connection, e);
                 connection.close().await?;
@@ -608,14 +807,15 @@ impl<S: Storage> P2pServer<S> {
             }
         };
 
-        let (peer, rx) = handshake.create_peer(connection, priority, self.peer_list.clone());
+        let (peer, rx) = handshake.create_peer(connection, priority, self.peer_list.clone(), features);
         Ok((peer, rx))
     }
 
     // this function handle all new connections
     // A new connection have to send an Handshake
     // if the handshake is valid, we accept it & register it on server
-    async fn verify_connection(&self, buf: &mut [u8], connection: &mut Connection) -> Result<Handshake, P2pError> {
+    // Returns the handshake with the features of the peer
+    async fn verify_connection(&self, buf: &mut [u8], connection: &mut Connection) -> Result<(Handshake, u8), P2pError> {
         trace!("New connection: {}", connection);
 
         // Exchange encryption keys
@@ -623,8 +823,9 @@ impl<S: Storage> P2pServer<S> {
 
         // Start handshake now
         connection.set_state(State::Handshake);
+        let mut our_handshake = None;
         if connection.is_out() {
-            self.send_handshake(&connection).await?;
+            our_handshake = Some(self.send_handshake(&connection).await?);
         }
 
         // wait on the handshake packet
@@ -633,6 +834,7 @@ impl<S: Storage> P2pServer<S> {
             Packet::Handshake(h) => h.into_owned(),
             _ => return Err(P2pError::ExpectedHandshake)
         };
+        let peer_handshake = Packet::Handshake(Cow::Borrowed(&handshake)).to_bytes();
 
         trace!("received handshake packet!");
         self.verify_handshake(connection, &mut handshake).await?;
@@ -640,15 +842,32 @@ impl<S: Storage> P2pServer<S> {
         trace!("Handshake has been verified");
         // if it's a outgoing connection, don't send the handshake back
         // because we have already sent it
-        if !connection.is_out() {
-            trace!("Sending handshake back to {}", connection);
-            self.send_handshake(&connection).await?;
+        let our_handshake = match our_handshake {
+            Some(our_handshake) => our_handshake,
+            None => {
+                trace!("Sending handshake back to {}", connection);
+                self.send_handshake(&connection).await?
+            }
+        };
+
+        self.verify_identity(buf, connection, &handshake, &our_handshake, &peer_handshake).await?;
+
+        let features = self.exchange_features(buf, connection, &handshake).await?;
+        // A peer advertising the Diffie-Hellman key exchange must have used it
+        // Features of a legacy session are not authenticated, so this only catches a fallback
+        // caused by a lost connection, a MITM is stopped by dh_key_exchange_peers
+        if connection.is_legacy_key_exchange() && features & FEATURE_DH_KEY_EXCHANGE != 0 {
+            debug!("{} supports the Diffie-Hellman key exchange but used the legacy one", connection);
+            if connection.is_out() {
+                self.legacy_key_exchange_peers.lock().await.remove(connection.get_address());
+            }
+            return Err(P2pError::KeyExchangeDowngrade)
         }
 
         // if we reach here, handshake is all good, we can start listening this new peer
         connection.set_state(State::Success);
 
-        Ok(handshake)
+        Ok((handshake, features))
     }
 
     async fn handle_new_peer(self: &Arc<Self>, peer: &Arc<Peer>, rx: Rx) -> Result<(), P2pError> {
@@ -717,17 +936,34 @@ impl<S: Storage> P2pServer<S> {
             }
         }
 
-        let stream = timeout(Duration::from_millis(PEER_TIMEOUT_INIT_OUTGOING_CONNECTION), TcpStream::connect(&addr)).await??;
-        let connection = Connection::new(stream, addr, true);
+        let stream = match &self.proxy {
+            Some(proxy) => timeout(Duration::from_millis(PEER_TIMEOUT_INIT_OUTGOING_CONNECTION), proxy.connect(&addr)).await??,
+            None => timeout(Duration::from_millis(PEER_TIMEOUT_INIT_OUTGOING_CONNECTION), TcpStream::connect(&addr)).await??
+        };
+        let mut connection = Connection::new(stream, addr, true, self.create_connection_limiter());
+        {
+            let mut legacy_peers = self.legacy_key_exchange_peers.lock().await;
+            if let Some(time) = legacy_peers.get(&addr).copied() {
+                if get_current_time_in_seconds().saturating_sub(time) < PEER_LEGACY_KEY_EXCHANGE_TIME {
+                    connection.set_legacy_key_exchange(true);
+                } else {
+                    // the peer may have been updated, try again the Diffie-Hellman key exchange
+                    legacy_peers.remove(&addr);
+                }
+            }
+        }
+
         Ok(connection)
     }
 
     // Send a handshake to a connection (this is used to determine if its a potential peer)
     // Handsake is sent only once, when we connect to a new peer, and we get it back from connection to make it a peer
-    async fn send_handshake(&self, connection: &Connection) -> Result<(), P2pError> {
+    // Returns the bytes sent as they are part of the transcript signed
+    async fn send_handshake(&self, connection: &Connection) -> Result<Vec<u8>, P2pError> {
         trace!("Sending handshake to {}", connection);
         let handshake = self.build_handshake().await?;
-        connection.send_bytes(&handshake).await
+        connection.send_bytes(&handshake).await?;
+        Ok(handshake)
     }
 
     // build a ping packet with the current state of the blockchain
@@ -848,7 +1084,15 @@ impl<S: Storage> P2pServer<S> {
             return Ok(None)
         }
 
-        let selected = rand::thread_rng().gen_range(0..count);
+        // Peers with a better score have more chances to be selected
+        let mut rng = rand::thread_rng();
+        let selected = match WeightedIndex::new(peers.iter().map(|p| p.get_score() - PEER_SCORE_MIN + 1)) {
+            Ok(weights) => weights.sample(&mut rng),
+            Err(e) => {
+                debug!("Error while building weights for random peer selection: {}", e);
+                rng.gen_range(0..count)
+            }
+        };
         // clone the Arc to prevent the lock until the end of the sync request
         Ok(peers.swap_remove_index(selected))
     }
@@ -981,7 +1225,8 @@ impl<S: Storage> P2pServer<S> {
 
             let current_time = get_current_time_in_seconds();
             // check if its time to send our peerlist
-            if current_time > last_peerlist_update + P2P_PING_PEER_LIST_DELAY {
+            // Peers addresses are never shared when using a proxy
+            if self.proxy.is_none() && current_time > last_peerlist_update + P2P_PING_PEER_LIST_DELAY {
                 trace!("Sending ping packet with peerlist...");
                 for peer in all_peers.iter() {
                     let new_peers = ping.get_mut_peers();
@@ -1097,6 +1342,67 @@ impl<S: Storage> P2pServer<S> {
         }
     }
 
+    // Fluff the stem txs that were not seen in fluff phase before their embargo
+    async fn dandelion_loop(self: Arc<Self>) {
+        debug!("Starting Dandelion++ embargo task...");
+        let Some(dandelion) = self.dandelion.as_ref() else {
+            return;
+        };
+
+        loop {
+            sleep(Duration::from_secs(DANDELION_EMBARGO_CHECK_INTERVAL)).await;
+            if !self.is_running() {
+                debug!("Dandelion++ embargo task is stopped!");
+                break;
+            }
+
+            for hash in dandelion.take_expired_embargoes(get_current_time_in_seconds()).await {
+                debug!("Embargo of stem tx {} has expired, fluffing it", hash);
+                self.fluff_tx(hash).await;
+            }
+        }
+    }
+
+    // Request the full block when the missing txs of a compact block are not received in time
+    // so the block doesn't stay in the propagation queue forever
+    async fn compact_blocks_loop(self: Arc<Self>) {
+        debug!("Starting compact blocks task...");
+        loop {
+            sleep(Duration::from_secs(COMPACT_BLOCK_CHECK_INTERVAL)).await;
+            if !self.is_running() {
+                debug!("Compact blocks task is stopped!");
+                break;
+            }
+
+            let now = get_current_time_in_seconds();
+            let expired: Vec<(Hash, PendingCompactBlock)> = {
+                let mut pending_compact_blocks = self.pending_compact_blocks.lock().await;
+                let hashes: Vec<Hash> = pending_compact_blocks.iter()
+                    .filter(|(_, pending)| pending.requested_at + COMPACT_BLOCK_TIMEOUT <= now)
+                    .map(|(hash, _)| hash.clone())
+                    .collect();
+
+                hashes.into_iter()
+                    .filter_map(|hash| pending_compact_blocks.pop(&hash).map(|pending| (hash, pending)))
+                    .collect()
+            };
+
+            for (hash, pending) in expired {
+                debug!("Missing txs of compact block {} were not received in time from {}, requesting the full block", hash, pending.peer);
+                // Let the block be propagated again by another peer in the meantime
+                self.blocks_propagation_queue.lock().await.pop(&hash);
+                pending.peer.penalize(&P2pError::TrackerRequestExpired);
+                if pending.peer.get_connection().is_closed() {
+                    continue;
+                }
+
+                if let Err(e) = self.object_tracker.request_object_from_peer(Arc::clone(&pending.peer), ObjectRequest::Block(hash.clone()), true).await {
+                    debug!("Error while requesting the full block {} to {}: {}", hash, pending.peer, e);
+                }
+            }
+        }
+    }
+
     // This function is used to broadcast PeerDisconnected event to listeners
     // We use a channel to avoid having to pass the Blockchain<S> to the Peerlist & Peers
     async fn event_loop(self: Arc<Self>, mut receiver: Receiver<Arc<Peer>>) {
@@ -1166,6 +1472,7 @@ impl<S: Storage> P2pServer<S> {
                             if let Err(e) = self.object_tracker.request_object_from_peer(Arc::clone(&peer), ObjectRequest::Transaction(hash.clone()), false).await {
                                     error!("Error while requesting TX {} to {} for block {}: {}", hash, peer, block_hash, e);
                                     peer.increment_fail_count();
+                                    peer.penalize(&e);
                                     continue;
                             }
 
@@ -1189,14 +1496,19 @@ impl<S: Storage> P2pServer<S> {
                         Err(e) => {
                             error!("Error while building block {} from peer {}: {}", block_hash, peer, e);
                             peer.increment_fail_count();
+                            peer.penalize(&e.into());
                             continue;
                         }
                     };
         
                     debug!("Adding received block {} from {} to chain", block_hash, peer);
-                    if let Err(e) = self.blockchain.add_new_block(block, true, false).await {
-                        error!("Error while adding new block from {}: {}", peer, e);
-                        peer.increment_fail_count();
+                    match self.blockchain.add_new_block(block, true, false).await {
+                        Ok(_) => peer.reward(PEER_SCORE_REWARD_BLOCK),
+                        Err(e) => {
+                            error!("Error while adding new block from {}: {}", peer, e);
+                            peer.increment_fail_count();
+                            peer.penalize(&e.into());
+                        }
                     }
                 }
             }
@@ -1239,10 +1551,11 @@ impl<S: Storage> P2pServer<S> {
                     }
                 },
                 // all packets to be sent to the peer are received here
-                Some(bytes) = rx.recv() => {
+                // their upload tokens are already taken, a throttled packet is only returned once its delay is elapsed
+                Some(bytes) = rx.recv(peer.get_connection().get_limiter()) => {
                     // there is a overhead of 4 for each packet (packet size u32 4 bytes, packet id u8 is counted in the packet size)
                     trace!("Sending packet with ID {}, size sent: {}, real size: {}", bytes[4], u32::from_be_bytes(bytes[0..4].try_into()?), bytes.len());
-                    timeout(Duration::from_millis(PEER_SEND_BYTES_TIMEOUT), peer.get_connection().send_bytes(&bytes)).await??;
+                    timeout(Duration::from_millis(PEER_SEND_BYTES_TIMEOUT), peer.get_connection().send_bytes_unthrottled(&bytes)).await??;
                     trace!("data sucessfully sent!");
                 }
             }
@@ -1274,10 +1587,10 @@ impl<S: Storage> P2pServer<S> {
                 res = self.listen_connection(&mut buf, &peer) => {
                     res?;
 
-                    // check that we don't have too many fails
+                    // check that we don't have too many fails or a too low score
                     // otherwise disconnect peer
-                    if peer.get_fail_count() >= PEER_FAIL_LIMIT {
-                        warn!("High fail count detected for {}! Closing connection...", peer);
+                    if peer.get_fail_count() >= PEER_FAIL_LIMIT || peer.has_low_score() {
+                        warn!("High fail count or low score detected for {}! Closing connection...", peer);
                         if let Err(e) = peer.close_and_temp_ban().await {
                             error!("Error while trying to close connection with {} due to high fail count: {}", peer, e);
                         }
@@ -1400,11 +1713,25 @@ impl<S: Storage> P2pServer<S> {
                 peer.get_connection().close().await?;
                 return Err(P2pError::InvalidPacket)
             },
+            Packet::IdentityProof(_) => {
+                error!("{} sent us identity proof packet (not valid!)", peer);
+                peer.get_connection().close().await?;
+                return Err(P2pError::InvalidPacket)
+            },
+            Packet::Features(_) => {
+                error!("{} sent us features packet (not valid!)", peer);
+                peer.get_connection().close().await?;
+                return Err(P2pError::InvalidPacket)
+            },
             Packet::KeyExchange(key) => {
                 trace!("{}: Rotate key packet", peer);
                 let key = key.into_owned();
                 peer.get_connection().rotate_peer_key(key).await?;
             },
+            Packet::DHKeyExchange(key_exchange) => {
+                trace!("{}: Rotate key packet using Diffie-Hellman", peer);
+                peer.get_connection().rotate_peer_key_dh(*key_exchange.get_public_key()).await?;
+            },
             Packet::TransactionPropagation(packet_wrapper) => {
                 trace!("{}: Transaction Propagation packet", peer);
                 let (hash, ping) = packet_wrapper.consume();
@@ -1427,8 +1754,19 @@ impl<S: Storage> P2pServer<S> {
                     }
                 }
 
+                // The tx is now in fluff phase, we don't have to fluff it ourself
+                if let Some(dandelion) = self.dandelion.as_ref() {
+                    if dandelion.remove_embargo(&hash).await {
+                        debug!("Stem tx {} has been fluffed by the network", hash);
+                        self.fluff_tx(hash.clone()).await;
+                    }
+                }
+
                 // Check that the tx is not in mempool or on disk already
-                if !self.blockchain.has_tx(&hash).await? {
+                // Peers with a bad score are not trusted enough to request objects from them
+                if peer.get_score() < PEER_SCORE_MIN_OBJECT_REQUEST {
+                    debug!("Ignoring tx {} propagated by {} due to its low score", hash, peer);
+                } else if !self.blockchain.has_tx(&hash).await? {
                     trace!("Requesting tx {} propagated because we don't have it", hash);
                     if !self.object_tracker.request_object_from_peer(Arc::clone(peer), ObjectRequest::Transaction(hash.clone()), true).await? {
                         debug!("TX propagated {} was already requested, ignoring", hash);
@@ -1445,6 +1783,32 @@ impl<S: Storage> P2pServer<S> {
                     txs_cache.put(hash.clone(), Direction::Out);
                 }
             },
+            Packet::StemTransaction(tx) => {
+                trace!("Received a stem transaction from {}", peer);
+                let tx = tx.into_owned();
+                let hash = tx.hash();
+                peer.get_txs_cache().lock().await.put(hash.clone(), Direction::In);
+
+                if self.blockchain.has_tx(&hash).await? {
+                    debug!("Stem tx {} from {} is already known, ignoring", hash, peer);
+                    return Ok(())
+                }
+
+                // Without Dandelion++ or in a fluff epoch, the tx is broadcasted directly
+                let fluff = match self.dandelion.as_ref() {
+                    Some(dandelion) => dandelion.is_fluff_epoch().await,
+                    None => true
+                };
+
+                if fluff {
+                    debug!("Fluffing stem tx {} received from {}", hash, peer);
+                    self.blockchain.add_tx_to_mempool_with_hash(tx, hash, true).await?;
+                } else {
+                    debug!("Relaying stem tx {} received from {}", hash, peer);
+                    self.blockchain.add_stem_tx_to_mempool_with_hash(tx, hash.clone(), false).await?;
+                    self.stem_tx(hash, Some(peer.get_id())).await;
+                }
+            },
             Packet::BlockPropagation(packet_wrapper) => {
                 trace!("Received a block propagation packet from {}", peer);
                 let (header, ping) = packet_wrapper.consume();
@@ -1454,56 +1818,211 @@ impl<S: Storage> P2pServer<S> {
                 let header = header.into_owned();
                 let block_hash = header.hash();
 
-                // verify that this block wasn't already sent by him
+                if !self.track_propagated_block(peer, &block_hash).await? {
+                    debug!("{}: {} with hash {} is already in our chain. Skipping", peer, header, block_hash);
+                    return Ok(())
+                }
+
+                // Check that we are not already waiting on it
                 {
-                    let mut blocks_propagation = peer.get_blocks_propagation().lock().await;
-                    if let Some(direction) = blocks_propagation.get_mut(&block_hash) {
-                        if !direction.update(Direction::In) {
-                            debug!("{} send us a block ({}) already tracked by him ({:?})", peer, block_hash, direction);
-                            // return Err(P2pError::AlreadyTrackedBlock(block_hash, *direction))
+                    let mut blocks_propagation_queue = self.blocks_propagation_queue.lock().await;
+                    if blocks_propagation_queue.contains(&block_hash) {
+                        // If we are waiting on the missing txs of a compact block, the full header is enough
+                        if self.pending_compact_blocks.lock().await.pop(&block_hash).is_none() {
+                            debug!("Block {} propagated is already in processing from another peer", block_hash);
+                            return Ok(())
                         }
-                    } else {
-                        debug!("Saving {} in blocks propagation cache for {}", block_hash, peer);
-                        blocks_propagation.put(block_hash.clone(),  Direction::In);
+                        debug!("Block {} was pending as a compact block, using the header from {}", block_hash, peer);
                     }
+                    blocks_propagation_queue.put(block_hash.clone(), ());
                 }
 
-                // Avoid sending the same block to a common peer that may have already got it
-                // because we track peerlist of each peers, we can try to determinate it
-                for common_peer in self.get_common_peers_for(&peer).await {
-                    debug!("{} is a common peer with {}, adding block {} to its propagation cache", common_peer, peer, block_hash);
-                    let mut blocks_propagation = common_peer.get_blocks_propagation().lock().await;
-                    // Out allow to get "In" again, because it's a prediction, don't block it completely
-                    if !blocks_propagation.contains(&block_hash) {
-                        blocks_propagation.put(block_hash.clone(), Direction::Out);
-                    }
+                let block_height = header.get_height();
+                debug!("Received block at height {} from {}", block_height, peer);
+                let peer = Arc::clone(peer);
+                // This will block the task if the bounded channel is full
+                if let Err(e) = self.blocks_processor.send((peer, header, block_hash)).await {
+                    error!("Error while sending block propagated to blocks processor task: {}", e);
                 }
+            },
+            Packet::CompactBlockPropagation(packet_wrapper) => {
+                trace!("Received a compact block propagation packet from {}", peer);
+                let (compact_block, ping) = packet_wrapper.consume();
+                ping.into_owned().update_peer(peer, &self.blockchain).await?;
 
-                // check that we don't have this block in our chain
-                {
-                    let storage = self.blockchain.get_storage().read().await;
-                    if storage.has_block_with_hash(&block_hash).await? {
-                        debug!("{}: {} with hash {} is already in our chain. Skipping", peer, header, block_hash);
-                        return Ok(())
-                    }
+                let (header, block_hash, salt, short_ids, prefilled_txs) = compact_block.consume();
+                let header = header.into_owned();
+                let block_hash = block_hash.into_owned();
+
+                if !self.track_propagated_block(peer, &block_hash).await? {
+                    debug!("{}: compact block {} is already in our chain. Skipping", peer, block_hash);
+                    return Ok(())
                 }
 
                 // Check that we are not already waiting on it
                 {
                     let mut blocks_propagation_queue = self.blocks_propagation_queue.lock().await;
                     if blocks_propagation_queue.contains(&block_hash) {
-                        debug!("Block {} propagated is already in processing from another peer", block_hash);
+                        debug!("Compact block {} propagated is already in processing from another peer", block_hash);
                         return Ok(())
                     }
                     blocks_propagation_queue.put(block_hash.clone(), ());
                 }
 
-                let block_height = header.get_height();
-                debug!("Received block at height {} from {}", block_height, peer);
-                let peer = Arc::clone(peer);
-                // This will block the task if the bounded channel is full
-                if let Err(e) = self.blocks_processor.send((peer, header, block_hash)).await {
-                    error!("Error while sending block propagated to blocks processor task: {}", e);
+                debug!("Received compact block {} at height {} with {} txs ({} prefilled) from {}", block_hash, header.get_height(), short_ids.len(), prefilled_txs.len(), peer);
+                let mut txs = {
+                    let mempool = self.blockchain.get_mempool().read().await;
+                    resolve_short_tx_ids(salt, &short_ids, mempool.get_txs().keys().map(AsRef::as_ref))
+                };
+
+                // Prefilled txs are added to our mempool so the block can be built from it
+                let mut prefilled_indexes = HashSet::with_capacity(prefilled_txs.len());
+                for prefilled in prefilled_txs {
+                    let (index, tx) = prefilled.consume();
+                    let tx = tx.into_owned();
+                    let hash = tx.hash();
+                    if get_short_tx_id(salt, &hash) != short_ids[index as usize] {
+                        debug!("Prefilled tx {} at index {} doesn't match its short id in compact block {}", hash, index, block_hash);
+                        self.blocks_propagation_queue.lock().await.pop(&block_hash);
+                        return Err(P2pError::InvalidCompactBlock(block_hash))
+                    }
+
+                    self.add_block_tx_to_mempool(tx, &hash).await;
+                    txs[index as usize] = Some(hash);
+                    prefilled_indexes.insert(index);
+                }
+
+                if let Some(header) = rebuild_header(&header, &txs, &block_hash) {
+                    debug!("Compact block {} rebuilt from our mempool", block_hash);
+                    if let Err(e) = self.blocks_processor.send((Arc::clone(peer), header, block_hash)).await {
+                        error!("Error while sending compact block to blocks processor task: {}", e);
+                    }
+                    return Ok(())
+                }
+
+                if txs.iter().all(Option::is_some) {
+                    debug!("Compact block {} rebuilt from our mempool has an invalid hash, requesting its txs", block_hash);
+                }
+                let missing = get_txs_to_request(&mut txs, &prefilled_indexes);
+
+                let pending = PendingCompactBlock {
+                    peer: Arc::clone(peer),
+                    requested_at: get_current_time_in_seconds(),
+                    header,
+                    salt,
+                    short_ids,
+                    txs
+                };
+
+                debug!("Requesting {} missing txs of compact block {} to {}", missing.len(), block_hash, peer);
+                self.pending_compact_blocks.lock().await.put(block_hash.clone(), pending);
+                peer.send_packet(Packet::GetBlockTransactions(Cow::Owned(GetBlockTransactions::new(block_hash, missing)))).await?;
+            },
+            Packet::GetBlockTransactions(request) => {
+                trace!("Received a block transactions request from {}", peer);
+                let request = request.into_owned();
+                let block_hash = request.get_block_hash();
+
+                // Only the txs of a compact block we sent to this peer can be requested
+                let txs_count = self.propagated_compact_blocks.lock().await.get(block_hash).copied();
+                let sent = matches!(peer.get_blocks_propagation().lock().await.peek(block_hash), Some(Direction::Out | Direction::Both));
+                let Some(txs_count) = txs_count.filter(|_| sent) else {
+                    return Err(P2pError::UnknownCompactBlock(block_hash.clone()))
+                };
+
+                // Indexes must be unique and sorted like we request them
+                if !are_valid_tx_indexes(request.get_indexes(), txs_count) {
+                    return Err(P2pError::InvalidBlockTransactionsRequest(block_hash.clone()))
+                }
+
+                let block = {
+                    let storage = self.blockchain.get_storage().read().await;
+                    storage.get_block_by_hash(block_hash).await?
+                };
+
+                let block_txs = block.get_transactions();
+                let mut transactions = Vec::with_capacity(request.get_indexes().len());
+                for index in request.get_indexes() {
+                    let tx = block_txs.get(*index as usize)
+                        .ok_or_else(|| P2pError::InvalidBlockTransactionIndex(*index, block_hash.clone()))?;
+                    transactions.push(Cow::Borrowed(tx.get_inner()));
+                }
+
+                debug!("Sending {} txs of block {} to {}", transactions.len(), block_hash, peer);
+                peer.send_packet(Packet::BlockTransactions(BlockTransactions::new(Cow::Borrowed(block_hash), transactions))).await?;
+            },
+            Packet::LightHeadersRequest(request) => {
+                trace!("Received a light headers request from {}", peer);
+                let (topoheight, count) = request.into_owned().consume();
+                let headers = self.get_light_headers(topoheight, count.min(LIGHT_CLIENT_MAX_HEADERS)).await?;
+                debug!("Sending {} light headers from topoheight {} to {}", headers.len(), topoheight, peer);
+                peer.send_packet(Packet::LightHeadersResponse(LightHeadersResponse::new(headers))).await?;
+            },
+            Packet::StateProofRequest(request) => {
+                trace!("Received a state proof request from {}", peer);
+                let proof = self.build_state_proof(&request).await?;
+                peer.send_packet(Packet::StateProofResponse(StateProofResponse::new(request, proof))).await?;
+            },
+            Packet::LightHeadersResponse(_) | Packet::StateProofResponse(_) => {
+                // We never request them, only light clients do
+                error!("{} sent us a light client response", peer);
+                return Err(P2pError::InvalidPacket)
+            },
+            Packet::BlockTransactions(response) => {
+                trace!("Received block transactions from {}", peer);
+                let (block_hash, transactions) = response.consume();
+                let block_hash = block_hash.into_owned();
+
+                let pending = self.pending_compact_blocks.lock().await.pop(&block_hash);
+                let mut pending = match pending {
+                    Some(pending) if pending.peer.get_id() == peer.get_id() => pending,
+                    Some(pending) => {
+                        // Requested to another peer, put it back
+                        self.pending_compact_blocks.lock().await.put(block_hash.clone(), pending);
+                        return Err(P2pError::UnrequestedBlockTransactions(block_hash))
+                    },
+                    None => {
+                        if self.blocks_propagation_queue.lock().await.contains(&block_hash) {
+                            // Block was received in full from another peer in the meantime
+                            debug!("Ignoring late txs of compact block {} from {}", block_hash, peer);
+                            return Ok(())
+                        }
+                        return Err(P2pError::UnrequestedBlockTransactions(block_hash))
+                    }
+                };
+
+                let missing: Vec<usize> = pending.txs.iter()
+                    .enumerate()
+                    .filter(|(_, tx)| tx.is_none())
+                    .map(|(index, _)| index)
+                    .collect();
+
+                let mut valid = missing.len() == transactions.len();
+                if valid {
+                    for (index, tx) in missing.into_iter().zip(transactions) {
+                        let tx = tx.into_owned();
+                        let hash = tx.hash();
+                        if get_short_tx_id(pending.salt, &hash) != pending.short_ids[index] {
+                            debug!("Tx {} at index {} doesn't match its short id in compact block {}", hash, index, block_hash);
+                            valid = false;
+                            break;
+                        }
+
+                        self.add_block_tx_to_mempool(tx, &hash).await;
+                        pending.txs[index] = Some(hash);
+                    }
+                }
+
+                let header = valid.then(|| rebuild_header(&pending.header, &pending.txs, &block_hash))
+                    .flatten();
+                let Some(header) = header else {
+                    self.blocks_propagation_queue.lock().await.pop(&block_hash);
+                    return Err(P2pError::InvalidCompactBlock(block_hash))
+                };
+
+                debug!("Compact block {} rebuilt with the txs received from {}", block_hash, peer);
+                if let Err(e) = self.blocks_processor.send((Arc::clone(peer), header, block_hash)).await {
+                    error!("Error while sending compact block to blocks processor task: {}", e);
                 }
             },
             Packet::ChainRequest(packet_wrapper) => {
@@ -1630,7 +2149,14 @@ impl<S: Storage> P2pServer<S> {
                     },
                     ObjectRequest::Transaction(hash) => {
                         debug!("{} asked tx {}", peer, hash);
-                        match self.blockchain.get_tx(hash).await {
+                        // Stem txs are not shared to not reveal we are on their path
+                        let tx = if self.blockchain.get_mempool().read().await.is_stem_tx(hash) {
+                            Err(BlockchainError::TxNotFound(hash.clone()))
+                        } else {
+                            self.blockchain.get_tx(hash).await
+                        };
+
+                        match tx {
                             Ok(tx) => {
                                 debug!("tx {} found, sending it", hash);
                                 peer.send_packet(Packet::ObjectResponse(ObjectResponse::Transaction(Cow::Borrowed(&tx)))).await?;
@@ -1681,6 +2207,7 @@ impl<S: Storage> P2pServer<S> {
                     let nonces_cache = mempool.get_caches();
                     let txs = nonces_cache.values()
                         .flat_map(|v| v.get_txs())
+                        .filter(|tx| !mempool.is_stem_tx(tx))
                         .skip(skip).take(NOTIFY_MAX_LEN)
                         .map(|tx| Cow::Borrowed(tx.as_ref()))
                         .collect::<IndexSet<_>>();
@@ -1811,6 +2338,7 @@ impl<S: Storage> P2pServer<S> {
                 e => {
                     error!("Error occured while handling incoming packet #{} from {}: {}", packet_id, peer, e);
                     peer.increment_fail_count();
+                    peer.penalize(&e);
                 }
             }
         }
@@ -1959,6 +2487,215 @@ impl<S: Storage> P2pServer<S> {
         Ok(())
     }
 
+    // Get the headers starting at a topoheight for a light client
+    // Headers below our pruned topoheight are not available
+    async fn get_light_headers(&self, topoheight: u64, count: u16) -> Result<Vec<LightHeader>, BlockchainError> {
+        let storage = self.blockchain.get_storage().read().await;
+        if storage.get_pruned_topoheight().await?.is_some_and(|pruned| topoheight < pruned) {
+            debug!("Light headers requested at topoheight {} are pruned", topoheight);
+            return Ok(Vec::new())
+        }
+
+        let top_topoheight = self.blockchain.get_topo_height();
+        let mut headers = Vec::with_capacity(count as usize);
+        for topoheight in (topoheight..topoheight.saturating_add(count as u64)).take_while(|topoheight| *topoheight <= top_topoheight) {
+            let hash = storage.get_hash_at_topo_height(topoheight).await?;
+            let header = storage.get_block_header_by_hash(&hash).await?;
+            let difficulty = storage.get_difficulty_for_block_hash(&hash).await?;
+            headers.push(LightHeader::new(topoheight, header.as_ref().clone(), difficulty));
+        }
+
+        Ok(headers)
+    }
+
+    // Build the proof of the last balance or nonce of an account in the state tree
+    // The state used is the one at the requested topoheight, or our last state root if it's higher
+    // Returns None if there is no version or if we don't have the state root (pruned or fast synced chain)
+    async fn build_state_proof(&self, request: &StateProofRequest) -> Result<Option<StateProof>, BlockchainError> {
+        let storage = self.blockchain.get_storage().read().await;
+        let Some(state_root_topoheight) = storage.get_state_root_topoheight().await? else {
+            debug!("No state root built, can't build the proof");
+            return Ok(None)
+        };
+
+        let state_topoheight = request.get_topoheight().min(state_root_topoheight);
+        let state_root = match storage.get_balances_merkle_hash_at_topoheight(state_topoheight).await {
+            Ok(root) => root,
+            Err(BlockchainError::NotFoundOnDisk(_)) => {
+                debug!("No state root at topoheight {}, can't build the proof", state_topoheight);
+                return Ok(None)
+            },
+            Err(e) => return Err(e)
+        };
+
+        let key = request.get_key();
+        let version = match request.get_asset() {
+            Some(asset) => storage.get_balance_at_maximum_topoheight(key, asset, state_topoheight).await?
+                .map(|(topoheight, version)| (topoheight, StateLeaf::Balance { key: key.clone(), asset: asset.clone(), version })),
+            None => storage.get_nonce_at_maximum_topoheight(key, state_topoheight).await?
+                .map(|(topoheight, version)| (topoheight, StateLeaf::Nonce { key: key.clone(), version }))
+        };
+
+        let Some((version_topoheight, leaf)) = version else {
+            return Ok(None)
+        };
+
+        // Versions rewritten by the pruning don't match the tree anymore
+        let proof = match build_state_tree_proof(&*storage, &state_root, &leaf.get_path()).await? {
+            Some((value, proof)) if value == leaf.get_value_hash(version_topoheight) => proof,
+            _ => {
+                debug!("Version at topoheight {} not found in the state tree at topoheight {}", version_topoheight, state_topoheight);
+                return Ok(None)
+            }
+        };
+
+        Ok(Some(StateProof::new(version_topoheight, state_topoheight, leaf, proof)))
+    }
+
+    // Get all the peers we can download blocks above this topoheight from
+    // the sync peer is always included by the downloader
+    async fn get_sync_download_peers(&self, min_topoheight: u64) -> Vec<Arc<Peer>> {
+        self.peer_list.get_cloned_peers().await
+            .into_iter()
+            .filter(|peer| peer.get_pruned_topoheight().map_or(true, |pruned| pruned < min_topoheight))
+            .collect()
+    }
+
+    // Build a downloader for the objects of a chain response
+    // In boost sync mode, the sync peer is trusted and requested alone for the whole window
+    fn new_sync_downloader(&self, peer: &Arc<Peer>, peers: Vec<Arc<Peer>>, requests: Vec<(ObjectRequest, u64)>) -> ObjectDownloader {
+        if !self.allow_boost_sync() {
+            return ObjectDownloader::new(Arc::clone(peer), peers, requests)
+        }
+
+        let mut downloader = ObjectDownloader::new(Arc::clone(peer), Vec::new(), requests);
+        downloader.set_max_requests_per_peer(CHAIN_SYNC_DOWNLOAD_WINDOW);
+        downloader
+    }
+
+    // Download the headers of all the blocks we don't have from the sync peer
+    // and verify the basic chain structure before requesting any block body
+    // Blocks must be ordered by topoheight otherwise it will give incorrect results
+    async fn download_headers(&self, peer: &Arc<Peer>, blocks: IndexSet<Hash>, common_topoheight: u64) -> Result<ChainValidator<'_, S>, BlockchainError> {
+        let mut requests = Vec::with_capacity(blocks.len());
+        for hash in blocks {
+            // check if we already have the block to not request it
+            if self.blockchain.has_block(&hash).await? {
+                trace!("We already have block {}, skipping", hash);
+                continue;
+            }
+            requests.push((ObjectRequest::BlockHeader(hash), 0));
+        }
+
+        // Starting topoheight must be the next topoheight after common block
+        let mut chain_validator = ChainValidator::new(&self.blockchain, common_topoheight + 1);
+        // Headers are only requested from the sync peer as they are validated against its chain
+        let mut downloader = self.new_sync_downloader(peer, Vec::new(), requests);
+        while let Some(response) = downloader.next().await? {
+            let OwnedObjectResponse::BlockHeader(header, hash) = response else {
+                error!("{} sent us an invalid object response", peer);
+                return Err(P2pError::ExpectedBlock.into())
+            };
+
+            trace!("Received {} with hash {}", header, hash);
+            match chain_validator.insert_block(hash, header).await {
+                // Block was propagated to us in the meantime
+                Err(BlockchainError::AlreadyInChain) => {},
+                res => res?
+            }
+        }
+
+        Ok(chain_validator)
+    }
+
+    // Download the blocks with their txs from all the peers having them
+    // and add them in chain in the same order as the headers
+    // Returns the number of blocks added
+    async fn download_blocks(&self, peer: &Arc<Peer>, headers: Vec<(Hash, Arc<BlockHeader>)>, common_topoheight: u64) -> Result<usize, BlockchainError> {
+        let peers = self.get_sync_download_peers(common_topoheight).await;
+        debug!("Downloading {} blocks from {} peers", headers.len(), peers.len());
+        let requests = headers.into_iter()
+            .map(|(hash, header)| (ObjectRequest::Block(hash), header.get_height()))
+            .collect();
+
+        let mut count = 0;
+        let mut downloader = self.new_sync_downloader(peer, peers, requests);
+        while let Some(response) = downloader.next().await? {
+            let OwnedObjectResponse::Block(block, hash) = response else {
+                error!("{} sent us an invalid block response", peer);
+                return Err(P2pError::ExpectedBlock.into())
+            };
+
+            trace!("Received block {} at height {}", hash, block.get_height());
+            match self.blockchain.add_new_block(block, false, false).await {
+                Ok(()) => count += 1,
+                Err(BlockchainError::AlreadyInChain) => trace!("Block {} was already added", hash),
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(count)
+    }
+
+    // Download only the txs we don't have of each block and add the blocks in chain
+    // in the same order as the headers
+    // After a rewind, most of them are already known as TXs can be included in several blocks
+    // and the TXs of the popped blocks are back in our mempool
+    // Returns the number of blocks added
+    async fn download_blocks_txs(&self, peer: &Arc<Peer>, headers: Vec<(Hash, Arc<BlockHeader>)>, common_topoheight: u64) -> Result<usize, BlockchainError> {
+        let mut requested = HashSet::new();
+        let mut requests = Vec::new();
+        for (_, header) in headers.iter() {
+            for tx_hash in header.get_txs_hashes() {
+                // check first on disk and in mempool
+                if requested.contains(tx_hash) || self.blockchain.has_tx(tx_hash).await? {
+                    continue;
+                }
+
+                requested.insert(tx_hash.clone());
+                requests.push((ObjectRequest::Transaction(tx_hash.clone()), header.get_height()));
+            }
+        }
+
+        let peers = self.get_sync_download_peers(common_topoheight).await;
+        debug!("Downloading {} txs for {} blocks from {} peers", requests.len(), headers.len(), peers.len());
+
+        let mut count = 0;
+        let mut downloaded: HashMap<Hash, Arc<Transaction>> = HashMap::with_capacity(requests.len());
+        let mut downloader = self.new_sync_downloader(peer, peers, requests);
+        for (hash, header) in headers {
+            let mut transactions = Vec::with_capacity(header.get_txs_count());
+            for tx_hash in header.get_txs_hashes() {
+                // TXs are received in the blocks order, wait until we have this one
+                while requested.contains(tx_hash) && !downloaded.contains_key(tx_hash) {
+                    let Some(OwnedObjectResponse::Transaction(tx, tx_hash)) = downloader.next().await? else {
+                        error!("{} sent us an invalid transaction response", peer);
+                        return Err(P2pError::ExpectedTransaction.into())
+                    };
+
+                    trace!("Received transaction {} from sync", tx_hash);
+                    downloaded.insert(tx_hash, Arc::new(tx));
+                }
+
+                let tx = match downloaded.get(tx_hash) {
+                    Some(tx) => Arc::clone(tx),
+                    None => self.blockchain.get_tx(tx_hash).await?
+                };
+                transactions.push(Immutable::Arc(tx));
+            }
+
+            // Assemble back the block and add it to the chain
+            let block = Block::new(Immutable::Arc(header), transactions);
+            match self.blockchain.add_new_block(block, false, false).await {
+                Ok(()) => count += 1,
+                Err(BlockchainError::AlreadyInChain) => trace!("Block {} was already added", hash),
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(count)
+    }
+
     // Handle a chain response from another peer
     // We receive a list of blocks hashes ordered by their topoheight
     // It also contains a CommonPoint which is a block hash point where we have the same topoheight as our peer
@@ -2050,25 +2787,7 @@ impl<S: Storage> P2pServer<S> {
                 // request all blocks header and verify basic chain structure
                 // Starting topoheight must be the next topoheight after common block
                 // Blocks in chain response must be ordered by topoheight otherwise it will give incorrect results 
-                let mut chain_validator = ChainValidator::new(&self.blockchain, common_topoheight + 1);
-                for hash in blocks {
-                    trace!("Request block header for chain validator: {}", hash);
-
-                    // check if we already have the block to not request it
-                    if self.blockchain.has_block(&hash).await? {
-                        trace!("We already have block {}, skipping", hash);
-                        continue;
-                    }
-
-                    let response = peer.request_blocking_object(ObjectRequest::BlockHeader(hash)).await?;
-                    if let OwnedObjectResponse::BlockHeader(header, hash) = response {
-                        trace!("Received {} with hash {}", header, hash);
-                        chain_validator.insert_block(hash, header).await?;
-                    } else {
-                        error!("{} sent us an invalid object response", peer);
-                        return Err(P2pError::ExpectedBlock.into())
-                    }
-                }
+                let chain_validator = self.download_headers(peer, blocks, common_topoheight).await?;
 
                 // Verify that it has a higher cumulative difficulty than us
                 // Otherwise we don't switch to his chain
@@ -2081,113 +2800,17 @@ impl<S: Storage> P2pServer<S> {
                 warn!("Rewinding chain because of {} (pop count: {})", peer, pop_count);
                 self.blockchain.rewind_chain(pop_count, false).await?;
 
-                // now retrieve all txs from all blocks header and add block in chain
-                for (hash, header) in chain_validator.get_blocks() {
-                    trace!("Processing block {} from chain validator", hash);
-                    // we don't already have this block, lets retrieve its txs and add in our chain
-                    if !self.blockchain.has_block(&hash).await? {
-                        let mut transactions = Vec::new(); // don't pre allocate
-                        for tx_hash in header.get_txs_hashes() {
-                            // check first on disk in case it was already fetch by a previous block
-                            // it can happens as TXs can be integrated in multiple blocks and executed only one time
-                            // check if we find it
-                            if let Some(tx) = self.blockchain.get_tx(tx_hash).await.ok() {
-                                trace!("Found the transaction {} on disk", tx_hash);
-                                transactions.push(Immutable::Arc(tx));
-                            } else { // otherwise, ask it from peer
-                                let response = peer.request_blocking_object(ObjectRequest::Transaction(tx_hash.clone())).await?;
-                                if let OwnedObjectResponse::Transaction(tx, _) = response {
-                                    trace!("Received transaction {} at block {} from {}", tx_hash, hash, peer);
-                                    transactions.push(Immutable::Owned(tx));
-                                } else {
-                                    error!("{} sent us an invalid block response", peer);
-                                    return Err(P2pError::ExpectedTransaction.into())
-                                }
-                            }
-                        }
-
-                        // Assemble back the block and add it to the chain
-                        let block = Block::new(Immutable::Arc(header), transactions);
-                        self.blockchain.add_new_block(block, false, false).await?; // don't broadcast block because it's syncing
-                    }
-                }
+                // now retrieve the txs we don't have from our peers and add the blocks in chain
+                let count = self.download_blocks_txs(peer, chain_validator.get_blocks().collect(), common_topoheight).await?;
+                info!("we've synced {} blocks from {} after rewinding", count, peer);
             }
         } else {
-            // no rewind are needed, process normally
+            // no rewind are needed, sync headers first
             // it will first add blocks to sync, and then all alt-tips blocks if any (top blocks)
-            let mut total_requested: usize = 0;
-            let mut final_blocker = None;
-            // If boost sync is allowed, we can request all blocks in parallel,
-            // Create a new group in Object Tracker to be notified of a failure
-            let (group_id, mut notifier) = if self.allow_boost_sync() {
-                let (group_id, notifier) = self.object_tracker.get_group_manager().next_group_id().await;
-                (Some(group_id), Some(notifier))
-            } else {
-                (None, None)
-            };
-
-            // Peekable is here to help to know if we are at the last element
-            // so we create only one channel for the last blocker
-            let mut blocks_iter = blocks.into_iter().peekable();
-            while let Some(hash) = blocks_iter.next() {
-                if !self.blockchain.has_block(&hash).await? {
-                    trace!("Block {} is not found, asking it to {} (index = {})", hash, peer.get_outgoing_address(), total_requested);
-                    // if it's allowed by the user, request all blocks in parallel
-                    if self.allow_boost_sync() {
-                        if let Some(notifier) = &mut notifier {
-                            // Check if we don't have any message pending in the channel
-                            if let Ok(err) = notifier.try_recv() {
-                                debug!("An error has occured in batch while requesting chain in boost mode");
-                                return Err(P2pError::BoostSyncModeFailed(Box::new(err)).into());
-                            }
-                        }
-
-                        let is_last = blocks_iter.peek().is_none();
-                        if let Some(blocker) = self.object_tracker.request_object_from_peer_with(Arc::clone(peer), ObjectRequest::Block(hash.clone()), group_id, is_last, is_last).await? {
-                            final_blocker = Some(blocker);
-                        }
-                    } else {
-                        // Otherwise, request them one by one and wait for the response
-                        let response = peer.request_blocking_object(ObjectRequest::Block(hash)).await?;
-                        if let OwnedObjectResponse::Block(block, hash) = response {
-                            trace!("Received block {} at height {} from {}", hash, block.get_height(), peer);
-                            self.blockchain.add_new_block(block, false, false).await?;
-                        } else {
-                            error!("{} sent us an invalid block response", peer);
-                            return Err(P2pError::ExpectedBlock.into())
-                        }
-                    }
-                    total_requested += 1;
-                } else {
-                    trace!("Block {} is already in chain, skipping it", hash);
-                }
-            }
-
-            if let (Some(mut notifier), Some(mut blocker)) = (notifier, final_blocker) {
-                debug!("Waiting for final blocker to finish...");
-                select! {
-                    res = &mut notifier => {
-                        let err = res.map_err(|e| P2pError::BoostSyncModeBlockerResponseError(e))?;
-                        debug!("An error has occured while requesting chain in boost mode: {}", err);
-                        return Err(err.into());
-                    },
-                    res = blocker.recv() => match res {
-                        Ok(()) => {
-                            debug!("Final blocker finished");
-                            if let Some(group_id) = group_id {
-                                self.object_tracker.get_group_manager().unregister_group(group_id).await;
-                            } else {
-                                warn!("Group ID is None while it should not be");
-                            }
-                        },
-                        Err(e) => {
-                            error!("Error while waiting for final blocker: {}", e);
-                            return Err(P2pError::BoostSyncModeBlockerError.into());
-                        }
-                    }
-                }
-            }
-            info!("we've synced {} on {} blocks and {} top blocks from {}", total_requested, blocks_len, top_len, peer);
+            // In boost sync mode, blocks are requested in parallel to the peer
+            let chain_validator = self.download_headers(peer, blocks, common_topoheight).await?;
+            let count = self.download_blocks(peer, chain_validator.get_blocks().collect(), common_topoheight).await?;
+            info!("we've synced {} on {} blocks and {} top blocks from {}", count, blocks_len, top_len, peer);
         }
 
         let peer_topoheight = peer.get_topoheight();
@@ -2232,14 +2855,26 @@ impl<S: Storage> P2pServer<S> {
         self.max_peers
     }
 
+    // Create the bandwidth limiter for a new connection
+    // None if no limit is configured
+    fn create_connection_limiter(&self) -> Option<ConnectionLimiter> {
+        let limits = &self.bandwidth_limits;
+        if limits.global_upload.is_none() && limits.global_download.is_none() && limits.peer_upload.is_none() && limits.peer_download.is_none() {
+            return None
+        }
+
+        Some(ConnectionLimiter::new(Arc::clone(&self.global_bandwidth_limiter), limits))
+    }
+
     // Get our unique peer ID
     pub fn get_peer_id(&self) -> u64 {
-        self.peer_id
+        self.identity.get_peer_id()
     }
 
     // Check if we are accepting new connections by verifying if we have free slots available
+    // If we're full, a connected peer with a bad score may be evicted for the new one
     pub async fn accept_new_connections(&self) -> bool {
-        self.get_peer_count().await < self.get_max_peers()
+        self.get_peer_count().await < self.get_max_peers() || self.peer_list.has_peer_to_evict().await
     }
 
     // Returns the count of peers connected
@@ -2259,7 +2894,7 @@ impl<S: Storage> P2pServer<S> {
     }
 
     pub fn is_internal_id(&self, id: u64) -> bool {
-        id == self.peer_id
+        id == self.get_peer_id()
     }
 
     // Verify if this peer id is already used by a peer
@@ -2287,6 +2922,11 @@ impl<S: Storage> P2pServer<S> {
         &self.peer_list
     }
 
+    // Verify if the txs are relayed through the Dandelion++ stem phase
+    pub fn is_dandelion_enabled(&self) -> bool {
+        self.dandelion.is_some()
+    }
+
     // Broadcast a new transaction hash using propagation packet
     // This is used so we don't overload the network during spam or high transactions count
     // We simply share its hash to nodes and others nodes can check if they have it already or not
@@ -2326,18 +2966,132 @@ impl<S: Storage> P2pServer<S> {
         }
     }
 
+    // Register a block propagated by a peer in the propagation caches
+    // Returns false if the block is already in our chain
+    async fn track_propagated_block(&self, peer: &Arc<Peer>, block_hash: &Hash) -> Result<bool, P2pError> {
+        // verify that this block wasn't already sent by him
+        {
+            let mut blocks_propagation = peer.get_blocks_propagation().lock().await;
+            if let Some(direction) = blocks_propagation.get_mut(block_hash) {
+                if !direction.update(Direction::In) {
+                    debug!("{} send us a block ({}) already tracked by him ({:?})", peer, block_hash, direction);
+                    // return Err(P2pError::AlreadyTrackedBlock(block_hash, *direction))
+                }
+            } else {
+                debug!("Saving {} in blocks propagation cache for {}", block_hash, peer);
+                blocks_propagation.put(block_hash.clone(),  Direction::In);
+            }
+        }
+
+        // Avoid sending the same block to a common peer that may have already got it
+        // because we track peerlist of each peers, we can try to determinate it
+        for common_peer in self.get_common_peers_for(peer).await {
+            debug!("{} is a common peer with {}, adding block {} to its propagation cache", common_peer, peer, block_hash);
+            let mut blocks_propagation = common_peer.get_blocks_propagation().lock().await;
+            // Out allow to get "In" again, because it's a prediction, don't block it completely
+            if !blocks_propagation.contains(block_hash) {
+                blocks_propagation.put(block_hash.clone(), Direction::Out);
+            }
+        }
+
+        // check that we don't have this block in our chain
+        let storage = self.blockchain.get_storage().read().await;
+        Ok(!storage.has_block_with_hash(block_hash).await?)
+    }
+
+    // Add a tx received with a compact block to our mempool
+    // If it fails, the blocks processor will request it again
+    async fn add_block_tx_to_mempool(&self, tx: Transaction, hash: &Hash) {
+        if self.blockchain.has_tx(hash).await.unwrap_or(false) {
+            return;
+        }
+
+        if let Err(e) = self.blockchain.add_tx_to_mempool_with_hash(tx, hash.clone(), false).await {
+            debug!("Error while adding tx {} of a compact block to mempool: {}", hash, e);
+        }
+    }
+
+    // Build a compact block for a peer
+    // txs not present in its cache are prefilled as it probably doesn't have them
+    async fn build_compact_block_packet(&self, peer: &Peer, header: &BlockHeader, txs: &[Immutable<Transaction>], hash: &Hash, salt: u64, short_ids: &[ShortTxId], ping: &Ping<'_>) -> Bytes {
+        let prefilled_txs = {
+            let txs_cache = peer.get_txs_cache().lock().await;
+            header.get_txs_hashes().iter()
+                .zip(txs)
+                .enumerate()
+                .filter(|(_, (tx_hash, _))| !txs_cache.contains(*tx_hash))
+                .map(|(index, (_, tx))| PrefilledTransaction::new(index as u16, Cow::Borrowed(tx.get_inner())))
+                .collect()
+        };
+
+        let mut compact_header = header.clone();
+        compact_header.txs_hashes.clear();
+
+        let compact_block = CompactBlock::new(Cow::Owned(compact_header), Cow::Borrowed(hash), salt, short_ids.to_vec(), prefilled_txs);
+        Bytes::from(Packet::CompactBlockPropagation(PacketWrapper::new(Cow::Owned(compact_block), Cow::Borrowed(ping))).to_bytes())
+    }
+
+    // Relay a tx in stem phase to the stem peer of the current epoch
+    // It is never sent back to the peer that relayed it to us
+    // It is fluffed directly if Dandelion++ is disabled or if no stem peer is available
+    pub async fn stem_tx(&self, hash: Hash, from: Option<u64>) {
+        let Some(dandelion) = self.dandelion.as_ref() else {
+            self.fluff_tx(hash).await;
+            return;
+        };
+
+        let tx = match self.blockchain.get_mempool().read().await.get_tx(&hash) {
+            Ok(tx) => tx,
+            Err(e) => {
+                debug!("Stem tx {} not found in mempool: {}", hash, e);
+                return;
+            }
+        };
+
+        let peers = self.peer_list.get_cloned_peers().await;
+        let Some(peer) = dandelion.get_stem_peer(&peers, from).await else {
+            debug!("No stem peer available, fluffing tx {}", hash);
+            self.fluff_tx(hash).await;
+            return;
+        };
+
+        // If it's not fluffed by the network before the embargo, we will do it
+        dandelion.add_embargo(hash.clone()).await;
+        peer.get_txs_cache().lock().await.put(hash.clone(), Direction::In);
+
+        debug!("Relaying stem tx {} to {}", hash, peer);
+        if let Err(e) = peer.send_packet(Packet::StemTransaction(Cow::Borrowed(&tx))).await {
+            debug!("Error while relaying stem tx {} to {}: {}", hash, peer, e);
+        }
+    }
+
+    // Move a stem tx to the fluff phase and broadcast it to all our peers
+    pub async fn fluff_tx(&self, hash: Hash) {
+        if self.blockchain.fluff_tx(&hash).await {
+            self.broadcast_tx_hash(hash).await;
+        }
+    }
+
     // broadcast block to all peers that can accept directly this new block
-    pub async fn broadcast_block(&self, block: &BlockHeader, cumulative_difficulty: CumulativeDifficulty, our_topoheight: u64, our_height: u64, pruned_topoheight: Option<u64>, hash: &Hash, lock: bool) {
+    // peers supporting it receive a compact block instead of the full header
+    pub async fn broadcast_block(&self, block: &BlockHeader, txs: &[Immutable<Transaction>], cumulative_difficulty: CumulativeDifficulty, our_topoheight: u64, our_height: u64, pruned_topoheight: Option<u64>, hash: &Hash, lock: bool) {
         debug!("Broadcasting block {} at height {}", hash, block.get_height());
         // we build the ping packet ourself this time (we have enough data for it)
         // because this function can be call from Blockchain, which would lead to a deadlock
         let ping = Ping::new(Cow::Borrowed(hash), our_topoheight, our_height, pruned_topoheight, cumulative_difficulty, IndexSet::new());
         let block_packet = Packet::BlockPropagation(PacketWrapper::new(Cow::Borrowed(block), Cow::Borrowed(&ping)));
         let packet_block_bytes = Bytes::from(block_packet.to_bytes());
-        let packet_ping_bytes = Bytes::from(Packet::Ping(Cow::Owned(ping)).to_bytes());
+        let packet_ping_bytes = Bytes::from(Packet::Ping(Cow::Borrowed(&ping)).to_bytes());
+
+        // Salt is random for each block so short ids collisions can't be precomputed
+        let salt: u64 = rand::thread_rng().gen();
+        let short_ids: Vec<ShortTxId> = block.get_txs_hashes().iter()
+            .map(|tx_hash| get_short_tx_id(salt, tx_hash))
+            .collect();
 
         trace!("Locking peer list for broadcasting block {}", hash);
         trace!("start broadcasting block {} to all peers", hash);
+        let mut propagated_as_compact = false;
         for peer in self.peer_list.get_cloned_peers().await {
             // if the peer can directly accept this new block, send it
             let peer_height = peer.get_height();
@@ -2357,8 +3111,19 @@ impl<S: Storage> P2pServer<S> {
                     // he should not send it back to us if it's a block found by us
                     blocks_propagation.put(hash.clone(), if lock { Direction::Both } else { Direction::Out });
 
-                    debug!("Broadcast {} to {} (lock: {})", hash, peer, lock);
-                    if let Err(e) = peer.send_bytes(packet_block_bytes.clone()).await {
+                    let bytes = if peer.supports_compact_blocks() {
+                        // Register it before sending so its txs can be requested directly
+                        if !propagated_as_compact {
+                            self.propagated_compact_blocks.lock().await.put(hash.clone(), txs.len());
+                            propagated_as_compact = true;
+                        }
+                        self.build_compact_block_packet(&peer, block, txs, hash, salt, &short_ids, &ping).await
+                    } else {
+                        packet_block_bytes.clone()
+                    };
+
+                    debug!("Broadcast {} to {} (lock: {}, compact: {})", hash, peer, lock, peer.supports_compact_blocks());
+                    if let Err(e) = peer.send_bytes(bytes).await {
                         debug!("Error on broadcast block {} to {}: {}", hash, peer, e);
                     }
                     trace!("{} has been broadcasted to {}", hash, peer);
@@ -0,0 +1,461 @@
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap, HashSet}
+};
+use log::debug;
+use parl_common::{
+    block::BlockHeader,
+    crypto::{hash, Hash, HASH_SIZE},
+    serializer::{Reader, ReaderError, Serializer, Writer},
+    transaction::Transaction
+};
+
+// Short id of a transaction in a compact block
+pub type ShortTxId = u64;
+
+// Short id is the first 8 bytes of the hash of the salt and the tx hash
+// The salt is random for each compact block so a collision can't be crafted in advance
+pub fn get_short_tx_id(salt: u64, tx_hash: &Hash) -> ShortTxId {
+    let mut bytes = [0u8; 8 + HASH_SIZE];
+    bytes[0..8].copy_from_slice(&salt.to_le_bytes());
+    bytes[8..].copy_from_slice(tx_hash.as_bytes());
+    let hash = hash(&bytes);
+
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&hash.as_bytes()[0..8]);
+    u64::from_le_bytes(id)
+}
+
+// Verify that an index can follow the previous one in a list of tx indexes
+// Indexes must be unique, sorted and below the count of txs in the block
+pub fn is_next_tx_index(previous: Option<u16>, index: u16, count: usize) -> bool {
+    (index as usize) < count && previous.map_or(true, |previous| previous < index)
+}
+
+// Verify a list of tx indexes of a block having `count` txs
+pub fn are_valid_tx_indexes(indexes: &[u16], count: usize) -> bool {
+    let mut previous = None;
+    for index in indexes {
+        if !is_next_tx_index(previous, *index, count) {
+            return false
+        }
+        previous = Some(*index);
+    }
+    true
+}
+
+// Find the tx hashes of a compact block among the txs we know
+// A short id matching several txs is considered as missing
+pub fn resolve_short_tx_ids<'a, I>(salt: u64, short_ids: &[ShortTxId], known_txs: I) -> Vec<Option<Hash>>
+where
+    I: IntoIterator<Item = &'a Hash>
+{
+    let wanted: HashSet<ShortTxId> = short_ids.iter().copied().collect();
+    let mut resolved: HashMap<ShortTxId, Option<Hash>> = HashMap::new();
+    for hash in known_txs {
+        let short_id = get_short_tx_id(salt, hash);
+        if !wanted.contains(&short_id) {
+            continue;
+        }
+
+        match resolved.entry(short_id) {
+            Entry::Occupied(mut entry) => {
+                entry.insert(None);
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(Some(hash.clone()));
+            }
+        };
+    }
+
+    short_ids.iter()
+        .map(|short_id| resolved.get(short_id).cloned().flatten())
+        .collect()
+}
+
+// Rebuild the full header of a compact block once its txs are resolved
+// Returns None if a tx is missing or if the rebuilt header doesn't match the block hash
+pub fn rebuild_header(header: &BlockHeader, txs: &[Option<Hash>], block_hash: &Hash) -> Option<BlockHeader> {
+    let mut header = header.clone();
+    header.txs_hashes = txs.iter().cloned().collect::<Option<_>>()?;
+    (header.hash() == *block_hash).then_some(header)
+}
+
+// Get the indexes of the txs to request when a compact block can't be rebuilt
+// If all the txs were resolved, a short id collided with another tx of our mempool
+// and all the txs that weren't prefilled are requested again
+pub fn get_txs_to_request(txs: &mut [Option<Hash>], prefilled_indexes: &HashSet<u16>) -> Vec<u16> {
+    if txs.iter().all(Option::is_some) {
+        for (index, tx) in txs.iter_mut().enumerate() {
+            if !prefilled_indexes.contains(&(index as u16)) {
+                *tx = None;
+            }
+        }
+    }
+
+    txs.iter()
+        .enumerate()
+        .filter(|(_, tx)| tx.is_none())
+        .map(|(index, _)| index as u16)
+        .collect()
+}
+
+// Transaction sent directly in the compact block
+// because we guess the peer doesn't have it
+#[derive(Clone, Debug)]
+pub struct PrefilledTransaction<'a> {
+    // Index of the transaction in the block
+    index: u16,
+    transaction: Cow<'a, Transaction>
+}
+
+impl<'a> PrefilledTransaction<'a> {
+    pub fn new(index: u16, transaction: Cow<'a, Transaction>) -> Self {
+        Self {
+            index,
+            transaction
+        }
+    }
+
+    pub fn consume(self) -> (u16, Cow<'a, Transaction>) {
+        (self.index, self.transaction)
+    }
+}
+
+// Block propagated without the full tx hashes
+// The receiver rebuilds it using its mempool
+// and requests all the missing transactions in one packet
+#[derive(Clone, Debug)]
+pub struct CompactBlock<'a> {
+    // Header without any tx hash
+    header: Cow<'a, BlockHeader>,
+    // Expected hash of the block once rebuilt
+    block_hash: Cow<'a, Hash>,
+    salt: u64,
+    // Short ids of all the transactions in the block order
+    short_ids: Vec<ShortTxId>,
+    // Ordered by index
+    prefilled_txs: Vec<PrefilledTransaction<'a>>
+}
+
+impl<'a> CompactBlock<'a> {
+    pub fn new(header: Cow<'a, BlockHeader>, block_hash: Cow<'a, Hash>, salt: u64, short_ids: Vec<ShortTxId>, prefilled_txs: Vec<PrefilledTransaction<'a>>) -> Self {
+        Self {
+            header,
+            block_hash,
+            salt,
+            short_ids,
+            prefilled_txs
+        }
+    }
+
+    pub fn get_block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.header.get_height()
+    }
+
+    pub fn consume(self) -> (Cow<'a, BlockHeader>, Cow<'a, Hash>, u64, Vec<ShortTxId>, Vec<PrefilledTransaction<'a>>) {
+        (self.header, self.block_hash, self.salt, self.short_ids, self.prefilled_txs)
+    }
+}
+
+impl<'a> Serializer for CompactBlock<'a> {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let header = BlockHeader::read(reader)?;
+        if header.get_txs_count() != 0 {
+            debug!("Compact block header contains tx hashes");
+            return Err(ReaderError::InvalidValue)
+        }
+
+        let block_hash = reader.read_hash()?;
+        let salt = reader.read_u64()?;
+
+        let count = reader.read_u16()?;
+        let mut short_ids = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            short_ids.push(reader.read_u64()?);
+        }
+
+        let prefilled_count = reader.read_u16()?;
+        if prefilled_count > count {
+            debug!("Compact block has more prefilled txs than txs");
+            return Err(ReaderError::InvalidValue)
+        }
+
+        let mut prefilled_txs: Vec<PrefilledTransaction> = Vec::with_capacity(prefilled_count as usize);
+        for _ in 0..prefilled_count {
+            let index = reader.read_u16()?;
+            // Indexes must be ordered and unique
+            if !is_next_tx_index(prefilled_txs.last().map(|prefilled| prefilled.index), index, count as usize) {
+                debug!("Invalid prefilled tx index {} in compact block", index);
+                return Err(ReaderError::InvalidValue)
+            }
+
+            let transaction = Transaction::read(reader)?;
+            prefilled_txs.push(PrefilledTransaction::new(index, Cow::Owned(transaction)));
+        }
+
+        Ok(Self::new(Cow::Owned(header), Cow::Owned(block_hash), salt, short_ids, prefilled_txs))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        self.header.write(writer);
+        writer.write_hash(&self.block_hash);
+        writer.write_u64(&self.salt);
+
+        writer.write_u16(self.short_ids.len() as u16);
+        for short_id in &self.short_ids {
+            writer.write_u64(short_id);
+        }
+
+        writer.write_u16(self.prefilled_txs.len() as u16);
+        for prefilled in &self.prefilled_txs {
+            writer.write_u16(prefilled.index);
+            prefilled.transaction.write(writer);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.header.size()
+        + self.block_hash.size()
+        + self.salt.size()
+        + 2 + self.short_ids.len() * 8
+        + 2 + self.prefilled_txs.iter().map(|prefilled| 2 + prefilled.transaction.size()).sum::<usize>()
+    }
+}
+
+// Request the missing transactions of a compact block by their index
+#[derive(Clone, Debug)]
+pub struct GetBlockTransactions {
+    block_hash: Hash,
+    indexes: Vec<u16>
+}
+
+impl GetBlockTransactions {
+    pub fn new(block_hash: Hash, indexes: Vec<u16>) -> Self {
+        Self {
+            block_hash,
+            indexes
+        }
+    }
+
+    pub fn get_block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn get_indexes(&self) -> &Vec<u16> {
+        &self.indexes
+    }
+}
+
+impl Serializer for GetBlockTransactions {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let block_hash = reader.read_hash()?;
+        let count = reader.read_u16()?;
+        let mut indexes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            indexes.push(reader.read_u16()?);
+        }
+
+        Ok(Self::new(block_hash, indexes))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.write_hash(&self.block_hash);
+        writer.write_u16(self.indexes.len() as u16);
+        for index in &self.indexes {
+            writer.write_u16(*index);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.block_hash.size() + 2 + self.indexes.len() * 2
+    }
+}
+
+// Response to a GetBlockTransactions request
+// Transactions are in the same order as the requested indexes
+#[derive(Clone, Debug)]
+pub struct BlockTransactions<'a> {
+    block_hash: Cow<'a, Hash>,
+    transactions: Vec<Cow<'a, Transaction>>
+}
+
+impl<'a> BlockTransactions<'a> {
+    pub fn new(block_hash: Cow<'a, Hash>, transactions: Vec<Cow<'a, Transaction>>) -> Self {
+        Self {
+            block_hash,
+            transactions
+        }
+    }
+
+    pub fn get_block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn consume(self) -> (Cow<'a, Hash>, Vec<Cow<'a, Transaction>>) {
+        (self.block_hash, self.transactions)
+    }
+}
+
+impl<'a> Serializer for BlockTransactions<'a> {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let block_hash = reader.read_hash()?;
+        let count = reader.read_u16()?;
+        let mut transactions = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            transactions.push(Cow::Owned(Transaction::read(reader)?));
+        }
+
+        Ok(Self::new(Cow::Owned(block_hash), transactions))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.write_hash(&self.block_hash);
+        writer.write_u16(self.transactions.len() as u16);
+        for transaction in &self.transactions {
+            transaction.write(writer);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.block_hash.size() + 2 + self.transactions.iter().map(|tx| tx.size()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexSet;
+    use parl_common::crypto::KeyPair;
+    use super::*;
+
+    fn tx_hashes(values: &[u8]) -> Vec<Hash> {
+        values.iter().map(|value| Hash::new([*value; HASH_SIZE])).collect()
+    }
+
+    // Header of a block with these txs, and the same header without them as sent in a compact block
+    fn build_headers(txs: &[Hash]) -> (BlockHeader, BlockHeader) {
+        let miner = KeyPair::new().get_public_key().compress();
+        let header = BlockHeader::new(0, 1, 0, IndexSet::from([Hash::zero()]), [0u8; 32], miner, txs.iter().cloned().collect());
+        let mut compact_header = header.clone();
+        compact_header.txs_hashes.clear();
+        (header, compact_header)
+    }
+
+    #[test]
+    fn test_compact_block_serde() {
+        let txs = tx_hashes(&[1, 2, 3]);
+        let (header, compact_header) = build_headers(&txs);
+        let block_hash = header.hash();
+        let short_ids: Vec<ShortTxId> = txs.iter().map(|tx| get_short_tx_id(42, tx)).collect();
+
+        let compact_block = CompactBlock::new(Cow::Owned(compact_header), Cow::Borrowed(&block_hash), 42, short_ids.clone(), Vec::new());
+        let bytes = compact_block.to_bytes();
+        assert_eq!(bytes.len(), compact_block.size());
+
+        let (read_header, read_hash, salt, read_short_ids, prefilled_txs) = CompactBlock::from_bytes(&bytes).unwrap().consume();
+        assert_eq!(read_header.get_txs_count(), 0);
+        assert_eq!(read_header.get_height(), header.get_height());
+        assert_eq!(read_hash.as_ref(), &block_hash);
+        assert_eq!(salt, 42);
+        assert_eq!(read_short_ids, short_ids);
+        assert!(prefilled_txs.is_empty());
+    }
+
+    #[test]
+    fn test_compact_block_rejects_invalid_prefilled_index() {
+        let (_, compact_header) = build_headers(&[]);
+        let compact_block = CompactBlock::new(Cow::Owned(compact_header), Cow::Owned(Hash::zero()), 0, vec![1, 2], Vec::new());
+        let mut bytes = compact_block.to_bytes();
+        // Replace the empty prefilled txs by one at index 2 of 2 txs
+        bytes.truncate(bytes.len() - 2);
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&2u16.to_be_bytes());
+        assert!(matches!(CompactBlock::from_bytes(&bytes), Err(ReaderError::InvalidValue)));
+
+        // More prefilled txs than txs
+        bytes.truncate(bytes.len() - 4);
+        bytes.extend_from_slice(&3u16.to_be_bytes());
+        assert!(matches!(CompactBlock::from_bytes(&bytes), Err(ReaderError::InvalidValue)));
+    }
+
+    #[test]
+    fn test_tx_indexes_validation() {
+        assert!(are_valid_tx_indexes(&[], 0));
+        assert!(are_valid_tx_indexes(&[0, 2, 4], 5));
+        // out of range
+        assert!(!are_valid_tx_indexes(&[0, 5], 5));
+        // unsorted
+        assert!(!are_valid_tx_indexes(&[2, 1], 5));
+        // duplicated
+        assert!(!are_valid_tx_indexes(&[1, 1], 5));
+
+        assert!(is_next_tx_index(None, 0, 1));
+        assert!(!is_next_tx_index(Some(3), 3, 5));
+    }
+
+    #[test]
+    fn test_rebuild_compact_block_from_mempool() {
+        let txs = tx_hashes(&[1, 2, 3]);
+        let (header, compact_header) = build_headers(&txs);
+        let block_hash = header.hash();
+        let short_ids: Vec<ShortTxId> = txs.iter().map(|tx| get_short_tx_id(7, tx)).collect();
+
+        // Our mempool has all the txs and others
+        let mempool: Vec<Hash> = tx_hashes(&[9, 3, 1, 8, 2]);
+        let resolved = resolve_short_tx_ids(7, &short_ids, mempool.iter());
+        assert_eq!(resolved, txs.iter().cloned().map(Some).collect::<Vec<_>>());
+
+        let rebuilt = rebuild_header(&compact_header, &resolved, &block_hash).unwrap();
+        assert_eq!(rebuilt.hash(), block_hash);
+        assert_eq!(rebuilt.get_txs_hashes(), header.get_txs_hashes());
+
+        // A tx missing from our mempool must be requested
+        let mut resolved = resolve_short_tx_ids(7, &short_ids, tx_hashes(&[1, 3]).iter());
+        assert_eq!(resolved[1], None);
+        assert!(rebuild_header(&compact_header, &resolved, &block_hash).is_none());
+        assert_eq!(get_txs_to_request(&mut resolved, &HashSet::new()), vec![1]);
+    }
+
+    #[test]
+    fn test_compact_block_short_id_collision() {
+        let txs = tx_hashes(&[1, 2, 3]);
+        let (header, compact_header) = build_headers(&txs);
+        let block_hash = header.hash();
+        let short_ids: Vec<ShortTxId> = txs.iter().map(|tx| get_short_tx_id(7, tx)).collect();
+
+        // A short id matching several txs is missing
+        let resolved = resolve_short_tx_ids(7, &short_ids, tx_hashes(&[1, 2, 2, 3]).iter());
+        assert_eq!(resolved, vec![Some(txs[0].clone()), None, Some(txs[2].clone())]);
+
+        // Another tx of our mempool matched a short id: the block hash is different
+        let mut resolved = vec![Some(txs[0].clone()), Some(Hash::max()), Some(txs[2].clone())];
+        assert!(rebuild_header(&compact_header, &resolved, &block_hash).is_none());
+
+        // All the txs that weren't prefilled are fetched again
+        let prefilled = HashSet::from([2u16]);
+        assert_eq!(get_txs_to_request(&mut resolved, &prefilled), vec![0, 1]);
+        assert_eq!(resolved, vec![None, None, Some(txs[2].clone())]);
+    }
+
+    #[test]
+    fn test_short_tx_id() {
+        let tx_hash = Hash::zero();
+        assert_eq!(get_short_tx_id(1, &tx_hash), get_short_tx_id(1, &tx_hash));
+        assert_ne!(get_short_tx_id(1, &tx_hash), get_short_tx_id(2, &tx_hash));
+    }
+
+    #[test]
+    fn test_get_block_transactions_serde() {
+        let request = GetBlockTransactions::new(Hash::zero(), vec![0, 4, 8]);
+        let bytes = request.to_bytes();
+        assert_eq!(bytes.len(), request.size());
+
+        let request = GetBlockTransactions::from_bytes(&bytes).unwrap();
+        assert_eq!(request.get_indexes(), &vec![0, 4, 8]);
+        assert_eq!(request.get_block_hash(), &Hash::zero());
+    }
+}
@@ -17,6 +17,22 @@ use std::{
     fmt::{Display, Error, Formatter}
 };
 
+// Optional features supported by the node
+// Peer can receive compact blocks
+pub const FEATURE_COMPACT_BLOCKS: u8 = 1 << 0;
+// Peer can receive stem txs from Dandelion++
+pub const FEATURE_DANDELION: u8 = 1 << 1;
+// Peer serves block headers and state proofs to light clients
+pub const FEATURE_LIGHT_CLIENT: u8 = 1 << 2;
+// Peer uses the Diffie-Hellman key exchange, it must never use the legacy one
+pub const FEATURE_DH_KEY_EXCHANGE: u8 = 1 << 3;
+// All the features supported by this node
+pub const SUPPORTED_FEATURES: u8 = FEATURE_COMPACT_BLOCKS | FEATURE_DANDELION | FEATURE_LIGHT_CLIENT | FEATURE_DH_KEY_EXCHANGE;
+
+// First daemon version sending its features in a packet after the handshake
+// Older nodes would reject this packet, they don't support any feature
+pub const FEATURES_MIN_VERSION: (u64, u64, u64) = (1, 1, 0);
+
 // this Handshake is the first data sent when connecting to the server
 // If handshake is valid, server reply with his own handshake
 // We just have to repeat this request to all peers until we reach max connection
@@ -85,9 +101,10 @@ impl<'a> Handshake<'a> {
     }
 
     // Create a new peer using its connection and this handshake packet
-    pub fn create_peer(self, connection: Connection, priority: bool, peer_list: SharedPeerList) -> (Peer, Rx) {
+    // features are the ones received after the handshake
+    pub fn create_peer(self, connection: Connection, priority: bool, peer_list: SharedPeerList, features: u8) -> (Peer, Rx) {
         let peers = HashSet::new();
-        Peer::new(connection, self.get_peer_id(), self.node_tag.into_owned(), self.local_port, self.version.into_owned(), self.top_hash.into_owned(), self.topoheight, self.height, self.pruned_topoheight, priority, self.cumulative_difficulty.into_owned(), peer_list, peers, self.can_be_shared)
+        Peer::new(connection, self.get_peer_id(), self.node_tag.into_owned(), self.local_port, self.version.into_owned(), self.top_hash.into_owned(), self.topoheight, self.height, self.pruned_topoheight, priority, self.cumulative_difficulty.into_owned(), peer_list, peers, self.can_be_shared, features)
     }
 
     pub fn get_version(&self) -> &String {
@@ -110,6 +127,10 @@ impl<'a> Handshake<'a> {
         self.peer_id
     }
 
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port
+    }
+
     pub fn get_utc_time(&self) -> TimestampSeconds {
         self.utc_time
     }
@@ -133,6 +154,23 @@ impl<'a> Handshake<'a> {
     pub fn get_pruned_topoheight(&self) -> &Option<u64> {
         &self.pruned_topoheight
     }
+
+    // Parse the major, minor and patch numbers of the daemon version
+    // Version is built as "major.minor.patch-commit"
+    fn get_semantic_version(&self) -> Option<(u64, u64, u64)> {
+        let version = self.version.split('-').next()?;
+        let mut numbers = version.split('.').map(|n| n.parse::<u64>().ok());
+        let version = (numbers.next()??, numbers.next()??, numbers.next()??);
+        if numbers.next().is_some() {
+            return None
+        }
+        Some(version)
+    }
+
+    // Verify if the peer sends its features after the handshake
+    pub fn supports_features(&self) -> bool {
+        self.get_semantic_version().map_or(false, |version| version >= FEATURES_MIN_VERSION)
+    }
 }
 
 impl Serializer for Handshake<'_> {
@@ -247,3 +285,61 @@ impl Display for Handshake<'_> {
         write!(f, "Handshake[version: {}, node tag: {}, network_id: {}, peer_id: {}, utc_time: {}, block_height: {}, block_top_hash: {}]", self.get_version(), node_tag, hex::encode(self.get_network_id()), self.get_peer_id(), self.get_utc_time(), self.get_block_height(), self.get_block_top_hash())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Handshake as written by the nodes that don't send their features
+    fn baseline_handshake_bytes(version: &str) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.write_string(&version.to_owned());
+        Network::Testnet.write(&mut writer);
+        writer.write_optional_string(&Some("node".to_owned()));
+        writer.write_bytes(&[7; 16]);
+        writer.write_u64(&42);
+        writer.write_u16(2125);
+        writer.write_u64(&1700000000);
+        writer.write_u64(&100);
+        writer.write_u64(&90);
+        Some(10u64).write(&mut writer);
+        writer.write_hash(&Hash::new([1; 32]));
+        writer.write_hash(&Hash::new([2; 32]));
+        CumulativeDifficulty::zero().write(&mut writer);
+        writer.write_bool(true);
+        writer.bytes()
+    }
+
+    fn create_handshake(version: &str) -> Handshake<'static> {
+        Handshake::new(Cow::Owned(version.to_owned()), Network::Testnet, Cow::Owned(Some("node".to_owned())), Cow::Owned([7; 16]), 42, 2125, 1700000000, 100, 90, Some(10), Cow::Owned(Hash::new([1; 32])), Cow::Owned(Hash::new([2; 32])), Cow::Owned(CumulativeDifficulty::zero()), true)
+    }
+
+    #[test]
+    fn test_baseline_handshake_is_accepted() {
+        let bytes = baseline_handshake_bytes("1.0.1-abcdef1");
+        let mut reader = Reader::new(&bytes);
+        let handshake = Handshake::read(&mut reader).unwrap();
+        assert_eq!(reader.total_read(), bytes.len());
+        assert_eq!(handshake.get_version(), "1.0.1-abcdef1");
+        assert_eq!(handshake.get_peer_id(), 42);
+        assert_eq!(*handshake.get_pruned_topoheight(), Some(10));
+        // an old node doesn't send its features
+        assert!(!handshake.supports_features());
+    }
+
+    #[test]
+    fn test_handshake_is_written_in_baseline_format() {
+        let handshake = create_handshake("1.1.0-abcdef1");
+        let bytes = handshake.to_bytes();
+        assert_eq!(bytes, baseline_handshake_bytes("1.1.0-abcdef1"));
+        assert_eq!(bytes.len(), handshake.size());
+        assert!(handshake.supports_features());
+    }
+
+    #[test]
+    fn test_supports_features() {
+        for (version, expected) in [("1.0.1-abcdef1", false), ("1.0.99", false), ("1.1.0", true), ("1.2.3-abcdef1", true), ("2.0.0-abcdef1", true), ("1.1", false), ("1.1.0.0", false), ("dev", false)] {
+            assert_eq!(create_handshake(version).supports_features(), expected, "version {}", version);
+        }
+    }
+}
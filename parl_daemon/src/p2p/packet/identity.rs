@@ -0,0 +1,53 @@
+use parl_common::{
+    crypto::{PublicKey, Signature},
+    serializer::{Serializer, Reader, ReaderError, Writer}
+};
+
+// Identity proof sent by each peer after the handshake
+// The signature is done over the handshake transcript
+// using the persistent identity key of the node
+#[derive(Clone, Debug)]
+pub struct IdentityProof {
+    // Identity public key of the node
+    public_key: PublicKey,
+    // Signature of the handshake transcript
+    signature: Signature
+}
+
+impl IdentityProof {
+    pub fn new(public_key: PublicKey, signature: Signature) -> Self {
+        Self {
+            public_key,
+            signature
+        }
+    }
+
+    pub fn get_public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn get_signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn consume(self) -> (PublicKey, Signature) {
+        (self.public_key, self.signature)
+    }
+}
+
+impl Serializer for IdentityProof {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let public_key = PublicKey::read(reader)?;
+        let signature = Signature::read(reader)?;
+        Ok(Self::new(public_key, signature))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        self.public_key.write(writer);
+        self.signature.write(writer);
+    }
+
+    fn size(&self) -> usize {
+        self.public_key.size() + self.signature.size()
+    }
+}
@@ -0,0 +1,48 @@
+use parl_common::serializer::{Serializer, Reader, ReaderError, Writer};
+
+use crate::p2p::encryption::DHPublicKeyBytes;
+
+// Diffie-Hellman key exchange packet
+// It is sent in plaintext when the connection is established,
+// and then encrypted at each key rotation with a fresh public key
+#[derive(Clone, Debug)]
+pub struct DHKeyExchange {
+    // Version of the key exchange supported
+    version: u8,
+    // X25519 public key
+    public_key: DHPublicKeyBytes
+}
+
+impl DHKeyExchange {
+    pub fn new(version: u8, public_key: DHPublicKeyBytes) -> Self {
+        Self {
+            version,
+            public_key
+        }
+    }
+
+    pub fn get_version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn get_public_key(&self) -> &DHPublicKeyBytes {
+        &self.public_key
+    }
+}
+
+impl Serializer for DHKeyExchange {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let version = reader.read_u8()?;
+        let public_key = DHPublicKeyBytes::read(reader)?;
+        Ok(Self::new(version, public_key))
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u8(self.version);
+        self.public_key.write(writer);
+    }
+
+    fn size(&self) -> usize {
+        1 + self.public_key.size()
+    }
+}
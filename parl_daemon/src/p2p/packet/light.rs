@@ -0,0 +1,172 @@
+use std::borrow::Cow;
+use parl_common::{
+    crypto::{Hash, PublicKey},
+    light::{LightHeader, StateProof},
+    serializer::{Reader, ReaderError, Serializer, Writer}
+};
+
+// Light client sub-protocol
+// Light clients request the headers in topological order to verify their PoW
+// and then request proofs of the balances and nonces of their accounts
+
+// Request the headers starting at a topoheight
+#[derive(Clone, Debug)]
+pub struct LightHeadersRequest {
+    topoheight: u64,
+    count: u16
+}
+
+impl LightHeadersRequest {
+    pub fn new(topoheight: u64, count: u16) -> Self {
+        Self {
+            topoheight,
+            count
+        }
+    }
+
+    pub fn consume(self) -> (u64, u16) {
+        (self.topoheight, self.count)
+    }
+}
+
+impl Serializer for LightHeadersRequest {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u64(&self.topoheight);
+        writer.write_u16(self.count);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let topoheight = reader.read_u64()?;
+        let count = reader.read_u16()?;
+        Ok(Self::new(topoheight, count))
+    }
+
+    fn size(&self) -> usize {
+        self.topoheight.size() + self.count.size()
+    }
+}
+
+// Headers ordered by topoheight
+// It may contains less headers than requested if we reached our top topoheight
+#[derive(Debug)]
+pub struct LightHeadersResponse {
+    headers: Vec<LightHeader>
+}
+
+impl LightHeadersResponse {
+    pub fn new(headers: Vec<LightHeader>) -> Self {
+        Self {
+            headers
+        }
+    }
+
+    pub fn consume(self) -> Vec<LightHeader> {
+        self.headers
+    }
+}
+
+impl Serializer for LightHeadersResponse {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u16(self.headers.len() as u16);
+        for header in &self.headers {
+            header.write(writer);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let count = reader.read_u16()?;
+        let mut headers = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            headers.push(LightHeader::read(reader)?);
+        }
+        Ok(Self::new(headers))
+    }
+}
+
+// Request the proof of the last balance or nonce of an account at or below a topoheight
+#[derive(Clone, Debug)]
+pub struct StateProofRequest {
+    key: PublicKey,
+    // None to request the nonce
+    asset: Option<Hash>,
+    topoheight: u64
+}
+
+impl StateProofRequest {
+    pub fn new(key: PublicKey, asset: Option<Hash>, topoheight: u64) -> Self {
+        Self {
+            key,
+            asset,
+            topoheight
+        }
+    }
+
+    pub fn get_key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    pub fn get_asset(&self) -> Option<&Hash> {
+        self.asset.as_ref()
+    }
+
+    pub fn get_topoheight(&self) -> u64 {
+        self.topoheight
+    }
+}
+
+impl Serializer for StateProofRequest {
+    fn write(&self, writer: &mut Writer) {
+        self.key.write(writer);
+        self.asset.write(writer);
+        writer.write_u64(&self.topoheight);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let key = PublicKey::read(reader)?;
+        let asset = Option::read(reader)?;
+        let topoheight = reader.read_u64()?;
+        Ok(Self::new(key, asset, topoheight))
+    }
+
+    fn size(&self) -> usize {
+        self.key.size() + self.asset.size() + self.topoheight.size()
+    }
+}
+
+// The proof is None if we don't have any version for this request
+pub struct StateProofResponse<'a> {
+    request: Cow<'a, StateProofRequest>,
+    proof: Option<StateProof>
+}
+
+impl<'a> StateProofResponse<'a> {
+    pub fn new(request: Cow<'a, StateProofRequest>, proof: Option<StateProof>) -> Self {
+        Self {
+            request,
+            proof
+        }
+    }
+
+    pub fn consume(self) -> (Cow<'a, StateProofRequest>, Option<StateProof>) {
+        (self.request, self.proof)
+    }
+}
+
+impl Serializer for StateProofResponse<'_> {
+    fn write(&self, writer: &mut Writer) {
+        self.request.write(writer);
+        self.proof.write(writer);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let request = StateProofRequest::read(reader)?;
+        let proof = Option::read(reader)?;
+        Ok(Self::new(Cow::Owned(request), proof))
+    }
+}
+
+impl std::fmt::Debug for StateProofResponse<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StateProofResponse[{:?}, found: {}]", self.request, self.proof.is_some())
+    }
+}
@@ -5,6 +5,10 @@ pub mod object;
 pub mod inventory;
 pub mod bootstrap_chain;
 pub mod peer_disconnected;
+pub mod key_exchange;
+pub mod identity;
+pub mod compact_block;
+pub mod light;
 
 use self::bootstrap_chain::{BootstrapChainRequest, BootstrapChainResponse};
 use self::inventory::{NotifyInventoryResponse, NotifyInventoryRequest};
@@ -12,13 +16,18 @@ use self::object::{ObjectRequest, ObjectResponse};
 use self::chain::{ChainRequest, ChainResponse};
 use self::handshake::Handshake;
 use self::peer_disconnected::PacketPeerDisconnected;
+use self::key_exchange::DHKeyExchange;
+use self::identity::IdentityProof;
+use self::compact_block::{BlockTransactions, CompactBlock, GetBlockTransactions};
+use self::light::{LightHeadersRequest, LightHeadersResponse, StateProofRequest, StateProofResponse};
 use self::ping::Ping;
 use std::borrow::Cow;
 use log::{debug, trace};
 use parl_common::{
     serializer::{Serializer, Reader, ReaderError, Writer},
     block::BlockHeader,
-    crypto::Hash
+    crypto::Hash,
+    transaction::Transaction
 };
 
 use super::EncryptionKey;
@@ -38,6 +47,23 @@ const NOTIFY_INV_RESPONSE_ID: u8 = 10;
 const BOOTSTRAP_CHAIN_REQUEST_ID: u8 = 11;
 const BOOTSTRAP_CHAIN_RESPONSE_ID: u8 = 12;
 const PEER_DISCONNECTED_ID: u8 = 13;
+const DH_KEY_EXCHANGE_ID: u8 = 14;
+const IDENTITY_PROOF_ID: u8 = 15;
+const COMPACT_BLOCK_PROPAGATION_ID: u8 = 16;
+const GET_BLOCK_TXS_ID: u8 = 17;
+const BLOCK_TXS_ID: u8 = 18;
+const STEM_TX_ID: u8 = 19;
+const LIGHT_HEADERS_REQUEST_ID: u8 = 20;
+const LIGHT_HEADERS_RESPONSE_ID: u8 = 21;
+const STATE_PROOF_REQUEST_ID: u8 = 22;
+const STATE_PROOF_RESPONSE_ID: u8 = 23;
+const FEATURES_ID: u8 = 24;
+
+// Packets that are never delayed by the bandwidth limits
+// Block propagation must stay fast for the network and ping keeps the connection alive
+pub fn is_high_priority_packet(bytes: &[u8]) -> bool {
+    matches!(bytes.first(), Some(&BLOCK_PROPAGATION_ID) | Some(&COMPACT_BLOCK_PROPAGATION_ID) | Some(&BLOCK_TXS_ID) | Some(&PING_ID))
+}
 
 // PacketWrapper allows us to link any Packet to a Ping
 #[derive(Debug)]
@@ -81,12 +107,25 @@ impl<'a, T: Serializer + Clone> Serializer for PacketWrapper<'a, T> {
 #[derive(Debug)]
 pub enum Packet<'a> {
     Handshake(Cow<'a, Handshake<'a>>), // first packet to connect to a node
+    // Bit flags of the supported features, sent after the handshake
+    Features(u8),
     // packet contains tx hash, view this packet as a "notification"
     // instead of sending the TX directly, we notify our peers
     // so the peer that already have this TX in mempool don't have to read it again
     // imo: can be useful when the network is spammed by alot of txs
     TransactionPropagation(PacketWrapper<'a, Hash>),
     BlockPropagation(PacketWrapper<'a, BlockHeader>),
+    // Block propagated with short tx ids, rebuilt from the mempool
+    CompactBlockPropagation(PacketWrapper<'a, CompactBlock<'a>>),
+    GetBlockTransactions(Cow<'a, GetBlockTransactions>),
+    BlockTransactions(BlockTransactions<'a>),
+    // Full transaction relayed in the Dandelion++ stem phase
+    StemTransaction(Cow<'a, Transaction>),
+    // Light client sub-protocol
+    LightHeadersRequest(Cow<'a, LightHeadersRequest>),
+    LightHeadersResponse(LightHeadersResponse),
+    StateProofRequest(Cow<'a, StateProofRequest>),
+    StateProofResponse(StateProofResponse<'a>),
     ChainRequest(PacketWrapper<'a, ChainRequest>),
     ChainResponse(ChainResponse),
     Ping(Cow<'a, Ping<'a>>),
@@ -99,12 +138,16 @@ pub enum Packet<'a> {
     PeerDisconnected(PacketPeerDisconnected),
     // Encryption
     KeyExchange(Cow<'a, EncryptionKey>),
+    DHKeyExchange(Cow<'a, DHKeyExchange>),
+    // Authentication
+    IdentityProof(Cow<'a, IdentityProof>),
 }
 
 impl Packet<'_> {
     pub fn get_id(&self) -> u8 {
         match self {
             Packet::Handshake(_) => HANDSHAKE_ID,
+            Packet::Features(_) => FEATURES_ID,
             Packet::TransactionPropagation(_) => TX_PROPAGATION_ID,
             Packet::BlockPropagation(_) => BLOCK_PROPAGATION_ID,
             Packet::ChainRequest(_) => CHAIN_REQUEST_ID,
@@ -118,6 +161,16 @@ impl Packet<'_> {
             Packet::BootstrapChainResponse(_) => BOOTSTRAP_CHAIN_RESPONSE_ID,
             Packet::PeerDisconnected(_) => PEER_DISCONNECTED_ID,
             Packet::KeyExchange(_) => KEY_EXCHANGE_ID,
+            Packet::DHKeyExchange(_) => DH_KEY_EXCHANGE_ID,
+            Packet::IdentityProof(_) => IDENTITY_PROOF_ID,
+            Packet::CompactBlockPropagation(_) => COMPACT_BLOCK_PROPAGATION_ID,
+            Packet::GetBlockTransactions(_) => GET_BLOCK_TXS_ID,
+            Packet::BlockTransactions(_) => BLOCK_TXS_ID,
+            Packet::StemTransaction(_) => STEM_TX_ID,
+            Packet::LightHeadersRequest(_) => LIGHT_HEADERS_REQUEST_ID,
+            Packet::LightHeadersResponse(_) => LIGHT_HEADERS_RESPONSE_ID,
+            Packet::StateProofRequest(_) => STATE_PROOF_REQUEST_ID,
+            Packet::StateProofResponse(_) => STATE_PROOF_RESPONSE_ID,
         }
     }
 }
@@ -129,6 +182,7 @@ impl<'a> Serializer for Packet<'a> {
         let packet = match id {
             KEY_EXCHANGE_ID => Packet::KeyExchange(Cow::Owned(EncryptionKey::read(reader)?)),
             HANDSHAKE_ID => Packet::Handshake(Cow::Owned(Handshake::read(reader)?)),
+            FEATURES_ID => Packet::Features(u8::read(reader)?),
             TX_PROPAGATION_ID => Packet::TransactionPropagation(PacketWrapper::read(reader)?),
             BLOCK_PROPAGATION_ID => Packet::BlockPropagation(PacketWrapper::read(reader)?),
             CHAIN_REQUEST_ID => Packet::ChainRequest(PacketWrapper::read(reader)?),
@@ -141,6 +195,16 @@ impl<'a> Serializer for Packet<'a> {
             BOOTSTRAP_CHAIN_REQUEST_ID => Packet::BootstrapChainRequest(BootstrapChainRequest::read(reader)?),
             BOOTSTRAP_CHAIN_RESPONSE_ID => Packet::BootstrapChainResponse(BootstrapChainResponse::read(reader)?),
             PEER_DISCONNECTED_ID => Packet::PeerDisconnected(PacketPeerDisconnected::read(reader)?),
+            DH_KEY_EXCHANGE_ID => Packet::DHKeyExchange(Cow::Owned(DHKeyExchange::read(reader)?)),
+            IDENTITY_PROOF_ID => Packet::IdentityProof(Cow::Owned(IdentityProof::read(reader)?)),
+            COMPACT_BLOCK_PROPAGATION_ID => Packet::CompactBlockPropagation(PacketWrapper::read(reader)?),
+            GET_BLOCK_TXS_ID => Packet::GetBlockTransactions(Cow::Owned(GetBlockTransactions::read(reader)?)),
+            BLOCK_TXS_ID => Packet::BlockTransactions(BlockTransactions::read(reader)?),
+            STEM_TX_ID => Packet::StemTransaction(Cow::Owned(Transaction::read(reader)?)),
+            LIGHT_HEADERS_REQUEST_ID => Packet::LightHeadersRequest(Cow::Owned(LightHeadersRequest::read(reader)?)),
+            LIGHT_HEADERS_RESPONSE_ID => Packet::LightHeadersResponse(LightHeadersResponse::read(reader)?),
+            STATE_PROOF_REQUEST_ID => Packet::StateProofRequest(Cow::Owned(StateProofRequest::read(reader)?)),
+            STATE_PROOF_RESPONSE_ID => Packet::StateProofResponse(StateProofResponse::read(reader)?),
             id => {
                 debug!("invalid packet id received: {}", id);
                 return Err(ReaderError::InvalidValue)
@@ -158,6 +222,7 @@ impl<'a> Serializer for Packet<'a> {
         let (id, serializer): (u8, &dyn Serializer) = match self {
             Packet::KeyExchange(key) => (KEY_EXCHANGE_ID, key),
             Packet::Handshake(handshake) => (HANDSHAKE_ID, handshake.as_ref()),
+            Packet::Features(features) => (FEATURES_ID, features),
             Packet::TransactionPropagation(tx) => (TX_PROPAGATION_ID, tx),
             Packet::BlockPropagation(block) => (BLOCK_PROPAGATION_ID, block),
             Packet::ChainRequest(request) => (CHAIN_REQUEST_ID, request),
@@ -170,6 +235,16 @@ impl<'a> Serializer for Packet<'a> {
             Packet::BootstrapChainRequest(request) => (BOOTSTRAP_CHAIN_REQUEST_ID, request),
             Packet::BootstrapChainResponse(response) => (BOOTSTRAP_CHAIN_RESPONSE_ID, response),
             Packet::PeerDisconnected(disconnected) => (PEER_DISCONNECTED_ID, disconnected),
+            Packet::DHKeyExchange(key_exchange) => (DH_KEY_EXCHANGE_ID, key_exchange.as_ref()),
+            Packet::IdentityProof(proof) => (IDENTITY_PROOF_ID, proof.as_ref()),
+            Packet::CompactBlockPropagation(compact_block) => (COMPACT_BLOCK_PROPAGATION_ID, compact_block),
+            Packet::GetBlockTransactions(request) => (GET_BLOCK_TXS_ID, request.as_ref()),
+            Packet::BlockTransactions(response) => (BLOCK_TXS_ID, response),
+            Packet::StemTransaction(tx) => (STEM_TX_ID, tx.as_ref()),
+            Packet::LightHeadersRequest(request) => (LIGHT_HEADERS_REQUEST_ID, request.as_ref()),
+            Packet::LightHeadersResponse(response) => (LIGHT_HEADERS_RESPONSE_ID, response),
+            Packet::StateProofRequest(request) => (STATE_PROOF_REQUEST_ID, request.as_ref()),
+            Packet::StateProofResponse(response) => (STATE_PROOF_RESPONSE_ID, response),
         };
 
         let packet = serializer.to_bytes();
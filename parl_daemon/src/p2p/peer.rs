@@ -3,7 +3,8 @@ use crate::{
         PEER_FAIL_TIME_RESET, PEER_BLOCK_CACHE_SIZE, PEER_TX_CACHE_SIZE,
         PEER_TEMP_BAN_TIME, PEER_TIMEOUT_BOOTSTRAP_STEP,
         PEER_TIMEOUT_REQUEST_OBJECT, CHAIN_SYNC_TIMEOUT_SECS,
-        PEER_PACKET_CHANNEL_SIZE
+        PEER_PACKET_CHANNEL_SIZE,
+        PEER_SCORE_BAN_THRESHOLD
     },
     p2p::packet::PacketWrapper
 };
@@ -32,11 +33,15 @@ use super::{
             ObjectRequest,
             OwnedObjectResponse
         },
+        handshake::{FEATURE_COMPACT_BLOCKS, FEATURE_DANDELION},
+        is_high_priority_packet,
         Packet
     },
     peer_list::SharedPeerList,
+    bandwidth::ConnectionLimiter,
     connection::Connection,
-    error::P2pError
+    error::P2pError,
+    score::{apply_score_delta, decay_score, decay_score_since}
 };
 use std::{
     num::NonZeroUsize,
@@ -45,12 +50,13 @@ use std::{
     fmt::{Display, Error, Formatter},
     hash::{Hash as StdHash, Hasher},
     net::{IpAddr, SocketAddr},
-    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicU8, Ordering},
     time::Duration
 };
 use tokio::{
+    select,
     sync::{broadcast, mpsc, oneshot::Sender, Mutex},
-    time::timeout,
+    time::{sleep_until, timeout, Instant},
 };
 use lru::LruCache;
 use bytes::Bytes;
@@ -67,7 +73,65 @@ use log::{
 pub type RequestedObjects = HashMap<ObjectRequest, Sender<OwnedObjectResponse>>;
 
 pub type Tx = mpsc::Sender<Bytes>;
-pub type Rx = mpsc::Receiver<Bytes>;
+pub type Rx = PacketReceiver;
+
+// Receiving side of the writer task channels
+// High priority packets have their own channel so they are never stuck
+// behind the normal packets waiting on the bandwidth limits
+pub struct PacketReceiver {
+    high_priority: mpsc::Receiver<Bytes>,
+    normal: mpsc::Receiver<Bytes>,
+    // Normal packet waiting until its upload delay is elapsed
+    delayed: Option<(Bytes, Instant)>
+}
+
+impl PacketReceiver {
+    pub fn new(high_priority: mpsc::Receiver<Bytes>, normal: mpsc::Receiver<Bytes>) -> Self {
+        Self {
+            high_priority,
+            normal,
+            delayed: None
+        }
+    }
+
+    // Wait for the next packet that can be sent right now
+    // Its upload tokens are already taken from the limiter
+    // High priority packets are returned while a normal packet is throttled
+    pub async fn recv(&mut self, limiter: Option<&ConnectionLimiter>) -> Option<Bytes> {
+        let Self { high_priority, normal, delayed } = self;
+        loop {
+            let deadline = delayed.as_ref().map(|(_, deadline)| *deadline);
+            select! {
+                biased;
+                Some(bytes) = high_priority.recv() => {
+                    // Counted so the normal packets pay for it
+                    if let Some(limiter) = limiter {
+                        limiter.take_upload(bytes.len());
+                    }
+                    return Some(bytes)
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    return delayed.take().map(|(bytes, _)| bytes)
+                },
+                Some(bytes) = normal.recv(), if deadline.is_none() => {
+                    let delay = limiter.map_or(Duration::ZERO, |limiter| limiter.take_upload(bytes.len()));
+                    if delay.is_zero() {
+                        return Some(bytes)
+                    }
+
+                    trace!("Delaying upload of {} bytes by {:?}", bytes.len(), delay);
+                    *delayed = Some((bytes, Instant::now() + delay));
+                },
+                else => return None
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.high_priority.close();
+        self.normal.close();
+    }
+}
 
 // Enum used to track the state of a task
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -111,6 +175,10 @@ pub struct Peer {
     last_fail_count: AtomicU64,
     // fail count: if greater than 20, we should close this connection
     fail_count: AtomicU8,
+    // score based on the peer behavior, decayed over time
+    score: AtomicI64,
+    // last time the score was updated
+    last_score_update: AtomicU64,
     // shared pointer to the peer list in case of disconnection
     peer_list: SharedPeerList,
     // map of requested objects from this peer
@@ -146,8 +214,12 @@ pub struct Peer {
     outgoing_address: SocketAddr,
     // Determine if this peer allows to be shared to others and/or through API
     sharable: bool,
+    // Features flags sent in its handshake
+    features: u8,
     // Channel to send bytes to the writer task
     tx: Tx,
+    // Channel for the packets that must not wait on the bandwidth limits
+    high_priority_tx: Tx,
     // Channel to notify the tasks to exit
     exit_channel: broadcast::Sender<()>,
     // Tracking dedicated tasks
@@ -156,7 +228,7 @@ pub struct Peer {
 }
 
 impl Peer {
-    pub fn new(connection: Connection, id: u64, node_tag: Option<String>, local_port: u16, version: String, top_hash: Hash, topoheight: u64, height: u64, pruned_topoheight: Option<u64>, priority: bool, cumulative_difficulty: CumulativeDifficulty, peer_list: SharedPeerList, peers_received: HashSet<SocketAddr>, sharable: bool) -> (Self, Rx) {
+    pub fn new(connection: Connection, id: u64, node_tag: Option<String>, local_port: u16, version: String, top_hash: Hash, topoheight: u64, height: u64, pruned_topoheight: Option<u64>, priority: bool, cumulative_difficulty: CumulativeDifficulty, peer_list: SharedPeerList, peers_received: HashSet<SocketAddr>, sharable: bool, features: u8) -> (Self, Rx) {
         let mut outgoing_address = *connection.get_address();
         outgoing_address.set_port(local_port);
 
@@ -167,6 +239,7 @@ impl Peer {
 
         let (exit_channel, _) = broadcast::channel(1);
         let (tx, rx) = mpsc::channel(PEER_PACKET_CHANNEL_SIZE);
+        let (high_priority_tx, high_priority_rx) = mpsc::channel(PEER_PACKET_CHANNEL_SIZE);
 
         (Self {
             connection,
@@ -180,6 +253,8 @@ impl Peer {
             priority,
             last_fail_count: AtomicU64::new(0),
             fail_count: AtomicU8::new(0),
+            score: AtomicI64::new(0),
+            last_score_update: AtomicU64::new(get_current_time_in_seconds()),
             last_chain_sync: AtomicU64::new(0),
             peer_list,
             objects_requested: Mutex::new(HashMap::new()),
@@ -198,11 +273,13 @@ impl Peer {
             sync_chain: Mutex::new(None),
             outgoing_address,
             sharable,
+            features,
             exit_channel,
             tx,
+            high_priority_tx,
             read_task: Mutex::new(TaskState::Inactive),
             write_task: Mutex::new(TaskState::Inactive),
-        }, rx)
+        }, PacketReceiver::new(high_priority_rx, rx))
     }
 
     // Subscribe to the exit channel to be notified when peer disconnects
@@ -331,6 +408,16 @@ impl Peer {
         self.sharable
     }
 
+    // Check if the peer can receive compact blocks
+    pub fn supports_compact_blocks(&self) -> bool {
+        self.features & FEATURE_COMPACT_BLOCKS != 0
+    }
+
+    // Check if the peer can receive stem txs
+    pub fn supports_dandelion(&self) -> bool {
+        self.features & FEATURE_DANDELION != 0
+    }
+
     // Get the last time we got a fail from the peer
     pub fn get_last_fail_count(&self) -> u64 {
         self.last_fail_count.load(Ordering::Acquire)
@@ -377,6 +464,49 @@ impl Peer {
         self.set_last_fail_count(current_time);
     }
 
+    // Get the current score of the peer with the decay applied
+    pub fn get_score(&self) -> i64 {
+        let elapsed = get_current_time_in_seconds().saturating_sub(self.last_score_update.load(Ordering::Acquire));
+        decay_score(self.score.load(Ordering::Acquire), elapsed)
+    }
+
+    // Restore the score of the peer from the stored peerlist
+    pub fn set_score(&self, score: i64, updated_at: TimestampSeconds) {
+        self.score.store(score, Ordering::Release);
+        self.last_score_update.store(updated_at, Ordering::Release);
+    }
+
+    // Apply a reward or a penalty to the score and returns the new score
+    // The decay reference time only moves by whole half-lives to not lose the remainder
+    fn update_score(&self, delta: i64) -> i64 {
+        let (score, updated_at) = decay_score_since(
+            self.score.load(Ordering::Acquire),
+            self.last_score_update.load(Ordering::Acquire),
+            get_current_time_in_seconds()
+        );
+        let score = apply_score_delta(score, delta);
+        self.set_score(score, updated_at);
+        score
+    }
+
+    // Reward the peer for useful data delivered
+    pub fn reward(&self, amount: i64) {
+        let score = self.update_score(amount);
+        trace!("{} rewarded by {}, score is now {}", self, amount, score);
+    }
+
+    // Penalize the peer based on the error category
+    pub fn penalize(&self, err: &P2pError) {
+        let penalty = err.get_score_penalty();
+        let score = self.update_score(-penalty);
+        debug!("{} penalized by {} for '{}', score is now {}", self, penalty, err, score);
+    }
+
+    // Check if the score is too low to keep the peer connected
+    pub fn has_low_score(&self) -> bool {
+        self.get_score() <= PEER_SCORE_BAN_THRESHOLD
+    }
+
     // Get the last time we got a chain sync request
     // This is used to prevent spamming the chain sync packet
     pub fn get_last_chain_sync(&self) -> TimestampSeconds {
@@ -635,8 +765,15 @@ impl Peer {
 
     // Send packet bytes to the peer
     // This will send the bytes to the writer task through its channel
+    // High priority packets use their own channel to overtake the throttled ones
     pub async fn send_bytes(&self, bytes: Bytes) -> Result<(), P2pError> {
-        self.tx.send(bytes).await
+        let tx = if is_high_priority_packet(&bytes) {
+            &self.high_priority_tx
+        } else {
+            &self.tx
+        };
+
+        tx.send(bytes).await
             .map_err(|e| P2pError::SendError(e.to_string()))
     }
 
@@ -686,7 +823,7 @@ impl Display for Peer {
         let read_task = self.read_task.try_lock().map(|v| *v).unwrap_or(TaskState::Unknown);
         let write_task = self.write_task.try_lock().map(|v| *v).unwrap_or(TaskState::Unknown);
 
-        write!(f, "Peer[connection: {}, id: {}, topoheight: {}, top hash: {}, height: {}, pruned: {}, priority: {}, tag: {}, version: {}, fail count: {}, out: {}, peers: {}, tasks: {:?}/{:?}]",
+        write!(f, "Peer[connection: {}, id: {}, topoheight: {}, top hash: {}, height: {}, pruned: {}, priority: {}, tag: {}, version: {}, fail count: {}, score: {}, out: {}, peers: {}, tasks: {:?}/{:?}]",
             self.get_connection(),
             self.get_id(),
             self.get_topoheight(),
@@ -697,6 +834,7 @@ impl Display for Peer {
             self.get_node_tag().as_ref().unwrap_or(&"None".to_owned()),
             self.get_version(),
             self.get_fail_count(),
+            self.get_score(),
             self.is_out(),
             peers,
             read_task,
@@ -727,3 +865,49 @@ impl StdHash for Peer {
         self.get_id().hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::time::sleep;
+    use super::*;
+    use crate::p2p::bandwidth::{BandwidthLimiter, BandwidthLimits};
+
+    #[tokio::test]
+    async fn test_high_priority_packet_overtakes_throttled_packet() {
+        let limits = BandwidthLimits {
+            peer_upload: Some(1000),
+            ..Default::default()
+        };
+        let limiter = ConnectionLimiter::new(Arc::new(BandwidthLimiter::new(None, None)), &limits);
+
+        let (high_priority_tx, high_priority_rx) = mpsc::channel(8);
+        let (tx, rx) = mpsc::channel(8);
+        let mut receiver = PacketReceiver::new(high_priority_rx, rx);
+
+        // First packet empties the bucket, the second one must wait one second
+        tx.send(Bytes::from(vec![1u8; 1000])).await.unwrap();
+        tx.send(Bytes::from(vec![2u8; 1000])).await.unwrap();
+
+        let start = Instant::now();
+        assert_eq!(receiver.recv(Some(&limiter)).await.unwrap()[0], 1);
+
+        // Queued while the second packet is throttled
+        let sender = tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            high_priority_tx.send(Bytes::from(vec![3u8; 10])).await.unwrap();
+            high_priority_tx
+        });
+
+        assert_eq!(receiver.recv(Some(&limiter)).await.unwrap()[0], 3);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // The throttled packet is still sent once its delay is elapsed
+        assert_eq!(receiver.recv(Some(&limiter)).await.unwrap()[0], 2);
+        assert!(start.elapsed() >= Duration::from_millis(1000));
+
+        drop(sender.await.unwrap());
+        drop(tx);
+        assert!(receiver.recv(Some(&limiter)).await.is_none());
+    }
+}
@@ -3,12 +3,18 @@ use crate::{
         P2P_EXTEND_PEERLIST_DELAY,
         PEER_FAIL_LIMIT,
         PEER_FAIL_TO_CONNECT_LIMIT,
+        PEER_SCORE_EVICTION_THRESHOLD,
         PEER_TEMP_BAN_TIME_ON_CONNECT,
         PEER_TIMEOUT_DISCONNECT
     },
     p2p::packet::peer_disconnected::PacketPeerDisconnected
 };
-use super::{peer::Peer, packet::Packet, error::P2pError};
+use super::{
+    ban::{BanList, BanRule, IpRange},
+    peer::Peer,
+    packet::Packet,
+    error::P2pError
+};
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     fmt::{self, Display, Formatter},
@@ -40,6 +46,8 @@ pub struct PeerList {
     // times its local port
     stored_peers: RwLock<HashMap<IpAddr, StoredPeer>>,
     filename: String,
+    // Ban rules for IP ranges, with optional expiry
+    bans: RwLock<BanList>,
     // used to notify the server that a peer disconnected
     // this is done through a channel to not have to handle generic types
     // and to be flexible in the future
@@ -62,6 +70,9 @@ pub struct StoredPeer {
     local_port: u16,
     // Until when the peer is banned
     temp_ban_until: Option<u64>,
+    // Score of the peer when it was last seen
+    #[serde(default)]
+    score: i64,
     state: StoredPeerState
 }
 
@@ -117,7 +128,7 @@ impl PeerList {
         Ok(peers)
     }
 
-    pub fn new(capacity: usize, filename: String, peer_disconnect_channel: Option<Sender<Arc<Peer>>>) -> SharedPeerList {
+    pub fn new(capacity: usize, filename: String, bans_filename: String, peer_disconnect_channel: Option<Sender<Arc<Peer>>>) -> SharedPeerList {
         let stored_peers = match Self::load_stored_peers(&filename) {
             Ok(peers) => peers,
             Err(e) => {
@@ -132,6 +143,7 @@ impl PeerList {
                 peers: RwLock::new(HashMap::with_capacity(capacity)),
                 stored_peers: RwLock::new(stored_peers),
                 filename,
+                bans: RwLock::new(BanList::load(bans_filename)),
                 peer_disconnect_channel
             }
         )
@@ -157,6 +169,11 @@ impl PeerList {
             let peers = peers.values().cloned().collect::<Vec<Arc<Peer>>>();
             (peer, peers)
         };
+
+        {
+            let mut stored_peers = self.stored_peers.write().await;
+            Self::save_peer_score(&mut stored_peers, &peer);
+        }
  
         // If peer allows us to share it, we have to notify all peers that have this peer in common
         if peer.sharable() {
@@ -199,7 +216,23 @@ impl PeerList {
 
     // Add a new peer to the list
     // This will returns an error if peerlist is full
+    // If the list is full, a peer with a lower score than the new one may be evicted
     pub async fn add_peer(&self, peer: &Arc<Peer>, max_peers: usize) -> Result<(), P2pError> {
+        // Restore the score the peer had during its last connection
+        {
+            let stored_peers = self.stored_peers.read().await;
+            if let Some(stored_peer) = stored_peers.get(&peer.get_outgoing_address().ip()) {
+                peer.set_score(stored_peer.get_score(), stored_peer.get_last_seen());
+            }
+        }
+
+        if self.size().await >= max_peers {
+            let threshold = PEER_SCORE_EVICTION_THRESHOLD.min(peer.get_score());
+            let evicted = self.find_peer_to_evict(threshold).await.ok_or(P2pError::PeerListFull)?;
+            info!("Peerlist is full, evicting {} for {}", evicted, peer);
+            evicted.close().await?;
+        }
+
         {
             let mut peers = self.peers.write().await;
             if peers.len() >= max_peers {
@@ -235,6 +268,31 @@ impl PeerList {
         }
     }
 
+    // Save the current score of a peer in the stored peerlist
+    fn save_peer_score(stored_peers: &mut HashMap<IpAddr, StoredPeer>, peer: &Peer) {
+        if let Some(stored_peer) = stored_peers.get_mut(&peer.get_outgoing_address().ip()) {
+            stored_peer.set_score(peer.get_score());
+            stored_peer.set_last_seen(get_current_time_in_seconds());
+        }
+    }
+
+    // Find the connected peer with the lowest score below the threshold
+    // Priority peers are never evicted
+    async fn find_peer_to_evict(&self, threshold: i64) -> Option<Arc<Peer>> {
+        let peers = self.peers.read().await;
+        peers.values()
+            .filter(|peer| !peer.is_priority())
+            .map(|peer| (peer.get_score(), peer))
+            .filter(|(score, _)| *score < threshold)
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, peer)| Arc::clone(peer))
+    }
+
+    // Check if a connected peer can be evicted to accept a new connection
+    pub async fn has_peer_to_evict(&self) -> bool {
+        self.find_peer_to_evict(PEER_SCORE_EVICTION_THRESHOLD).await.is_some()
+    }
+
     // Verify if the peer is connected (in peerlist)
     pub async fn has_peer(&self, peer_id: &u64) -> bool {
         let peers = self.peers.read().await;
@@ -272,6 +330,13 @@ impl PeerList {
             peers.drain().collect::<Vec<(u64, Arc<Peer>)>>()
         };
         info!("Closing {} peers", peers.len());
+        {
+            let mut stored_peers = self.stored_peers.write().await;
+            for (_, peer) in peers.iter() {
+                Self::save_peer_score(&mut stored_peers, peer);
+            }
+        }
+
         for (_, peer) in peers {
             debug!("Closing {}", peer);
             spawn_task(format!("p2p-disconnect-{}", peer.get_connection().get_address()), async move {
@@ -354,8 +419,16 @@ impl PeerList {
         self.addr_has_state(ip, StoredPeerState::Blacklist).await
     }
 
-    // Verify that the peer is not blacklisted or temp banned
+    // Verify that the peer is not banned, blacklisted or temp banned
     pub async fn is_allowed(&self, ip: &IpAddr) -> bool {
+        {
+            let bans = self.bans.read().await;
+            if let Some(rule) = bans.get_rule_for(ip, get_current_time_in_seconds()) {
+                trace!("{} is banned by {}", ip, rule);
+                return false
+            }
+        }
+
         let stored_peers = self.stored_peers.read().await;
         if let Some(stored_peer) = stored_peers.get(&ip) {
             // If peer is blacklisted, don't accept it
@@ -443,6 +516,43 @@ impl PeerList {
         }
     }
 
+    // Ban an IP range, permanently or for a duration in seconds
+    // All connected peers in this range are disconnected
+    pub async fn ban_range(&self, range: IpRange, reason: Option<String>, duration: Option<u64>) {
+        let now = get_current_time_in_seconds();
+        let rule = BanRule::new(range, reason, now, duration.map(|duration| now.saturating_add(duration)));
+        info!("Banning {}", rule);
+        {
+            let mut bans = self.bans.write().await;
+            bans.add(rule);
+        }
+
+        let banned_peers: Vec<Arc<Peer>> = {
+            let peers = self.peers.read().await;
+            peers.values().filter(|peer| range.contains(&peer.get_connection().get_address().ip())).cloned().collect()
+        };
+
+        for peer in banned_peers {
+            if let Err(e) = peer.close_internal().await {
+                error!("Error while trying to close {} for being banned: {}", peer, e);
+            }
+        }
+    }
+
+    // Remove the ban rule for this exact range
+    // Returns false if the range wasn't banned
+    pub async fn unban_range(&self, range: &IpRange) -> bool {
+        let mut bans = self.bans.write().await;
+        bans.remove(range)
+    }
+
+    // Get all active ban rules, expired ones are deleted
+    pub async fn get_bans(&self) -> Vec<BanRule> {
+        let mut bans = self.bans.write().await;
+        bans.clean_expired(get_current_time_in_seconds());
+        bans.get_rules().clone()
+    }
+
     // temp ban a peer for a duration in seconds
     // this will also close the peer
     pub async fn temp_ban_peer(&self, peer: &Peer, seconds: u64) {
@@ -562,6 +672,7 @@ impl StoredPeer {
             fail_count: 0,
             local_port,
             temp_ban_until: None,
+            score: 0,
             state
         }
     }
@@ -574,6 +685,10 @@ impl StoredPeer {
         &self.state
     }
 
+    fn get_last_seen(&self) -> TimestampSeconds {
+        self.last_seen
+    }
+
     fn set_last_seen(&mut self, last_seen: TimestampSeconds) {
         self.last_seen = last_seen;
     }
@@ -609,6 +724,14 @@ impl StoredPeer {
     fn get_local_port(&self) -> u16 {
         self.local_port
     }
+
+    fn get_score(&self) -> i64 {
+        self.score
+    }
+
+    fn set_score(&mut self, score: i64) {
+        self.score = score;
+    }
 }
 
 impl Display for StoredPeer {
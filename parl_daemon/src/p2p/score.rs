@@ -0,0 +1,71 @@
+use parl_common::time::TimestampSeconds;
+use crate::config::{PEER_SCORE_HALF_LIFE, PEER_SCORE_MAX, PEER_SCORE_MIN};
+
+// Score of a peer is halved every PEER_SCORE_HALF_LIFE seconds
+// so old behaviors, good or bad, are slowly forgotten
+pub fn decay_score(score: i64, elapsed: u64) -> i64 {
+    let halvings = elapsed / PEER_SCORE_HALF_LIFE;
+    if halvings >= 63 {
+        return 0
+    }
+
+    score / (1i64 << halvings)
+}
+
+// Decay a score last updated at `updated_at`
+// Returns the new score and the time from which the next decay must be computed,
+// so the time elapsed since the last half-life is not lost on frequent updates
+pub fn decay_score_since(score: i64, updated_at: TimestampSeconds, now: TimestampSeconds) -> (i64, TimestampSeconds) {
+    let elapsed = now.saturating_sub(updated_at);
+    let halvings = elapsed / PEER_SCORE_HALF_LIFE;
+    if halvings == 0 {
+        return (score, updated_at)
+    }
+
+    (decay_score(score, elapsed), now - elapsed % PEER_SCORE_HALF_LIFE)
+}
+
+// Apply a reward (positive) or a penalty (negative) to a score
+// Result is kept in the allowed bounds
+pub fn apply_score_delta(score: i64, delta: i64) -> i64 {
+    score.saturating_add(delta).clamp(PEER_SCORE_MIN, PEER_SCORE_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decay_score() {
+        assert_eq!(decay_score(80, 0), 80);
+        assert_eq!(decay_score(80, PEER_SCORE_HALF_LIFE - 1), 80);
+        assert_eq!(decay_score(80, PEER_SCORE_HALF_LIFE), 40);
+        assert_eq!(decay_score(-80, PEER_SCORE_HALF_LIFE * 2), -20);
+        assert_eq!(decay_score(-80, u64::MAX), 0);
+    }
+
+    #[test]
+    fn test_decay_score_since() {
+        // less than a half-life: the reference time is kept
+        assert_eq!(decay_score_since(80, 100, 100 + PEER_SCORE_HALF_LIFE - 1), (80, 100));
+
+        // the remainder is carried over to the next update
+        let now = 100 + PEER_SCORE_HALF_LIFE + 10;
+        assert_eq!(decay_score_since(80, 100, now), (40, 100 + PEER_SCORE_HALF_LIFE));
+
+        // updating every few seconds still decays the score
+        let (mut score, mut updated_at) = (80, 0);
+        for now in (0..=PEER_SCORE_HALF_LIFE * 2).step_by(10) {
+            (score, updated_at) = decay_score_since(score, updated_at, now);
+        }
+        assert_eq!(score, 20);
+    }
+
+    #[test]
+    fn test_apply_score_delta() {
+        assert_eq!(apply_score_delta(0, 5), 5);
+        assert_eq!(apply_score_delta(PEER_SCORE_MAX, 5), PEER_SCORE_MAX);
+        assert_eq!(apply_score_delta(PEER_SCORE_MIN, -5), PEER_SCORE_MIN);
+        assert_eq!(apply_score_delta(10, i64::MIN), PEER_SCORE_MIN);
+    }
+}
@@ -1,10 +1,7 @@
 use std::{
     borrow::Cow,
     time::{Duration, Instant},
-    sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering}
-    },
+    sync::Arc,
     collections::HashMap
 };
 use bytes::Bytes;
@@ -12,7 +9,6 @@ use tokio::{
     sync::{
         mpsc::{Sender, Receiver, self},
         RwLock,
-        oneshot,
         Mutex,
         broadcast
     },
@@ -30,7 +26,7 @@ use crate::{
         blockchain::Blockchain,
         storage::Storage
     },
-    config::PEER_TIMEOUT_REQUEST_OBJECT
+    config::{PEER_SCORE_REWARD_BLOCK, PEER_SCORE_REWARD_TX, PEER_TIMEOUT_REQUEST_OBJECT}
 };
 use super::{
     packet::{
@@ -86,23 +82,49 @@ struct Request {
     response: Option<OwnedObjectResponse>,
     // Timestamp when it got requested
     requested_at: Option<Instant>,
-    // If it linked to a group
-    group_id: Option<u64>,
     // If it has to be broadcast on handling or not
-    broadcast: bool
+    broadcast: bool,
+    // Other peers having the object, used if the peer doesn't answer
+    candidates: Vec<Arc<Peer>>
 }
 
 impl Request {
-    pub fn new(request: ObjectRequest, peer: Arc<Peer>, group_id: Option<u64>, broadcast: bool) -> Self {
+    pub fn new(request: ObjectRequest, peer: Arc<Peer>, broadcast: bool) -> Self {
         Self {
             request,
             peer,
             sender: None,
             response: None,
             requested_at: None,
-            group_id,
-            broadcast
+            broadcast,
+            candidates: Vec::new()
+        }
+    }
+
+    // Register another peer having the object
+    // If it's not requested yet, the peer with the best score is used
+    pub fn add_candidate(&mut self, mut peer: Arc<Peer>) {
+        if peer.get_id() == self.peer.get_id() || self.candidates.iter().any(|p| p.get_id() == peer.get_id()) {
+            return;
+        }
+
+        if !self.is_requested() && peer.get_score() > self.peer.get_score() {
+            std::mem::swap(&mut self.peer, &mut peer);
         }
+        self.candidates.push(peer);
+    }
+
+    // Request the object again from the connected candidate with the best score
+    // Returns false if there is no candidate left
+    pub fn retry_with_best_candidate(&mut self) -> bool {
+        self.candidates.retain(|peer| !peer.get_connection().is_closed());
+        let Some((index, _)) = self.candidates.iter().enumerate().max_by_key(|(_, peer)| peer.get_score()) else {
+            return false;
+        };
+
+        self.peer = self.candidates.swap_remove(index);
+        self.requested_at = None;
+        true
     }
 
     pub fn get_object(&self) -> &ObjectRequest {
@@ -121,10 +143,6 @@ impl Request {
         self.response.take()
     }
 
-    pub fn get_group_id(&self) -> Option<u64> {
-        self.group_id
-    }
-
     pub fn set_requested(&mut self) {
         self.requested_at = Some(Instant::now());
     }
@@ -166,47 +184,6 @@ impl Drop for Request {
     }
 }
 
-pub struct GroupManager {
-    // This is used to have unique id for each group of requests
-    group_id: AtomicU64,
-    groups: Mutex<HashMap<u64, oneshot::Sender<P2pError>>>
-}
-
-impl GroupManager {
-    pub fn new() -> Self {
-        Self {
-            group_id: AtomicU64::new(0),
-            groups: Mutex::new(HashMap::new())
-        }
-    }
-
-    // Generate a new group id
-    pub async fn next_group_id(&self) -> (u64, oneshot::Receiver<P2pError>) {
-        let mut groups = self.groups.lock().await;
-        let id = self.group_id.fetch_add(1, Ordering::SeqCst);
-
-        let (sender, receiver) = oneshot::channel();
-        groups.insert(id, sender);
-        (id, receiver)
-    }
-
-    // Unregister an existing group id by removing it
-    pub async fn unregister_group(&self, group_id: u64) {
-        let mut groups = self.groups.lock().await;
-        groups.remove(&group_id);
-    }
-
-    // Notify the requester about the failure
-    pub async fn notify_group(&self, group_id: u64, err: P2pError) {
-        let mut groups = self.groups.lock().await;
-        if let Some(sender) = groups.remove(&group_id) {
-            if sender.send(err).is_err() {
-                warn!("Error while sending group error");
-            }
-        }
-    }
-}
-
 struct ExpirableCache {
     cache: Mutex<HashMap<Hash, Instant>>
 }
@@ -246,9 +223,6 @@ pub struct ObjectTracker {
     handler_sender: Sender<OwnedObjectResponse>,
     // queue of requests with preserved order
     queue: RwLock<Queue<Hash, Request>>,
-    // Group Manager for batched requests
-    // If one fail, all the group is removed
-    group: GroupManager,
     // Requests that should be ignored
     // They got canceled but already requested
     cache: ExpirableCache
@@ -272,7 +246,6 @@ impl ObjectTracker {
             request_sender,
             handler_sender,
             queue: RwLock::new(Queue::new()),
-            group: GroupManager::new(),
             cache: ExpirableCache::new()
         });
         
@@ -324,20 +297,17 @@ impl ObjectTracker {
         self.cache.remove(hash).await
     }
 
-    // Returns the group manager used
-    pub fn get_group_manager(&self) -> &GroupManager {
-        &self.group
-    }
-
     // Handle the object response and returns the error if any
     async fn handle_object_response_internal<S: Storage>(&self, blockchain: &Arc<Blockchain<S>>, response: OwnedObjectResponse, broadcast: bool, peer: &Arc<Peer>) -> Result<(), P2pError> {
         match response {
             OwnedObjectResponse::Transaction(tx, hash) => {
                 blockchain.add_tx_to_mempool_with_hash(tx, hash, broadcast).await?;
+                peer.reward(PEER_SCORE_REWARD_TX);
             },
             OwnedObjectResponse::Block(block, _) => {
                 // We don't broadcast it to others peers but we broadcast it to our miners in case
                 blockchain.add_new_block(block, broadcast, false).await?;
+                peer.reward(PEER_SCORE_REWARD_BLOCK);
             }
             e => {
                 warn!("ObjectTracker received an invalid object response from {}: {:?}", peer, e);
@@ -385,10 +355,9 @@ impl ObjectTracker {
                     Some(response) => {
                         let (_, request) = queue.pop().unwrap();
                         if let Err(e) = self.handle_object_response_internal(&blockchain, response, request.broadcast(), request.get_peer()).await {
-                            if request.get_group_id().is_none() {
-                                warn!("Error while handling object response for {} in ObjectTracker from {}: {}", request.get_hash(), request.get_peer(), e);
-                            }
-                            self.clean_queue(&mut queue, request.get_peer().get_id(), request.get_group_id().map(|v| (v, e))).await;
+                            warn!("Error while handling object response for {} in ObjectTracker from {}: {}", request.get_hash(), request.get_peer(), e);
+                            request.get_peer().penalize(&e);
+                            self.clean_queue(&mut queue, request.get_peer().get_id()).await;
                         }
                     },
                     None => {
@@ -396,8 +365,18 @@ impl ObjectTracker {
                             // check if the request is timed out
                             if requested_at.elapsed() > TIME_OUT {
                                 warn!("Request timed out for object {}", request.get_hash());
+                                let peer = Arc::clone(request.get_peer());
+                                peer.penalize(&P2pError::TrackerRequestExpired);
+                                // another peer announced it, request it from the best one
+                                if request.retry_with_best_candidate() {
+                                    debug!("Requesting object {} again from {}", request.get_hash(), request.get_peer());
+                                    if self.request_sender.try_send(request.get_hash().clone()).is_ok() {
+                                        break;
+                                    }
+                                }
+
                                 let (_, request) = queue.pop().unwrap();
-                                self.clean_queue(&mut queue, request.get_peer().get_id(), request.get_group_id().map(|v| (v, P2pError::TrackerRequestExpired))).await;
+                                self.clean_queue(&mut queue, peer.get_id()).await;
                             } else {
                                 break;
                             }
@@ -471,45 +450,31 @@ impl ObjectTracker {
 
     // Request the object from the peer or return false if it is already requested
     pub async fn request_object_from_peer(&self, peer: Arc<Peer>, request: ObjectRequest, broadcast: bool) -> Result<bool, P2pError> {
-        self.request_object_from_peer_with(peer, request, None, false, broadcast).await?;
-        Ok(true)
-    }
-
-    // Request the object from the peer and returns the response blocker
-    pub async fn request_object_from_peer_with(&self, peer: Arc<Peer>, request: ObjectRequest, group_id: Option<u64>, blocker: bool, broadcast: bool) -> Result<Option<ResponseBlocker>, P2pError> {
         trace!("Requesting object {} from {}", request.get_hash(), peer);
-        let (listener, hash) = {
+        let hash = {
             let mut queue = self.queue.write().await;
             let hash = request.get_hash().clone();
-            let mut req = Request::new(request, peer, group_id, broadcast);
-
-            let listener = if blocker {
-                Some(req.get_response_blocker())
-            } else {
-                None
-            };
+            let req = Request::new(request, peer, broadcast);
 
             if !queue.push(hash.clone(), req) {
                 debug!("Object already requested in ObjectTracker: {}", hash);
-                return Ok(None)
+                // keep the peer in case the one requested doesn't answer
+                if let Some(existing) = queue.get_mut(&hash) {
+                    existing.add_candidate(peer);
+                }
+                return Ok(true)
             }
-            (listener, hash)
+            hash
         };
 
         trace!("Transfering object request {} to task", hash);
         self.request_sender.send(hash).await?;
-        Ok(listener)
+        Ok(true)
     }
 
-    // Clean the queue from all requests from the given peer or from the group if it is specified
-    async fn clean_queue(&self, queue: &mut Queue<Hash, Request>, peer_id: u64, group: Option<(u64, P2pError)>) {
+    // Clean the queue from all requests from the given peer
+    async fn clean_queue(&self, queue: &mut Queue<Hash, Request>, peer_id: u64) {
         let iter = queue.extract_if(|(_, request)| {
-            if let (Some((failed_group, _)), Some(request_group)) = (group.as_ref(), request.get_group_id()) {
-                if *failed_group == request_group {
-                    return true;
-                }
-            }
-
             let peer = request.get_peer();
             if peer.get_id() == peer_id || peer.get_connection().is_closed() {
                 return true;
@@ -534,12 +499,6 @@ impl ObjectTracker {
             debug!("Adding requested object with hash {} in expirable cache", hash);
             self.cache.insert(hash).await;
         }
-
-        // Delete all from the same group if one of them failed
-        if let Some((group, err)) = group {
-            debug!("Group {} failed", group);
-            self.group.notify_group(group, err).await;
-        }
     }
 
     // Request the object from the peer
@@ -555,7 +514,7 @@ impl ObjectTracker {
             let peer = request.get_peer();
             if let Err(e) = peer.send_bytes(packet).await {
                 warn!("Error while requesting object {} using Object Tracker: {}", request_hash, e);
-                Some((peer.get_id(), request.get_group_id().map(|v| (v, e))))
+                Some(peer.get_id())
             } else {
                 None
             }
@@ -564,9 +523,9 @@ impl ObjectTracker {
             None
         };
 
-        if let Some((peer_id, group)) = fail {
+        if let Some(peer_id) = fail {
             warn!("cleaning queue because of failure");
-            self.clean_queue(&mut queue, peer_id, group).await;
+            self.clean_queue(&mut queue, peer_id).await;
         }
     }
 }
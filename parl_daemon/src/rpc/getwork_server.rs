@@ -9,7 +9,8 @@ use std::{
             Ordering
         },
         Arc
-    }
+    },
+    time::Duration
 };
 use actix::{
     Actor,
@@ -34,10 +35,15 @@ use rand::{
 };
 use serde::Serialize;
 use serde_json::json;
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{watch, Mutex},
+    time::timeout
+};
 use parl_common::{
     api::daemon::{
         GetMinerWorkResult,
+        NewBlockTemplateEvent,
+        NotifyEvent,
         SubmitMinerWorkParams
     },
     block::{
@@ -51,6 +57,7 @@ use parl_common::{
     },
     difficulty::Difficulty,
     immutable::Immutable,
+    metrics::Counter,
     rpc_server::{
         InternalRpcError,
         RpcResponseError
@@ -75,6 +82,9 @@ use crate::{
 
 pub type SharedGetWorkServer<S> = Arc<GetWorkServer<S>>;
 
+// Max time a get_block_template call waits for a new template
+pub const LONG_POLL_TIME_OUT: Duration = Duration::from_secs(20);
+
 #[derive(Serialize, PartialEq)]
 #[serde(rename_all = "snake_case")] 
 pub enum Response {
@@ -206,6 +216,65 @@ impl<S: Storage> Handler<Response> for GetWorkWebSocketHandler<S> {
     }
 }
 
+// Version of the block template, long polls wait for its next change
+// A TX added in mempool changes it only once the fees added since the last version reach the threshold
+pub struct BlockTemplateVersion {
+    // fees of the TXs added in mempool since the last version
+    pending_fees: AtomicU64,
+    // minimum pending fees to build a new template for a mempool change
+    fee_threshold: u64,
+    version: watch::Sender<u64>
+}
+
+impl BlockTemplateVersion {
+    pub fn new(fee_threshold: u64) -> Self {
+        let (version, _) = watch::channel(0);
+        Self {
+            pending_fees: AtomicU64::new(0),
+            fee_threshold,
+            version
+        }
+    }
+
+    // Returns the current version
+    pub fn get(&self) -> u64 {
+        *self.version.borrow()
+    }
+
+    // Add the fee of a TX added in mempool
+    // Returns true if the pending fees reached the threshold
+    pub fn add_fee(&self, fee: u64) -> bool {
+        let pending_fees = self.pending_fees.fetch_add(fee, Ordering::SeqCst).saturating_add(fee);
+        if pending_fees < self.fee_threshold {
+            debug!("Pending fees {} are below the threshold of {}", pending_fees, self.fee_threshold);
+            return false;
+        }
+        true
+    }
+
+    // Start a new version and reset the pending fees
+    // The version is changed even if nobody listens
+    // so a long poll started later with an old version returns directly
+    pub fn increment(&self) -> u64 {
+        self.pending_fees.store(0, Ordering::SeqCst);
+        self.version.send_modify(|version| *version += 1);
+        self.get()
+    }
+
+    // Wait until the version is different from the one given
+    // Returns the current version, which may be the same if the time out is reached
+    pub async fn wait_for_change(&self, version: u64, time_out: Duration) -> u64 {
+        let mut receiver = self.version.subscribe();
+        let current = *receiver.borrow();
+        if current == version && timeout(time_out, receiver.changed()).await.is_err() {
+            trace!("No new block template before long poll timeout");
+        }
+
+        let current = *receiver.borrow();
+        current
+    }
+}
+
 pub struct GetWorkServer<S: Storage> {
     miners: Mutex<HashMap<Addr<GetWorkWebSocketHandler<S>>, Miner>>,
     blockchain: Arc<Blockchain<S>>,
@@ -216,18 +285,25 @@ pub struct GetWorkServer<S: Storage> {
     last_header_hash: Mutex<Option<Hash>>,
     // used only when a new TX is received in mempool
     last_notify: AtomicU64,
-    notify_rate_limit_ms: u64
+    notify_rate_limit_ms: u64,
+    template_version: BlockTemplateVersion,
+    // blocks submitted by all miners since the server started
+    accepted_blocks: Counter,
+    rejected_blocks: Counter
 }
 
 impl<S: Storage> GetWorkServer<S> {
-    pub fn new(blockchain: Arc<Blockchain<S>>) -> Self {
+    pub fn new(blockchain: Arc<Blockchain<S>>, fee_threshold: u64) -> Self {
         Self {
             miners: Mutex::new(HashMap::new()),
             blockchain,
             mining_jobs: Mutex::new(LruCache::new(NonZeroUsize::new(STABLE_LIMIT as usize).unwrap())),
             last_header_hash: Mutex::new(None),
             last_notify: AtomicU64::new(0),
-            notify_rate_limit_ms: 500 // maximum one time every 500ms
+            notify_rate_limit_ms: 500, // maximum one time every 500ms
+            template_version: BlockTemplateVersion::new(fee_threshold),
+            accepted_blocks: Counter::new(),
+            rejected_blocks: Counter::new()
         }
     }
 
@@ -237,6 +313,14 @@ impl<S: Storage> GetWorkServer<S> {
         self.miners.lock().await.len()
     }
 
+    pub fn get_accepted_blocks(&self) -> u64 {
+        self.accepted_blocks.get()
+    }
+
+    pub fn get_rejected_blocks(&self) -> u64 {
+        self.rejected_blocks.get()
+    }
+
     // Returns the list of miners connected to the getwork server
     pub fn get_miners(&self) -> &Mutex<HashMap<Addr<GetWorkWebSocketHandler<S>>, Miner>> {
         trace!("get miners");
@@ -370,6 +454,11 @@ impl<S: Storage> GetWorkServer<S> {
         };
 
         // update miner stats
+        match &response {
+            Response::BlockAccepted => self.accepted_blocks.inc(),
+            Response::BlockRejected(_) => self.rejected_blocks.inc(),
+            _ => {}
+        }
         {
             let mut miners = self.miners.lock().await;
             if let Some(miner) = miners.get_mut(&addr) {
@@ -429,9 +518,26 @@ impl<S: Storage> GetWorkServer<S> {
         (now - last_notify < self.notify_rate_limit_ms, now)
     }
 
-    // notify every miners connected to the getwork server
+    // Returns the version of the current block template
+    pub fn get_template_version(&self) -> u64 {
+        self.template_version.get()
+    }
+
+    // Wait until the template version is different from the one given
+    // Returns the current version, which may be the same if the long poll timed out
+    pub async fn wait_for_new_template(&self, version: u64) -> u64 {
+        self.template_version.wait_for_change(version, LONG_POLL_TIME_OUT).await
+    }
+
+    // notify every miners connected to the getwork server for a new TX in mempool
+    // a new job is built only once the fees added since the last one reach the threshold
     // each miner have his own task so nobody wait on other
-    pub async fn notify_new_job_rate_limited(&self) -> Result<(), InternalRpcError> {
+    pub async fn notify_new_job_rate_limited(&self, fee: u64) -> Result<(), InternalRpcError> {
+        if !self.template_version.add_fee(fee) {
+            debug!("Fee threshold not reached, no need to notify miners");
+            return Ok(());
+        }
+
         let (rate_limit_reached, now) = self.is_rate_limited();
         if rate_limit_reached {
             debug!("Rate limit reached, no need to notify miners");
@@ -444,13 +550,22 @@ impl<S: Storage> GetWorkServer<S> {
 
     // notify every miners connected to the getwork server
     // each miner have his own task so nobody wait on other
+    // long polls and NewBlockTemplate subscribers are notified too
     pub async fn notify_new_job(&self) -> Result<(), InternalRpcError> {
         trace!("notify new job");
-        // Check that there is at least one miner connected
+        let long_poll_id = self.template_version.increment();
+
+        let rpc = self.blockchain.get_rpc().read().await.clone();
+        let notify_event = match rpc.as_ref() {
+            Some(rpc) => rpc.is_event_tracked(&NotifyEvent::NewBlockTemplate).await,
+            None => false
+        };
+
+        // Check that there is at least one miner connected or a client waiting for it
         // otherwise, no need to build a new job
         {
             let miners = self.miners.lock().await;
-            if miners.is_empty() {
+            if miners.is_empty() && !notify_event {
                 debug!("No miners connected, no need to notify them");
                 return Ok(());
             }
@@ -466,6 +581,18 @@ impl<S: Storage> GetWorkServer<S> {
 
         let mut job = MinerWork::new(header.get_work_hash(), header.timestamp);
         let height = header.height;
+        let topoheight = self.blockchain.get_topo_height();
+
+        // the template is built for the dev address, subscribers request their own with get_block_template
+        if let Some(rpc) = rpc.filter(|_| notify_event) {
+            let value = NewBlockTemplateEvent {
+                long_poll_id,
+                height,
+                topoheight,
+                difficulty
+            };
+            rpc.notify_clients_with(&NotifyEvent::NewBlockTemplate, value).await;
+        }
 
         // save the header used for job in cache
         {
@@ -480,7 +607,6 @@ impl<S: Storage> GetWorkServer<S> {
         let mut miners = self.miners.lock().await;
         miners.retain(|addr, _| addr.connected());
 
-        let topoheight = self.blockchain.get_topo_height();
         for (addr, miner) in miners.iter() {
             debug!("Notifying {} for new job", miner);
             let addr = addr.clone();
@@ -507,3 +633,50 @@ impl<S: Storage> GetWorkServer<S> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_threshold() {
+        let version = BlockTemplateVersion::new(100);
+        assert!(!version.add_fee(40));
+        assert!(!version.add_fee(50));
+        assert!(version.add_fee(10));
+        // stays reached until a new version is built
+        assert!(version.add_fee(0));
+
+        // a new version resets the pending fees
+        assert_eq!(version.increment(), 1);
+        assert!(!version.add_fee(99));
+        assert!(version.add_fee(u64::MAX));
+
+        // every TX is enough without threshold
+        let version = BlockTemplateVersion::new(0);
+        assert!(version.add_fee(0));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_new_version() {
+        let version = Arc::new(BlockTemplateVersion::new(0));
+        assert_eq!(version.get(), 0);
+        assert_eq!(version.increment(), 1);
+
+        // an old version returns directly
+        assert_eq!(version.wait_for_change(0, Duration::from_secs(60)).await, 1);
+        // the current version is returned once the time out is reached
+        assert_eq!(version.wait_for_change(1, Duration::from_millis(10)).await, 1);
+
+        // the next version wakes up the long poll
+        let handle = {
+            let version = Arc::clone(&version);
+            spawn_task("test-long-poll", async move {
+                version.wait_for_change(1, Duration::from_secs(60)).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(version.increment(), 2);
+        assert_eq!(handle.await.unwrap(), 2);
+    }
+}
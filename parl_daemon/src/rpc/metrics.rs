@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use parl_common::{
+    metrics::MetricsEncoder,
+    rpc_server::RPCServerHandler
+};
+use crate::core::{
+    blockchain::Blockchain,
+    error::BlockchainError,
+    storage::Storage
+};
+use super::DaemonRpcServer;
+
+// Build the metrics of the node in the Prometheus text format
+pub async fn encode_metrics<S: Storage>(server: &DaemonRpcServer<S>) -> Result<String, BlockchainError> {
+    let blockchain: &Arc<Blockchain<S>> = server.get_rpc_handler().get_data();
+    let mut encoder = MetricsEncoder::new();
+
+    encoder.gauge("parl_height", "Current height of the chain", blockchain.get_height());
+    encoder.gauge("parl_topoheight", "Current topoheight of the chain", blockchain.get_topo_height());
+    encoder.gauge("parl_stable_height", "Current stable height of the chain", blockchain.get_stable_height());
+    encoder.gauge("parl_difficulty", "Difficulty at the current tips", blockchain.get_difficulty().await);
+
+    {
+        let storage = blockchain.get_storage().read().await;
+        let average_block_time = blockchain.get_average_block_time::<S>(&*storage).await?;
+        encoder.gauge("parl_average_block_time_seconds", "Average time between the last blocks", average_block_time as f64 / 1000f64);
+        encoder.gauge("parl_storage_size_bytes", "Size of the storage on disk", storage.get_size_on_disk().await?);
+    }
+
+    {
+        let mempool = blockchain.get_mempool().read().await;
+        let bytes: usize = mempool.get_txs().values().map(|tx| tx.get_size()).sum();
+        encoder.gauge("parl_mempool_transactions", "Transactions in mempool", mempool.size());
+        encoder.gauge("parl_mempool_bytes", "Size of the transactions in mempool", bytes);
+    }
+
+    if let Some(p2p) = blockchain.get_p2p().read().await.as_ref() {
+        let (outgoing, incoming) = {
+            let peers = p2p.get_peer_list().get_peers().read().await;
+            let outgoing = peers.values().filter(|peer| peer.is_out()).count();
+            (outgoing, peers.len() - outgoing)
+        };
+        encoder.labeled_gauge("parl_peers", "Connected peers by direction", "direction", [("in", incoming), ("out", outgoing)]);
+        encoder.gauge("parl_syncing", "1 if the node is syncing the chain", p2p.is_syncing_chain() as u8);
+    }
+
+    let metrics = blockchain.get_metrics();
+    encoder.counter("parl_orphaned_blocks_total", "Blocks removed from the DAG order", metrics.orphaned_blocks.get());
+    encoder.histogram("parl_block_verification_seconds", "Time to verify and add a new block", &metrics.block_verification);
+    encoder.histogram("parl_tx_verification_seconds", "Time to verify and add a transaction in mempool", &metrics.tx_verification);
+
+    if let Some(getwork) = server.getwork_server() {
+        encoder.gauge("parl_getwork_miners", "Miners connected to the GetWork server", getwork.count_miners().await);
+        encoder.counter("parl_getwork_accepted_blocks_total", "Blocks accepted from the GetWork miners", getwork.get_accepted_blocks());
+        encoder.counter("parl_getwork_rejected_blocks_total", "Blocks rejected from the GetWork miners", getwork.get_rejected_blocks());
+    }
+
+    let calls = server.get_rpc_handler().get_method_calls().get_values();
+    encoder.labeled_counter("parl_rpc_requests_total", "RPC requests by method", "method", calls.iter().map(|(method, count)| (method.as_str(), *count)));
+
+    Ok(encoder.finish())
+}
@@ -1,5 +1,7 @@
 pub mod rpc;
 pub mod getwork_server;
+pub mod metrics;
+pub mod rest;
 
 use crate::{
     core::{
@@ -29,9 +31,10 @@ use actix_web_actors::ws::WsResponseBuilder;
 use serde_json::{Value, json};
 use tokio::sync::Mutex;
 use parl_common::{
-    api::daemon::NotifyEvent,
+    api::daemon::{EventFilter, NotifyEvent},
     config,
-    crypto::Address,
+    crypto::{Address, Hash, PublicKey},
+    metrics::METRICS_CONTENT_TYPE,
     rpc_server::{
         json_rpc,
         websocket,
@@ -42,15 +45,20 @@ use parl_common::{
         },
         InternalRpcError,
         RPCHandler,
+        RpcAuthConfig,
+        RpcRateLimitConfig,
         RPCServerHandler,
         WebSocketServerHandler
     },
+    transaction::{Transaction, TransactionType},
     utils::spawn_task,
 };
 use std::{
     collections::HashSet,
+    fs,
     sync::Arc,
 };
+use anyhow::Context;
 use log::{
     trace,
     debug,
@@ -67,7 +75,7 @@ pub type SharedDaemonRpcServer<S> = Arc<DaemonRpcServer<S>>;
 
 pub struct DaemonRpcServer<S: Storage> {
     handle: Mutex<Option<ServerHandle>>,
-    websocket: WebSocketServerShared<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent>>,
+    websocket: WebSocketServerShared<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent, EventFilter>>,
     getwork: Option<SharedGetWorkServer<S>>
 }
 
@@ -83,18 +91,98 @@ pub enum ApiError {
     NoWebSocketServer
 }
 
+// Accounts, assets and burns involved in an event
+// Used to select the subscriptions receiving it
+#[derive(Default)]
+pub struct EventScope {
+    keys: HashSet<PublicKey>,
+    assets: HashSet<Hash>,
+    burns: Vec<u64>
+}
+
+impl EventScope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_transaction(tx: &Transaction) -> Self {
+        let mut scope = Self::new();
+        scope.add_transaction(tx);
+        scope
+    }
+
+    pub fn add_key(&mut self, key: PublicKey) {
+        self.keys.insert(key);
+    }
+
+    pub fn add_asset(&mut self, asset: Hash) {
+        self.assets.insert(asset);
+    }
+
+    pub fn add_transaction(&mut self, tx: &Transaction) {
+        self.keys.insert(tx.get_source().clone());
+        match tx.get_data() {
+            TransactionType::Transfers(transfers) => {
+                for transfer in transfers {
+                    self.keys.insert(transfer.get_destination().clone());
+                    self.assets.insert(transfer.get_asset().clone());
+                }
+            },
+            TransactionType::Burn(payload) => {
+                self.assets.insert(payload.asset.clone());
+                self.burns.push(payload.amount);
+            }
+        }
+    }
+
+    pub fn matches(&self, filter: &EventFilter) -> bool {
+        if !filter.keys.is_empty() && !filter.keys.iter().any(|address| self.keys.contains(address.get_public_key())) {
+            return false
+        }
+
+        if !filter.assets.is_empty() && !filter.assets.iter().any(|asset| self.assets.contains(asset)) {
+            return false
+        }
+
+        if filter.min_burn_amount.is_some() || filter.max_burn_amount.is_some() {
+            let min = filter.min_burn_amount.unwrap_or(0);
+            let max = filter.max_burn_amount.unwrap_or(u64::MAX);
+            return self.burns.iter().any(|amount| *amount >= min && *amount <= max)
+        }
+
+        true
+    }
+}
+
 impl<S: Storage> DaemonRpcServer<S> {
-    pub async fn new(bind_address: String, blockchain: Arc<Blockchain<S>>, disable_getwork_server: bool) -> Result<SharedDaemonRpcServer<S>, BlockchainError> {
+    pub async fn new(bind_address: String, blockchain: Arc<Blockchain<S>>, disable_getwork_server: bool, block_template_fee_threshold: u64, enable_admin_methods: bool, auth_config_path: Option<String>, rate_limit_config_path: Option<String>, ipc_path: Option<String>, ipc_permissions: String) -> Result<SharedDaemonRpcServer<S>, BlockchainError> {
         let getwork: Option<SharedGetWorkServer<S>> = if !disable_getwork_server {
             info!("Creating GetWork server...");
-            Some(Arc::new(GetWorkServer::new(blockchain.clone())))
+            Some(Arc::new(GetWorkServer::new(blockchain.clone(), block_template_fee_threshold)))
         } else {
             None
         };
 
         // create the RPC Handler which will register and contains all available methods
         let mut rpc_handler = RPCHandler::new(blockchain);
-        rpc::register_methods(&mut rpc_handler, !disable_getwork_server);
+        rpc::register_methods(&mut rpc_handler, !disable_getwork_server, enable_admin_methods);
+
+        if let Some(path) = auth_config_path {
+            info!("Loading RPC auth config from {}", path);
+            let content = fs::read_to_string(&path).with_context(|| format!("Error while reading RPC auth config {}", path))?;
+            let auth_config: RpcAuthConfig = serde_json::from_str(&content).with_context(|| format!("Invalid RPC auth config {}", path))?;
+            rpc_handler.set_auth_config(auth_config)?;
+        }
+
+        if let Some(path) = rate_limit_config_path {
+            info!("Loading RPC rate limit config from {}", path);
+            let content = fs::read_to_string(&path).with_context(|| format!("Error while reading RPC rate limit config {}", path))?;
+            let mut rate_limit_config: RpcRateLimitConfig = serde_json::from_str(&content).with_context(|| format!("Invalid RPC rate limit config {}", path))?;
+            for (method, cost) in rpc::DEFAULT_METHOD_COSTS {
+                rate_limit_config.method_costs.entry(method.to_owned()).or_insert(cost);
+            }
+            rpc_handler.set_rate_limit_config(rate_limit_config);
+        }
 
         // create the default websocket server (support event & rpc methods)
         let ws = WebSocketServer::new(EventWebSocketHandler::new(rpc_handler));
@@ -113,8 +201,16 @@ impl<S: Storage> DaemonRpcServer<S> {
                     // Traditional HTTP
                     .route("/json_rpc", web::post().to(json_rpc::<Arc<Blockchain<S>>, DaemonRpcServer<S>>))
                     // WebSocket support
-                    .route("/json_rpc", web::get().to(websocket::<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent>, DaemonRpcServer<S>>))
+                    .route("/json_rpc", web::get().to(websocket::<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent, EventFilter>, DaemonRpcServer<S>>))
                     .route("/getwork/{address}/{worker}", web::get().to(getwork_endpoint::<S>))
+                    .route("/metrics", web::get().to(metrics_endpoint::<S>))
+                    // REST API for explorers, using the same handlers as JSON-RPC
+                    .route("/info", web::get().to(rest::get_info::<S>))
+                    .route("/blocks/topoheight/{topoheight}", web::get().to(rest::get_block_at_topoheight::<S>))
+                    .route("/blocks/{hash}", web::get().to(rest::get_block_by_hash::<S>))
+                    .route("/txs/{hash}", web::get().to(rest::get_transaction::<S>))
+                    .route("/accounts/{address}/history", web::get().to(rest::get_account_history::<S>))
+                    .route("/assets", web::get().to(rest::get_assets::<S>))
                     .service(index)
             })
             .disable_signals()
@@ -129,6 +225,22 @@ impl<S: Storage> DaemonRpcServer<S> {
             }
             spawn_task("rpc-server", http_server);
         }
+
+        if let Some(path) = ipc_path {
+            let mode = u32::from_str_radix(&ipc_permissions, 8).with_context(|| format!("Invalid RPC IPC permissions {}", ipc_permissions))?;
+            #[cfg(unix)]
+            {
+                let ws = Arc::clone(server.get_websocket());
+                spawn_task("rpc-ipc-server", async move {
+                    if let Err(e) = websocket::run_ipc_server(ws, path, mode).await {
+                        error!("Error while running the RPC IPC server: {}", e);
+                    }
+                });
+            }
+            #[cfg(not(unix))]
+            warn!("RPC IPC server on {} with mode {:o} is only supported on unix systems", path, mode);
+        }
+
         Ok(server)
     }
 
@@ -151,6 +263,12 @@ impl<S: Storage> DaemonRpcServer<S> {
         Ok(())
     }
 
+    // Notify only the clients with a filter matching the scope of the event
+    pub async fn notify_clients_in_scope(&self, event: &NotifyEvent, value: Value, scope: &EventScope) -> Result<(), anyhow::Error> {
+        self.get_websocket().get_handler().notify_with_filter(event, value, |filter| scope.matches(filter)).await;
+        Ok(())
+    }
+
     pub async fn stop(&self) {
         info!("Stopping RPC Server...");
         let mut handle = self.handle.lock().await;
@@ -167,8 +285,8 @@ impl<S: Storage> DaemonRpcServer<S> {
     }
 }
 
-impl<S: Storage> WebSocketServerHandler<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent>> for DaemonRpcServer<S> {
-    fn get_websocket(&self) -> &WebSocketServerShared<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent>> {
+impl<S: Storage> WebSocketServerHandler<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent, EventFilter>> for DaemonRpcServer<S> {
+    fn get_websocket(&self) -> &WebSocketServerShared<EventWebSocketHandler<Arc<Blockchain<S>>, NotifyEvent, EventFilter>> {
         &self.websocket
     }
 }
@@ -185,9 +303,33 @@ async fn index() -> impl Responder {
     HttpResponse::Ok().body(format!("Hello, world!\nRunning on: {}", config::VERSION))
 }
 
+async fn metrics_endpoint<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest) -> HttpResponse {
+    // metrics are only given to the admins when authentication is enabled
+    match server.get_rpc_handler().get_role(request.headers()) {
+        Ok(Some(role)) if !role.is_method_allowed("metrics") => return HttpResponse::Forbidden().body("Metrics are not allowed for this client"),
+        Err(_) => return HttpResponse::Unauthorized().body("Invalid credentials"),
+        _ => {}
+    }
+
+    match metrics::encode_metrics(&server).await {
+        Ok(metrics) => HttpResponse::Ok().content_type(METRICS_CONTENT_TYPE).body(metrics),
+        Err(e) => {
+            error!("Error while building metrics: {}", e);
+            HttpResponse::InternalServerError().body("Error while building metrics")
+        }
+    }
+}
+
 async fn getwork_endpoint<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest, stream: Payload, path: Path<(String, String)>) -> Result<HttpResponse, Error> {
     match &server.getwork {
         Some(getwork) => {
+            // miners must be allowed to submit blocks when authentication is enabled
+            match server.get_rpc_handler().get_role(request.headers()) {
+                Ok(Some(role)) if !role.is_method_allowed("submit_block") => return Ok(HttpResponse::Forbidden().body("Mining is not allowed for this client")),
+                Err(_) => return Ok(HttpResponse::Unauthorized().body("Invalid credentials")),
+                _ => {}
+            }
+
             let (addr, worker) = path.into_inner();
             if worker.len() > 32 {
                 return Ok(HttpResponse::BadRequest().body("Worker name must be less or equal to 32 chars"))
@@ -218,3 +360,39 @@ async fn getwork_endpoint<S: Storage>(server: Data<DaemonRpcServer<S>>, request:
         None => Ok(HttpResponse::NotFound().reason("GetWork server is not enabled").finish()) // getwork server is not started
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use parl_common::{config::PARL_ASSET, crypto::KeyPair};
+    use super::*;
+
+    #[test]
+    fn test_event_scope_matches() {
+        let miner = KeyPair::new().get_public_key().compress();
+        let other = KeyPair::new().get_public_key().compress();
+
+        let mut scope = EventScope::new();
+        scope.add_key(miner.clone());
+        scope.add_asset(PARL_ASSET);
+        scope.burns.push(500);
+
+        assert!(scope.matches(&EventFilter::default()));
+
+        let mut filter = EventFilter {
+            keys: vec![other.clone().to_address(false)],
+            ..Default::default()
+        };
+        assert!(!scope.matches(&filter));
+        filter.keys.push(miner.to_address(false));
+        assert!(scope.matches(&filter));
+
+        filter.assets.push(Hash::new([1u8; 32]));
+        assert!(!scope.matches(&filter));
+        filter.assets.push(PARL_ASSET);
+        filter.min_burn_amount = Some(1000);
+        assert!(!scope.matches(&filter));
+        filter.min_burn_amount = Some(100);
+        filter.max_burn_amount = Some(500);
+        assert!(scope.matches(&filter));
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+use anyhow::Error as AnyError;
+use actix_web::{
+    http::header::{ContentType, CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+    web::{Data, Path, Query},
+    HttpRequest,
+    HttpResponse
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use parl_common::{
+    api::daemon::{GetAccountHistoryParams, GetAssetsParams},
+    config::PARL_ASSET,
+    crypto::{hash, Address, Hash},
+    rpc_server::{InternalRpcError, RPCServerHandler}
+};
+use crate::core::{
+    blockchain::Blockchain,
+    error::BlockchainError,
+    storage::{DifficultyProvider, Storage}
+};
+use super::DaemonRpcServer;
+
+// Data below the stable height can't change anymore
+const STABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+// Shared caches must not keep responses that required credentials
+const PRIVATE_STABLE_CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+const UNSTABLE_CACHE_CONTROL: &str = "no-cache";
+
+#[derive(Deserialize)]
+pub struct BlockQuery {
+    #[serde(default)]
+    include_txs: bool
+}
+
+#[derive(Deserialize)]
+pub struct AccountHistoryQuery {
+    asset: Option<Hash>,
+    minimum_topoheight: Option<u64>,
+    maximum_topoheight: Option<u64>
+}
+
+// Only the lookups that found nothing in the storage are a 404
+fn is_not_found_error(error: &AnyError) -> bool {
+    matches!(
+        error.downcast_ref::<BlockchainError>(),
+        Some(BlockchainError::NotFoundOnDisk(_)
+            | BlockchainError::TxNotFound(_)
+            | BlockchainError::BlockNotFound(_)
+            | BlockchainError::BlockHeightNotFound(_)
+            | BlockchainError::AccountNotFound(_)
+            | BlockchainError::AssetNotFound(_))
+    )
+}
+
+fn error_response(error: &InternalRpcError) -> HttpResponse {
+    let mut response = match error {
+        InternalRpcError::Unauthorized => HttpResponse::Unauthorized(),
+        InternalRpcError::MethodNotAllowed(_) => HttpResponse::Forbidden(),
+        InternalRpcError::RateLimited => HttpResponse::TooManyRequests(),
+        InternalRpcError::InvalidJSONRequest
+        | InternalRpcError::InvalidJSONParams(_)
+        | InternalRpcError::InvalidParams(_)
+        | InternalRpcError::InvalidParamsAny(_)
+        | InternalRpcError::ExpectedParams
+        | InternalRpcError::UnexpectedParams => HttpResponse::BadRequest(),
+        InternalRpcError::AnyError(e) | InternalRpcError::CustomAny(_, e) if is_not_found_error(e) => HttpResponse::NotFound(),
+        InternalRpcError::MethodNotFound(_) => HttpResponse::NotFound(),
+        _ => HttpResponse::InternalServerError()
+    };
+
+    response.json(json!({
+        "code": error.get_code(),
+        "message": error.to_string()
+    }))
+}
+
+// Call the RPC method with the role and rate limits of the client
+async fn call_method<S: Storage>(server: &DaemonRpcServer<S>, request: &HttpRequest, method: &str, params: Value) -> Result<Value, HttpResponse> {
+    let handler = server.get_rpc_handler();
+    let context = handler.create_context(request).map_err(|e| error_response(e.get_error()))?;
+    handler.call_method(&context, method, params).await.map_err(|e| error_response(&e))
+}
+
+// Responses are private to the client when authentication is enabled
+fn is_private<S: Storage>(server: &DaemonRpcServer<S>) -> bool {
+    server.get_rpc_handler().is_auth_enabled()
+}
+
+// Stable results can be cached forever and are identified by the hash of their content
+fn cached_response(request: &HttpRequest, result: &Value, stable: bool, private: bool) -> HttpResponse {
+    if !stable {
+        return HttpResponse::Ok()
+            .insert_header((CACHE_CONTROL, UNSTABLE_CACHE_CONTROL))
+            .json(result)
+    }
+
+    let body = result.to_string();
+    let etag = format!("\"{}\"", hash(body.as_bytes()));
+    let not_modified = request.headers().get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.split(',').any(|tag| tag.trim() == etag));
+    let cache_control = if private { PRIVATE_STABLE_CACHE_CONTROL } else { STABLE_CACHE_CONTROL };
+
+    if not_modified {
+        return HttpResponse::NotModified()
+            .insert_header((ETAG, etag))
+            .insert_header((CACHE_CONTROL, cache_control))
+            .finish()
+    }
+
+    HttpResponse::Ok()
+        .insert_header((ETAG, etag))
+        .insert_header((CACHE_CONTROL, cache_control))
+        .content_type(ContentType::json())
+        .body(body)
+}
+
+fn is_block_stable<S: Storage>(blockchain: &Arc<Blockchain<S>>, block: &Value) -> bool {
+    block["height"].as_u64().map_or(false, |height| height < blockchain.get_stable_height())
+}
+
+fn is_topoheight_stable<S: Storage>(blockchain: &Arc<Blockchain<S>>, topoheight: Option<u64>) -> bool {
+    topoheight.map_or(false, |topoheight| topoheight < blockchain.get_stable_topoheight())
+}
+
+pub async fn get_info<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest) -> HttpResponse {
+    match call_method(&server, &request, "get_info", Value::Null).await {
+        Ok(result) => cached_response(&request, &result, false, is_private(&server)),
+        Err(response) => response
+    }
+}
+
+pub async fn get_block_by_hash<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest, path: Path<String>, query: Query<BlockQuery>) -> HttpResponse {
+    let params = json!({
+        "hash": path.into_inner(),
+        "include_txs": query.include_txs
+    });
+
+    match call_method(&server, &request, "get_block_by_hash", params).await {
+        Ok(result) => {
+            let stable = is_block_stable(server.get_rpc_handler().get_data(), &result);
+            cached_response(&request, &result, stable, is_private(&server))
+        },
+        Err(response) => response
+    }
+}
+
+pub async fn get_block_at_topoheight<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest, path: Path<u64>, query: Query<BlockQuery>) -> HttpResponse {
+    let topoheight = path.into_inner();
+    let params = json!({
+        "topoheight": topoheight,
+        "include_txs": query.include_txs
+    });
+
+    match call_method(&server, &request, "get_block_at_topoheight", params).await {
+        Ok(result) => {
+            // a stable block can still be reordered until its topoheight is stable
+            let blockchain = server.get_rpc_handler().get_data();
+            let stable = is_topoheight_stable(blockchain, Some(topoheight)) && is_block_stable(blockchain, &result);
+            cached_response(&request, &result, stable, is_private(&server))
+        },
+        Err(response) => response
+    }
+}
+
+pub async fn get_transaction<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest, path: Path<String>) -> HttpResponse {
+    let params = json!({
+        "hash": path.into_inner()
+    });
+
+    let result = match call_method(&server, &request, "get_transaction", params).await {
+        Ok(result) => result,
+        Err(response) => return response
+    };
+
+    // a transaction is stable once the block executing it is stable
+    let blockchain = server.get_rpc_handler().get_data();
+    let stable = match serde_json::from_value::<Option<Hash>>(result["executed_in_block"].clone()) {
+        Ok(Some(block_hash)) => {
+            let storage = blockchain.get_storage().read().await;
+            storage.get_height_for_block_hash(&block_hash).await.map_or(false, |height| height < blockchain.get_stable_height())
+        },
+        _ => false
+    };
+
+    cached_response(&request, &result, stable, is_private(&server))
+}
+
+pub async fn get_account_history<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest, path: Path<String>, query: Query<AccountHistoryQuery>) -> HttpResponse {
+    let address = match Address::from_string(&path) {
+        Ok(address) => address,
+        Err(_) => return error_response(&InternalRpcError::InvalidParams("Invalid address"))
+    };
+
+    let query = query.into_inner();
+    let stable = is_topoheight_stable(server.get_rpc_handler().get_data(), query.maximum_topoheight);
+    let params = json!(GetAccountHistoryParams {
+        address,
+        asset: query.asset.unwrap_or(PARL_ASSET),
+        minimum_topoheight: query.minimum_topoheight,
+        maximum_topoheight: query.maximum_topoheight
+    });
+
+    match call_method(&server, &request, "get_account_history", params).await {
+        Ok(result) => cached_response(&request, &result, stable, is_private(&server)),
+        Err(response) => response
+    }
+}
+
+pub async fn get_assets<S: Storage>(server: Data<DaemonRpcServer<S>>, request: HttpRequest, query: Query<GetAssetsParams>) -> HttpResponse {
+    let query = query.into_inner();
+    let stable = is_topoheight_stable(server.get_rpc_handler().get_data(), query.maximum_topoheight);
+
+    match call_method(&server, &request, "get_assets", json!(query)).await {
+        Ok(result) => cached_response(&request, &result, stable, is_private(&server)),
+        Err(response) => response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test::TestRequest};
+    use crate::core::error::DiskContext;
+    use super::*;
+
+    #[test]
+    fn test_cached_response() {
+        let result = json!({ "height": 10 });
+        let request = TestRequest::default().to_http_request();
+        let response = cached_response(&request, &result, true, false);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), STABLE_CACHE_CONTROL);
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        let request = TestRequest::default().insert_header((IF_NONE_MATCH, etag)).to_http_request();
+        assert_eq!(cached_response(&request, &result, true, false).status(), StatusCode::NOT_MODIFIED);
+
+        // unstable data is always sent again
+        let response = cached_response(&request, &result, false, false);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(ETAG).is_none());
+
+        // only the client can cache it when authentication is enabled
+        let response = cached_response(&request, &result, true, true);
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), PRIVATE_STABLE_CACHE_CONTROL);
+    }
+
+    #[test]
+    fn test_error_response() {
+        let not_found = InternalRpcError::from(BlockchainError::BlockNotFound(Hash::zero()));
+        assert_eq!(error_response(&not_found).status(), StatusCode::NOT_FOUND);
+
+        // the context keeps the storage error
+        let not_found = InternalRpcError::AnyError(AnyError::from(BlockchainError::NotFoundOnDisk(DiskContext::Tips)).context("Error while retrieving tips"));
+        assert_eq!(error_response(&not_found).status(), StatusCode::NOT_FOUND);
+
+        let internal = InternalRpcError::from(BlockchainError::InvalidNetwork);
+        assert_eq!(error_response(&internal).status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let internal = InternalRpcError::AnyError(AnyError::msg("storage is corrupted"));
+        assert_eq!(error_response(&internal).status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert_eq!(error_response(&InternalRpcError::InvalidParams("Invalid address")).status(), StatusCode::BAD_REQUEST);
+    }
+}
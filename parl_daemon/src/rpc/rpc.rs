@@ -14,7 +14,10 @@ use crate::{
         mempool::Mempool,
         storage::Storage
     },
-    p2p::peer::Peer,
+    p2p::{
+        ban::IpRange,
+        peer::Peer
+    },
     BLOCK_TIME
 };
 use super::{InternalRpcError, ApiError};
@@ -23,9 +26,12 @@ use parl_common::{
         daemon::{
             AccountHistoryEntry,
             AccountHistoryType,
+            BanEntry,
+            BanPeerParams,
             BlockType,
             CreateMinerWorkParams,
             CreateMinerWorkResult,
+            DevFeeThreshold,
             GetAccountAssetsParams,
             GetAccountHistoryParams,
             GetAccountRegistrationParams,
@@ -44,6 +50,7 @@ use parl_common::{
             GetHeightRangeParams,
             GetInfoResult,
             GetMempoolCacheParams,
+            GetMempoolCacheResult,
             GetNonceAtTopoHeightParams,
             GetNonceParams,
             GetNonceResult,
@@ -65,6 +72,7 @@ use parl_common::{
             SubmitBlockParams,
             SubmitTransactionParams,
             TransactionResponse,
+            UnbanParams,
             ValidateAddressParams,
             ValidateAddressResult,
             ExtractKeyFromAddressParams,
@@ -77,6 +85,8 @@ use parl_common::{
         SplitAddressParams,
         SplitAddressResult,
     },
+    account::{VersionedBalance, VersionedNonce},
+    asset::{AssetData, AssetWithData},
     async_handler,
     block::{
         Block,
@@ -90,7 +100,7 @@ use parl_common::{
         PARL_ASSET
     },
     context::Context,
-    crypto::Hash,
+    crypto::{Address, Hash, Hashable},
     difficulty::{
         CumulativeDifficulty,
         Difficulty
@@ -98,7 +108,9 @@ use parl_common::{
     immutable::Immutable,
     rpc_server::{
         parse_params,
-        RPCHandler
+        OpenRpcInfo,
+        RPCHandler,
+        RpcRole
     },
     serializer::Serializer,
     time::TimestampSeconds,
@@ -291,7 +303,10 @@ pub async fn get_transaction_response_for_hash<S: Storage>(storage: &S, mempool:
     match storage.get_transaction(hash).await {
         Ok(tx) => get_transaction_response(storage, &tx, hash, false, None).await,
         Err(_) => {
-            let tx = mempool.get_sorted_tx(hash).context("Error while retrieving transaction from disk and mempool")?;
+            // Stem txs are not public until they are fluffed
+            let tx = mempool.get_sorted_tx(hash).ok()
+                .filter(|tx| !tx.is_stem())
+                .context("Error while retrieving transaction from disk and mempool")?;
             get_transaction_response(storage, &tx.get_tx(), hash, true, Some(tx.get_first_seen())).await
         }
     }
@@ -315,63 +330,100 @@ pub async fn get_peer_entry(peer: &Peer) -> PeerEntry {
         peers: Cow::Owned(peers),
         pruned_topoheight: peer.get_pruned_topoheight(),
         cumulative_difficulty: Cow::Owned(cumulative_difficulty),
-        connected_on: peer.get_connection().connected_on()
+        connected_on: peer.get_connection().connected_on(),
+        score: peer.get_score(),
+        bytes_sent: peer.get_connection().bytes_out(),
+        bytes_received: peer.get_connection().bytes_in()
     }
 }
 
+// Rate limit cost of the methods reading a lot from the storage
+// They can be overridden in the rate limit config
+pub const DEFAULT_METHOD_COSTS: [(&str, u64); 8] = [
+    ("get_blocks_range_by_topoheight", 10),
+    ("get_blocks_range_by_height", 10),
+    ("get_account_history", 10),
+    ("get_transactions", 5),
+    ("get_accounts", 5),
+    ("get_assets", 5),
+    ("get_account_assets", 5),
+    ("get_mempool", 5)
+];
+
 // This function is used to register all the RPC methods
-pub fn register_methods<S: Storage>(handler: &mut RPCHandler<Arc<Blockchain<S>>>, allow_mining_methods: bool) {
+pub fn register_methods<S: Storage>(handler: &mut RPCHandler<Arc<Blockchain<S>>>, allow_mining_methods: bool, allow_admin_methods: bool) {
     info!("Registering RPC methods...");
-    handler.register_method("get_version", async_handler!(version::<S>));
-    handler.register_method("get_height", async_handler!(get_height::<S>));
-    handler.register_method("get_topoheight", async_handler!(get_topoheight::<S>));
-    handler.register_method("get_stableheight", async_handler!(get_stableheight::<S>));
-    handler.register_method("get_block_at_topoheight", async_handler!(get_block_at_topoheight::<S>));
-    handler.register_method("get_blocks_at_height", async_handler!(get_blocks_at_height::<S>));
-    handler.register_method("get_block_by_hash", async_handler!(get_block_by_hash::<S>));
-    handler.register_method("get_top_block", async_handler!(get_top_block::<S>));
-    handler.register_method("get_balance", async_handler!(get_balance::<S>));
-    handler.register_method("has_balance", async_handler!(has_balance::<S>));
-    handler.register_method("get_balance_at_topoheight", async_handler!(get_balance_at_topoheight::<S>));
-    handler.register_method("get_info", async_handler!(get_info::<S>));
-    handler.register_method("get_nonce", async_handler!(get_nonce::<S>));
-    handler.register_method("has_nonce", async_handler!(has_nonce::<S>));
-    handler.register_method("get_nonce_at_topoheight", async_handler!(get_nonce_at_topoheight::<S>));
-    handler.register_method("get_asset", async_handler!(get_asset::<S>));
-    handler.register_method("get_assets", async_handler!(get_assets::<S>));
-    handler.register_method("count_assets", async_handler!(count_assets::<S>));
-    handler.register_method("count_accounts", async_handler!(count_accounts::<S>));
-    handler.register_method("count_transactions", async_handler!(count_transactions::<S>));
-    handler.register_method("submit_transaction", async_handler!(submit_transaction::<S>));
-    handler.register_method("get_transaction", async_handler!(get_transaction::<S>));
-    handler.register_method("get_transaction_executor", async_handler!(get_transaction_executor::<S>));
-    handler.register_method("p2p_status", async_handler!(p2p_status::<S>));
-    handler.register_method("get_peers", async_handler!(get_peers::<S>));
-    handler.register_method("get_mempool", async_handler!(get_mempool::<S>));
-    handler.register_method("get_tips", async_handler!(get_tips::<S>));
-    handler.register_method("get_dag_order", async_handler!(get_dag_order::<S>));
-    handler.register_method("get_blocks_range_by_topoheight", async_handler!(get_blocks_range_by_topoheight::<S>));
-    handler.register_method("get_blocks_range_by_height", async_handler!(get_blocks_range_by_height::<S>));
-    handler.register_method("get_transactions", async_handler!(get_transactions::<S>));
-    handler.register_method("get_account_history", async_handler!(get_account_history::<S>));
-    handler.register_method("get_account_assets", async_handler!(get_account_assets::<S>));
-    handler.register_method("get_accounts", async_handler!(get_accounts::<S>));
-    handler.register_method("is_account_registered", async_handler!(is_account_registered::<S>));
-    handler.register_method("get_account_registration_topoheight", async_handler!(get_account_registration_topoheight::<S>));
-    handler.register_method("is_tx_executed_in_block", async_handler!(is_tx_executed_in_block::<S>));
-    handler.register_method("get_dev_fee_thresholds", async_handler!(get_dev_fee_thresholds::<S>));
-    handler.register_method("get_size_on_disk", async_handler!(get_size_on_disk::<S>));
-    handler.register_method("get_mempool_cache", async_handler!(get_mempool_cache::<S>));
-    handler.register_method("get_difficulty", async_handler!(get_difficulty::<S>));
-    handler.register_method("validate_address", async_handler!(validate_address::<S>));
-    handler.register_method("split_address", async_handler!(split_address::<S>));
-    handler.register_method("extract_key_from_address", async_handler!(extract_key_from_address::<S>));
-
+    handler.register_method::<(), String>("get_version", async_handler!(version::<S>));
+    handler.register_method::<(), u64>("get_height", async_handler!(get_height::<S>));
+    handler.register_method::<(), u64>("get_topoheight", async_handler!(get_topoheight::<S>));
+    handler.register_method::<(), u64>("get_stableheight", async_handler!(get_stableheight::<S>));
+    handler.register_method::<GetBlockAtTopoHeightParams, RPCBlockResponse>("get_block_at_topoheight", async_handler!(get_block_at_topoheight::<S>));
+    handler.register_method::<GetBlocksAtHeightParams, Vec<RPCBlockResponse>>("get_blocks_at_height", async_handler!(get_blocks_at_height::<S>));
+    handler.register_method::<GetBlockByHashParams, RPCBlockResponse>("get_block_by_hash", async_handler!(get_block_by_hash::<S>));
+    handler.register_method::<GetTopBlockParams, RPCBlockResponse>("get_top_block", async_handler!(get_top_block::<S>));
+    handler.register_method::<GetBalanceParams, GetBalanceResult>("get_balance", async_handler!(get_balance::<S>));
+    handler.register_method::<HasBalanceParams, HasBalanceResult>("has_balance", async_handler!(has_balance::<S>));
+    handler.register_method::<GetBalanceAtTopoHeightParams, VersionedBalance>("get_balance_at_topoheight", async_handler!(get_balance_at_topoheight::<S>));
+    handler.register_method::<(), GetInfoResult>("get_info", async_handler!(get_info::<S>));
+    handler.register_method::<GetNonceParams, GetNonceResult>("get_nonce", async_handler!(get_nonce::<S>));
+    handler.register_method::<HasNonceParams, HasNonceResult>("has_nonce", async_handler!(has_nonce::<S>));
+    handler.register_method::<GetNonceAtTopoHeightParams, VersionedNonce>("get_nonce_at_topoheight", async_handler!(get_nonce_at_topoheight::<S>));
+    handler.register_method::<GetAssetParams, AssetData>("get_asset", async_handler!(get_asset::<S>));
+    handler.register_method::<GetAssetsParams, Vec<AssetWithData>>("get_assets", async_handler!(get_assets::<S>));
+    handler.register_method::<(), u64>("count_assets", async_handler!(count_assets::<S>));
+    handler.register_method::<(), u64>("count_accounts", async_handler!(count_accounts::<S>));
+    handler.register_method::<(), u64>("count_transactions", async_handler!(count_transactions::<S>));
+    handler.register_method::<SubmitTransactionParams, bool>("submit_transaction", async_handler!(submit_transaction::<S>));
+    handler.register_method::<GetTransactionParams, TransactionResponse>("get_transaction", async_handler!(get_transaction::<S>));
+    handler.register_method::<GetTransactionExecutorParams, GetTransactionExecutorResult>("get_transaction_executor", async_handler!(get_transaction_executor::<S>));
+    handler.register_method::<(), P2pStatusResult>("p2p_status", async_handler!(p2p_status::<S>));
+    handler.register_method::<(), GetPeersResponse>("get_peers", async_handler!(get_peers::<S>));
+    handler.register_method::<(), Vec<TransactionResponse>>("get_mempool", async_handler!(get_mempool::<S>));
+    handler.register_method::<(), Vec<Hash>>("get_tips", async_handler!(get_tips::<S>));
+    handler.register_method::<GetTopoHeightRangeParams, Vec<Hash>>("get_dag_order", async_handler!(get_dag_order::<S>));
+    handler.register_method::<GetTopoHeightRangeParams, Vec<RPCBlockResponse>>("get_blocks_range_by_topoheight", async_handler!(get_blocks_range_by_topoheight::<S>));
+    handler.register_method::<GetHeightRangeParams, Vec<RPCBlockResponse>>("get_blocks_range_by_height", async_handler!(get_blocks_range_by_height::<S>));
+    handler.register_method::<GetTransactionsParams, Vec<Option<TransactionResponse>>>("get_transactions", async_handler!(get_transactions::<S>));
+    handler.register_method::<GetAccountHistoryParams, Vec<AccountHistoryEntry>>("get_account_history", async_handler!(get_account_history::<S>));
+    handler.register_method::<GetAccountAssetsParams, Vec<Hash>>("get_account_assets", async_handler!(get_account_assets::<S>));
+    handler.register_method::<GetAccountsParams, Vec<Address>>("get_accounts", async_handler!(get_accounts::<S>));
+    handler.register_method::<IsAccountRegisteredParams, bool>("is_account_registered", async_handler!(is_account_registered::<S>));
+    handler.register_method::<GetAccountRegistrationParams, u64>("get_account_registration_topoheight", async_handler!(get_account_registration_topoheight::<S>));
+    handler.register_method::<IsTxExecutedInBlockParams, bool>("is_tx_executed_in_block", async_handler!(is_tx_executed_in_block::<S>));
+    handler.register_method::<(), Vec<DevFeeThreshold>>("get_dev_fee_thresholds", async_handler!(get_dev_fee_thresholds::<S>));
+    handler.register_method::<(), SizeOnDiskResult>("get_size_on_disk", async_handler!(get_size_on_disk::<S>));
+    handler.register_method::<GetMempoolCacheParams, GetMempoolCacheResult>("get_mempool_cache", async_handler!(get_mempool_cache::<S>));
+    handler.register_method::<(), GetDifficultyResult>("get_difficulty", async_handler!(get_difficulty::<S>));
+    handler.register_method::<ValidateAddressParams, ValidateAddressResult>("validate_address", async_handler!(validate_address::<S>));
+    handler.register_method::<SplitAddressParams, SplitAddressResult>("split_address", async_handler!(split_address::<S>));
+    handler.register_method::<ExtractKeyFromAddressParams, ExtractKeyFromAddressResult>("extract_key_from_address", async_handler!(extract_key_from_address::<S>));
+
+    // roles that can be given to the clients in the RPC auth config
+    // "public" can only read the chain and submit transactions
+    let public_methods: Vec<String> = handler.get_method_names().map(String::from).collect();
+    handler.register_role("public", RpcRole::with_methods(public_methods.iter().cloned()));
+
+    let mining_methods = ["get_block_template", "create_miner_work", "submit_block"];
     if allow_mining_methods {
-        handler.register_method("get_block_template", async_handler!(get_block_template::<S>));
-        handler.register_method("create_miner_work", async_handler!(create_miner_work::<S>));
-        handler.register_method("submit_block", async_handler!(submit_block::<S>));
+        handler.register_method::<GetBlockTemplateParams, GetBlockTemplateResult>("get_block_template", async_handler!(get_block_template::<S>));
+        // a long poll can wait for the next template without blocking the session
+        handler.set_method_concurrent("get_block_template");
+        handler.register_method::<CreateMinerWorkParams, CreateMinerWorkResult>("create_miner_work", async_handler!(create_miner_work::<S>));
+        handler.register_method::<SubmitBlockParams, bool>("submit_block", async_handler!(submit_block::<S>));
     }
+    handler.register_role("miner", RpcRole::with_methods(public_methods.into_iter().chain(mining_methods.into_iter().map(String::from))));
+
+    if allow_admin_methods {
+        handler.register_method::<BanPeerParams, bool>("ban_peer", async_handler!(ban_peer::<S>));
+        handler.register_method::<UnbanParams, bool>("unban", async_handler!(unban::<S>));
+        handler.register_method::<(), Vec<BanEntry>>("list_bans", async_handler!(list_bans::<S>));
+    }
+    handler.register_role("admin", RpcRole::all());
+
+    handler.enable_discover(OpenRpcInfo {
+        title: "PARL Daemon JSON-RPC API".into(),
+        version: VERSION.into()
+    });
 }
 
 async fn version<S: Storage>(_: &Context, body: Value) -> Result<Value, InternalRpcError> {
@@ -439,12 +491,25 @@ async fn get_block_template<S: Storage>(context: &Context, body: Value) -> Resul
         return Err(InternalRpcError::InvalidParamsAny(BlockchainError::InvalidNetwork.into()))
     }
 
+    // the getwork server tracks the template changes
+    // without it, a long poll would return directly and the client would spin
+    let getwork = {
+        let rpc = blockchain.get_rpc().read().await;
+        rpc.as_ref().and_then(|rpc| rpc.getwork_server().clone())
+    };
+    let long_poll_id = match (getwork, params.long_poll_id) {
+        (Some(getwork), Some(version)) => Some(getwork.wait_for_new_template(version).await),
+        (Some(getwork), None) => Some(getwork.get_template_version()),
+        (None, Some(_)) => return Err(InternalRpcError::InvalidParams("Long polling requires the GetWork server")),
+        (None, None) => None
+    };
+
     let storage = blockchain.get_storage().read().await;
     let block = blockchain.get_block_template_for_storage(&storage, params.address.into_owned().to_public_key()).await.context("Error while retrieving block template")?;
     let (difficulty, _) = blockchain.get_difficulty_at_tips(&*storage, block.get_tips().iter()).await.context("Error while retrieving difficulty at tips")?;
     let height = block.height;
     let topoheight = blockchain.get_topo_height();
-    Ok(json!(GetBlockTemplateResult { template: block.to_hex(), height, topoheight, difficulty }))
+    Ok(json!(GetBlockTemplateResult { template: block.to_hex(), height, topoheight, difficulty, long_poll_id }))
 }
 
 async fn create_miner_work<S: Storage>(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
@@ -694,7 +759,10 @@ async fn submit_transaction<S: Storage>(context: &Context, body: Value) -> Resul
         .map_err(|err| InternalRpcError::InvalidParamsAny(err.into()))?;
 
     let blockchain: &Arc<Blockchain<S>> = context.get()?;
-    blockchain.add_tx_to_mempool(transaction, true).await?;
+    // Submitted txs start in the stem phase to hide that they come from us
+    // They are added in the fluff phase when no peer can relay them
+    let hash = transaction.hash();
+    blockchain.add_stem_tx_to_mempool_with_hash(transaction, hash, true).await?;
 
     Ok(json!(true))
 }
@@ -782,6 +850,57 @@ async fn get_peers<S: Storage>(context: &Context, body: Value) -> Result<Value,
     }
 }
 
+// Ban an IP address or a CIDR range, connected peers in it are disconnected
+async fn ban_peer<S: Storage>(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    let params: BanPeerParams = parse_params(body)?;
+    let range: IpRange = params.address.parse().map_err(|e| InternalRpcError::InvalidParamsAny(e.into()))?;
+
+    let blockchain: &Arc<Blockchain<S>> = context.get()?;
+    let p2p = { blockchain.get_p2p().read().await.clone() };
+    match p2p.as_ref() {
+        Some(p2p) => {
+            p2p.get_peer_list().ban_range(range, params.reason.map(Cow::into_owned), params.duration).await;
+            Ok(json!(true))
+        },
+        None => Err(InternalRpcError::InvalidParamsAny(ApiError::NoP2p.into()))
+    }
+}
+
+// Remove a ban rule, returns false if no rule was found for this range
+async fn unban<S: Storage>(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    let params: UnbanParams = parse_params(body)?;
+    let range: IpRange = params.address.parse().map_err(|e| InternalRpcError::InvalidParamsAny(e.into()))?;
+
+    let blockchain: &Arc<Blockchain<S>> = context.get()?;
+    let p2p = { blockchain.get_p2p().read().await.clone() };
+    match p2p.as_ref() {
+        Some(p2p) => Ok(json!(p2p.get_peer_list().unban_range(&range).await)),
+        None => Err(InternalRpcError::InvalidParamsAny(ApiError::NoP2p.into()))
+    }
+}
+
+async fn list_bans<S: Storage>(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    if body != Value::Null {
+        return Err(InternalRpcError::UnexpectedParams)
+    }
+
+    let blockchain: &Arc<Blockchain<S>> = context.get()?;
+    let p2p = { blockchain.get_p2p().read().await.clone() };
+    match p2p.as_ref() {
+        Some(p2p) => {
+            let bans = p2p.get_peer_list().get_bans().await;
+            let entries: Vec<BanEntry> = bans.iter().map(|rule| BanEntry {
+                range: Cow::Owned(rule.get_range().to_string()),
+                reason: Cow::Borrowed(rule.get_reason()),
+                created_at: rule.get_created_at(),
+                expires_at: rule.get_expires_at()
+            }).collect();
+            Ok(json!(entries))
+        },
+        None => Err(InternalRpcError::InvalidParamsAny(ApiError::NoP2p.into()))
+    }
+}
+
 async fn get_mempool<S: Storage>(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
     if body != Value::Null {
         return Err(InternalRpcError::UnexpectedParams)
@@ -790,7 +909,7 @@ async fn get_mempool<S: Storage>(context: &Context, body: Value) -> Result<Value
     let storage = blockchain.get_storage().read().await;
     let mempool = blockchain.get_mempool().read().await;
     let mut transactions: Vec<Value> = Vec::new();
-    for (hash, sorted_tx) in mempool.get_txs() {
+    for (hash, sorted_tx) in mempool.get_txs().iter().filter(|(_, tx)| !tx.is_stem()) {
         transactions.push(get_transaction_response(&*storage, sorted_tx.get_tx(), hash, true, Some(sorted_tx.get_first_seen())).await?);
     }
 
@@ -1,12 +1,16 @@
 pub mod config;
 
 use std::{
+    future,
     time::Duration,
-    sync::atomic::{
-        AtomicU64,
-        Ordering,
-        AtomicUsize,
-        AtomicBool
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+            AtomicUsize,
+            AtomicBool
+        },
+        Arc
     },
     thread
 };
@@ -50,6 +54,11 @@ use parl_common::{
         difficulty_from_hash,
         Difficulty
     },
+    metrics::{
+        run_metrics_server,
+        Counter,
+        MetricsEncoder
+    },
     prompt::{
         command::CommandManager,
         LogLevel,
@@ -131,7 +140,12 @@ pub struct MinerConfig {
     num_threads: Option<u16>,
     /// Worker name to be displayed on daemon side
     #[clap(short, long, default_value_t = String::from("default"))]
-    worker: String
+    worker: String,
+    /// Bind address of the metrics HTTP server (disabled by default)
+    /// 
+    /// Metrics are served in the Prometheus text format on /metrics.
+    #[clap(long)]
+    metrics_bind_address: Option<String>
 }
 
 #[derive(Clone)]
@@ -154,6 +168,9 @@ static CURRENT_TOPO_HEIGHT: AtomicU64 = AtomicU64::new(0);
 static BLOCKS_FOUND: AtomicUsize = AtomicUsize::new(0);
 static BLOCKS_REJECTED: AtomicUsize = AtomicUsize::new(0);
 static HASHRATE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static RECONNECTS: AtomicUsize = AtomicUsize::new(0);
+// timestamp in milliseconds of the last job received
+static LAST_JOB_TIME: AtomicU64 = AtomicU64::new(0);
 
 lazy_static! {
     static ref HASHRATE_LAST_TIME: Mutex<Instant> = Mutex::new(Instant::now());
@@ -200,11 +217,24 @@ async fn main() -> Result<()> {
     let (sender, _) = broadcast::channel::<ThreadNotification>(threads as usize);
     // mpsc channel to send from threads to the "communication" task.
     let (block_sender, block_receiver) = mpsc::channel::<MinerWork>(threads as usize);
+    // hashes computed by each thread
+    let mut threads_hashes = Vec::with_capacity(threads as usize);
     for id in 0..threads {
         debug!("Starting thread #{}", id);
-        if let Err(e) = start_thread(id, sender.subscribe(), block_sender.clone()) {
+        let hashes = Arc::new(Counter::new());
+        if let Err(e) = start_thread(id, sender.subscribe(), block_sender.clone(), Arc::clone(&hashes)) {
             error!("Error while creating Mining Thread #{}: {}", id, e);
+            continue;
         }
+        threads_hashes.push((id, hashes));
+    }
+
+    if let Some(bind_address) = config.metrics_bind_address {
+        spawn_task("metrics", async move {
+            if let Err(e) = run_metrics_server(bind_address, move || future::ready(encode_metrics(&threads_hashes))).await {
+                error!("Error while running the metrics server: {}", e);
+            }
+        });
     }
 
     // start communication task
@@ -262,6 +292,7 @@ fn benchmark(threads: usize, iterations: usize) {
 async fn communication_task(daemon_address: String, job_sender: broadcast::Sender<ThreadNotification<'_>>, mut block_receiver: mpsc::Receiver<MinerWork<'_>>, address: Address, worker: String) {
     info!("Starting communication task");
     let daemon_address = sanitize_daemon_address(&daemon_address);
+    let mut connected_once = false;
     'main: loop {
         info!("Trying to connect to {}", daemon_address);
         let client = match connect_async(format!("{}/getwork/{}/{}", daemon_address, address.to_string(), worker)).await {
@@ -293,6 +324,10 @@ async fn communication_task(daemon_address: String, job_sender: broadcast::Sende
             }
         };
         WEBSOCKET_CONNECTED.store(true, Ordering::SeqCst);
+        if connected_once {
+            RECONNECTS.fetch_add(1, Ordering::SeqCst);
+        }
+        connected_once = true;
         info!("Connected successfully to {}", daemon_address);
         let (mut write, mut read) = client.split();
         loop {
@@ -343,6 +378,7 @@ async fn handle_websocket_message(message: Result<Message, TungsteniteError>, jo
                     info!("New job received: difficulty {} at height {}", format_difficulty(job.difficulty), job.height);
                     let block = MinerWork::from_hex(job.template).context("Error while decoding new job received from daemon")?;
                     CURRENT_TOPO_HEIGHT.store(job.topoheight, Ordering::SeqCst);
+                    LAST_JOB_TIME.store(get_current_time_in_millis(), Ordering::SeqCst);
 
                     if let Err(e) = job_sender.send(ThreadNotification::NewJob(block, job.difficulty, job.height)) {
                         error!("Error while sending new job to threads: {}", e);
@@ -376,7 +412,7 @@ async fn handle_websocket_message(message: Result<Message, TungsteniteError>, jo
     Ok(false)
 }
 
-fn start_thread(id: u16, mut job_receiver: broadcast::Receiver<ThreadNotification<'static>>, block_sender: mpsc::Sender<MinerWork<'static>>) -> Result<(), Error> {
+fn start_thread(id: u16, mut job_receiver: broadcast::Receiver<ThreadNotification<'static>>, block_sender: mpsc::Sender<MinerWork<'static>>, hashes: Arc<Counter>) -> Result<(), Error> {
     let builder = thread::Builder::new().name(format!("Mining Thread #{}", id));
     builder.spawn(move || {
         let mut job: MinerWork;
@@ -436,6 +472,7 @@ fn start_thread(id: u16, mut job_receiver: broadcast::Receiver<ThreadNotificatio
                             }
                             job.set_timestamp(get_current_time_in_millis()).unwrap();
                             HASHRATE_COUNTER.fetch_add(UPDATE_EVERY_NONCE as usize, Ordering::SeqCst);
+                            hashes.inc_by(UPDATE_EVERY_NONCE);
                         }
 
                         hash = job.get_pow_hash(&mut scratch_pad).unwrap();
@@ -457,6 +494,28 @@ fn start_thread(id: u16, mut job_receiver: broadcast::Receiver<ThreadNotificatio
     Ok(())
 }
 
+// Build the metrics of the miner in the Prometheus text format
+fn encode_metrics(threads_hashes: &[(u16, Arc<Counter>)]) -> String {
+    let mut encoder = MetricsEncoder::new();
+    encoder.gauge("parl_miner_connected", "1 if the miner is connected to the daemon", WEBSOCKET_CONNECTED.load(Ordering::SeqCst) as u8);
+    encoder.gauge("parl_miner_topoheight", "Topoheight of the current job", CURRENT_TOPO_HEIGHT.load(Ordering::SeqCst));
+
+    let threads: Vec<(String, u64)> = threads_hashes.iter().map(|(id, hashes)| (id.to_string(), hashes.get())).collect();
+    encoder.labeled_counter("parl_miner_hashes_total", "Hashes computed by each thread, its rate is the thread hashrate", "thread", threads.iter().map(|(id, hashes)| (id.as_str(), *hashes)));
+
+    encoder.counter("parl_miner_blocks_accepted_total", "Blocks accepted by the daemon", BLOCKS_FOUND.load(Ordering::SeqCst));
+    encoder.counter("parl_miner_blocks_rejected_total", "Blocks rejected by the daemon", BLOCKS_REJECTED.load(Ordering::SeqCst));
+
+    let last_job = LAST_JOB_TIME.load(Ordering::SeqCst);
+    if last_job != 0 {
+        let age = get_current_time_in_millis().saturating_sub(last_job);
+        encoder.gauge("parl_miner_job_age_seconds", "Time since the last job was received", age as f64 / 1000f64);
+    }
+    encoder.counter("parl_miner_reconnects_total", "Reconnections to the daemon", RECONNECTS.load(Ordering::SeqCst));
+
+    encoder.finish()
+}
+
 async fn run_prompt(prompt: ShareablePrompt) -> Result<()> {
     let command_manager = CommandManager::new(prompt.clone());
     command_manager.register_default_commands()?;
@@ -1,5 +1,6 @@
 mod rpc;
 mod rpc_server;
+mod transaction_summary;
 mod xswd;
 
 use serde::ser::Serialize;
@@ -7,11 +8,14 @@ use parl_common::{api::wallet::NotifyEvent, rpc_server::WebSocketServerHandler};
 
 pub use self::{
     rpc_server::{WalletRpcServer, WalletRpcServerShared, AuthConfig},
+    transaction_summary::TransactionSummary,
     xswd::{
         XSWD,
         AppStateShared,
         PermissionResult,
         PermissionRequest,
+        Permission,
+        TrustedApplication,
         XSWDPermissionHandler,
         XSWDNodeMethodHandler
     },
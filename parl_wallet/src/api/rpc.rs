@@ -1,4 +1,4 @@
-use std::{sync::Arc, borrow::Cow};
+use std::{sync::Arc, borrow::Cow, collections::HashSet};
 use anyhow::Context as AnyContext;
 use parl_common::{
     api::{
@@ -19,20 +19,29 @@ use parl_common::{
             StoreParams,
             TransactionResponse,
             SetOnlineModeParams,
+            GetDaemonEndpointsResult,
+            TransactionEntry,
+            AddWebhookParams,
+            RemoveWebhookParams,
+            WebhookResponse
         },
+        query::QueryResult,
         SplitAddressParams,
         SplitAddressResult,
         DataElement,
-        DataHash
+        DataHash,
+        DataValue
     },
     async_handler,
     config::{VERSION, PARL_ASSET},
     context::Context,
-    crypto::Hashable,
+    crypto::{Address, Hash, Hashable, Signature},
+    network::Network,
     rpc_server::{
         parse_params,
         websocket::WebSocketSessionShared,
         InternalRpcError,
+        OpenRpcInfo,
         RPCHandler
     },
     serializer::Serializer,
@@ -41,7 +50,8 @@ use parl_common::{
 use serde_json::{Value, json};
 use crate::{
     wallet::Wallet,
-    error::WalletError
+    error::WalletError,
+    webhook::Webhook
 };
 use super::xswd::XSWDWebSocketHandler;
 use log::{info, warn};
@@ -49,36 +59,45 @@ use log::{info, warn};
 // Register all RPC methods
 pub fn register_methods(handler: &mut RPCHandler<Arc<Wallet>>) {
     info!("Registering RPC methods...");
-    handler.register_method("get_version", async_handler!(get_version));
-    handler.register_method("get_network", async_handler!(get_network));
-    handler.register_method("get_nonce", async_handler!(get_nonce));
-    handler.register_method("get_topoheight", async_handler!(get_topoheight));
-    handler.register_method("get_address", async_handler!(get_address));
-    handler.register_method("split_address", async_handler!(split_address));
-    handler.register_method("rescan", async_handler!(rescan));
-    handler.register_method("get_balance", async_handler!(get_balance));
-    handler.register_method("has_balance", async_handler!(has_balance));
-    handler.register_method("get_tracked_assets", async_handler!(get_tracked_assets));
-    handler.register_method("get_asset_precision", async_handler!(get_asset_precision));
-    handler.register_method("get_transaction", async_handler!(get_transaction));
-    handler.register_method("build_transaction", async_handler!(build_transaction));
-    handler.register_method("list_transactions", async_handler!(list_transactions));
-    handler.register_method("is_online", async_handler!(is_online));
-    handler.register_method("set_online_mode", async_handler!(set_online_mode));
-    handler.register_method("set_offline_mode", async_handler!(set_offline_mode));
-    handler.register_method("sign_data", async_handler!(sign_data));
-    handler.register_method("estimate_fees", async_handler!(estimate_fees));
+    handler.register_method::<(), String>("get_version", async_handler!(get_version));
+    handler.register_method::<(), Network>("get_network", async_handler!(get_network));
+    handler.register_method::<(), u64>("get_nonce", async_handler!(get_nonce));
+    handler.register_method::<(), u64>("get_topoheight", async_handler!(get_topoheight));
+    handler.register_method::<GetAddressParams, Address>("get_address", async_handler!(get_address));
+    handler.register_method::<SplitAddressParams, SplitAddressResult>("split_address", async_handler!(split_address));
+    handler.register_method::<RescanParams, bool>("rescan", async_handler!(rescan));
+    handler.register_method::<GetBalanceParams, u64>("get_balance", async_handler!(get_balance));
+    handler.register_method::<GetBalanceParams, bool>("has_balance", async_handler!(has_balance));
+    handler.register_method::<(), HashSet<Hash>>("get_tracked_assets", async_handler!(get_tracked_assets));
+    handler.register_method::<GetAssetPrecisionParams, u8>("get_asset_precision", async_handler!(get_asset_precision));
+    handler.register_method::<GetTransactionParams, TransactionEntry>("get_transaction", async_handler!(get_transaction));
+    handler.register_method::<BuildTransactionParams, TransactionResponse>("build_transaction", async_handler!(build_transaction));
+    handler.register_method::<ListTransactionsParams, Vec<TransactionEntry>>("list_transactions", async_handler!(list_transactions));
+    handler.register_method::<(), bool>("is_online", async_handler!(is_online));
+    handler.register_method::<SetOnlineModeParams, bool>("set_online_mode", async_handler!(set_online_mode));
+    handler.register_method::<(), bool>("set_offline_mode", async_handler!(set_offline_mode));
+    handler.register_method::<(), GetDaemonEndpointsResult>("get_daemon_endpoints", async_handler!(get_daemon_endpoints));
+    handler.register_method::<DataElement, Signature>("sign_data", async_handler!(sign_data));
+    handler.register_method::<EstimateFeesParams, u64>("estimate_fees", async_handler!(estimate_fees));
+    handler.register_method::<AddWebhookParams, bool>("add_webhook", async_handler!(add_webhook));
+    handler.register_method::<RemoveWebhookParams, bool>("remove_webhook", async_handler!(remove_webhook));
+    handler.register_method::<(), Vec<WebhookResponse>>("list_webhooks", async_handler!(list_webhooks));
 
     // These functions allow to have an encrypted DB directly in the wallet storage
     // You can retrieve keys, values, have differents trees, and store values
     // It is restricted in XSWD context (each app access to their own trees), and open to everything in RPC
     // Keys and values can be anything
-    handler.register_method("get_matching_keys", async_handler!(get_matching_keys));
-    handler.register_method("get_value_from_key", async_handler!(get_value_from_key));
-    handler.register_method("store", async_handler!(store));
-    handler.register_method("delete", async_handler!(delete));
-    handler.register_method("has_key", async_handler!(has_key));
-    handler.register_method("query_db", async_handler!(query_db));
+    handler.register_method::<GetMatchingKeysParams, Vec<DataValue>>("get_matching_keys", async_handler!(get_matching_keys));
+    handler.register_method::<GetValueFromKeyParams, DataElement>("get_value_from_key", async_handler!(get_value_from_key));
+    handler.register_method::<StoreParams, bool>("store", async_handler!(store));
+    handler.register_method::<DeleteParams, bool>("delete", async_handler!(delete));
+    handler.register_method::<HasKeyParams, bool>("has_key", async_handler!(has_key));
+    handler.register_method::<QueryDBParams, QueryResult>("query_db", async_handler!(query_db));
+
+    handler.enable_discover(OpenRpcInfo {
+        title: "PARL Wallet JSON-RPC API".into(),
+        version: VERSION.into()
+    });
 }
 
 // Retrieve the version of the wallet
@@ -315,7 +334,9 @@ async fn set_online_mode(context: &Context, body: Value) -> Result<Value, Intern
         return Err(InternalRpcError::InvalidRequestStr("Wallet is already connected to a daemon"))
     }
 
-    wallet.set_online_mode(&params.daemon_address, params.auto_reconnect).await?;
+    let mut daemon_addresses = vec![params.daemon_address];
+    daemon_addresses.extend(params.fallback_daemon_addresses);
+    wallet.set_online_mode_with_endpoints(daemon_addresses, params.auto_reconnect).await?;
 
     Ok(json!(true))
 }
@@ -336,6 +357,67 @@ async fn set_offline_mode(context: &Context, body: Value) -> Result<Value, Inter
     Ok(json!(true))
 }
 
+// Retrieve the daemon currently used and all the daemons configured
+async fn get_daemon_endpoints(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    if body != Value::Null {
+        return Err(InternalRpcError::UnexpectedParams)
+    }
+
+    let wallet: &Arc<Wallet> = context.get()?;
+    let network_handler = wallet.get_network_handler().await.lock().await;
+    let result = if let Some(network_handler) = network_handler.as_ref() {
+        GetDaemonEndpointsResult {
+            active: network_handler.get_active_endpoint().await,
+            endpoints: network_handler.get_endpoints().to_vec()
+        }
+    } else {
+        GetDaemonEndpointsResult {
+            active: None,
+            endpoints: Vec::new()
+        }
+    };
+
+    Ok(json!(result))
+}
+
+// Register a webhook that will receive the wallet events
+async fn add_webhook(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    let params: AddWebhookParams = parse_params(body)?;
+    let webhook = Webhook::new(params.url, params.secret, params.events).map_err(WalletError::from)?;
+
+    let wallet: &Arc<Wallet> = context.get()?;
+    wallet.add_webhook(webhook).await?;
+
+    Ok(json!(true))
+}
+
+// Delete a webhook and all its pending deliveries
+async fn remove_webhook(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    let params: RemoveWebhookParams = parse_params(body)?;
+
+    let wallet: &Arc<Wallet> = context.get()?;
+    wallet.remove_webhook(&params.url).await?;
+
+    Ok(json!(true))
+}
+
+// List all webhooks registered, secrets are not returned
+async fn list_webhooks(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
+    if body != Value::Null {
+        return Err(InternalRpcError::UnexpectedParams)
+    }
+
+    let wallet: &Arc<Wallet> = context.get()?;
+    let storage = wallet.get_storage().read().await;
+    let webhooks = storage.get_webhooks()?;
+    let response = webhooks.iter().map(|webhook| WebhookResponse {
+        url: Cow::Borrowed(webhook.get_url()),
+        events: Cow::Borrowed(webhook.get_events())
+    }).collect::<Vec<_>>();
+
+    Ok(json!(response))
+}
+
 // Sign any data converted in bytes format
 async fn sign_data(context: &Context, body: Value) -> Result<Value, InternalRpcError> {
     let params: DataElement = parse_params(body)?;
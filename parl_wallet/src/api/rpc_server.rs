@@ -73,6 +73,9 @@ where
                     .wrap(auth)
                     // WebSocket support
                     .route("/json_rpc", web::get().to(websocket::<EventWebSocketHandler<W, NotifyEvent>, Self>))
+                    // Dedicated WebSocket endpoint for events subscriptions
+                    // It shares the same handler, so it is also behind the basic auth
+                    .route("/ws", web::get().to(websocket::<EventWebSocketHandler<W, NotifyEvent>, Self>))
                     // HTTP support
                     .route("/json_rpc", web::post().to(json_rpc::<W, WalletRpcServer<W>>))
                     .service(index)
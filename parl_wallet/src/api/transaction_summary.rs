@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter}
+};
+use anyhow::Result;
+use parl_common::{
+    api::DataElement,
+    config::{COIN_DECIMALS, PARL_ASSET},
+    crypto::{Address, Hash},
+    transaction::builder::TransactionTypeBuilder,
+    utils::{format_coin, format_parl}
+};
+use crate::storage::EncryptedStorage;
+
+// Human readable review of a transaction requested by an application
+// It is shown to the user before accepting the request
+#[derive(Debug)]
+pub struct TransactionSummary {
+    // All transfers with their recipient
+    transfers: Vec<TransferSummary>,
+    // Asset and amount burned
+    burn: Option<AmountSummary>,
+    // Estimated fee in PARL
+    fee: u64,
+    // If the transaction will be broadcasted by the wallet
+    broadcast: bool
+}
+
+#[derive(Debug)]
+pub struct AmountSummary {
+    asset: Hash,
+    amount: u64,
+    // Decimals of the asset, None if not tracked by the wallet
+    decimals: Option<u8>
+}
+
+#[derive(Debug)]
+pub struct TransferSummary {
+    destination: Address,
+    // Label from the address book if known
+    label: Option<String>,
+    value: AmountSummary,
+    extra_data: Option<DataElement>
+}
+
+impl AmountSummary {
+    fn new(storage: &EncryptedStorage, asset: Hash, amount: u64) -> Self {
+        let decimals = if asset == PARL_ASSET {
+            Some(COIN_DECIMALS)
+        } else {
+            storage.get_asset_decimals(&asset).ok()
+        };
+
+        Self {
+            asset,
+            amount,
+            decimals
+        }
+    }
+
+    pub fn get_asset(&self) -> &Hash {
+        &self.asset
+    }
+
+    pub fn get_amount(&self) -> u64 {
+        self.amount
+    }
+}
+
+impl Display for AmountSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.decimals {
+            Some(decimals) if self.asset == PARL_ASSET => write!(f, "{} PARL", format_coin(self.amount, decimals)),
+            Some(decimals) => write!(f, "{} of {}", format_coin(self.amount, decimals), self.asset),
+            // We don't know the decimals, show the atomic units
+            None => write!(f, "{} atomic units of unknown asset {}", self.amount, self.asset)
+        }
+    }
+}
+
+impl TransferSummary {
+    pub fn get_destination(&self) -> &Address {
+        &self.destination
+    }
+
+    pub fn get_label(&self) -> &Option<String> {
+        &self.label
+    }
+
+    pub fn get_value(&self) -> &AmountSummary {
+        &self.value
+    }
+
+    pub fn get_extra_data(&self) -> &Option<DataElement> {
+        &self.extra_data
+    }
+}
+
+impl TransactionSummary {
+    // Decode the transaction type using the wallet storage for asset decimals and address book labels
+    pub fn new(storage: &EncryptedStorage, tx_type: &TransactionTypeBuilder, fee: u64, broadcast: bool) -> Result<Self> {
+        let mut transfers = Vec::new();
+        let mut burn = None;
+        match tx_type {
+            TransactionTypeBuilder::Transfers(builders) => {
+                for builder in builders {
+                    let label = storage.get_contact_label(builder.destination.get_public_key())?;
+                    transfers.push(TransferSummary {
+                        destination: builder.destination.clone(),
+                        label,
+                        value: AmountSummary::new(storage, builder.asset.clone(), builder.amount),
+                        extra_data: builder.extra_data.clone()
+                    });
+                }
+            },
+            TransactionTypeBuilder::Burn(payload) => {
+                burn = Some(AmountSummary::new(storage, payload.asset.clone(), payload.amount));
+            }
+        };
+
+        Ok(Self {
+            transfers,
+            burn,
+            fee,
+            broadcast
+        })
+    }
+
+    pub fn get_transfers(&self) -> &Vec<TransferSummary> {
+        &self.transfers
+    }
+
+    pub fn get_burn(&self) -> &Option<AmountSummary> {
+        &self.burn
+    }
+
+    pub fn get_fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.broadcast
+    }
+
+    // Total amount spent for each asset, fee included
+    pub fn get_total_spent(&self) -> HashMap<Hash, u64> {
+        let mut total: HashMap<Hash, u64> = HashMap::new();
+        let values = self.transfers.iter()
+            .map(|transfer| &transfer.value)
+            .chain(self.burn.iter());
+
+        for value in values {
+            let amount = total.entry(value.asset.clone()).or_insert(0);
+            *amount = amount.saturating_add(value.amount);
+        }
+
+        let amount = total.entry(PARL_ASSET).or_insert(0);
+        *amount = amount.saturating_add(self.fee);
+
+        total
+    }
+
+    // Verify that the amount spent for each asset is below the limit set
+    // Returns the first asset exceeding its limit
+    pub fn find_exceeded_limit<'a>(&self, limits: &'a HashMap<Hash, u64>) -> Option<(&'a Hash, u64)> {
+        let total = self.get_total_spent();
+        limits.iter()
+            .find(|(asset, limit)| total.get(*asset).map_or(false, |spent| *spent > **limit))
+            .map(|(asset, limit)| (asset, *limit))
+    }
+}
+
+impl Display for TransactionSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for transfer in &self.transfers {
+            match &transfer.label {
+                Some(label) => writeln!(f, "Send {} to {} ({})", transfer.value, label, transfer.destination)?,
+                None => writeln!(f, "Send {} to {}", transfer.value, transfer.destination)?
+            };
+
+            if let Some(extra_data) = &transfer.extra_data {
+                let extra_data = serde_json::to_string(extra_data).map_err(|_| fmt::Error)?;
+                writeln!(f, "  Extra data: {}", extra_data)?;
+            }
+        }
+
+        if let Some(burn) = &self.burn {
+            writeln!(f, "Burn {}", burn)?;
+        }
+
+        writeln!(f, "Estimated fee: {} PARL", format_parl(self.fee))?;
+        write!(f, "Broadcast: {}", if self.broadcast { "yes" } else { "no" })
+    }
+}
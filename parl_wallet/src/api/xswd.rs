@@ -41,14 +41,17 @@ use tokio::sync::{
 };
 use parl_common::{
     api::{
-        wallet::NotifyEvent,
+        wallet::{
+            BuildTransactionParams,
+            NotifyEvent
+        },
         EventResult
     },
     context::Context,
     crypto::{
         elgamal::PublicKey as DecompressedPublicKey,
-        Signature,
-        SIGNATURE_SIZE
+        Hash,
+        Signature
     },
     rpc_server::{
         websocket::{
@@ -69,10 +72,18 @@ use parl_common::{
         Serializer,
         Writer
     },
+    time::{
+        get_current_time_in_seconds,
+        TimestampSeconds
+    },
     utils::spawn_task
 };
 use serde::{Deserialize, Serialize};
-use crate::config::XSWD_BIND_ADDRESS;
+use crate::config::{
+    XSWD_BIND_ADDRESS,
+    XSWD_PERMISSION_EXPIRATION
+};
+use super::transaction_summary::TransactionSummary;
 use log::{
     debug,
     info,
@@ -94,6 +105,9 @@ use log::{
 // For security reasons, in case the signed token leaks, at each connection,
 // the wallet will request the authorization of the user
 // but will keep already-configured permissions.
+// Permissions set to always allow/deny are saved in the wallet storage
+// with an expiration, and restored only when the application
+// sends back the signature given by the wallet at its registration.
 pub struct XSWD<W>
 where
     W: Clone + Send + Sync + XSWDPermissionHandler + XSWDNodeMethodHandler + 'static
@@ -129,7 +143,9 @@ pub enum XSWDError {
     #[error("Application permissions are not signed")]
     ApplicationPermissionsNotSigned,
     #[error("Invalid signature for application data")]
-    InvalidSignatureForApplicationData
+    InvalidSignatureForApplicationData,
+    #[error("Spending limit exceeded")]
+    SpendingLimitExceeded
 }
 
 impl From<XSWDError> for InternalRpcError {
@@ -148,6 +164,14 @@ pub trait XSWDPermissionHandler {
     async fn cancel_request_permission(&self, app_state: &AppStateShared) -> Result<(), Error>;
     // Public key to use to verify the signature
     async fn get_public_key(&self) -> Result<&DecompressedPublicKey, Error>;
+    // Sign the application data so it can be verified on its next connections
+    async fn sign_application_data(&self, data: &[u8]) -> Result<Signature, Error>;
+    // Retrieve the remembered permissions of an application
+    async fn get_trusted_application(&self, id: &String) -> Result<Option<TrustedApplication>, Error>;
+    // Save the remembered permissions of an application
+    async fn save_trusted_application(&self, app: &TrustedApplication) -> Result<(), Error>;
+    // Decode the transaction requested into a human readable summary
+    async fn get_transaction_summary(&self, params: &BuildTransactionParams) -> Result<TransactionSummary, Error>;
 }
 
 #[async_trait]
@@ -334,6 +358,11 @@ where
         self.websocket.get_handler()
     }
 
+    // Returns the number of applications connected
+    pub async fn count_sessions(&self) -> usize {
+        self.websocket.count_connections().await
+    }
+
     pub async fn stop(&self) {
         info!("Stopping XSWD...");
         self.handle.stop(false).await;
@@ -368,10 +397,148 @@ impl Permission {
     }
 }
 
+// Application with its permissions remembered across connections
+#[derive(Debug)]
+pub struct TrustedApplication {
+    // Application ID in hexadecimal format
+    id: String,
+    // Name of the app
+    name: String,
+    // URL of the app if exists
+    url: Option<String>,
+    // Permission for each method with its expiration timestamp in seconds
+    permissions: HashMap<String, (Permission, TimestampSeconds)>,
+    // Maximum amount per asset that can be spent in one transaction, fee included
+    // Requests above it are rejected without asking the user
+    spending_limits: HashMap<Hash, u64>
+}
+
+impl TrustedApplication {
+    pub fn new(id: String, name: String, url: Option<String>) -> Self {
+        Self {
+            id,
+            name,
+            url,
+            permissions: HashMap::new(),
+            spending_limits: HashMap::new()
+        }
+    }
+
+    pub fn get_id(&self) -> &String {
+        &self.id
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_url(&self) -> &Option<String> {
+        &self.url
+    }
+
+    pub fn get_permissions(&self) -> &HashMap<String, (Permission, TimestampSeconds)> {
+        &self.permissions
+    }
+
+    // Remember a permission for a method until the expiration timestamp
+    pub fn set_permission(&mut self, method: String, permission: Permission, expire_at: TimestampSeconds) {
+        self.permissions.insert(method, (permission, expire_at));
+    }
+
+    // Retrieve all permissions that are not expired yet
+    pub fn get_valid_permissions(&self, now: TimestampSeconds) -> HashMap<String, Permission> {
+        self.permissions.iter()
+            .filter(|(_, (_, expire_at))| *expire_at > now)
+            .map(|(method, (permission, _))| (method.clone(), *permission))
+            .collect()
+    }
+
+    pub fn get_spending_limits(&self) -> &HashMap<Hash, u64> {
+        &self.spending_limits
+    }
+
+    // Set the maximum amount of an asset that can be spent in one transaction
+    pub fn set_spending_limit(&mut self, asset: Hash, limit: u64) {
+        self.spending_limits.insert(asset, limit);
+    }
+
+    // Returns false if no limit was set for this asset
+    pub fn remove_spending_limit(&mut self, asset: &Hash) -> bool {
+        self.spending_limits.remove(asset).is_some()
+    }
+
+    // Check if all the remembered permissions are expired
+    // Spending limits never expire
+    pub fn is_expired(&self, now: TimestampSeconds) -> bool {
+        self.spending_limits.is_empty() && self.permissions.values().all(|(_, expire_at)| *expire_at <= now)
+    }
+}
+
+impl Serializer for TrustedApplication {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let id = reader.read_string()?;
+        let name = reader.read_string()?;
+        let url = reader.read_optional_string()?;
+        let permissions_count = reader.read_u8()?;
+        let mut permissions = HashMap::with_capacity(permissions_count as usize);
+        for _ in 0..permissions_count {
+            let method = reader.read_string()?;
+            let permission = Permission::from_id(reader.read_u8()?).ok_or(ReaderError::InvalidValue)?;
+            let expire_at = reader.read_u64()?;
+            permissions.insert(method, (permission, expire_at));
+        }
+
+        let limits_count = reader.read_u8()?;
+        let mut spending_limits = HashMap::with_capacity(limits_count as usize);
+        for _ in 0..limits_count {
+            spending_limits.insert(reader.read_hash()?, reader.read_u64()?);
+        }
+
+        Ok(Self {
+            id,
+            name,
+            url,
+            permissions,
+            spending_limits
+        })
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.write_string(&self.id);
+        writer.write_string(&self.name);
+        writer.write_optional_string(&self.url);
+        writer.write_u8(self.permissions.len() as u8);
+
+        for (method, (permission, expire_at)) in &self.permissions {
+            writer.write_string(method);
+            writer.write_u8(permission.get_id());
+            writer.write_u64(expire_at);
+        }
+
+        writer.write_u8(self.spending_limits.len() as u8);
+        for (asset, limit) in &self.spending_limits {
+            writer.write_hash(asset);
+            writer.write_u64(limit);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.id.size() +
+        self.name.size() +
+        self.url.size() +
+        1 +
+        self.permissions.iter().map(|(k, _)| k.size() + 1 + 8).sum::<usize>() +
+        1 +
+        self.spending_limits.iter().map(|(k, _)| k.size() + 8).sum::<usize>()
+    }
+}
+
 pub enum PermissionRequest<'a> {
     // bool tell if it was already signed or not
     Application(bool),
-    Request(&'a RpcRequest)
+    Request(&'a RpcRequest),
+    // Request to build a transaction with its summary to review
+    Transaction(&'a RpcRequest, TransactionSummary)
 }
 
 pub enum PermissionResult {
@@ -466,11 +633,29 @@ where
         }
 
         let permission = permissions.get(&request.method).map(|v| *v).unwrap_or(Permission::Ask);
+        // User has denied access to this method, no need to go further
+        if let Permission::DenyAlways = permission {
+            return Err(RpcResponseError::new(request.id.clone(), XSWDError::PermissionDenied))
+        }
+
+        // Transactions are reviewed and verified against the application policy
+        // even if the user has always accepted this method
+        let summary = if request.method == "build_transaction" {
+            Some(self.review_transaction(app, request).await?)
+        } else {
+            None
+        };
+
         match permission {
             // Request permission from user
             Permission::Ask => {
+                let request_permission = match summary {
+                    Some(summary) => PermissionRequest::Transaction(request, summary),
+                    None => PermissionRequest::Request(request)
+                };
+
                 let result = self.handler.get_data()
-                .request_permission(app, PermissionRequest::Request(request)).await
+                .request_permission(app, request_permission).await
                 .map_err(|err| RpcResponseError::new(request.id.clone(), InternalRpcError::CustomAny(0, err)))?;
 
                 match result {
@@ -478,12 +663,14 @@ where
                     PermissionResult::Deny => Err(RpcResponseError::new(request.id.clone(), XSWDError::PermissionDenied)),
                     PermissionResult::AlwaysAllow => {
                         permissions.insert(request.method.clone(), Permission::AcceptAlways);
+                        self.remember_permission(app, &request.method, Permission::AcceptAlways).await;
                         Ok(())
                     },
                     PermissionResult::AlwaysDeny => {
-                        permissions.insert(request.method.clone(), Permission::AcceptAlways);
+                        permissions.insert(request.method.clone(), Permission::DenyAlways);
+                        self.remember_permission(app, &request.method, Permission::DenyAlways).await;
                         Err(RpcResponseError::new(request.id.clone(), XSWDError::PermissionDenied))
-                    }   
+                    }
                 }
             }
             // User has already accepted this method
@@ -493,9 +680,67 @@ where
         }
     }
 
+    // Build the summary of the transaction requested
+    // and reject it if it exceeds the spending limits set for the application
+    async fn review_transaction(&self, app: &AppStateShared, request: &RpcRequest) -> Result<TransactionSummary, RpcResponseError> {
+        let value = request.params.clone().ok_or_else(|| RpcResponseError::new(request.id.clone(), InternalRpcError::ExpectedParams))?;
+        let params: BuildTransactionParams = serde_json::from_value(value)
+            .map_err(|e| RpcResponseError::new(request.id.clone(), InternalRpcError::InvalidJSONParams(e)))?;
+
+        let wallet = self.handler.get_data();
+        let summary = wallet.get_transaction_summary(&params).await
+            .map_err(|err| RpcResponseError::new(request.id.clone(), InternalRpcError::CustomAny(0, err)))?;
+
+        let trusted = wallet.get_trusted_application(&app.id).await
+            .map_err(|err| RpcResponseError::new(request.id.clone(), InternalRpcError::CustomAny(0, err)))?;
+
+        if let Some(trusted) = trusted {
+            if let Some((asset, limit)) = summary.find_exceeded_limit(trusted.get_spending_limits()) {
+                info!("Transaction from {} rejected: spending limit of {} for asset {} exceeded", app.name, limit, asset);
+                return Err(RpcResponseError::new(request.id.clone(), XSWDError::SpendingLimitExceeded))
+            }
+        }
+
+        Ok(summary)
+    }
+
+    // Save the permission in the wallet so it is restored on the next connections of the application
+    async fn remember_permission(&self, app: &AppStateShared, method: &String, permission: Permission) {
+        let wallet = self.handler.get_data();
+        let mut trusted = match wallet.get_trusted_application(&app.id).await {
+            Ok(Some(trusted)) => trusted,
+            Ok(None) => TrustedApplication::new(app.id.clone(), app.name.clone(), app.url.clone()),
+            Err(e) => {
+                error!("Error while retrieving trusted application {}: {}", app.id, e);
+                return;
+            }
+        };
+
+        trusted.set_permission(method.clone(), permission, get_current_time_in_seconds() + XSWD_PERMISSION_EXPIRATION);
+        if let Err(e) = wallet.save_trusted_application(&trusted).await {
+            error!("Error while saving permission for application {}: {}", app.id, e);
+        }
+    }
+
+    // Retrieve the connected application with this ID
+    pub async fn get_application_with_id(&self, id: &String) -> Option<AppStateShared> {
+        let applications = self.applications.read().await;
+        applications.values().find(|app| app.get_id() == id).cloned()
+    }
+
+    // Reset the permissions of the connected application with this ID
+    // Used when the user revoke a trusted application
+    pub async fn revoke_application_permissions(&self, id: &String) {
+        let applications = self.applications.read().await;
+        for app in applications.values().filter(|app| app.get_id() == id) {
+            let mut permissions = app.permissions.lock().await;
+            permissions.clear();
+        }
+    }
+
     async fn add_application(&self, session: &WebSocketSessionShared<Self>, message: &[u8]) -> Result<Value, RpcResponseError> {
         // Application is not registered, register it
-        let app_data: ApplicationData = serde_json::from_slice::<ApplicationData>(&message)
+        let mut app_data: ApplicationData = serde_json::from_slice::<ApplicationData>(&message)
             .map_err(|_| RpcResponseError::new(None, XSWDError::InvalidApplicationData))?;
         // Sanity check
         {
@@ -536,11 +781,6 @@ where
                 return Err(RpcResponseError::new(None, XSWDError::ApplicationPermissionsNotSigned))
             }
 
-            if app_data.signature.is_some() {
-                // TODO: verify the signature
-                return Err(RpcResponseError::new(None, XSWDError::InvalidSignatureForApplicationData))
-            }
-
             if app_data.permissions.len() > 255 {
                 return Err(RpcResponseError::new(None, XSWDError::TooManyPermissions))
             }
@@ -549,16 +789,15 @@ where
         let wallet = self.handler.get_data();
         // Verify the signature of the app data to validate permissions previously set
         if let Some(signature) = &app_data.signature {
+            // signature is not included in the serialized data
             let bytes = app_data.to_bytes();
-            // remove signature bytes for verification
-            let bytes = &bytes[0..bytes.len() - SIGNATURE_SIZE];
             let key = wallet.get_public_key().await
                 .map_err(|e| {
                     error!("error while retrieving public key: {}", e);
                     RpcResponseError::new(None, InternalRpcError::InternalError("Error while retrieving wallet public key"))
                 })?;
 
-            if signature.verify(bytes, key) {
+            if !signature.verify(&bytes, key) {
                 return Err(RpcResponseError::new(None, XSWDError::InvalidSignatureForApplicationData));
             }
         }
@@ -569,6 +808,30 @@ where
         }
 
         let has_signature = app_data.signature.is_some();
+        // Restore the permissions remembered for this application
+        if has_signature {
+            let trusted = wallet.get_trusted_application(&app_data.id).await
+                .map_err(|e| {
+                    error!("error while retrieving trusted application: {}", e);
+                    RpcResponseError::new(None, InternalRpcError::InternalError("Error while retrieving application permissions"))
+                })?;
+
+            if let Some(trusted) = trusted {
+                app_data.permissions.extend(trusted.get_valid_permissions(get_current_time_in_seconds()));
+            }
+        }
+
+        // Identity of the application (without permissions) to sign once accepted
+        // The application can send the signature back on its next connections to restore its permissions
+        let identity = ApplicationData {
+            id: app_data.id.clone(),
+            name: app_data.name.clone(),
+            description: app_data.description.clone(),
+            url: app_data.url.clone(),
+            permissions: HashMap::new(),
+            signature: None
+        }.to_bytes();
+
         let state = Arc::new(AppState::new(app_data));
         {
             let mut applications = self.applications.write().await;
@@ -596,12 +859,19 @@ where
             return Err(RpcResponseError::new(None, XSWDError::PermissionDenied))
         }
 
+        let signature = wallet.sign_application_data(&identity).await
+            .map_err(|e| {
+                error!("error while signing application data: {}", e);
+                RpcResponseError::new(None, InternalRpcError::InternalError("Error while signing application data"))
+            })?;
+
         Ok(json!({
             "jsonrpc": "2.0",
             "id": Value::Null,
             "result": {
                 "message": "Application has been registered",
-                "success": true
+                "success": true,
+                "signature": signature
             }
         }))
     }
@@ -764,3 +1034,43 @@ where
     let response = server.handle_connection(request, body).await?;
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use parl_common::{config::PARL_ASSET, serializer::Serializer};
+    use super::{Permission, TrustedApplication};
+
+    #[test]
+    fn test_trusted_application_serialization() {
+        let mut app = TrustedApplication::new("0".repeat(64), "Test".to_owned(), Some("https://parl.example".to_owned()));
+        app.set_permission("get_balance".to_owned(), Permission::AcceptAlways, 100);
+        app.set_permission("build_transaction".to_owned(), Permission::DenyAlways, 200);
+        app.set_spending_limit(PARL_ASSET, 1000);
+
+        let bytes = app.to_bytes();
+        assert_eq!(bytes.len(), app.size());
+
+        let deserialized = TrustedApplication::from_bytes(&bytes).unwrap();
+        assert_eq!(deserialized.get_id(), app.get_id());
+        assert_eq!(deserialized.get_url(), app.get_url());
+        assert_eq!(deserialized.get_permissions().len(), 2);
+        assert_eq!(deserialized.get_spending_limits().get(&PARL_ASSET), Some(&1000));
+    }
+
+    #[test]
+    fn test_trusted_application_expiration() {
+        let mut app = TrustedApplication::new("0".repeat(64), "Test".to_owned(), None);
+        app.set_permission("get_balance".to_owned(), Permission::AcceptAlways, 100);
+        app.set_permission("get_address".to_owned(), Permission::AcceptAlways, 200);
+
+        let permissions = app.get_valid_permissions(150);
+        assert_eq!(permissions.len(), 1);
+        assert!(permissions.contains_key("get_address"));
+        assert!(!app.is_expired(150));
+        assert!(app.is_expired(200));
+
+        // Spending limits are kept even when permissions are expired
+        app.set_spending_limit(PARL_ASSET, 1000);
+        assert!(!app.is_expired(200));
+    }
+}
@@ -3,6 +3,8 @@ use lazy_static::lazy_static;
 
 pub const DIR_PATH: &str = "wallets/";
 pub const XSWD_BIND_ADDRESS: &str = "0.0.0.0:44325";
+// Duration in seconds of a permission remembered for a XSWD application (30 days)
+pub const XSWD_PERMISSION_EXPIRATION: u64 = 30 * 24 * 60 * 60;
 pub const PASSWORD_HASH_SIZE: usize = 32;
 pub const SALT_SIZE: usize = 32;
 pub const KEY_SIZE: usize = 32;
@@ -11,6 +13,21 @@ pub const KEY_SIZE: usize = 32;
 pub const DEFAULT_DAEMON_ADDRESS: &str = "http://127.0.0.1:5800";
 // Auto reconnect interval in seconds for Network Handler
 pub const AUTO_RECONNECT_INTERVAL: u64 = 5;
+// Interval in seconds to compare the daemon used against the fallback ones
+pub const DAEMON_HEALTH_CHECK_INTERVAL: u64 = 60;
+// Maximum topoheight difference tolerated behind a fallback daemon before switching
+pub const MAX_DAEMON_TOPOHEIGHT_LAG: u64 = 10;
+
+// Timeout in seconds for a webhook request
+pub const WEBHOOK_REQUEST_TIMEOUT: u64 = 10;
+// Interval in seconds to check for webhook deliveries to retry
+pub const WEBHOOK_QUEUE_CHECK_INTERVAL: u64 = 5;
+// Delay in seconds before the first retry, doubled on each failure
+pub const WEBHOOK_RETRY_BASE_DELAY: u64 = 5;
+// Maximum delay in seconds between two retries
+pub const WEBHOOK_RETRY_MAX_DELAY: u64 = 60 * 60;
+// Maximum attempts before dropping a delivery (around one day)
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 35;
 
 lazy_static! {
     pub static ref PASSWORD_ALGORITHM: Argon2<'static> = {
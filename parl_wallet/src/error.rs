@@ -1,6 +1,9 @@
 use thiserror::Error;
 use chacha20poly1305::Error as CryptoError;
-use super::network_handler::NetworkError;
+use super::{
+    network_handler::NetworkError,
+    webhook::WebhookError
+};
 use parl_common::{
     crypto::Hash,
     rpc_server::InternalRpcError,
@@ -82,6 +85,14 @@ pub enum WalletError {
     CiphertextDecode,
     #[error(transparent)]
     AEADCipherFormatError(#[from] CipherFormatError),
+    #[error("No webhook registered for {}", _0)]
+    WebhookNotFound(String),
+    #[error(transparent)]
+    WebhookError(#[from] WebhookError),
+    #[error("No trusted application found with ID {}", _0)]
+    TrustedApplicationNotFound(String),
+    #[error("No spending limit set for asset {}", _0)]
+    SpendingLimitNotFound(Hash),
 }
 
 impl WalletError {
@@ -8,6 +8,8 @@ pub mod entry;
 pub mod mnemonics;
 pub mod transaction_builder;
 pub mod error;
+pub mod webhook;
+pub mod metrics;
 
 #[cfg(feature = "api_server")]
 pub mod api;
\ No newline at end of file
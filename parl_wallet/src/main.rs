@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     ops::ControlFlow,
     path::Path,
     sync::Arc,
@@ -48,11 +49,15 @@ use parl_common::{
         format_parl
     }
 };
-#[cfg(feature = "api_server")]
-use parl_common::utils::spawn_task;
+use parl_common::{
+    metrics::run_metrics_server,
+    utils::spawn_task
+};
 use parl_wallet::{
     wallet::Wallet,
-    config::{DEFAULT_DAEMON_ADDRESS, DIR_PATH}
+    config::{DEFAULT_DAEMON_ADDRESS, DIR_PATH},
+    metrics::encode_metrics,
+    webhook::Webhook
 };
 
 #[cfg(feature = "api_server")]
@@ -60,14 +65,17 @@ use {
     parl_wallet::{
         api::{
             AuthConfig,
+            Permission,
             PermissionResult,
-            AppStateShared
+            AppStateShared,
+            TransactionSummary
         },
         wallet::XSWDEvent,
     },
     parl_common::{
         rpc_server::RpcRequest,
-        prompt::ShareablePrompt
+        prompt::ShareablePrompt,
+        time::get_current_time_in_seconds
     },
     anyhow::Error,
     tokio::sync::mpsc::UnboundedReceiver
@@ -97,6 +105,11 @@ pub struct Config {
     /// Daemon address to use
     #[clap(long, default_value_t = String::from(DEFAULT_DAEMON_ADDRESS))]
     daemon_address: String,
+    /// Fallback daemon address to use if the main one goes down or falls behind
+    /// 
+    /// This option can be used several times to add more daemons.
+    #[clap(long)]
+    fallback_daemon_address: Vec<String>,
     /// Disable online mode
     #[clap(long)]
     offline_mode: bool,
@@ -147,6 +160,11 @@ pub struct Config {
     /// Network selected for chain
     #[clap(long, value_enum, default_value_t = Network::Mainnet)]
     network: Network,
+    /// Bind address of the metrics HTTP server (disabled by default)
+    /// 
+    /// Metrics are served in the Prometheus text format on /metrics.
+    #[clap(long)]
+    metrics_bind_address: Option<String>,
     /// RPC Server configuration
     #[cfg(feature = "api_server")]
     #[structopt(flatten)]
@@ -266,6 +284,12 @@ async fn xswd_handler(mut receiver: UnboundedReceiver<XSWDEvent>, prompt: Sharea
                 if callback.send(res).is_err() {
                     error!("Error while sending permission response back to XSWD");
                 }
+            },
+            XSWDEvent::RequestTransaction(app_state, _, summary, callback) => {
+                let res = xswd_handle_request_transaction(&prompt, app_state, summary).await;
+                if callback.send(res).is_err() {
+                    error!("Error while sending transaction response back to XSWD");
+                }
             }
         };
     }
@@ -310,13 +334,53 @@ async fn xswd_handle_request_permission(prompt: &ShareablePrompt, app_state: App
     })
 }
 
+#[cfg(feature = "api_server")]
+async fn xswd_handle_request_transaction(prompt: &ShareablePrompt, app_state: AppStateShared, summary: TransactionSummary) -> Result<PermissionResult, Error> {
+    let message = format!(
+        "XSWD: {} requests to build a transaction\r\n{}\r\nDo you want to allow this transaction ?\r\n([A]llow / [D]eny / [AA] Always Allow / [AD] Always Deny): ",
+        app_state.get_name(),
+        summary.to_string().replace("\n", "\r\n")
+    );
+
+    let answer = prompt.read_valid_str_value(prompt.colorize_string(Color::Blue, &message), vec!["a", "d", "aa", "ad"]).await?;
+    Ok(match answer.as_str() {
+        "a" => PermissionResult::Allow,
+        "d" => PermissionResult::Deny,
+        "aa" => PermissionResult::AlwaysAllow,
+        "ad" => PermissionResult::AlwaysDeny,
+        _ => unreachable!()
+    })
+}
+
 // Apply the config passed in params
 async fn apply_config(wallet: &Arc<Wallet>, #[cfg(feature = "api_server")] prompt: &ShareablePrompt) {
     let config: Config = Config::parse();
 
+    // Resume the webhooks deliveries before going online so no event is missed
+    match wallet.start_webhooks().await {
+        Ok(true) => info!("Webhooks enabled"),
+        Ok(false) => {},
+        Err(e) => error!("Error while starting webhooks: {}", e)
+    };
+
+    if let Some(bind_address) = config.metrics_bind_address {
+        let wallet = Arc::clone(wallet);
+        spawn_task("metrics", async move {
+            let res = run_metrics_server(bind_address, move || {
+                let wallet = Arc::clone(&wallet);
+                async move { encode_metrics(&wallet).await }
+            }).await;
+            if let Err(e) = res {
+                error!("Error while running the metrics server: {}", e);
+            }
+        });
+    }
+
     if !config.offline_mode {
         info!("Trying to connect to daemon at '{}'", config.daemon_address);
-        if let Err(e) = wallet.set_online_mode(&config.daemon_address, true).await {
+        let mut daemon_addresses = vec![config.daemon_address];
+        daemon_addresses.extend(config.fallback_daemon_address);
+        if let Err(e) = wallet.set_online_mode_with_endpoints(daemon_addresses, true).await {
             error!("Couldn't connect to daemon: {}", e);
             info!("You can activate online mode using 'online_mode [daemon_address]'");
         } else {
@@ -375,10 +439,17 @@ async fn setup_wallet_command_manager(wallet: Arc<Wallet>, command_manager: &Com
     command_manager.add_command(Command::with_optional_arguments("history", "Show all your transactions", vec![Arg::new("page", ArgType::Number)], CommandHandler::Async(async_handler!(history))))?;
     command_manager.add_command(Command::with_optional_arguments("online_mode", "Set your wallet in online mode", vec![Arg::new("daemon_address", ArgType::String)], CommandHandler::Async(async_handler!(online_mode))))?;
     command_manager.add_command(Command::new("offline_mode", "Set your wallet in offline mode", CommandHandler::Async(async_handler!(offline_mode))))?;
+    command_manager.add_command(Command::new("daemon", "Show the daemon used and all daemons configured", CommandHandler::Async(async_handler!(daemon))))?;
     command_manager.add_command(Command::with_optional_arguments("rescan", "Rescan balance and transactions", vec![Arg::new("topoheight", ArgType::Number)], CommandHandler::Async(async_handler!(rescan))))?;
     command_manager.add_command(Command::with_optional_arguments("seed", "Show seed of selected language", vec![Arg::new("language", ArgType::Number)], CommandHandler::Async(async_handler!(seed))))?;
     command_manager.add_command(Command::new("nonce", "Show current nonce", CommandHandler::Async(async_handler!(nonce))))?;
     command_manager.add_command(Command::new("set_nonce", "Set new nonce", CommandHandler::Async(async_handler!(set_nonce))))?;
+    command_manager.add_command(Command::with_required_arguments("add_webhook", "Send all wallet events to a webhook URL", vec![Arg::new("url", ArgType::String), Arg::new("secret", ArgType::String)], CommandHandler::Async(async_handler!(add_webhook))))?;
+    command_manager.add_command(Command::with_required_arguments("remove_webhook", "Delete a webhook and its pending notifications", vec![Arg::new("url", ArgType::String)], CommandHandler::Async(async_handler!(remove_webhook))))?;
+    command_manager.add_command(Command::new("list_webhooks", "Show all registered webhooks", CommandHandler::Async(async_handler!(list_webhooks))))?;
+    command_manager.add_command(Command::with_required_arguments("add_contact", "Save a label for an address in the address book", vec![Arg::new("address", ArgType::String), Arg::new("label", ArgType::String)], CommandHandler::Async(async_handler!(add_contact))))?;
+    command_manager.add_command(Command::with_required_arguments("remove_contact", "Delete an address from the address book", vec![Arg::new("address", ArgType::String)], CommandHandler::Async(async_handler!(remove_contact))))?;
+    command_manager.add_command(Command::new("list_contacts", "Show all addresses saved in the address book", CommandHandler::Async(async_handler!(list_contacts))))?;
 
     #[cfg(feature = "api_server")]
     {
@@ -393,6 +464,16 @@ async fn setup_wallet_command_manager(wallet: Arc<Wallet>, command_manager: &Com
 
         // Stop API Server (RPC or XSWD)
         command_manager.add_command(Command::new("stop_api_server", "Stop the API (XSWD/RPC) Server", CommandHandler::Async(async_handler!(stop_api_server))))?;
+
+        // XSWD applications with remembered permissions
+        command_manager.add_command(Command::new("list_trusted_apps", "Show all XSWD applications with remembered permissions", CommandHandler::Async(async_handler!(list_trusted_apps))))?;
+        command_manager.add_command(Command::with_required_arguments("revoke_trusted_app", "Revoke all remembered permissions of a XSWD application", vec![Arg::new("id", ArgType::String)], CommandHandler::Async(async_handler!(revoke_trusted_app))))?;
+        command_manager.add_command(Command::with_required_arguments("set_spending_limit", "Set the maximum amount of an asset a XSWD application can spend in one transaction", vec![
+            Arg::new("id", ArgType::String),
+            Arg::new("asset", ArgType::Hash),
+            Arg::new("amount", ArgType::Number)
+        ], CommandHandler::Async(async_handler!(set_spending_limit))))?;
+        command_manager.add_command(Command::with_required_arguments("remove_spending_limit", "Remove the spending limit of an asset for a XSWD application", vec![Arg::new("id", ArgType::String), Arg::new("asset", ArgType::Hash)], CommandHandler::Async(async_handler!(remove_spending_limit))))?;
     }
 
     let mut context = command_manager.get_context().lock()?;
@@ -425,7 +506,15 @@ async fn prompt_message_builder(prompt: &Prompt, command_manager: Option<&Comman
                 prompt.colorize_string(Color::Green, &format_parl(storage.get_plaintext_balance_for(&PARL_ASSET).await.unwrap_or(0))),
             );
             let status = if wallet.is_online().await {
-                prompt.colorize_str(Color::Green, "Online")
+                // show the node used, it may change on a failover
+                match wallet.get_daemon_endpoint().await {
+                    Some(endpoint) => format!(
+                        "{} ({})",
+                        prompt.colorize_str(Color::Green, "Online"),
+                        prompt.colorize_string(Color::BrightBlack, &endpoint)
+                    ),
+                    None => prompt.colorize_str(Color::Green, "Online")
+                }
             } else {
                 prompt.colorize_str(Color::Red, "Offline")
             };
@@ -858,6 +947,128 @@ async fn online_mode(manager: &CommandManager, mut arguments: ArgumentManager) -
     Ok(())
 }
 
+// Register a webhook receiving all wallet events
+async fn add_webhook(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let url = arguments.get_value("url")?.to_string_value()?;
+    let secret = arguments.get_value("secret")?.to_string_value()?;
+    let webhook = Webhook::new(url, secret, HashSet::new()).context("Invalid webhook")?;
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    wallet.add_webhook(webhook).await.context("Error while adding webhook")?;
+    manager.message("Webhook added");
+    Ok(())
+}
+
+// Delete a webhook
+async fn remove_webhook(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let url = arguments.get_value("url")?.to_string_value()?;
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    wallet.remove_webhook(&url).await.context("Error while removing webhook")?;
+    manager.message("Webhook removed");
+    Ok(())
+}
+
+// List all webhooks with the pending notifications
+async fn list_webhooks(manager: &CommandManager, _: ArgumentManager) -> Result<(), CommandError> {
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let storage = wallet.get_storage().read().await;
+    let webhooks = storage.get_webhooks().context("Error while retrieving webhooks")?;
+    if webhooks.is_empty() {
+        manager.message("No webhook registered");
+        return Ok(())
+    }
+
+    let deliveries = storage.get_webhook_deliveries().context("Error while retrieving webhook deliveries")?;
+    manager.message(format!("Webhooks ({}):", webhooks.len()));
+    for webhook in webhooks {
+        let pending = deliveries.iter().filter(|d| d.get_url() == webhook.get_url()).count();
+        manager.message(format!("- {} ({} pending)", webhook.get_url(), pending));
+    }
+    Ok(())
+}
+
+// Save a label for an address in the address book
+async fn add_contact(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let address = arguments.get_value("address")?.to_string_value()?;
+    let label = arguments.get_value("label")?.to_string_value()?;
+    let address = Address::from_string(&address).context("Invalid address")?;
+    if label.is_empty() || label.len() > u8::MAX as usize {
+        return Err(CommandError::InvalidArgument("Label must be between 1 and 255 bytes".to_owned()))
+    }
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let mut storage = wallet.get_storage().write().await;
+    storage.set_contact(address.get_public_key(), &label).context("Error while saving contact")?;
+    manager.message(format!("Contact {} saved", label));
+    Ok(())
+}
+
+// Delete an address from the address book
+async fn remove_contact(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let address = arguments.get_value("address")?.to_string_value()?;
+    let address = Address::from_string(&address).context("Invalid address")?;
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let mut storage = wallet.get_storage().write().await;
+    if storage.remove_contact(address.get_public_key()).context("Error while removing contact")? {
+        manager.message("Contact removed");
+    } else {
+        manager.warn("No contact found for this address");
+    }
+    Ok(())
+}
+
+// List all addresses saved in the address book
+async fn list_contacts(manager: &CommandManager, _: ArgumentManager) -> Result<(), CommandError> {
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let storage = wallet.get_storage().read().await;
+    let contacts = storage.get_contacts().context("Error while retrieving contacts")?;
+    if contacts.is_empty() {
+        manager.message("No contact saved");
+        return Ok(())
+    }
+
+    let mainnet = wallet.get_network().is_mainnet();
+    manager.message(format!("Contacts ({}):", contacts.len()));
+    for (key, label) in contacts {
+        manager.message(format!("- {}: {}", label, key.as_address(mainnet)));
+    }
+    Ok(())
+}
+
+// Show the daemon currently used and the fallback ones
+async fn daemon(manager: &CommandManager, _: ArgumentManager) -> Result<(), CommandError> {
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let network_handler = wallet.get_network_handler().await.lock().await;
+    if let Some(network_handler) = network_handler.as_ref() {
+        let active = network_handler.get_active_endpoint().await;
+        match active.as_ref() {
+            Some(endpoint) => manager.message(format!("Active daemon: {}", endpoint)),
+            None => manager.message("Active daemon: shared API")
+        };
+
+        let endpoints = network_handler.get_endpoints();
+        if endpoints.len() > 1 {
+            manager.message(format!("Daemons configured ({}):", endpoints.len()));
+            for endpoint in endpoints {
+                let status = if active.as_ref() == Some(endpoint) { " (active)" } else { "" };
+                manager.message(format!("- {}{}", endpoint, status));
+            }
+        }
+    } else {
+        manager.error("Wallet is offline");
+    }
+    Ok(())
+}
+
 // Set your wallet in offline mode
 async fn offline_mode(manager: &CommandManager, _: ArgumentManager) -> Result<(), CommandError> {
     let context = manager.get_context().lock()?;
@@ -981,6 +1192,90 @@ async fn start_xswd(manager: &CommandManager, _: ArgumentManager) -> Result<(),
     Ok(())
 }
 
+// List all XSWD applications with their remembered permissions
+#[cfg(feature = "api_server")]
+async fn list_trusted_apps(manager: &CommandManager, _: ArgumentManager) -> Result<(), CommandError> {
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let storage = wallet.get_storage().read().await;
+    let applications = storage.get_trusted_applications().context("Error while retrieving trusted applications")?;
+    if applications.is_empty() {
+        manager.message("No trusted application");
+        return Ok(())
+    }
+
+    let now = get_current_time_in_seconds();
+    manager.message(format!("Trusted applications ({}):", applications.len()));
+    for app in applications {
+        let url = app.get_url().as_ref().map(String::as_str).unwrap_or("no URL");
+        manager.message(format!("- {} ({}) [{}]", app.get_name(), url, app.get_id()));
+        for (method, (permission, expire_at)) in app.get_permissions() {
+            let permission = match permission {
+                Permission::AcceptAlways => "always allowed",
+                Permission::DenyAlways => "always denied",
+                Permission::Ask => "ask"
+            };
+
+            if *expire_at > now {
+                let days = (expire_at - now) / (24 * 60 * 60);
+                manager.message(format!("  {}: {} (expires in {} days)", method, permission, days));
+            } else {
+                manager.message(format!("  {}: {} (expired)", method, permission));
+            }
+        }
+
+        for (asset, limit) in app.get_spending_limits() {
+            let decimals = storage.get_asset_decimals(asset).unwrap_or(COIN_DECIMALS);
+            manager.message(format!("  spending limit: {} of {}", format_coin(*limit, decimals), asset));
+        }
+    }
+    Ok(())
+}
+
+// Set the maximum amount of an asset a XSWD application can spend in one transaction
+#[cfg(feature = "api_server")]
+async fn set_spending_limit(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let id = arguments.get_value("id")?.to_string_value()?;
+    let asset = arguments.get_value("asset")?.to_hash()?;
+    let amount = arguments.get_value("amount")?.to_number()?;
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    let decimals = {
+        let storage = wallet.get_storage().read().await;
+        storage.get_asset_decimals(&asset).unwrap_or(COIN_DECIMALS)
+    };
+
+    wallet.set_spending_limit(&id, asset.clone(), amount).await.context("Error while setting spending limit")?;
+    manager.message(format!("Spending limit set to {} of {}", format_coin(amount, decimals), asset));
+    Ok(())
+}
+
+// Remove the spending limit of an asset for a XSWD application
+#[cfg(feature = "api_server")]
+async fn remove_spending_limit(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let id = arguments.get_value("id")?.to_string_value()?;
+    let asset = arguments.get_value("asset")?.to_hash()?;
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    wallet.remove_spending_limit(&id, &asset).await.context("Error while removing spending limit")?;
+    manager.message("Spending limit removed");
+    Ok(())
+}
+
+// Revoke all remembered permissions of a XSWD application
+#[cfg(feature = "api_server")]
+async fn revoke_trusted_app(manager: &CommandManager, mut arguments: ArgumentManager) -> Result<(), CommandError> {
+    let id = arguments.get_value("id")?.to_string_value()?;
+
+    let context = manager.get_context().lock()?;
+    let wallet: &Arc<Wallet> = context.get()?;
+    wallet.revoke_trusted_application(&id).await.context("Error while revoking trusted application")?;
+    manager.message("Trusted application revoked");
+    Ok(())
+}
+
 // broadcast tx if possible
 // submit_transaction increase the local nonce in storage in case of success
 async fn broadcast_tx(wallet: &Wallet, manager: &CommandManager, tx: Transaction) {
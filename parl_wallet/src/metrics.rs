@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use parl_common::metrics::MetricsEncoder;
+#[cfg(feature = "api_server")]
+use parl_common::rpc_server::WebSocketServerHandler;
+#[cfg(feature = "api_server")]
+use crate::api::APIServer;
+use crate::wallet::Wallet;
+
+// Sync state of the network handler exposed in the wallet metrics
+#[derive(Default)]
+pub struct WalletMetrics {
+    // topoheight of the daemon at the last sync
+    daemon_topoheight: AtomicU64,
+    syncing: AtomicBool,
+    // blocks processed since the current sync or rescan started
+    sync_processed_blocks: AtomicU64
+}
+
+impl WalletMetrics {
+    pub fn start_sync(&self, daemon_topoheight: u64) {
+        self.daemon_topoheight.store(daemon_topoheight, Ordering::Relaxed);
+        self.sync_processed_blocks.store(0, Ordering::Relaxed);
+        self.syncing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn end_sync(&self) {
+        self.syncing.store(false, Ordering::Relaxed);
+    }
+
+    pub fn set_daemon_topoheight(&self, topoheight: u64) {
+        self.daemon_topoheight.store(topoheight, Ordering::Relaxed);
+    }
+
+    pub fn on_block_processed(&self) {
+        self.sync_processed_blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_daemon_topoheight(&self) -> u64 {
+        self.daemon_topoheight.load(Ordering::Relaxed)
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.syncing.load(Ordering::Relaxed)
+    }
+
+    pub fn get_sync_processed_blocks(&self) -> u64 {
+        self.sync_processed_blocks.load(Ordering::Relaxed)
+    }
+}
+
+// Build the metrics of the wallet in the Prometheus text format
+pub async fn encode_metrics(wallet: &Wallet) -> String {
+    let mut encoder = MetricsEncoder::new();
+    let metrics = wallet.get_metrics();
+
+    let (synced_topoheight, pending_transactions) = {
+        let storage = wallet.get_storage().read().await;
+        let synced_topoheight = storage.get_synced_topoheight().unwrap_or(0);
+        // each transaction not yet confirmed increased the unconfirmed nonce
+        let pending = storage.get_unconfirmed_nonce().saturating_sub(storage.get_nonce().unwrap_or(0));
+        (synced_topoheight, pending)
+    };
+    let daemon_topoheight = metrics.get_daemon_topoheight();
+
+    encoder.gauge("parl_wallet_online", "1 if the wallet is connected to a daemon", wallet.is_online().await as u8);
+    encoder.gauge("parl_wallet_synced_topoheight", "Topoheight synced by the wallet", synced_topoheight);
+    encoder.gauge("parl_wallet_daemon_topoheight", "Topoheight of the daemon at the last sync", daemon_topoheight);
+    encoder.gauge("parl_wallet_sync_lag_blocks", "Blocks the wallet is behind the daemon", daemon_topoheight.saturating_sub(synced_topoheight));
+    encoder.gauge("parl_wallet_syncing", "1 if a sync or rescan is in progress", metrics.is_syncing() as u8);
+    encoder.gauge("parl_wallet_sync_processed_blocks", "Blocks processed by the current or last sync", metrics.get_sync_processed_blocks());
+    encoder.gauge("parl_wallet_pending_transactions", "Transactions created but not confirmed yet", pending_transactions);
+
+    #[cfg(feature = "api_server")]
+    {
+        let sessions = match wallet.get_api_server().lock().await.as_ref() {
+            Some(APIServer::RPCServer(server)) => server.get_websocket().count_connections().await,
+            Some(APIServer::XSWD(xswd)) => xswd.count_sessions().await,
+            None => 0
+        };
+        encoder.gauge("parl_wallet_api_sessions", "WebSocket sessions opened on the API server", sessions);
+    }
+
+    encoder.finish()
+}
@@ -8,8 +8,12 @@ use std::{
 };
 use thiserror::Error;
 use anyhow::Error;
-use log::{debug, error, trace, warn};
-use tokio::{sync::Mutex, task::JoinHandle, time::sleep};
+use log::{debug, error, info, trace, warn};
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+    time::{interval_at, sleep, Instant}
+};
 use parl_common::{
     account::CiphertextCache,
     api::{
@@ -27,12 +31,17 @@ use parl_common::{
         Address,
         Hash
     },
+    network::Network,
     serializer::Serializer,
     transaction::Role,
-    utils::{sanitize_daemon_address, spawn_task}
+    utils::{is_ipc_address, sanitize_daemon_address, spawn_task}
 };
 use crate::{
-    config::AUTO_RECONNECT_INTERVAL,
+    config::{
+        AUTO_RECONNECT_INTERVAL,
+        DAEMON_HEALTH_CHECK_INTERVAL,
+        MAX_DAEMON_TOPOHEIGHT_LAG
+    },
     daemon_api::DaemonAPI,
     entry::{
         EntryData,
@@ -60,7 +69,22 @@ pub enum NetworkError {
     #[error(transparent)]
     DaemonAPIError(#[from] Error),
     #[error("Network mismatch")]
-    NetworkMismatch
+    NetworkMismatch,
+    #[error("No healthy daemon available")]
+    NoHealthyDaemon,
+    #[error("Connection with daemon {} was lost", _0)]
+    ConnectionLost(String),
+    #[error("Daemon {} is {} blocks behind {}", _0, _1, _2)]
+    DaemonLagging(String, u64, String)
+}
+
+// Health state of a daemon endpoint
+#[derive(Debug, Clone)]
+pub struct DaemonHealth {
+    // Version returned by the daemon
+    pub version: String,
+    // Current topoheight of the daemon
+    pub topoheight: u64
 }
 
 pub struct NetworkHandler {
@@ -71,16 +95,46 @@ pub struct NetworkHandler {
     // api to communicate with daemon
     // It is behind a Arc to be shared across several wallets
     // in case someone make a custom service and don't want to create a new connection
-    api: Arc<DaemonAPI>
+    // The RwLock allows to swap it when we switch to another daemon
+    api: RwLock<Arc<DaemonAPI>>,
+    // All daemon addresses that can be used
+    // Empty if the handler was created with an already created daemon API
+    endpoints: Vec<String>,
+    // Daemon address currently used
+    active_endpoint: RwLock<Option<String>>
 }
 
 impl NetworkHandler {
     // Create a new network handler with a wallet and a daemon address
     // This will create itself a DaemonAPI and verify if connection is possible
     pub async fn new<S: ToString>(wallet: Arc<Wallet>, daemon_address: S) -> Result<SharedNetworkHandler, Error> {
-        let s = daemon_address.to_string();
-        let api = DaemonAPI::new(format!("{}/json_rpc", sanitize_daemon_address(s.as_str()))).await?;
-        Self::with_api(wallet, Arc::new(api)).await
+        Self::with_endpoints(wallet, vec![daemon_address.to_string()]).await
+    }
+
+    // Create a new network handler with a wallet and several daemon addresses
+    // The healthiest daemon is selected, others are used as fallback
+    // if the connection is lost or if the selected daemon falls behind
+    pub async fn with_endpoints(wallet: Arc<Wallet>, endpoints: Vec<String>) -> Result<SharedNetworkHandler, Error> {
+        let (endpoint, api) = if endpoints.len() == 1 {
+            // Keep the real error if we only have one daemon
+            let endpoint = endpoints[0].clone();
+            let (api, health) = Self::connect_to_daemon(wallet.get_network(), &endpoint).await?;
+            debug!("Connected to daemon {} running version {}", endpoint, health.version);
+            (endpoint, api)
+        } else {
+            let (endpoint, api, health) = Self::select_healthiest_daemon(wallet.get_network(), &endpoints).await
+                .ok_or(NetworkError::NoHealthyDaemon)?;
+            debug!("Connected to daemon {} running version {} at topoheight {}", endpoint, health.version, health.topoheight);
+            (endpoint, api)
+        };
+
+        Ok(Arc::new(Self {
+            task: Mutex::new(None),
+            wallet,
+            api: RwLock::new(Arc::new(api)),
+            endpoints,
+            active_endpoint: RwLock::new(Some(endpoint))
+        }))
     }
 
     // Create a new network handler with an already created daemon API
@@ -92,10 +146,141 @@ impl NetworkHandler {
         Ok(Arc::new(Self {
             task: Mutex::new(None),
             wallet,
-            api
+            api: RwLock::new(api),
+            endpoints: Vec::new(),
+            active_endpoint: RwLock::new(None)
         }))
     }
 
+    // Connect to a daemon and verify that it is usable by the wallet
+    async fn connect_to_daemon(network: &Network, daemon_address: &str) -> Result<(DaemonAPI, DaemonHealth), Error> {
+        // the IPC socket only serves the JSON-RPC endpoint
+        let target = if is_ipc_address(daemon_address) {
+            daemon_address.to_owned()
+        } else {
+            format!("{}/json_rpc", sanitize_daemon_address(daemon_address))
+        };
+        let api = DaemonAPI::new(target).await?;
+        match Self::check_daemon_health(network, &api).await {
+            Ok(health) => Ok((api, health)),
+            Err(e) => {
+                if let Err(e) = api.disconnect().await {
+                    debug!("Error while closing websocket connection: {}", e);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // Verify the daemon version, network and retrieve its topoheight
+    async fn check_daemon_health(network: &Network, api: &DaemonAPI) -> Result<DaemonHealth, Error> {
+        let version = api.get_version().await?;
+        let info = api.get_info().await?;
+        if info.network != *network {
+            return Err(NetworkError::NetworkMismatch.into())
+        }
+
+        Ok(DaemonHealth {
+            version,
+            topoheight: info.topoheight
+        })
+    }
+
+    // Connect to all daemons and keep the one with the highest topoheight
+    // Connections to the other daemons are closed
+    async fn select_healthiest_daemon(network: &Network, endpoints: &[String]) -> Option<(String, DaemonAPI, DaemonHealth)> {
+        let mut best: Option<(String, DaemonAPI, DaemonHealth)> = None;
+        for endpoint in endpoints {
+            let (api, health) = match Self::connect_to_daemon(network, endpoint).await {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Daemon {} is not healthy: {}", endpoint, e);
+                    continue;
+                }
+            };
+            trace!("Daemon {} is at topoheight {}", endpoint, health.topoheight);
+
+            let replace = best.as_ref().map(|(_, _, h)| health.topoheight > h.topoheight).unwrap_or(true);
+            let unused = if replace {
+                best.replace((endpoint.clone(), api, health)).map(|(_, api, _)| api)
+            } else {
+                Some(api)
+            };
+
+            if let Some(api) = unused {
+                if let Err(e) = api.disconnect().await {
+                    debug!("Error while closing websocket connection: {}", e);
+                }
+            }
+        }
+
+        best
+    }
+
+    // Switch to the healthiest daemon available
+    // Current daemon is also a candidate in case it is back online
+    // Wallet state above the common point with the new daemon will be rolled back on next sync
+    // Returns true if we are now connected to a different daemon
+    async fn switch_daemon(&self) -> Result<bool, NetworkError> {
+        let (endpoint, api, health) = Self::select_healthiest_daemon(self.wallet.get_network(), &self.endpoints).await
+            .ok_or(NetworkError::NoHealthyDaemon)?;
+
+        let previous = {
+            let mut active = self.active_endpoint.write().await;
+            active.replace(endpoint.clone())
+        };
+
+        let old_api = {
+            let mut lock = self.api.write().await;
+            std::mem::replace(&mut *lock, Arc::new(api))
+        };
+
+        if let Err(e) = old_api.disconnect().await {
+            debug!("Error while closing websocket connection: {}", e);
+        }
+
+        let switched = previous.as_ref() != Some(&endpoint);
+        if switched {
+            info!("Switched to daemon {} running version {} at topoheight {}", endpoint, health.version, health.topoheight);
+        }
+
+        Ok(switched)
+    }
+
+    // Verify that the current daemon is not falling behind the others
+    async fn check_daemon_lag(&self) -> Result<(), NetworkError> {
+        let Some(active) = self.get_active_endpoint().await else {
+            return Ok(())
+        };
+
+        let info = self.get_api().await.get_info().await?;
+        for endpoint in self.endpoints.iter().filter(|e| **e != active) {
+            let (api, health) = match Self::connect_to_daemon(self.wallet.get_network(), endpoint).await {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!("Daemon {} is not healthy: {}", endpoint, e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = api.disconnect().await {
+                debug!("Error while closing websocket connection: {}", e);
+            }
+
+            let lag = health.topoheight.saturating_sub(info.topoheight);
+            if lag > MAX_DAEMON_TOPOHEIGHT_LAG {
+                return Err(NetworkError::DaemonLagging(active, lag, endpoint.clone()))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check if we have other daemons to switch to
+    pub fn has_fallback_daemons(&self) -> bool {
+        self.endpoints.len() > 1
+    }
+
     // Start the internal loop to sync all missed blocks and all newly added blocks
     pub async fn start(self: &Arc<Self>, auto_reconnect: bool) -> Result<(), NetworkError> {
         trace!("Starting network handler");
@@ -104,11 +289,17 @@ impl NetworkHandler {
             return Err(NetworkError::AlreadyRunning)
         }
 
-        if !self.api.is_online() {
+        let api = self.get_api().await;
+        if !api.is_online() {
             debug!("API is offline, trying to reconnect");
-            if !self.api.reconnect().await? {
-                error!("Couldn't reconnect to server");
-                return Err(NetworkError::NotRunning)
+            if !api.reconnect().await? {
+                if !self.has_fallback_daemons() {
+                    error!("Couldn't reconnect to server");
+                    return Err(NetworkError::NotRunning)
+                }
+
+                warn!("Couldn't reconnect to server, switching to another daemon");
+                self.switch_daemon().await?;
             }
         }
 
@@ -123,17 +314,38 @@ impl NetworkHandler {
                 // Notify that we are offline
                 zelf.wallet.propagate_event(Event::Offline).await;
 
+                let api = zelf.get_api().await;
                 if !auto_reconnect {
                     // Turn off the websocket connection
-                    if let Err(e) = zelf.api.disconnect().await {
+                    if let Err(e) = api.disconnect().await {
                         debug!("Error while closing websocket connection: {}", e);
                     }
 
                     break res;
+                } else if zelf.has_fallback_daemons() {
+                    // Switch to the healthiest daemon, it may be the same one if its still the best
+                    match zelf.switch_daemon().await {
+                        Ok(switched) => {
+                            if switched {
+                                // Unconfirmed state was built against the previous daemon mempool
+                                // and may never be included by the new one
+                                let mut storage = zelf.wallet.get_storage().write().await;
+                                storage.delete_unconfirmed_balances().await?;
+                                storage.clear_tx_cache();
+                            }
+
+                            // Notify that we are back online
+                            zelf.wallet.propagate_event(Event::Online).await;
+                        },
+                        Err(e) => {
+                            error!("Couldn't switch to another daemon: {}, trying again in {} seconds", e, AUTO_RECONNECT_INTERVAL);
+                            sleep(Duration::from_secs(AUTO_RECONNECT_INTERVAL)).await;
+                        }
+                    }
                 } else {
-                    if !zelf.api.is_online() {
+                    if !api.is_online() {
                         debug!("API is offline, trying to reconnect");
-                        if !zelf.api.reconnect().await? {
+                        if !api.reconnect().await? {
                             error!("Couldn't reconnect to server, trying again in {} seconds", AUTO_RECONNECT_INTERVAL);
                             sleep(Duration::from_secs(AUTO_RECONNECT_INTERVAL)).await;
                         } else {
@@ -170,7 +382,7 @@ impl NetworkHandler {
             }
 
             // Turn off the websocket connection
-            if let Err(e) = self.api.disconnect().await {
+            if let Err(e) = self.get_api().await.disconnect().await {
                 debug!("Error while closing websocket connection: {}", e);
             }
 
@@ -181,15 +393,26 @@ impl NetworkHandler {
     }
 
     // Retrieve the daemon API used
-    pub fn get_api(&self) -> &DaemonAPI {
-        &self.api
+    pub async fn get_api(&self) -> Arc<DaemonAPI> {
+        Arc::clone(&*self.api.read().await)
+    }
+
+    // Retrieve the daemon address currently used
+    // None if the handler was created with an already created daemon API
+    pub async fn get_active_endpoint(&self) -> Option<String> {
+        self.active_endpoint.read().await.clone()
+    }
+
+    // Retrieve all daemon addresses configured
+    pub fn get_endpoints(&self) -> &[String] {
+        &self.endpoints
     }
 
     // check if the network handler is running (that we have a task and its not finished)
     pub async fn is_running(&self) -> bool {
         let task = self.task.lock().await;
         if let Some(handle) = task.as_ref() {
-            !handle.is_finished() && self.api.is_online()
+            !handle.is_finished() && self.get_api().await.is_online()
         } else {
             false
         }
@@ -201,6 +424,7 @@ impl NetworkHandler {
     async fn process_block(&self, address: &Address, block: BlockResponse, topoheight: u64) -> Result<Option<(HashSet<Hash>, Option<u64>)>, Error> {
         let block_hash = block.hash.into_owned();
         debug!("Processing block {} at topoheight {}", block_hash, topoheight);
+        self.wallet.get_metrics().on_block_processed();
 
         if block.miner.is_mainnet() != self.wallet.get_network().is_mainnet() {
             debug!("Block {} at topoheight {} is not on the same network as the wallet", block_hash, topoheight);
@@ -344,10 +568,10 @@ impl NetworkHandler {
                 let mut tx_topoheight = topoheight;
 
                 // New transaction entry that may be linked to us, check if TX was executed
-                if !self.api.is_tx_executed_in_block(&tx.hash, &block_hash).await? {
+                if !self.get_api().await.is_tx_executed_in_block(&tx.hash, &block_hash).await? {
                     warn!("Transaction {} was a good candidate but was not executed in block {}, searching its block executor", tx.hash, block_hash);
                     // Don't skip the TX, we may have missed it
-                    match self.api.get_transaction_executor(&tx.hash).await {
+                    match self.get_api().await.get_transaction_executor(&tx.hash).await {
                         Ok(executor) => {
                             tx_topoheight = executor.block_topoheight;
                             debug!("Transaction {} was executed in block {} at topoheight {}", tx.hash, executor.block_hash, executor.block_topoheight);
@@ -394,7 +618,7 @@ impl NetworkHandler {
     // When the block is requested, we don't limit the syncing to asset in parameter
     async fn get_balance_and_transactions(&self, topoheight_processed: &mut HashSet<u64>, address: &Address, asset: &Hash, min_topoheight: u64, balances: bool, highest_nonce: &mut Option<u64>) -> Result<(), Error> {
         // Retrieve the highest version
-        let (mut topoheight, mut version) = self.api.get_balance(address, asset).await.map(|res| (res.topoheight, res.version))?;
+        let (mut topoheight, mut version) = self.get_api().await.get_balance(address, asset).await.map(|res| (res.topoheight, res.version))?;
         // don't sync already synced blocks
         if min_topoheight >= topoheight {
             return Ok(())
@@ -408,7 +632,7 @@ impl NetworkHandler {
             // add this topoheight in cache to not re-process it (blocks are independant of asset to have faster sync)
             // if its not already processed, do it
             if topoheight_processed.insert(topoheight) {
-                let response = self.api.get_block_with_txs_at_topoheight(topoheight).await?;
+                let response = self.get_api().await.get_block_with_txs_at_topoheight(topoheight).await?;
                 let changes = self.process_block(address, response, topoheight).await?;
 
                 // Check if a change occured, we are the highest version and update balances is requested
@@ -461,7 +685,7 @@ impl NetworkHandler {
                 }
 
                 topoheight = previous;
-                version = self.api.get_balance_at_topoheight(address, asset, previous).await?;
+                version = self.get_api().await.get_balance_at_topoheight(address, asset, previous).await?;
             } else {
                 return Ok(())
             }
@@ -476,7 +700,7 @@ impl NetworkHandler {
     // All transactions / changes above the last valid topoheight will be deleted
     // Returns daemon topoheight along wallet stable topoheight and if back sync is needed
     async fn locate_sync_topoheight_and_clean(&self) -> Result<(u64, Hash, u64, bool), NetworkError> {
-        let info = self.api.get_info().await?;
+        let info = self.get_api().await.get_info().await?;
         let daemon_topoheight = info.topoheight;
         let daemon_block_hash = info.top_block_hash;
         let pruned_topoheight = info.pruned_topoheight.unwrap_or(0);
@@ -513,7 +737,7 @@ impl NetworkHandler {
 
                 if synced_topoheight > pruned_topoheight {
                     // Check if it's still a correct block
-                    let header = self.api.get_block_at_topoheight(synced_topoheight).await?;
+                    let header = self.get_api().await.get_block_at_topoheight(synced_topoheight).await?;
                     let block_hash = header.hash.into_owned();
                     if block_hash == top_block_hash {
                         // topoheight and block hash are equal, we are still on right chain
@@ -555,7 +779,7 @@ impl NetworkHandler {
 
             // Check if we are on the same chain
             debug!("Checking if we are on the same chain at topoheight {}", maximum);
-            let header = self.api.get_block_at_topoheight(maximum).await?;
+            let header = self.get_api().await.get_block_at_topoheight(maximum).await?;
             let block_hash = header.hash.into_owned();
             if block_hash == local_hash {
                 break Some(local_hash);
@@ -569,7 +793,7 @@ impl NetworkHandler {
         let block_hash = if let Some(block_hash) = block_hash {
             block_hash
         } else {
-            let response = self.api.get_block_at_topoheight(maximum).await?;
+            let response = self.get_api().await.get_block_at_topoheight(maximum).await?;
             response.hash.into_owned()
         };
 
@@ -607,7 +831,7 @@ impl NetworkHandler {
             nonce
         } else if sync_nonce {
             trace!("no nonce provided, fetching it from daemon");
-            match self.api.get_nonce(&address).await.map(|v| v.version) {
+            match self.get_api().await.get_nonce(&address).await.map(|v| v.version) {
                 Ok(v) => Some(v.get_nonce()),
                 Err(e) => {
                     debug!("Error while fetching last nonce: {}", e);
@@ -631,7 +855,7 @@ impl NetworkHandler {
             assets
         } else {
             trace!("no assets provided, fetching all assets");
-            self.api.get_account_assets(address).await?
+            self.get_api().await.get_account_assets(address).await?
         };
 
         trace!("assets: {}", assets.len());
@@ -646,7 +870,7 @@ impl NetworkHandler {
                 let storage = self.wallet.get_storage().read().await;
                 storage.contains_asset(&asset).await?
             } {
-                let data = self.api.get_asset(&asset).await?;
+                let data = self.get_api().await.get_asset(&asset).await?;
                 
                 // Add the asset to the storage
                 {
@@ -659,7 +883,7 @@ impl NetworkHandler {
             }
 
             // get the balance for this asset
-            let result = self.api.get_balance(&address, &asset).await?;
+            let result = self.get_api().await.get_balance(&address, &asset).await?;
             trace!("found balance at topoheight: {}", result.topoheight);
             balances.insert(asset, result.version.take_balance());
         }
@@ -731,6 +955,7 @@ impl NetworkHandler {
         // First, locate the last topoheight valid for syncing
         let (daemon_topoheight, daemon_block_hash, wallet_topoheight, sync_back) = self.locate_sync_topoheight_and_clean().await?;
         debug!("Daemon topoheight: {}, wallet topoheight: {}, sync back: {}", daemon_topoheight, wallet_topoheight, sync_back);
+        self.wallet.get_metrics().set_daemon_topoheight(daemon_topoheight);
 
         let mut sync_new_blocks = false;
         // Sync back is requested, sync the head state again
@@ -770,7 +995,11 @@ impl NetworkHandler {
         // we have something that changed, sync transactions
         if sync_new_blocks {
             debug!("Syncing new blocks");
-            self.sync_new_blocks(address, wallet_topoheight, true).await?;
+            let metrics = self.wallet.get_metrics();
+            metrics.start_sync(daemon_topoheight);
+            let res = self.sync_new_blocks(address, wallet_topoheight, true).await;
+            metrics.end_sync();
+            res?;
         }
 
         // Update the topoheight and block hash for wallet
@@ -795,21 +1024,26 @@ impl NetworkHandler {
         // Do a first sync to be up-to-date with the daemon
         self.sync(&address, None).await?;
 
+        let api = self.get_api().await;
         // Thanks to websocket, we can be notified when a new block is added in chain
         // this allows us to have a instant sync of each new block instead of polling periodically
-        let mut on_new_block = self.api.on_new_block_event().await?;
+        let mut on_new_block = api.on_new_block_event().await?;
 
         // Because DAG can reorder any blocks in stable height, its possible we missed some txs because they were not executed
         // when the block was added. We must check on DAG reorg for each block just to be sure
-        let mut on_block_ordered = self.api.on_block_ordered_event().await?;
+        let mut on_block_ordered = api.on_block_ordered_event().await?;
 
         // For better security, verify that an orphaned TX isn't in our ledger
         // This is rare event but may happen if someone try to do something shady
-        let mut on_transaction_orphaned = self.api.on_transaction_orphaned_event().await?;
+        let mut on_transaction_orphaned = api.on_transaction_orphaned_event().await?;
 
         // Network events to detect if we are online or offline
-        let mut on_connection = self.api.on_connection().await;
-        let mut on_connection_lost = self.api.on_connection_lost().await;
+        let mut on_connection = api.on_connection().await;
+        let mut on_connection_lost = api.on_connection_lost().await;
+
+        // Periodically compare the current daemon with the others to detect if it falls behind
+        let period = Duration::from_secs(DAEMON_HEALTH_CHECK_INTERVAL);
+        let mut health_check = interval_at(Instant::now() + period, period);
 
         loop {
             tokio::select! {
@@ -843,7 +1077,7 @@ impl NetworkHandler {
 
                     if process_block {
                         // Sync this block again as it may have some TXs executed
-                        let block = self.api.get_block_at_topoheight(topoheight).await?;
+                        let block = api.get_block_at_topoheight(topoheight).await?;
                         if let Some((assets, _)) = self.process_block(&address, block, topoheight).await? {
                             debug!("Found changes for assets: {}", assets.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", "));
                         }
@@ -877,7 +1111,17 @@ impl NetworkHandler {
                 res = on_connection_lost.recv() => {
                     trace!("on_connection_lost");
                     res?;
+                    if self.has_fallback_daemons() {
+                        // Stop here so we can switch to another daemon
+                        let endpoint = self.get_active_endpoint().await.unwrap_or_default();
+                        return Err(NetworkError::ConnectionLost(endpoint).into())
+                    }
+
                     self.wallet.propagate_event(Event::Offline).await;
+                },
+                _ = health_check.tick(), if self.has_fallback_daemons() => {
+                    trace!("daemon health check");
+                    self.check_daemon_lag().await?;
                 }
             }
         }
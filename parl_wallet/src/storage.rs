@@ -47,8 +47,11 @@ use crate::{
         TransactionEntry,
         Transfer
     },
-    error::WalletError
+    error::WalletError,
+    webhook::{Webhook, WebhookDelivery}
 };
+#[cfg(feature = "api_server")]
+use crate::api::TrustedApplication;
 use log::{trace, debug, error};
 
 // keys used to retrieve from storage
@@ -131,6 +134,15 @@ pub struct EncryptedStorage {
     assets: Tree,
     // This tree is used to store all topoheight where a change in the wallet occured
     changes_topoheight: Tree,
+    // webhooks registered with their URL as key
+    webhooks: Tree,
+    // notifications waiting to be delivered to webhooks
+    webhook_deliveries: Tree,
+    // address book: label for each public key
+    contacts: Tree,
+    // XSWD applications with their remembered permissions
+    #[cfg(feature = "api_server")]
+    trusted_applications: Tree,
     // The inner storage
     inner: Storage,
     // Caches
@@ -156,6 +168,11 @@ impl EncryptedStorage {
             extra: inner.db.open_tree(&cipher.hash_key("extra"))?,
             assets: inner.db.open_tree(&cipher.hash_key("assets"))?,
             changes_topoheight: inner.db.open_tree(&cipher.hash_key("changes_topoheight"))?,
+            webhooks: inner.db.open_tree(&cipher.hash_key("webhooks"))?,
+            webhook_deliveries: inner.db.open_tree(&cipher.hash_key("webhook_deliveries"))?,
+            contacts: inner.db.open_tree(&cipher.hash_key("contacts"))?,
+            #[cfg(feature = "api_server")]
+            trusted_applications: inner.db.open_tree(&cipher.hash_key("trusted_applications"))?,
             cipher,
             inner,
             balances_cache: Mutex::new(LruCache::new(NonZeroUsize::new(DEFAULT_CACHE_SIZE).unwrap())),
@@ -930,6 +947,171 @@ impl EncryptedStorage {
 
         Ok(highest)
     }
+
+    // Register or replace a webhook using its URL as key
+    pub fn add_webhook(&mut self, webhook: &Webhook) -> Result<()> {
+        trace!("add webhook {}", webhook.get_url());
+        self.save_to_disk(&self.webhooks, webhook.get_url().as_bytes(), &webhook.to_bytes())
+    }
+
+    // Delete a webhook and all its pending deliveries
+    // Returns false if no webhook was registered with this URL
+    pub fn remove_webhook(&mut self, url: &String) -> Result<bool> {
+        trace!("remove webhook {}", url);
+        if !self.contains_data(&self.webhooks, url.as_bytes())? {
+            return Ok(false)
+        }
+
+        self.delete_from_disk(&self.webhooks, url.as_bytes())?;
+        for delivery in self.get_webhook_deliveries()? {
+            if delivery.get_url() == url {
+                self.delete_webhook_delivery(delivery.get_id())?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Check if we have any webhook registered
+    pub fn has_webhooks(&self) -> bool {
+        !self.webhooks.is_empty()
+    }
+
+    // Retrieve all webhooks registered
+    pub fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        trace!("get webhooks");
+        let mut webhooks = Vec::new();
+        for res in self.webhooks.iter().values() {
+            let value = res?;
+            let webhook = Webhook::from_bytes(&self.cipher.decrypt_value(&value)?)?;
+            webhooks.push(webhook);
+        }
+
+        Ok(webhooks)
+    }
+
+    // Generate a unique id for a webhook delivery
+    // It is monotonic, even across restarts
+    pub fn generate_webhook_delivery_id(&self) -> Result<u64> {
+        Ok(self.inner.db.generate_id()?)
+    }
+
+    // Store or update a pending webhook delivery
+    pub fn set_webhook_delivery(&mut self, delivery: &WebhookDelivery) -> Result<()> {
+        trace!("set webhook delivery {}", delivery.get_id());
+        self.save_to_disk(&self.webhook_deliveries, &delivery.get_id().to_be_bytes(), &delivery.to_bytes())
+    }
+
+    // Delete a webhook delivery once delivered
+    pub fn delete_webhook_delivery(&mut self, id: u64) -> Result<()> {
+        trace!("delete webhook delivery {}", id);
+        self.delete_from_disk(&self.webhook_deliveries, &id.to_be_bytes())
+    }
+
+    // Check if we have any webhook delivery pending
+    pub fn has_webhook_deliveries(&self) -> bool {
+        !self.webhook_deliveries.is_empty()
+    }
+
+    // Retrieve all pending webhook deliveries
+    pub fn get_webhook_deliveries(&self) -> Result<Vec<WebhookDelivery>> {
+        trace!("get webhook deliveries");
+        let mut deliveries = Vec::new();
+        for res in self.webhook_deliveries.iter().values() {
+            let value = res?;
+            let delivery = WebhookDelivery::from_bytes(&self.cipher.decrypt_value(&value)?)?;
+            deliveries.push(delivery);
+        }
+
+        Ok(deliveries)
+    }
+
+    // Set the label of a public key in the address book
+    pub fn set_contact(&mut self, key: &PublicKey, label: &String) -> Result<()> {
+        trace!("set contact {}", label);
+        self.save_to_disk_with_encrypted_key(&self.contacts, key.as_bytes(), &label.to_bytes())
+    }
+
+    // Retrieve the label of a public key if present in the address book
+    pub fn get_contact_label(&self, key: &PublicKey) -> Result<Option<String>> {
+        trace!("get contact label");
+        if !self.contains_encrypted_data(&self.contacts, key.as_bytes())? {
+            return Ok(None)
+        }
+
+        self.load_from_disk_with_encrypted_key(&self.contacts, key.as_bytes()).map(Some)
+    }
+
+    // Delete a public key from the address book
+    // Returns false if it was not present
+    pub fn remove_contact(&mut self, key: &PublicKey) -> Result<bool> {
+        trace!("remove contact");
+        if !self.contains_encrypted_data(&self.contacts, key.as_bytes())? {
+            return Ok(false)
+        }
+
+        self.delete_from_disk_with_encrypted_key(&self.contacts, key.as_bytes())?;
+        Ok(true)
+    }
+
+    // Retrieve all public keys with their label from the address book
+    pub fn get_contacts(&self) -> Result<Vec<(PublicKey, String)>> {
+        trace!("get contacts");
+        let mut contacts = Vec::new();
+        for res in self.contacts.iter() {
+            let (key, value) = res?;
+            let key = PublicKey::from_bytes(&self.cipher.decrypt_value(&key)?)?;
+            let label = String::from_bytes(&self.cipher.decrypt_value(&value)?)?;
+            contacts.push((key, label));
+        }
+
+        Ok(contacts)
+    }
+
+    // Save a XSWD application with its remembered permissions using its ID as key
+    #[cfg(feature = "api_server")]
+    pub fn set_trusted_application(&mut self, app: &TrustedApplication) -> Result<()> {
+        trace!("set trusted application {}", app.get_id());
+        self.save_to_disk(&self.trusted_applications, app.get_id().as_bytes(), &app.to_bytes())
+    }
+
+    // Retrieve the remembered permissions of a XSWD application
+    #[cfg(feature = "api_server")]
+    pub fn get_trusted_application(&self, id: &String) -> Result<Option<TrustedApplication>> {
+        trace!("get trusted application {}", id);
+        if !self.contains_data(&self.trusted_applications, id.as_bytes())? {
+            return Ok(None)
+        }
+
+        self.load_from_disk(&self.trusted_applications, id.as_bytes()).map(Some)
+    }
+
+    // Delete a XSWD application and all its remembered permissions
+    // Returns false if no application was saved with this ID
+    #[cfg(feature = "api_server")]
+    pub fn remove_trusted_application(&mut self, id: &String) -> Result<bool> {
+        trace!("remove trusted application {}", id);
+        if !self.contains_data(&self.trusted_applications, id.as_bytes())? {
+            return Ok(false)
+        }
+
+        self.delete_from_disk(&self.trusted_applications, id.as_bytes())?;
+        Ok(true)
+    }
+
+    // Retrieve all XSWD applications with remembered permissions
+    #[cfg(feature = "api_server")]
+    pub fn get_trusted_applications(&self) -> Result<Vec<TrustedApplication>> {
+        trace!("get trusted applications");
+        let mut applications = Vec::new();
+        for res in self.trusted_applications.iter().values() {
+            let value = res?;
+            let app = TrustedApplication::from_bytes(&self.cipher.decrypt_value(&value)?)?;
+            applications.push(app);
+        }
+
+        Ok(applications)
+    }
 }
 
 impl Storage {
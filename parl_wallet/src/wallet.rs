@@ -29,6 +29,7 @@ use parl_common::{
         ecdlp::{self, ECDLPTablesFileView},
         elgamal::{Ciphertext, DecryptHandle, PublicKey as DecompressedPublicKey},
         Address,
+        Hash,
         Hashable,
         KeyPair,
         PublicKey,
@@ -56,6 +57,7 @@ use crate::{
     },
     daemon_api::DaemonAPI,
     error::WalletError,
+    metrics::WalletMetrics,
     mnemonics,
     network_handler::{
         NetworkHandler,
@@ -68,7 +70,8 @@ use crate::{
     transaction_builder::{
         EstimateFeesState,
         TransactionBuilderState
-    }
+    },
+    webhook::{Webhook, WebhookManager}
 };
 use chacha20poly1305::aead::OsRng;
 use rand::RngCore;
@@ -93,8 +96,14 @@ use {
         AppStateShared,
         PermissionResult,
         PermissionRequest,
+        TransactionSummary,
+        TrustedApplication,
         XSWDPermissionHandler
     },
+    parl_common::{
+        api::wallet::BuildTransactionParams,
+        time::get_current_time_in_seconds
+    },
     parl_common::rpc_server::{
         RPCHandler,
         RpcRequest,
@@ -223,8 +232,12 @@ pub struct Wallet {
     xswd_channel: RwLock<Option<UnboundedSender<XSWDEvent>>>,
     // Event broadcaster
     event_broadcaster: Mutex<Option<BroadcastSender<Event>>>,
+    // Deliver events to registered webhooks
+    webhook_manager: Mutex<Option<WebhookManager>>,
     // Precomputed tables byte array
-    precomputed_tables: PrecomputedTablesShared
+    precomputed_tables: PrecomputedTablesShared,
+    // Sync state exposed in the metrics
+    metrics: WalletMetrics
 }
 
 pub fn hash_password(password: String, salt: &[u8]) -> Result<[u8; PASSWORD_HASH_SIZE], WalletError> {
@@ -275,7 +288,9 @@ impl Wallet {
             #[cfg(feature = "api_server")]
             xswd_channel: RwLock::new(None),
             event_broadcaster: Mutex::new(None),
-            precomputed_tables
+            webhook_manager: Mutex::new(None),
+            precomputed_tables,
+            metrics: WalletMetrics::default()
         };
 
         Arc::new(zelf)
@@ -409,6 +424,14 @@ impl Wallet {
             }
         }
 
+        // Stop the webhooks, pending deliveries are kept in storage
+        {
+            let mut lock = self.webhook_manager.lock().await;
+            if let Some(manager) = lock.take() {
+                manager.stop();
+            }
+        }
+
         // Stop gracefully the network handler
         {
             let mut lock = self.network_handler.lock().await;
@@ -442,6 +465,14 @@ impl Wallet {
             }
         }
 
+        // Queue it for the webhooks
+        {
+            let lock = self.webhook_manager.lock().await;
+            if let Some(manager) = lock.as_ref() {
+                manager.notify(event.clone());
+            }
+        }
+
         // Broadcast to the event broadcaster
         {
             let mut lock = self.event_broadcaster.lock().await;
@@ -475,6 +506,47 @@ impl Wallet {
         broadcaster.take().is_some()
     }
 
+    // Start delivering events to the registered webhooks
+    // Deliveries pending from a previous session are sent again
+    // Returns false if no webhook is registered
+    pub async fn start_webhooks(self: &Arc<Self>) -> Result<bool, Error> {
+        let mut lock = self.webhook_manager.lock().await;
+        if lock.is_some() {
+            return Ok(true)
+        }
+
+        {
+            let storage = self.storage.read().await;
+            if !storage.has_webhooks() && !storage.has_webhook_deliveries() {
+                return Ok(false)
+            }
+        }
+
+        *lock = Some(WebhookManager::new(Arc::clone(&self))?);
+        Ok(true)
+    }
+
+    // Register a new webhook, replacing any webhook with the same URL
+    pub async fn add_webhook(self: &Arc<Self>, webhook: Webhook) -> Result<(), Error> {
+        {
+            let mut storage = self.storage.write().await;
+            storage.add_webhook(&webhook)?;
+        }
+
+        self.start_webhooks().await?;
+        Ok(())
+    }
+
+    // Delete a webhook and its pending deliveries
+    pub async fn remove_webhook(&self, url: &String) -> Result<(), Error> {
+        let mut storage = self.storage.write().await;
+        if !storage.remove_webhook(url)? {
+            return Err(WalletError::WebhookNotFound(url.clone()).into())
+        }
+
+        Ok(())
+    }
+
     // Enable RPC Server with requested authentication and bind address
     #[cfg(feature = "api_server")]
     pub async fn enable_rpc_server(self: &Arc<Self>, bind_address: String, config: Option<AuthConfig>) -> Result<(), Error> {
@@ -524,6 +596,67 @@ impl Wallet {
         &self.api_server
     }
 
+    // Delete all remembered permissions of a XSWD application
+    // If the application is currently connected, its permissions are reset too
+    #[cfg(feature = "api_server")]
+    pub async fn revoke_trusted_application(&self, id: &String) -> Result<(), Error> {
+        {
+            let mut storage = self.storage.write().await;
+            if !storage.remove_trusted_application(id)? {
+                return Err(WalletError::TrustedApplicationNotFound(id.clone()).into())
+            }
+        }
+
+        let lock = self.api_server.lock().await;
+        if let Some(APIServer::XSWD(xswd)) = lock.as_ref() {
+            xswd.get_handler().revoke_application_permissions(id).await;
+        }
+
+        Ok(())
+    }
+
+    // Set the maximum amount of an asset a XSWD application can spend in one transaction
+    // The application must be already trusted or currently connected
+    #[cfg(feature = "api_server")]
+    pub async fn set_spending_limit(&self, id: &String, asset: Hash, limit: u64) -> Result<(), Error> {
+        let trusted = {
+            let storage = self.storage.read().await;
+            storage.get_trusted_application(id)?
+        };
+
+        let mut trusted = match trusted {
+            Some(trusted) => trusted,
+            None => {
+                let lock = self.api_server.lock().await;
+                let app = match lock.as_ref() {
+                    Some(APIServer::XSWD(xswd)) => xswd.get_handler().get_application_with_id(id).await,
+                    _ => None
+                };
+
+                let app = app.ok_or_else(|| WalletError::TrustedApplicationNotFound(id.clone()))?;
+                TrustedApplication::new(app.get_id().clone(), app.get_name().clone(), app.get_url().clone())
+            }
+        };
+
+        trusted.set_spending_limit(asset, limit);
+        let mut storage = self.storage.write().await;
+        storage.set_trusted_application(&trusted)
+    }
+
+    // Remove the spending limit of an asset for a XSWD application
+    #[cfg(feature = "api_server")]
+    pub async fn remove_spending_limit(&self, id: &String, asset: &Hash) -> Result<(), Error> {
+        let mut storage = self.storage.write().await;
+        let mut trusted = storage.get_trusted_application(id)?
+            .ok_or_else(|| WalletError::TrustedApplicationNotFound(id.clone()))?;
+
+        if !trusted.remove_spending_limit(asset) {
+            return Err(WalletError::SpendingLimitNotFound(asset.clone()).into())
+        }
+
+        storage.set_trusted_application(&trusted)
+    }
+
     // Verify if a password is valid or not
     pub async fn is_valid_password(&self, password: String) -> Result<(), Error> {
         let mut encrypted_storage = self.storage.write().await;
@@ -667,7 +800,7 @@ impl Wallet {
         trace!("submit transaction");
         let network_handler = self.network_handler.lock().await;
         if let Some(network_handler) = network_handler.as_ref() {
-            network_handler.get_api().submit_transaction(transaction).await?;
+            network_handler.get_api().await.submit_transaction(transaction).await?;
             Ok(())
         } else {
             Err(WalletError::NotOnlineMode)
@@ -693,7 +826,7 @@ impl Wallet {
 
                             let addr = key.as_address(self.network.is_mainnet());
                             trace!("Checking if {} is registered in stable height", addr);
-                            let registered = network_handler.get_api().is_account_registered(&addr, true).await?;
+                            let registered = network_handler.get_api().await.is_account_registered(&addr, true).await?;
                             trace!("registered: {}", registered);
                             if registered {
                                 state.add_registered_key(addr.to_public_key());
@@ -726,6 +859,12 @@ impl Wallet {
 
     // set wallet in online mode: start a communication task which will keep the wallet synced
     pub async fn set_online_mode(self: &Arc<Self>, daemon_address: &String, auto_reconnect: bool) -> Result<(), WalletError> {
+        self.set_online_mode_with_endpoints(vec![daemon_address.clone()], auto_reconnect).await
+    }
+
+    // set wallet in online mode using several daemons
+    // the healthiest one is used and the wallet switch to another one if it goes down or falls behind
+    pub async fn set_online_mode_with_endpoints(self: &Arc<Self>, daemon_addresses: Vec<String>, auto_reconnect: bool) -> Result<(), WalletError> {
         trace!("Set online mode");
         if self.is_online().await {
             // user have to set in offline mode himself first
@@ -733,7 +872,7 @@ impl Wallet {
         }
 
         // create the network handler
-        let network_handler = NetworkHandler::new(Arc::clone(&self), daemon_address).await?;
+        let network_handler = NetworkHandler::with_endpoints(Arc::clone(&self), daemon_addresses).await?;
         // start the task
         network_handler.start(auto_reconnect).await?;
         *self.network_handler.lock().await = Some(network_handler);
@@ -803,7 +942,7 @@ impl Wallet {
                 storage.clear_tx_cache();
 
                 debug!("Retrieve current wallet nonce");
-                let nonce_result = network_handler.get_api()
+                let nonce_result = network_handler.get_api().await
                     .get_nonce(&self.get_address()).await
                     // User has no transactions/balances yet, set its nonce to 0
                     .map(|v| v.version.get_nonce()).unwrap_or(0);
@@ -836,6 +975,15 @@ impl Wallet {
         }
     }
 
+    // Get the daemon address currently used by the network handler
+    pub async fn get_daemon_endpoint(&self) -> Option<String> {
+        if let Some(network_handler) = self.network_handler.lock().await.as_ref() {
+            network_handler.get_active_endpoint().await
+        } else {
+            None
+        }
+    }
+
     // this function allow to user to get the network handler in case in want to stay in online mode
     // but want to pause / resume the syncing task through start/stop functions from it
     pub async fn get_network_handler(&self) -> &Mutex<Option<Arc<NetworkHandler>>> {
@@ -875,6 +1023,11 @@ impl Wallet {
         storage.get_nonce().unwrap_or(0)
     }
 
+    // Counters and latencies of the wallet
+    pub fn get_metrics(&self) -> &WalletMetrics {
+        &self.metrics
+    }
+
     // Encrypted storage of the wallet
     pub fn get_storage(&self) -> &RwLock<EncryptedStorage> {
         &self.storage
@@ -889,6 +1042,8 @@ impl Wallet {
 #[cfg(feature = "api_server")]
 pub enum XSWDEvent {
     RequestPermission(AppStateShared, RpcRequest, OneshotSender<Result<PermissionResult, Error>>),
+    // Request to build a transaction with its decoded summary
+    RequestTransaction(AppStateShared, RpcRequest, TransactionSummary, OneshotSender<Result<PermissionResult, Error>>),
     // bool represents if it was signed or not
     RequestApplication(AppStateShared, bool, OneshotSender<Result<PermissionResult, Error>>),
     CancelRequest(AppStateShared, OneshotSender<Result<(), Error>>)
@@ -905,7 +1060,8 @@ impl XSWDPermissionHandler for Arc<Wallet> {
             let (callback, receiver) = channel();
             let event = match request {
                 PermissionRequest::Application(signed) => XSWDEvent::RequestApplication(app_state, signed, callback),
-                PermissionRequest::Request(request) => XSWDEvent::RequestPermission(app_state, request.clone(), callback)
+                PermissionRequest::Request(request) => XSWDEvent::RequestPermission(app_state, request.clone(), callback),
+                PermissionRequest::Transaction(request, summary) => XSWDEvent::RequestTransaction(app_state, request.clone(), summary, callback)
             };
 
             // Send the XSWD Message
@@ -936,6 +1092,33 @@ impl XSWDPermissionHandler for Arc<Wallet> {
     async fn get_public_key(&self) -> Result<&DecompressedPublicKey, Error> {
         Ok(self.keypair.get_public_key())
     }
+
+    async fn sign_application_data(&self, data: &[u8]) -> Result<Signature, Error> {
+        Ok(self.keypair.sign(data))
+    }
+
+    // expired applications are ignored, they will be overwritten on next permission saved
+    async fn get_trusted_application(&self, id: &String) -> Result<Option<TrustedApplication>, Error> {
+        let storage = self.storage.read().await;
+        let app = storage.get_trusted_application(id)?;
+        Ok(app.filter(|app| !app.is_expired(get_current_time_in_seconds())))
+    }
+
+    async fn save_trusted_application(&self, app: &TrustedApplication) -> Result<(), Error> {
+        let mut storage = self.storage.write().await;
+        storage.set_trusted_application(app)
+    }
+
+    async fn get_transaction_summary(&self, params: &BuildTransactionParams) -> Result<TransactionSummary, Error> {
+        let fee = match &params.fee {
+            Some(FeeBuilder::Value(value)) => *value,
+            Some(FeeBuilder::Multiplier(multiplier)) => (self.estimate_fees(params.tx_type.clone()).await? as f64 * multiplier) as u64,
+            None => self.estimate_fees(params.tx_type.clone()).await?
+        };
+
+        let storage = self.storage.read().await;
+        TransactionSummary::new(&storage, &params.tx_type, fee, params.broadcast)
+    }
 }
 
 #[cfg(feature = "api_server")]
@@ -946,7 +1129,7 @@ impl XSWDNodeMethodHandler for Arc<Wallet> {
         let id = request.id;
         if let Some(network_handler) = network_handler.as_ref() {
             if network_handler.is_running().await {
-                let api = network_handler.get_api();
+                let api = network_handler.get_api().await;
                 let response = api.call(&request.method, &request.params).await.map_err(|e| RpcResponseError::new(id.clone(), InternalRpcError::Custom(-31999, e.to_string())))?;
 
                 return Ok(json!({
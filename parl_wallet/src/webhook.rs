@@ -0,0 +1,541 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::Duration
+};
+use anyhow::Error;
+use hmac::{Hmac, Mac};
+use log::{debug, error, trace, warn};
+use reqwest::Client;
+use serde_json::json;
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::interval
+};
+use parl_common::{
+    api::wallet::NotifyEvent,
+    serializer::{Reader, ReaderError, Serializer, Writer},
+    time::{get_current_time_in_seconds, TimestampSeconds},
+    utils::spawn_task
+};
+use crate::{
+    config::{
+        WEBHOOK_MAX_ATTEMPTS,
+        WEBHOOK_QUEUE_CHECK_INTERVAL,
+        WEBHOOK_REQUEST_TIMEOUT,
+        WEBHOOK_RETRY_BASE_DELAY,
+        WEBHOOK_RETRY_MAX_DELAY
+    },
+    wallet::{Event, Wallet}
+};
+
+// Headers sent with each webhook request
+pub const WEBHOOK_EVENT_HEADER: &str = "X-Parl-Event";
+pub const WEBHOOK_DELIVERY_HEADER: &str = "X-Parl-Delivery";
+// HMAC-SHA256 of the request body using the webhook secret, hex encoded
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Parl-Signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("Webhook URL must start with http:// or https://")]
+    InvalidUrl,
+    #[error("Webhook URL is too long, maximum is {} bytes", u8::MAX)]
+    UrlTooLong,
+    #[error("Webhook secret cannot be empty")]
+    EmptySecret,
+    #[error("Webhook secret is too long, maximum is {} bytes", u8::MAX)]
+    SecretTooLong,
+    #[error("Webhook {} returned status code {}", _0, _1)]
+    InvalidStatus(String, u16),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error)
+}
+
+// A webhook registered in the wallet
+// Each event accepted is sent to its URL with a signature header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    // URL where events are sent using a POST request
+    url: String,
+    // Secret used to sign each request body
+    secret: String,
+    // Events to send, all events if empty
+    events: HashSet<NotifyEvent>
+}
+
+impl Webhook {
+    pub fn new(url: String, secret: String, events: HashSet<NotifyEvent>) -> Result<Self, WebhookError> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(WebhookError::InvalidUrl)
+        }
+
+        if url.len() > u8::MAX as usize {
+            return Err(WebhookError::UrlTooLong)
+        }
+
+        if secret.is_empty() {
+            return Err(WebhookError::EmptySecret)
+        }
+
+        // both are stored with a u8 length prefix
+        if secret.len() > u8::MAX as usize {
+            return Err(WebhookError::SecretTooLong)
+        }
+
+        Ok(Self {
+            url,
+            secret,
+            events
+        })
+    }
+
+    pub fn get_url(&self) -> &String {
+        &self.url
+    }
+
+    pub fn get_secret(&self) -> &String {
+        &self.secret
+    }
+
+    pub fn get_events(&self) -> &HashSet<NotifyEvent> {
+        &self.events
+    }
+
+    // Check if this webhook should receive this event
+    pub fn accept(&self, event: &NotifyEvent) -> bool {
+        self.events.is_empty() || self.events.contains(event)
+    }
+}
+
+impl Serializer for Webhook {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let url = reader.read_string()?;
+        let secret = reader.read_string()?;
+        let count = reader.read_u8()?;
+        let mut events = HashSet::with_capacity(count as usize);
+        for _ in 0..count {
+            events.insert(NotifyEvent::read(reader)?);
+        }
+
+        Ok(Self {
+            url,
+            secret,
+            events
+        })
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.write_string(&self.url);
+        writer.write_string(&self.secret);
+        writer.write_u8(self.events.len() as u8);
+        for event in &self.events {
+            event.write(writer);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.url.size() + self.secret.size() + 1 + self.events.len()
+    }
+}
+
+// A notification waiting to be delivered to a webhook
+// It is kept in wallet storage until the webhook accepts it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookDelivery {
+    // Unique id of the delivery, also sent in headers
+    id: u64,
+    // Webhook URL
+    url: String,
+    // Kind of the event
+    event: NotifyEvent,
+    // JSON body to send
+    payload: Vec<u8>,
+    // Number of failed attempts
+    attempts: u32,
+    // Timestamp in seconds before which we should not retry
+    next_attempt: TimestampSeconds
+}
+
+impl WebhookDelivery {
+    pub fn new(id: u64, url: String, event: NotifyEvent, payload: Vec<u8>) -> Self {
+        Self {
+            id,
+            url,
+            event,
+            payload,
+            attempts: 0,
+            next_attempt: 0
+        }
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get_url(&self) -> &String {
+        &self.url
+    }
+
+    pub fn get_event(&self) -> &NotifyEvent {
+        &self.event
+    }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // Check if we can try to deliver it
+    pub fn is_due(&self, now: TimestampSeconds) -> bool {
+        self.next_attempt <= now
+    }
+
+    // Register a failed attempt and schedule the next one
+    // Returns false if the maximum attempts is reached
+    pub fn schedule_retry(&mut self, now: TimestampSeconds) -> bool {
+        self.attempts += 1;
+        if self.attempts >= WEBHOOK_MAX_ATTEMPTS {
+            return false
+        }
+
+        self.next_attempt = now + retry_delay(self.attempts);
+        true
+    }
+}
+
+impl Serializer for WebhookDelivery {
+    fn read(reader: &mut Reader) -> Result<Self, ReaderError> {
+        let id = reader.read_u64()?;
+        let url = reader.read_string()?;
+        let event = NotifyEvent::read(reader)?;
+        let len = reader.read_u32()?;
+        let payload = reader.read_bytes_ref(len as usize)?.to_vec();
+        let attempts = reader.read_u32()?;
+        let next_attempt = reader.read_u64()?;
+
+        Ok(Self {
+            id,
+            url,
+            event,
+            payload,
+            attempts,
+            next_attempt
+        })
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.write_u64(&self.id);
+        writer.write_string(&self.url);
+        self.event.write(writer);
+        writer.write_u32(&(self.payload.len() as u32));
+        writer.write_bytes(&self.payload);
+        writer.write_u32(&self.attempts);
+        writer.write_u64(&self.next_attempt);
+    }
+
+    fn size(&self) -> usize {
+        self.id.size() + self.url.size() + self.event.size() + 4 + self.payload.len() + self.attempts.size() + self.next_attempt.size()
+    }
+}
+
+// Delay in seconds before the next attempt, doubled on each failure
+pub fn retry_delay(attempts: u32) -> u64 {
+    WEBHOOK_RETRY_BASE_DELAY.checked_shl(attempts.saturating_sub(1))
+        .unwrap_or(WEBHOOK_RETRY_MAX_DELAY)
+        .min(WEBHOOK_RETRY_MAX_DELAY)
+}
+
+// Sign the payload with HMAC-SHA256 and return it in hex
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Name of the event as sent in JSON
+fn event_name(event: &NotifyEvent) -> String {
+    serde_json::to_value(event).ok()
+        .and_then(|v| v.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+// Send the delivery to its webhook
+// Only a 2xx status code is considered as delivered
+pub async fn send_delivery(client: &Client, webhook: &Webhook, delivery: &WebhookDelivery) -> Result<(), WebhookError> {
+    trace!("Sending delivery {} to {}", delivery.get_id(), webhook.get_url());
+    let signature = sign_payload(webhook.get_secret(), delivery.get_payload());
+    let response = client.post(webhook.get_url())
+        .header("Content-Type", "application/json")
+        .header(WEBHOOK_EVENT_HEADER, event_name(delivery.get_event()))
+        .header(WEBHOOK_DELIVERY_HEADER, delivery.get_id().to_string())
+        .header(WEBHOOK_SIGNATURE_HEADER, signature)
+        .body(delivery.get_payload().to_vec())
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(WebhookError::InvalidStatus(webhook.get_url().clone(), status.as_u16()))
+    }
+
+    Ok(())
+}
+
+// Manage the webhooks deliveries in background
+// Events are first stored in the persistent queue, then sent
+pub struct WebhookManager {
+    // Events propagated by the wallet
+    sender: UnboundedSender<Event>,
+    // Task handling the queue
+    task: JoinHandle<()>
+}
+
+impl WebhookManager {
+    pub fn new(wallet: Arc<Wallet>) -> Result<Self, Error> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(WEBHOOK_REQUEST_TIMEOUT))
+            .build()?;
+
+        let (sender, receiver) = unbounded_channel();
+        let task = spawn_task("webhook-manager", Self::run(wallet, client, receiver));
+
+        Ok(Self {
+            sender,
+            task
+        })
+    }
+
+    // Queue the event for all webhooks that accept it
+    pub fn notify(&self, event: Event) {
+        if self.sender.send(event).is_err() {
+            warn!("Webhook manager is not running anymore");
+        }
+    }
+
+    // Stop the background task
+    // Pending deliveries are kept in storage
+    pub fn stop(self) {
+        trace!("Stopping webhook manager");
+        self.task.abort();
+    }
+
+    async fn run(wallet: Arc<Wallet>, client: Client, mut receiver: UnboundedReceiver<Event>) {
+        // Deliver what was left from the previous session
+        if let Err(e) = Self::process_queue(&wallet, &client).await {
+            error!("Error while processing webhook deliveries: {}", e);
+        }
+
+        let mut ticker = interval(Duration::from_secs(WEBHOOK_QUEUE_CHECK_INTERVAL));
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(event) => {
+                        if let Err(e) = Self::queue_event(&wallet, event).await {
+                            error!("Error while queuing webhook event: {}", e);
+                        }
+                    },
+                    None => break
+                },
+                _ = ticker.tick() => {}
+            }
+
+            if let Err(e) = Self::process_queue(&wallet, &client).await {
+                error!("Error while processing webhook deliveries: {}", e);
+            }
+        }
+    }
+
+    // Store a delivery for each webhook accepting this event
+    async fn queue_event(wallet: &Arc<Wallet>, event: Event) -> Result<(), Error> {
+        let kind = event.kind();
+        let mut storage = wallet.get_storage().write().await;
+        for webhook in storage.get_webhooks()?.into_iter().filter(|w| w.accept(&kind)) {
+            let id = storage.generate_webhook_delivery_id()?;
+            let payload = serde_json::to_vec(&json!({
+                "id": id,
+                "event": kind,
+                "timestamp": get_current_time_in_seconds(),
+                "data": event
+            }))?;
+
+            debug!("Queuing delivery {} for webhook {}", id, webhook.get_url());
+            let delivery = WebhookDelivery::new(id, webhook.get_url().clone(), kind.clone(), payload);
+            storage.set_webhook_delivery(&delivery)?;
+        }
+
+        Ok(())
+    }
+
+    // Try to send all deliveries that are due
+    // Deliveries of a webhook are sent in order, we stop at the first failure
+    async fn process_queue(wallet: &Arc<Wallet>, client: &Client) -> Result<(), Error> {
+        let now = get_current_time_in_seconds();
+        let (mut deliveries, webhooks) = {
+            let storage = wallet.get_storage().read().await;
+            (storage.get_webhook_deliveries()?, storage.get_webhooks()?)
+        };
+        deliveries.sort_by_key(|d| d.get_id());
+
+        let mut failed: HashSet<String> = HashSet::new();
+        for mut delivery in deliveries {
+            let Some(webhook) = webhooks.iter().find(|w| w.get_url() == delivery.get_url()) else {
+                debug!("Webhook {} was removed, deleting delivery {}", delivery.get_url(), delivery.get_id());
+                wallet.get_storage().write().await.delete_webhook_delivery(delivery.get_id())?;
+                continue;
+            };
+
+            if failed.contains(delivery.get_url()) || !delivery.is_due(now) {
+                // Keep the order for this webhook
+                failed.insert(delivery.get_url().clone());
+                continue;
+            }
+
+            match send_delivery(client, webhook, &delivery).await {
+                Ok(()) => {
+                    trace!("Delivery {} sent to {}", delivery.get_id(), delivery.get_url());
+                    wallet.get_storage().write().await.delete_webhook_delivery(delivery.get_id())?;
+                },
+                Err(e) => {
+                    failed.insert(delivery.get_url().clone());
+                    let mut storage = wallet.get_storage().write().await;
+                    if delivery.schedule_retry(now) {
+                        warn!("Error while sending delivery {} (attempt {}): {}", delivery.get_id(), delivery.get_attempts(), e);
+                        storage.set_webhook_delivery(&delivery)?;
+                    } else {
+                        error!("Delivery {} to {} failed after {} attempts, dropping it: {}", delivery.get_id(), delivery.get_url(), delivery.get_attempts(), e);
+                        storage.delete_webhook_delivery(delivery.get_id())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener
+    };
+    use super::*;
+
+    // Accept one HTTP request, returns its raw headers and body
+    async fn receive_request(listener: TcpListener, status: &str) -> (String, Vec<u8>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let (headers, body) = loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                break (String::from_utf8_lossy(&buffer).to_lowercase(), Vec::new());
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+
+            let headers = String::from_utf8_lossy(&buffer[..pos]).to_lowercase();
+            let length = headers.lines()
+                .find_map(|l| l.strip_prefix("content-length: "))
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+
+            while buffer.len() < pos + 4 + length {
+                let n = stream.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+
+            let end = buffer.len().min(pos + 4 + length);
+            break (headers, buffer[pos + 4..end].to_vec());
+        };
+
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+        stream.write_all(response.as_bytes()).await.unwrap();
+        (headers, body)
+    }
+
+    fn create_webhook(url: String) -> Webhook {
+        Webhook::new(url, "secret".to_owned(), HashSet::new()).unwrap()
+    }
+
+    #[test]
+    fn test_serializer() {
+        let mut events = HashSet::new();
+        events.insert(NotifyEvent::NewTransaction);
+        events.insert(NotifyEvent::BalanceChanged);
+        let webhook = Webhook::new("http://127.0.0.1:8080/hook".to_owned(), "secret".to_owned(), events).unwrap();
+        assert_eq!(Webhook::from_bytes(&webhook.to_bytes()).unwrap(), webhook);
+
+        let mut delivery = WebhookDelivery::new(42, webhook.get_url().clone(), NotifyEvent::NewTransaction, vec![1; 1024]);
+        assert!(delivery.schedule_retry(100));
+        assert_eq!(WebhookDelivery::from_bytes(&delivery.to_bytes()).unwrap(), delivery);
+    }
+
+    #[test]
+    fn test_invalid_webhook() {
+        assert!(Webhook::new("ftp://127.0.0.1".to_owned(), "secret".to_owned(), HashSet::new()).is_err());
+        assert!(Webhook::new("http://127.0.0.1".to_owned(), "".to_owned(), HashSet::new()).is_err());
+        assert!(Webhook::new(format!("http://127.0.0.1/{}", "a".repeat(255)), "secret".to_owned(), HashSet::new()).is_err());
+        assert!(Webhook::new("http://127.0.0.1".to_owned(), "a".repeat(256), HashSet::new()).is_err());
+        assert!(Webhook::new("http://127.0.0.1".to_owned(), "a".repeat(255), HashSet::new()).is_ok());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), WEBHOOK_RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), WEBHOOK_RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(WEBHOOK_MAX_ATTEMPTS), WEBHOOK_RETRY_MAX_DELAY);
+
+        let mut delivery = WebhookDelivery::new(0, "http://127.0.0.1".to_owned(), NotifyEvent::Online, Vec::new());
+        for _ in 1..WEBHOOK_MAX_ATTEMPTS {
+            assert!(delivery.schedule_retry(0));
+            assert!(!delivery.is_due(0));
+        }
+        assert!(!delivery.schedule_retry(0));
+    }
+
+    #[tokio::test]
+    async fn test_send_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook = create_webhook(format!("http://{}/hook", listener.local_addr().unwrap()));
+        let payload = br#"{"event":"online"}"#.to_vec();
+        let delivery = WebhookDelivery::new(7, webhook.get_url().clone(), NotifyEvent::Online, payload.clone());
+
+        let server = tokio::spawn(receive_request(listener, "200 OK"));
+        let client = Client::new();
+        send_delivery(&client, &webhook, &delivery).await.unwrap();
+
+        let (headers, body) = server.await.unwrap();
+        assert_eq!(body, payload);
+        assert!(headers.contains("x-parl-event: online"));
+        assert!(headers.contains("x-parl-delivery: 7"));
+        let signature = sign_payload(webhook.get_secret(), &payload);
+        assert!(headers.contains(&format!("x-parl-signature: {}", signature)));
+    }
+
+    #[tokio::test]
+    async fn test_send_delivery_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook = create_webhook(format!("http://{}/hook", listener.local_addr().unwrap()));
+        let delivery = WebhookDelivery::new(1, webhook.get_url().clone(), NotifyEvent::Offline, b"{}".to_vec());
+
+        let server = tokio::spawn(receive_request(listener, "500 Internal Server Error"));
+        let client = Client::new();
+        let res = send_delivery(&client, &webhook, &delivery).await;
+        assert!(matches!(res, Err(WebhookError::InvalidStatus(_, 500))));
+        server.await.unwrap();
+    }
+}